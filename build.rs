@@ -1,16 +1,123 @@
+use std::env;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+
+/// The directory that the native Vosk library is looked up in, unless overridden by the
+/// VOSK_LIB_DIR environment variable.
+const DEFAULT_NATIVE_LIBS_DIR: &str = "./native-libs";
 
 fn main() {
-    let native_libs_dir = PathBuf::from("./native-libs");
+    println!("cargo:rerun-if-env-changed=VOSK_LIB_DIR");
+
+    // Without ASR support there's no need to link the Vosk library at all.
+    if env::var_os("CARGO_FEATURE_ASR").is_none() {
+        return;
+    }
+
+    let is_static = env::var_os("CARGO_FEATURE_STATIC_VOSK").is_some();
+    if env::var_os("CARGO_FEATURE_RUNTIME_VOSK").is_some() {
+        if is_static {
+            panic!("The static-vosk and runtime-vosk features can't be enabled together");
+        }
+        link_empty_vosk_library();
+        return;
+    }
+
+    let native_libs_dir = env::var_os("VOSK_LIB_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_NATIVE_LIBS_DIR));
+
+    match fs::canonicalize(&native_libs_dir) {
+        Ok(native_libs_dir) if is_static => link_static_vosk_library(&native_libs_dir),
+        Ok(native_libs_dir) => {
+            println!("cargo:rustc-link-search={}", native_libs_dir.display());
+        }
+        Err(err) => {
+            // Don't fail here, the library may still be found in one of the linker's default
+            // search paths. If it isn't, linking will fail with a more specific error.
+            println!(
+                "cargo:warning=Vosk library directory {} could not be read ({}). Set VOSK_LIB_DIR \
+                to the directory containing the Vosk library.",
+                native_libs_dir.display(),
+                err
+            );
+        }
+    }
+
+    let target_os = env::var("CARGO_CFG_TARGET_OS").unwrap_or_default();
+
+    if is_static {
+        // The static library doesn't carry its dependency on the C++ runtime with it.
+        match target_os.as_str() {
+            "windows" => (),
+            "macos" | "ios" => println!("cargo:rustc-link-lib=dylib=c++"),
+            _ => println!("cargo:rustc-link-lib=dylib=stdc++"),
+        }
+        // There is no dynamic library to be found at runtime, so there's no need for an rpath.
+        return;
+    }
+
+    // Make the executable look for dynamic libraries in its own directory. This is specific to the
+    // target platform, so we check the target rather than the host that the build script runs on.
+    match target_os.as_str() {
+        // Windows searches the executable's directory for DLLs by default. A missing DLL stops the
+        // executable before it can print anything, so with MSVC the DLL is only loaded once it's
+        // needed, which lets a missing DLL be reported as an error, see `ensure_vosk_loaded`.
+        "windows" => {
+            if env::var("CARGO_CFG_TARGET_ENV").as_deref() == Ok("msvc") {
+                println!("cargo:rustc-link-arg=/DELAYLOAD:libvosk.dll");
+                println!("cargo:rustc-link-lib=delayimp");
+            }
+        }
+        "macos" | "ios" => {
+            println!("cargo:rustc-link-arg=-Wl,-rpath,@loader_path");
+        }
+        _ => {
+            println!("cargo:rustc-link-arg=-Wl,-rpath,$ORIGIN");
+        }
+    }
+}
+
+/// Makes the static library the only Vosk library the linker can find first. vosk-sys links the
+/// library without saying how, which the linker takes to mean the dynamic library if there is one
+/// next to the static one, so only the static library is copied to a directory of its own, which
+/// is searched before any other.
+fn link_static_vosk_library(native_libs_dir: &Path) {
+    let file_name = static_vosk_library_file_name();
+    let static_lib_dir = PathBuf::from(env::var_os("OUT_DIR").unwrap()).join("static-vosk");
+    fs::create_dir_all(&static_lib_dir).unwrap();
+    let static_lib_path = native_libs_dir.join(file_name);
+    println!("cargo:rerun-if-changed={}", static_lib_path.display());
+    if let Err(err) = fs::copy(&static_lib_path, static_lib_dir.join(file_name)) {
+        panic!(
+            "The static Vosk library {} could not be read ({}). Set VOSK_LIB_DIR to the directory \
+            containing it.",
+            static_lib_path.display(),
+            err
+        );
+    }
     println!(
-        "cargo:rustc-link-search={}",
-        fs::canonicalize(native_libs_dir)
-            .unwrap()
-            .as_os_str()
-            .to_str()
-            .unwrap()
+        "cargo:rustc-link-search=native={}",
+        static_lib_dir.display()
     );
-    // Make the executable look for dynamic libraries in its own directory
-    println!("cargo:rustc-link-arg=-Wl,-rpath,$ORIGIN");
+}
+
+/// Lets vosk-sys link against an empty library instead of the Vosk library, so that the executable
+/// doesn't depend on it. Its functions are defined by the executable itself, which calls them in
+/// the library once it's loaded at runtime (see src/chapterize/vosk_library.rs).
+fn link_empty_vosk_library() {
+    let file_name = static_vosk_library_file_name();
+    let empty_lib_dir = PathBuf::from(env::var_os("OUT_DIR").unwrap()).join("runtime-vosk");
+    fs::create_dir_all(&empty_lib_dir).unwrap();
+    // An archive with no members
+    fs::write(empty_lib_dir.join(file_name), b"!<arch>\n").unwrap();
+    println!("cargo:rustc-link-search=native={}", empty_lib_dir.display());
+}
+
+/// The file name of a static library that vosk-sys's link to the Vosk library can resolve to.
+fn static_vosk_library_file_name() -> &'static str {
+    match env::var("CARGO_CFG_TARGET_OS").as_deref() {
+        Ok("windows") => "libvosk.lib",
+        _ => "libvosk.a",
+    }
 }
@@ -0,0 +1,75 @@
+use std::time::Duration;
+
+use crate::chapter::{Agreement, Chapter, ChapterSource};
+
+/// How far apart the starts of two chapters found by different detectors can be for them to count
+/// as the same chapter. Announcements are usually a few seconds after the chapter markers of the
+/// metadata, and silences end just before them.
+pub const DEFAULT_AGREEMENT_TOLERANCE: Duration = Duration::from_secs(5);
+
+/// The chapters found by the detectors that ran besides the one that the chapters are taken from,
+/// so that each chapter can be checked against them. A chapter that several detectors found is
+/// more likely to be right than one that only one of them found.
+#[derive(Clone, Debug, Default)]
+pub struct CrossCheck {
+    references: Vec<(ChapterSource, Vec<Duration>)>,
+    tolerance: Duration,
+}
+
+impl CrossCheck {
+    pub fn new(tolerance: Duration) -> Self {
+        Self {
+            references: Vec::new(),
+            tolerance,
+        }
+    }
+
+    /// Adds the chapters found by a detector. A detector that ran but found no chapters still
+    /// counts, as disagreeing with every chapter.
+    pub fn add_reference(&mut self, source: ChapterSource, chapters: &[Chapter]) {
+        let starts = chapters
+            .iter()
+            .filter(|chapter| chapter.source != ChapterSource::Inserted)
+            .map(|chapter| chapter.start)
+            .collect();
+        self.references.push((source, starts));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.references.is_empty()
+    }
+
+    /// Returns the chapter with the detectors that agree with it. Chapters that weren't detected,
+    /// such as the one inserted at 0:00, are returned as they are, as is every chapter if there's
+    /// nothing to check against. The detector that the chapter came from doesn't count twice.
+    pub fn chapter(&self, chapter: &Chapter) -> Chapter {
+        let mut checked = chapter.clone();
+        if self.is_empty() || chapter.source == ChapterSource::Inserted {
+            return checked;
+        }
+
+        let mut sources = vec![chapter.source];
+        for (source, starts) in &self.references {
+            if *source == chapter.source {
+                continue;
+            }
+            let agrees = starts
+                .iter()
+                .any(|start| start.abs_diff(chapter.start) <= self.tolerance);
+            if agrees && !sources.contains(source) {
+                sources.push(*source);
+            }
+        }
+        let num_detectors = 1 + self
+            .references
+            .iter()
+            .filter(|(source, _)| *source != chapter.source)
+            .count();
+
+        checked.agreement = Some(Agreement {
+            confidence: sources.len() as f32 / num_detectors as f32,
+            sources,
+        });
+        checked
+    }
+}
@@ -1,105 +1,802 @@
 use std::collections::VecDeque;
 use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+use std::process::{Child, ChildStdout, Command, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 use std::time::Duration;
 
-use color_eyre::eyre::{self, eyre, Context, ContextCompat};
 use symphonia::core::audio::{AudioBufferRef, Signal};
 use symphonia::core::codecs::{Decoder, DecoderOptions, CODEC_TYPE_NULL};
 use symphonia::core::conv::FromSample;
 use symphonia::core::errors::Error;
-use symphonia::core::formats::{FormatOptions, FormatReader, Track};
-use symphonia::core::io::MediaSourceStream;
-use symphonia::core::meta::MetadataOptions;
-use symphonia::core::probe::Hint;
+use symphonia::core::formats::{FormatOptions, FormatReader, Packet, Track};
+use symphonia::core::io::{MediaSource, MediaSourceStream};
+use symphonia::core::meta::{MetadataOptions, MetadataRevision, StandardTagKey};
+use symphonia::core::probe::{Hint, ProbeResult};
+use symphonia::core::units::TimeBase;
+
+use crate::chapter::{Chapter, ChapterSource};
+use crate::error::{ChapterizerError, IoResultExt, Result};
+use crate::timestamp::SampleOffset;
+
+/// Finds the track with the given id, or the first audio track with a known codec if no id is
+/// given.
+fn find_track(format: &dyn FormatReader, track_id: Option<u32>) -> Result<&Track> {
+    match track_id {
+        Some(track_id) => format.tracks().iter().find(|t| t.id == track_id).ok_or(
+            ChapterizerError::UnsupportedAudio("File contains no track with the selected id"),
+        ),
+        None => format
+            .tracks()
+            .iter()
+            .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+            .ok_or(ChapterizerError::UnsupportedAudio(
+                "File contains no supported audio tracks",
+            )),
+    }
+}
+
+/// Finds the track with the given id, or the first audio track with a known (decodeable) codec if
+/// no id is given, and creates a decoder for it.
+fn make_decoder(
+    format: &dyn FormatReader,
+    track_id: Option<u32>,
+) -> Result<(Track, Box<dyn Decoder>)> {
+    let track = find_track(format, track_id)?;
+
+    // Use the default options for the decoder.
+    let dec_opts: DecoderOptions = Default::default();
+
+    // Create a decoder for the track.
+    let decoder = symphonia::default::get_codecs()
+        .make(&track.codec_params, &dec_opts)
+        .map_err(|source| ChapterizerError::Decode {
+            context: "File uses an unsupported codec",
+            source,
+        })?;
+
+    Ok((track.clone(), decoder))
+}
+
+/// Converts a stream of samples from one sample rate to another using linear interpolation. This
+/// is crude, but good enough for speech recognition, which mostly needs the timing to be right.
+struct LinearResampler {
+    input_rate: u32,
+    output_rate: u32,
+    /// The position of the next output sample relative to the previous input sample, in input
+    /// samples.
+    pos: f64,
+    prev_sample: Option<i16>,
+}
+
+impl LinearResampler {
+    fn new(input_rate: u32, output_rate: u32) -> Self {
+        Self {
+            input_rate,
+            output_rate,
+            pos: 0.0,
+            prev_sample: None,
+        }
+    }
+
+    fn process(&mut self, samples: impl IntoIterator<Item = i16>, output: &mut VecDeque<i16>) {
+        let step = self.input_rate as f64 / self.output_rate as f64;
+        for sample in samples {
+            let Some(prev_sample) = self.prev_sample.replace(sample) else {
+                continue;
+            };
+            while self.pos < 1.0 {
+                let interpolated =
+                    prev_sample as f64 + (sample as f64 - prev_sample as f64) * self.pos;
+                output.push_back(interpolated.round() as i16);
+                self.pos += step;
+            }
+            self.pos -= 1.0;
+        }
+    }
+}
+
+/// A revision of the container metadata that was read mid-stream, e.g. the tags of the next
+/// stream in a chained stream.
+#[derive(Clone, Debug)]
+pub struct MetadataUpdate {
+    /// The position in the stream at which the revision was read, if known.
+    pub position: Option<Duration>,
+    /// The tags in the revision, as key-value pairs.
+    pub tags: Vec<(String, String)>,
+    /// The value of the title tag, if the revision has one.
+    pub title: Option<String>,
+}
+
+impl MetadataUpdate {
+    fn new(position: Option<Duration>, revision: &MetadataRevision) -> Self {
+        Self {
+            position,
+            tags: revision
+                .tags()
+                .iter()
+                .map(|tag| (tag.key.clone(), tag.value.to_string()))
+                .collect(),
+            title: revision
+                .tags()
+                .iter()
+                .find(|tag| matches!(tag.std_key, Some(StandardTagKey::TrackTitle)))
+                .map(|tag| tag.value.to_string()),
+        }
+    }
+}
 
 pub struct AudioProvider {
+    source: Source,
+    /// The decoded samples that haven't been provided yet.
+    queue: VecDeque<i16>,
+    /// Reused to convert the samples of each packet, so that decoding doesn't allocate.
+    scratch: Vec<i16>,
+    /// The sample rate of the samples provided. This is the sample rate of the track at the start
+    /// of the file, which is kept even if the sample rate changes mid-stream.
+    sample_rate: u32,
+    /// The position of the next sample to be provided.
+    position: SampleOffset,
+    /// Used to convert the samples to the output sample rate while the decoded sample rate differs
+    /// from it, e.g. in concatenated MP3 files.
+    resampler: Option<LinearResampler>,
+    /// Called with each metadata revision that is read mid-stream.
+    metadata_callback: Option<Box<dyn FnMut(MetadataUpdate) + Send>>,
+    /// The number of bytes of packet data read so far.
+    bytes_read: Arc<AtomicU64>,
+}
+
+/// Where the samples of an audio provider come from.
+enum Source {
+    /// A container, decoded by symphonia.
+    Decoded(DecodedSource),
+    /// Raw PCM, which is read as it is.
+    Raw(RawSource),
+}
+
+struct DecodedSource {
     format: Box<dyn FormatReader>,
     decoder: Box<dyn Decoder>,
     track_info: Track,
-    queue: VecDeque<i16>,
-    sample_rate: u32,
+    /// The id of the track that was asked for, if any, which is selected again when the decoder
+    /// is reset.
+    selected_track_id: Option<u32>,
+    /// A packet that couldn't be decoded because the decoder had to be reset first.
+    retry_packet: Option<Packet>,
 }
 
-impl AudioProvider {
-    pub fn new(src: File) -> eyre::Result<Self> {
-        // Create the media source stream.
-        let mss = MediaSourceStream::new(Box::new(src), Default::default());
+struct RawSource {
+    reader: Box<dyn Read + Send>,
+    format: RawFormat,
+    /// The duration of the audio, if it's read from a file, whose size gives it away.
+    total_duration: Option<Duration>,
+    /// Reused to read each chunk of audio, so that reading doesn't allocate.
+    bytes: Vec<u8>,
+}
 
-        // Create a probe hint using the file's extension. [Optional]
-        let hint = Hint::new();
-        // hint.with_extension("mp3");
+/// The sample rate that ffmpeg resamples audio to when it decodes it, which is what most Vosk
+/// models are trained on.
+const FFMPEG_SAMPLE_RATE: u32 = 16_000;
 
-        // Use the default options for metadata and format readers.
-        let meta_opts: MetadataOptions = Default::default();
-        let fmt_opts: FormatOptions = Default::default();
+/// The audio that an ffmpeg process decodes to its standard output. The process is killed if it's
+/// dropped before the audio is read to the end.
+struct FfmpegOutput {
+    child: Child,
+    stdout: ChildStdout,
+}
 
-        // Probe the media source.
-        let probed = symphonia::default::get_probe()
-            .format(&hint, mss, &fmt_opts, &meta_opts)
-            .wrap_err("File is of an unsupported format")?;
+impl Read for FfmpegOutput {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let num_bytes = self.stdout.read(buf)?;
+        if num_bytes == 0 && !buf.is_empty() {
+            match self.child.wait() {
+                Ok(status) if !status.success() => {
+                    log::warn!("ffmpeg failed ({}), the audio may be cut off", status)
+                }
+                Ok(_) => {}
+                Err(err) => log::warn!("Failed to wait for ffmpeg: {}", err),
+            }
+        }
+        Ok(num_bytes)
+    }
+}
 
-        // Get the instantiated format reader.
-        let format = probed.format;
+impl Drop for FfmpegOutput {
+    fn drop(&mut self) {
+        // Fails if it already exited, which is fine
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
 
-        // Find the first audio track with a known (decodeable) codec.
-        let track = format
-            .tracks()
-            .iter()
-            .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
-            .ok_or_else(|| eyre!("File contains no supported audio tracks"))?;
+/// The number of frames of raw PCM that are read at a time.
+const RAW_CHUNK_FRAMES: usize = 4096;
+
+/// Hints that help detect the format of an audio file, for containers that are hard to detect
+/// from their contents alone.
+#[derive(Clone, Debug, Default)]
+pub struct FormatHint {
+    /// The extension of the audio file, e.g. "mp3".
+    pub extension: Option<String>,
+    /// The MIME type of the audio file, e.g. "audio/mpeg".
+    pub mime_type: Option<String>,
+    /// If set, the audio is raw PCM in this format, which is read as it is instead of probed.
+    pub raw: Option<RawFormat>,
+    /// If set, audio that can't be decoded, e.g. because its codec isn't supported, is decoded by
+    /// ffmpeg instead.
+    pub ffmpeg_fallback: bool,
+    /// The id of the track to decode, e.g. for files with an audio track per language. Defaults to
+    /// the first audio track with a supported codec.
+    pub track_id: Option<u32>,
+}
+
+impl FormatHint {
+    /// Creates a hint using the extension of the path.
+    pub fn from_path(path: &Path) -> Self {
+        Self {
+            extension: path
+                .extension()
+                .map(|ext| ext.to_string_lossy().to_lowercase()),
+            mime_type: None,
+            raw: None,
+            ffmpeg_fallback: false,
+            track_id: None,
+        }
+    }
+}
+
+/// The encoding of the samples of raw PCM audio, named like the raw formats of ffmpeg and sox.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RawSampleFormat {
+    /// Signed 16-bit little-endian.
+    S16le,
+    /// Signed 16-bit big-endian.
+    S16be,
+    /// 32-bit float little-endian.
+    F32le,
+}
+
+impl RawSampleFormat {
+    pub const ALL: [Self; 3] = [Self::S16le, Self::S16be, Self::F32le];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::S16le => "s16le",
+            Self::S16be => "s16be",
+            Self::F32le => "f32le",
+        }
+    }
+
+    /// Returns the sample format with the name, e.g. "s16le".
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|format| format.name().eq_ignore_ascii_case(name))
+    }
+
+    fn sample_len(self) -> usize {
+        match self {
+            Self::S16le | Self::S16be => 2,
+            Self::F32le => 4,
+        }
+    }
+
+    /// Converts the bytes of a sample to a 16-bit sample.
+    fn read_sample(self, bytes: &[u8]) -> i16 {
+        match self {
+            Self::S16le => i16::from_le_bytes([bytes[0], bytes[1]]),
+            Self::S16be => i16::from_be_bytes([bytes[0], bytes[1]]),
+            Self::F32le => {
+                i16::from_sample(f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+            }
+        }
+    }
+}
+
+/// The layout of raw PCM audio, which has no header to read it from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RawFormat {
+    pub sample_format: RawSampleFormat,
+    pub sample_rate: u32,
+    pub num_channels: u16,
+}
+
+impl RawFormat {
+    fn frame_len(&self) -> usize {
+        self.sample_format.sample_len() * self.num_channels as usize
+    }
+
+    /// Returns the duration of the number of bytes of audio.
+    fn duration(&self, num_bytes: u64) -> Duration {
+        SampleOffset::new(num_bytes / self.frame_len() as u64).to_duration(self.sample_rate)
+    }
+}
+
+/// The path that stands for the standard input, which raw PCM can be piped to.
+pub const STDIN_PATH: &str = "-";
 
-        // Use the default options for the decoder.
-        let dec_opts: DecoderOptions = Default::default();
+/// Returns whether the path stands for the standard input.
+pub fn is_stdin(path: &Path) -> bool {
+    path.as_os_str() == STDIN_PATH
+}
+
+/// Returns the duration of the raw PCM file.
+fn raw_file_duration(src: &File, raw: &RawFormat) -> Result<Duration> {
+    let metadata = src
+        .metadata()
+        .io_context("Failed to read audio file metadata")?;
+    Ok(raw.duration(metadata.len()))
+}
+
+/// Probes the media source, returning a reader for its format along with any metadata found
+/// before the container.
+fn probe(src: impl MediaSource + 'static, format_hint: &FormatHint) -> Result<ProbeResult> {
+    // Create the media source stream.
+    let mss = MediaSourceStream::new(Box::new(src), Default::default());
+
+    // Create a probe hint using the file's extension and MIME type, if known.
+    let mut hint = Hint::new();
+    if let Some(extension) = &format_hint.extension {
+        hint.with_extension(extension);
+    }
+    if let Some(mime_type) = &format_hint.mime_type {
+        hint.mime_type(mime_type);
+    }
+
+    // Use the default options for metadata and format readers.
+    let meta_opts: MetadataOptions = Default::default();
+    let fmt_opts: FormatOptions = Default::default();
+
+    // Probe the media source.
+    symphonia::default::get_probe()
+        .format(&hint, mss, &fmt_opts, &meta_opts)
+        .map_err(|source| ChapterizerError::Decode {
+            context: "File is of an unsupported format",
+            source,
+        })
+}
+
+/// Probes the media source, returning a reader for its format.
+fn probe_format(
+    src: impl MediaSource + 'static,
+    format_hint: &FormatHint,
+) -> Result<Box<dyn FormatReader>> {
+    Ok(probe(src, format_hint)?.format)
+}
+
+/// Reads the chapters from the cue points of the media source, e.g. the cuesheet of a FLAC file,
+/// titled after their title tag if they have one. Returns no chapters if the format has no cue
+/// points.
+pub fn read_cue_chapters(
+    src: impl MediaSource + 'static,
+    format_hint: &FormatHint,
+) -> Result<Vec<Chapter>> {
+    let format = probe_format(src, format_hint)?;
+    let time_base = format.default_track().and_then(|track| {
+        let params = &track.codec_params;
+        params
+            .time_base
+            .or_else(|| params.sample_rate.map(|rate| TimeBase::new(1, rate)))
+    });
+    let Some(time_base) = time_base else {
+        return Ok(Vec::new());
+    };
+
+    let mut chapters = format
+        .cues()
+        .iter()
+        .enumerate()
+        .map(|(i, cue)| {
+            let time = time_base.calc_time(cue.start_ts);
+            let title = cue
+                .tags
+                .iter()
+                .find(|tag| tag.std_key == Some(StandardTagKey::TrackTitle))
+                .map(|tag| tag.value.to_string())
+                .unwrap_or_else(|| format!("Chapter {:02}", i + 1));
+            Chapter::new(
+                Duration::from_secs_f64(time.seconds as f64 + time.frac),
+                title,
+                ChapterSource::Metadata,
+            )
+        })
+        .collect::<Vec<_>>();
+    chapters.sort_by_key(|chapter| chapter.start);
+    Ok(chapters)
+}
+
+/// The properties of a track of an audio file.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct TrackProperties {
+    pub id: u32,
+    /// The short name of the codec, e.g. "mp3", or its id if the codec isn't supported.
+    pub codec: String,
+    /// Whether the codec is supported, i.e. whether the track can be chapterized.
+    pub is_supported: bool,
+    pub sample_rate: Option<u32>,
+    pub channels: Option<usize>,
+    pub duration: Option<Duration>,
+    pub language: Option<String>,
+}
+
+/// The properties of an audio file as seen by the audio provider.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct AudioProperties {
+    pub tracks: Vec<TrackProperties>,
+    /// The id of the track that would be chapterized, if any.
+    pub selected_track_id: Option<u32>,
+    /// The tags in the latest metadata revision, as key-value pairs.
+    pub tags: Vec<(String, String)>,
+    /// The number of embedded images, e.g. cover art.
+    pub num_visuals: usize,
+}
+
+/// Reads the properties of the audio file without decoding it.
+pub fn inspect_audio(src: File, format_hint: &FormatHint) -> Result<AudioProperties> {
+    if let Some(raw) = &format_hint.raw {
+        return Ok(AudioProperties {
+            tracks: vec![TrackProperties {
+                id: 0,
+                codec: format!("pcm_{}", raw.sample_format.name()),
+                is_supported: true,
+                sample_rate: Some(raw.sample_rate),
+                channels: Some(raw.num_channels as usize),
+                duration: Some(raw_file_duration(&src, raw)?),
+                language: None,
+            }],
+            selected_track_id: Some(0),
+            tags: Vec::new(),
+            num_visuals: 0,
+        });
+    }
+
+    let mut probed = probe(src, format_hint)?;
+
+    let codecs = symphonia::default::get_codecs();
+    let tracks = probed
+        .format
+        .tracks()
+        .iter()
+        .map(|track| {
+            let params = &track.codec_params;
+            let descriptor = codecs.get_codec(params.codec);
+            TrackProperties {
+                id: track.id,
+                codec: descriptor
+                    .map(|descriptor| descriptor.short_name.to_string())
+                    .unwrap_or_else(|| params.codec.to_string()),
+                is_supported: params.codec != CODEC_TYPE_NULL && descriptor.is_some(),
+                sample_rate: params.sample_rate,
+                channels: params.channels.map(|channels| channels.count()),
+                duration: params
+                    .time_base
+                    .zip(params.n_frames)
+                    .map(|(time_base, n_frames)| {
+                        let time = time_base.calc_time(n_frames);
+                        Duration::from_secs_f64(time.seconds as f64 + time.frac)
+                    }),
+                language: track.language.clone(),
+            }
+        })
+        .collect();
+    let selected_track_id = make_decoder(probed.format.as_ref(), format_hint.track_id)
+        .ok()
+        .map(|(track, _)| track.id);
 
-        // Create a decoder for the track.
-        let decoder = symphonia::default::get_codecs()
-            .make(&track.codec_params, &dec_opts)
-            .wrap_err("File uses an unsupported codec")?;
+    // Metadata in the container takes precedence over metadata found before it, e.g. ID3v2 tags
+    let format_metadata = probed.format.metadata();
+    let revision = match format_metadata.current() {
+        Some(revision) => Some(revision.clone()),
+        None => probed
+            .metadata
+            .get()
+            .and_then(|metadata| metadata.current().cloned()),
+    };
 
-        Ok(Self {
-            sample_rate: track
+    Ok(AudioProperties {
+        tracks,
+        selected_track_id,
+        tags: revision
+            .as_ref()
+            .map(|revision| {
+                revision
+                    .tags()
+                    .iter()
+                    .map(|tag| (tag.key.clone(), tag.value.to_string()))
+                    .collect()
+            })
+            .unwrap_or_default(),
+        num_visuals: revision.map_or(0, |revision| revision.visuals().len()),
+    })
+}
+
+/// Calculates the duration of the audio by walking over all of its packets without decoding
+/// them. This is much faster than decoding the audio, and gives the true duration of files whose
+/// metadata doesn't specify it, such as VBR MP3 files without a Xing header.
+pub fn scan_duration(src: File, format_hint: &FormatHint) -> Result<Duration> {
+    if let Some(raw) = &format_hint.raw {
+        return raw_file_duration(&src, raw);
+    }
+
+    let mut format = probe_format(src, format_hint)?;
+    let track = find_track(format.as_ref(), format_hint.track_id)?;
+    let track_id = track.id;
+    let time_base = match (track.codec_params.time_base, track.codec_params.sample_rate) {
+        (Some(time_base), _) => time_base,
+        (None, Some(sample_rate)) => TimeBase::new(1, sample_rate),
+        (None, None) => {
+            return Err(ChapterizerError::UnsupportedAudio(
+                "File track metadata does not specify time base or sample rate",
+            ))
+        }
+    };
+
+    let mut total_ts = 0u64;
+    loop {
+        match format.next_packet() {
+            Ok(packet) if packet.track_id() == track_id => total_ts += packet.dur,
+            Ok(_) => continue,
+            Err(Error::IoError(err)) if matches!(err.kind(), std::io::ErrorKind::UnexpectedEof) => {
+                break
+            }
+            Err(source) => {
+                return Err(ChapterizerError::Decode {
+                    context: "Failed to read packet while scanning duration",
+                    source,
+                })
+            }
+        }
+    }
+
+    let time = time_base.calc_time(total_ts);
+    Ok(Duration::from_secs_f64(time.seconds as f64 + time.frac))
+}
+
+impl AudioProvider {
+    pub fn new(src: File, format_hint: &FormatHint) -> Result<Self> {
+        if let Some(raw) = format_hint.raw {
+            let total_duration = raw_file_duration(&src, &raw)?;
+            return Self::raw(Box::new(src), raw, Some(total_duration));
+        }
+
+        let format = probe_format(src, format_hint)?;
+
+        let (track, decoder) = make_decoder(format.as_ref(), format_hint.track_id)?;
+
+        Ok(Self::with_source(
+            track
                 .codec_params
                 .sample_rate
-                .wrap_err("File track metadata does not specify sample rate")?,
-            track_info: track.clone(),
-            format,
-            decoder,
+                .ok_or(ChapterizerError::UnsupportedAudio(
+                    "File track metadata does not specify sample rate",
+                ))?,
+            Source::Decoded(DecodedSource {
+                track_info: track,
+                selected_track_id: format_hint.track_id,
+                format,
+                decoder,
+                retry_packet: None,
+            }),
+        ))
+    }
+
+    /// Creates a provider of the audio file as decoded by ffmpeg, for formats and codecs that
+    /// symphonia can't decode. ffmpeg mixes the audio down to mono and resamples it to 16 kHz. The
+    /// duration of the audio is read with ffprobe, if it can be.
+    pub fn ffmpeg(path: &Path) -> Result<Self> {
+        let total_duration = match crate::extract::probe(path) {
+            Ok(ffprobe) => ffprobe.format.and_then(|format| format.duration()),
+            Err(err) => {
+                log::debug!("Failed to probe audio file for its duration: {}", err);
+                None
+            }
+        };
+
+        let mut child = Command::new("ffmpeg")
+            .args(["-v", "error", "-nostdin", "-i"])
+            .arg(path)
+            .args(["-vn", "-f", "s16le", "-ac", "1", "-ar"])
+            .arg(FFMPEG_SAMPLE_RATE.to_string())
+            .arg("-")
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .spawn()
+            .io_context("Failed to run ffmpeg")?;
+        let stdout = child.stdout.take().expect("stdout is piped");
+
+        Self::raw(
+            Box::new(FfmpegOutput { child, stdout }),
+            RawFormat {
+                sample_format: RawSampleFormat::S16le,
+                sample_rate: FFMPEG_SAMPLE_RATE,
+                num_channels: 1,
+            },
+            total_duration,
+        )
+    }
+
+    /// Creates a provider of the raw PCM audio piped to the standard input.
+    pub fn stdin(raw: RawFormat) -> Result<Self> {
+        Self::raw(Box::new(io::stdin()), raw, None)
+    }
+
+    fn raw(
+        reader: Box<dyn Read + Send>,
+        raw: RawFormat,
+        total_duration: Option<Duration>,
+    ) -> Result<Self> {
+        if raw.sample_rate == 0 || raw.num_channels == 0 {
+            return Err(ChapterizerError::InvalidOptions(
+                "raw audio needs a sample rate and at least one channel",
+            ));
+        }
+        Ok(Self::with_source(
+            raw.sample_rate,
+            Source::Raw(RawSource {
+                reader,
+                format: raw,
+                total_duration,
+                bytes: Vec::new(),
+            }),
+        ))
+    }
+
+    fn with_source(sample_rate: u32, source: Source) -> Self {
+        Self {
+            source,
             queue: VecDeque::new(),
-        })
+            scratch: Vec::new(),
+            sample_rate,
+            position: SampleOffset::ZERO,
+            resampler: None,
+            metadata_callback: None,
+            bytes_read: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Returns a counter of the number of bytes of packet data read so far, which can be used to
+    /// estimate progress when the duration is unknown.
+    pub fn bytes_read_counter(&self) -> Arc<AtomicU64> {
+        self.bytes_read.clone()
+    }
+
+    /// Sets a callback that is called with each metadata revision that is read mid-stream, so that
+    /// tags and titles that change during playback aren't lost.
+    pub fn set_metadata_callback(&mut self, callback: impl FnMut(MetadataUpdate) + Send + 'static) {
+        self.metadata_callback = Some(Box::new(callback));
+    }
+
+    /// Pushes the decoded samples to the queue, resampling them to the output sample rate if the
+    /// sample rate changed mid-stream.
+    fn push_samples(&mut self, samples: &[i16], sample_rate: u32) {
+        if sample_rate == self.sample_rate {
+            self.resampler = None;
+            self.queue.extend(samples);
+            return;
+        }
+
+        let resampler = match &mut self.resampler {
+            Some(resampler) if resampler.input_rate == sample_rate => resampler,
+            resampler => {
+                log::warn!(
+                    "Sample rate changed mid-stream from {} Hz to {} Hz, resampling",
+                    self.sample_rate,
+                    sample_rate
+                );
+                resampler.insert(LinearResampler::new(sample_rate, self.sample_rate))
+            }
+        };
+        resampler.process(samples.iter().copied(), &mut self.queue);
     }
 
     pub fn sample_rate(&self) -> u32 {
         self.sample_rate
     }
 
+    /// Returns the position of the next sample to be provided, i.e. the number of samples
+    /// provided so far, at the output sample rate.
+    pub fn position(&self) -> SampleOffset {
+        self.position
+    }
+
     pub fn total_duration(&self) -> Option<Duration> {
-        let time_base = self.track_info.codec_params.time_base?;
-        let n_frames = self.track_info.codec_params.n_frames?;
+        let track_info = match &self.source {
+            Source::Decoded(source) => &source.track_info,
+            Source::Raw(source) => return source.total_duration,
+        };
+        let time_base = track_info.codec_params.time_base?;
+        let n_frames = track_info.codec_params.n_frames?;
         let time = time_base.calc_time(n_frames);
         Some(Duration::from_secs_f64(time.seconds as f64 + time.frac))
     }
 }
 
-impl Iterator for AudioProvider {
-    type Item = i16;
+impl AudioProvider {
+    /// Appends up to max_len samples to the buffer, decoding packets as needed. Returns the number
+    /// of samples appended, which is less than max_len only at the end of the stream.
+    pub fn fill_buffer(&mut self, buffer: &mut Vec<i16>, max_len: usize) -> usize {
+        let mut appended = 0;
+        while appended < max_len {
+            if self.queue.is_empty() && !self.decode_packet() {
+                break;
+            }
+            let len = (max_len - appended).min(self.queue.len());
+            buffer.extend(self.queue.drain(..len));
+            appended += len;
+        }
+        self.position += appended as u64;
+        appended
+    }
 
-    #[inline]
-    fn next(&mut self) -> Option<i16> {
-        if !self.queue.is_empty() {
-            return Some(self.queue.pop_front().unwrap());
+    /// Decodes the next packet of the selected track and pushes its samples to the queue. Returns
+    /// false at the end of the stream. A packet may not yield any samples, e.g. while the
+    /// resampler is waiting for its first sample.
+    fn decode_packet(&mut self) -> bool {
+        let mut samples = std::mem::take(&mut self.scratch);
+        samples.clear();
+        let sample_rate = match &mut self.source {
+            Source::Decoded(source) => source.decode_packet(
+                &mut samples,
+                self.sample_rate,
+                &mut self.metadata_callback,
+                &self.bytes_read,
+            ),
+            Source::Raw(source) => source.read_chunk(&mut samples, &self.bytes_read),
+        };
+        if let Some(sample_rate) = sample_rate {
+            self.push_samples(&samples, sample_rate);
         }
+        self.scratch = samples;
+        sample_rate.is_some()
+    }
+}
 
+impl DecodedSource {
+    /// Selects a track again and recreates the decoder, e.g. after the track list changed.
+    fn reset_decoder(&mut self, output_rate: u32) -> Result<()> {
+        let (track, decoder) = make_decoder(self.format.as_ref(), self.selected_track_id)?;
+        if track.codec_params.sample_rate != Some(output_rate) {
+            log::debug!(
+                "Sample rate changed to {:?} Hz after reset, resampling to {} Hz",
+                track.codec_params.sample_rate,
+                output_rate
+            );
+        }
+        self.track_info = track;
+        self.decoder = decoder;
+        Ok(())
+    }
+
+    /// Decodes the next packet of the selected track into the samples. Returns the sample rate of
+    /// the packet, or `None` at the end of the stream.
+    fn decode_packet(
+        &mut self,
+        samples: &mut Vec<i16>,
+        output_rate: u32,
+        metadata_callback: &mut Option<Box<dyn FnMut(MetadataUpdate) + Send>>,
+        bytes_read: &AtomicU64,
+    ) -> Option<u32> {
         // The decode loop.
         let decoded = loop {
-            // Get the next packet from the media format.
-            let packet = match self.format.next_packet() {
+            // Get the next packet from the media format, unless a packet needs to be retried.
+            let is_retry = self.retry_packet.is_some();
+            let packet = match self
+                .retry_packet
+                .take()
+                .map_or_else(|| self.format.next_packet(), Ok)
+            {
                 Ok(packet) => Some(packet),
                 Err(Error::ResetRequired) => {
-                    // The track list has been changed. Re-examine it and create a new set of decoders,
-                    // then restart the decode loop. This is an advanced feature and it is not
-                    // unreasonable to consider this "the end." As of v0.5.0, the only usage of this is
-                    // for chained OGG physical streams.
-                    unimplemented!();
+                    // The track list has been changed. Re-examine it and create a new decoder,
+                    // then restart the decode loop. As of v0.5.0, the only usage of this is for
+                    // chained OGG physical streams.
+                    if let Err(err) = self.reset_decoder(output_rate) {
+                        log::error!("Failed to reset decoder, stopping: {}", err);
+                        break None;
+                    }
+                    continue;
                 }
                 Err(err) => {
                     // eprintln!("{:#?}", err);
@@ -117,10 +814,8 @@ impl Iterator for AudioProvider {
             };
 
             // If there are no more packets, we've reached the end of the stream
-            let packet = match packet {
-                Some(packet) => packet,
-                None => return None,
-            };
+            let packet = packet?;
+            bytes_read.fetch_add(packet.buf().len() as u64, Ordering::Relaxed);
 
             // Consume any new metadata that has been read since the last packet.
             while !self.format.metadata().is_latest() {
@@ -128,6 +823,15 @@ impl Iterator for AudioProvider {
                 self.format.metadata().pop();
 
                 // Consume the new metadata at the head of the metadata queue.
+                if let (Some(callback), Some(revision)) =
+                    (metadata_callback.as_mut(), self.format.metadata().current())
+                {
+                    let position = self.track_info.codec_params.time_base.map(|time_base| {
+                        let time = time_base.calc_time(packet.ts());
+                        Duration::from_secs_f64(time.seconds as f64 + time.frac)
+                    });
+                    callback(MetadataUpdate::new(position, revision));
+                }
             }
 
             // If the packet does not belong to the selected track, skip over it.
@@ -138,6 +842,19 @@ impl Iterator for AudioProvider {
             // Decode the packet into audio samples.
             match self.decoder.decode(&packet) {
                 Ok(decoded) => break Some(decoded),
+                Err(Error::ResetRequired) => {
+                    // The codec parameters changed. Recreate the decoder and decode the packet
+                    // again (only once), so that no samples are lost and the timestamps stay
+                    // consistent.
+                    if let Err(err) = self.reset_decoder(output_rate) {
+                        log::error!("Failed to reset decoder, stopping: {}", err);
+                        break None;
+                    }
+                    if !is_retry {
+                        self.retry_packet = Some(packet);
+                    }
+                    continue;
+                }
                 Err(Error::IoError(_)) => {
                     // The packet failed to decode due to an IO error, skip the packet.
                     continue;
@@ -154,66 +871,115 @@ impl Iterator for AudioProvider {
             }
         };
 
-        if let Some(decoded) = decoded {
-            // Consume the decoded audio samples (see below).
-            // TODO: use dithering when converting sample?
-            // TODO: instead of only taking from 1 channel, mix multiple channels into mono?
-            // TODO: refactor this
-            let target_channel = 0usize;
-            match decoded {
-                AudioBufferRef::F32(buf) => {
-                    for &sample in buf.chan(target_channel) {
-                        self.queue.push_back(i16::from_sample(sample));
-                    }
+        // If there's nothing decoded, we've reached the end of the stream
+        let decoded = decoded?;
+
+        // Consume the decoded audio samples (see below).
+        // TODO: use dithering when converting sample?
+        // TODO: instead of only taking from 1 channel, mix multiple channels into mono?
+        // TODO: refactor this
+        let target_channel = 0usize;
+        let sample_rate = decoded.spec().rate;
+        match decoded {
+            AudioBufferRef::F32(buf) => {
+                for &sample in buf.chan(target_channel) {
+                    samples.push(i16::from_sample(sample));
                 }
-                AudioBufferRef::U8(buf) => {
-                    for &sample in buf.chan(target_channel) {
-                        self.queue.push_back(i16::from_sample(sample));
-                    }
+            }
+            AudioBufferRef::U8(buf) => {
+                for &sample in buf.chan(target_channel) {
+                    samples.push(i16::from_sample(sample));
                 }
-                AudioBufferRef::U16(buf) => {
-                    for &sample in buf.chan(target_channel) {
-                        self.queue.push_back(i16::from_sample(sample));
-                    }
+            }
+            AudioBufferRef::U16(buf) => {
+                for &sample in buf.chan(target_channel) {
+                    samples.push(i16::from_sample(sample));
                 }
-                AudioBufferRef::U24(buf) => {
-                    for &sample in buf.chan(target_channel) {
-                        self.queue.push_back(i16::from_sample(sample));
-                    }
+            }
+            AudioBufferRef::U24(buf) => {
+                for &sample in buf.chan(target_channel) {
+                    samples.push(i16::from_sample(sample));
                 }
-                AudioBufferRef::U32(buf) => {
-                    for &sample in buf.chan(target_channel) {
-                        self.queue.push_back(i16::from_sample(sample));
-                    }
+            }
+            AudioBufferRef::U32(buf) => {
+                for &sample in buf.chan(target_channel) {
+                    samples.push(i16::from_sample(sample));
                 }
-                AudioBufferRef::S8(buf) => {
-                    for &sample in buf.chan(target_channel) {
-                        self.queue.push_back(i16::from_sample(sample));
-                    }
+            }
+            AudioBufferRef::S8(buf) => {
+                for &sample in buf.chan(target_channel) {
+                    samples.push(i16::from_sample(sample));
                 }
-                AudioBufferRef::S16(buf) => {
-                    for &sample in buf.chan(target_channel) {
-                        self.queue.push_back(i16::from_sample(sample));
-                    }
+            }
+            AudioBufferRef::S16(buf) => {
+                for &sample in buf.chan(target_channel) {
+                    samples.push(i16::from_sample(sample));
                 }
-                AudioBufferRef::S24(buf) => {
-                    for &sample in buf.chan(target_channel) {
-                        self.queue.push_back(i16::from_sample(sample));
-                    }
+            }
+            AudioBufferRef::S24(buf) => {
+                for &sample in buf.chan(target_channel) {
+                    samples.push(i16::from_sample(sample));
                 }
-                AudioBufferRef::S32(buf) => {
-                    for &sample in buf.chan(target_channel) {
-                        self.queue.push_back(i16::from_sample(sample));
-                    }
+            }
+            AudioBufferRef::S32(buf) => {
+                for &sample in buf.chan(target_channel) {
+                    samples.push(i16::from_sample(sample));
                 }
-                AudioBufferRef::F64(buf) => {
-                    for &sample in buf.chan(target_channel) {
-                        self.queue.push_back(i16::from_sample(sample));
-                    }
+            }
+            AudioBufferRef::F64(buf) => {
+                for &sample in buf.chan(target_channel) {
+                    samples.push(i16::from_sample(sample));
+                }
+            }
+        }
+        Some(sample_rate)
+    }
+}
+
+impl RawSource {
+    /// Reads the next chunk of audio into the samples, downmixed to mono. Returns the sample rate
+    /// of the audio, or `None` at the end of the stream.
+    fn read_chunk(&mut self, samples: &mut Vec<i16>, bytes_read: &AtomicU64) -> Option<u32> {
+        let frame_len = self.format.frame_len();
+        self.bytes.resize(RAW_CHUNK_FRAMES * frame_len, 0);
+        // Reads from a pipe may return any number of bytes, so keep reading until a whole number
+        // of frames has been read
+        let mut len = 0;
+        while len == 0 || len % frame_len != 0 {
+            match self.reader.read(&mut self.bytes[len..]) {
+                Ok(0) => break,
+                Ok(num_bytes) => len += num_bytes,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => {
+                    log::error!("Failed to read raw audio, stopping: {}", err);
+                    break;
                 }
             }
         }
+        bytes_read.fetch_add(len as u64, Ordering::Relaxed);
 
+        let sample_format = self.format.sample_format;
+        for frame in self.bytes[..len].chunks_exact(frame_len) {
+            let sum: i32 = frame
+                .chunks_exact(sample_format.sample_len())
+                .map(|sample| sample_format.read_sample(sample) as i32)
+                .sum();
+            samples.push((sum / self.format.num_channels as i32) as i16);
+        }
+        (len >= frame_len).then_some(self.format.sample_rate)
+    }
+}
+
+impl Iterator for AudioProvider {
+    type Item = i16;
+
+    #[inline]
+    fn next(&mut self) -> Option<i16> {
+        while self.queue.is_empty() {
+            if !self.decode_packet() {
+                return None;
+            }
+        }
         self.queue.pop_front()
     }
 }
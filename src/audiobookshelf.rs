@@ -0,0 +1,122 @@
+use std::{
+    io::{self, Write},
+    time::Duration,
+};
+
+use crate::{
+    chapter::Chapter,
+    chapter_writer::ChapterWriter,
+    error::{IoResultExt, Result},
+};
+
+/// A chapter as Audiobookshelf stores it, with its start and end in seconds.
+#[derive(Debug, serde::Serialize)]
+struct AbsChapter {
+    id: usize,
+    start: f64,
+    end: f64,
+    title: String,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct AbsMetadata<'a> {
+    /// The duration of the book in seconds.
+    duration: f64,
+    chapters: &'a [AbsChapter],
+}
+
+/// Writes the chapters as the metadata.json file that Audiobookshelf keeps in the folder of each
+/// library item when it stores metadata with the items. Dropping the file into the folder of a
+/// book applies its chapters on the next library scan. Only the chapters and the duration of the
+/// book are written, so the rest of the book's metadata is left as scanned. Audiobookshelf can't
+/// nest chapters, so chapters in a section are titled with the title of the section too.
+pub struct AudiobookshelfWriter<W: Write> {
+    writer: W,
+    /// The duration of the book, if it's known before the end of the file.
+    duration: Option<Duration>,
+    chapters: Vec<AbsChapter>,
+    /// If the chapter has no explicit end, we still need the start time of the next chapter to
+    /// actually add it.
+    partial_chapter: Option<Chapter>,
+}
+
+impl<W: Write> AudiobookshelfWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            duration: None,
+            chapters: Vec::new(),
+            partial_chapter: None,
+        }
+    }
+
+    /// Sets the duration of the book, e.g. as the audio file declares it, which is written
+    /// instead of the duration the end of the file is reached at. That's short of the duration of
+    /// the book if the run is cut off.
+    pub fn with_duration(mut self, duration: Duration) -> Self {
+        self.duration = Some(duration);
+        self
+    }
+
+    /// Returns the underlying writer, without flushing it. Nothing is written to it before the
+    /// end of the file.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    /// Flushes the underlying writer and returns it. The metadata is written at the end of the
+    /// file, so that should be passed to the writer first.
+    pub fn finalize(mut self) -> Result<W> {
+        self.writer
+            .flush()
+            .io_context("Failed to flush Audiobookshelf metadata file")?;
+        Ok(self.writer)
+    }
+
+    /// Adds the chapter, using the given end time if it doesn't have an explicit end.
+    fn push_chapter(&mut self, chapter: Chapter, end: Duration) {
+        self.chapters.push(AbsChapter {
+            id: self.chapters.len(),
+            start: chapter.start.as_secs_f64(),
+            end: chapter.end.unwrap_or(end).as_secs_f64(),
+            title: chapter.flat_title().into_owned(),
+        });
+    }
+}
+
+impl<W: Write> ChapterWriter for AudiobookshelfWriter<W> {
+    fn on_chapter_start(&mut self, chapter: &Chapter) -> Result<()> {
+        if let Some(prev_chapter) = self.partial_chapter.take() {
+            self.push_chapter(prev_chapter, chapter.start);
+        }
+
+        self.partial_chapter = Some(chapter.clone());
+
+        Ok(())
+    }
+
+    fn on_end_of_file(&mut self, file_duration: Duration) -> Result<()> {
+        if let Some(chapter) = self.partial_chapter.take() {
+            self.push_chapter(chapter, file_duration);
+        }
+
+        let metadata = AbsMetadata {
+            duration: self.duration.unwrap_or(file_duration).as_secs_f64(),
+            chapters: &self.chapters,
+        };
+        serde_json::to_writer_pretty(&mut self.writer, &metadata)
+            .map_err(io::Error::from)
+            .io_context("Failed to write Audiobookshelf metadata")?;
+        self.writer
+            .write_all(b"\n")
+            .io_context("Failed to write Audiobookshelf metadata")?;
+
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.writer
+            .flush()
+            .io_context("Failed to flush Audiobookshelf metadata")
+    }
+}
@@ -0,0 +1,158 @@
+use std::{borrow::Cow, fmt, time::Duration};
+
+/// Where a chapter came from, so that wrong chapters can be traced back to what produced them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChapterSource {
+    /// Read from the container metadata, e.g. using ffprobe.
+    Metadata,
+    /// Read from ID3v2 CHAP frames.
+    Id3,
+    /// Detected using automatic speech recognition.
+    Asr,
+    /// Detected using silence detection.
+    Silence,
+    /// Added or changed by the user.
+    UserEdit,
+    /// Inserted by the chapterizer itself, e.g. to ensure the first chapter starts at 0:00.
+    Inserted,
+}
+
+impl fmt::Display for ChapterSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ChapterSource::Metadata => "metadata",
+            ChapterSource::Id3 => "id3",
+            ChapterSource::Asr => "asr",
+            ChapterSource::Silence => "silence",
+            ChapterSource::UserEdit => "user_edit",
+            ChapterSource::Inserted => "inserted",
+        })
+    }
+}
+
+/// Which of the detectors that ran found the chapter, see [`crate::agreement::CrossCheck`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct Agreement {
+    /// The detectors that found the chapter, starting with the one it was taken from.
+    pub sources: Vec<ChapterSource>,
+    /// The share of the detectors that ran that found the chapter, from 0 to 1.
+    pub confidence: f32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Chapter {
+    pub start: Duration,
+    /// The end of the chapter, if known. If not, the chapter ends where the next one starts. An
+    /// explicit end allows for gaps between chapters, e.g. to exclude music interludes.
+    pub end: Option<Duration>,
+    pub title: String,
+    /// The identifier of the chapter as it was announced, e.g. "07", "07.5" for "chapter seven and
+    /// a half" or "12A" for "chapter twelve a". Only chapters found by speech recognition have one.
+    pub id: Option<String>,
+    /// A longer description of the chapter, if any. Only some output formats can hold it.
+    pub description: Option<String>,
+    /// The title of the section the chapter belongs to, e.g. "Part 02", for books whose chapters
+    /// are grouped. The chapters of a section are consecutive.
+    pub parent: Option<String>,
+    pub source: ChapterSource,
+    /// Which detectors agree on the chapter, if it was cross-checked.
+    pub agreement: Option<Agreement>,
+    /// The words that the chapter was announced with as they were recognized, e.g. "chapter 7".
+    /// Only chapters found by speech recognition have them.
+    pub announcement: Option<String>,
+    /// How confident speech recognition is in the announcement of the chapter, as the share of its
+    /// transcripts in which "chapter" is followed by a number. Only chapters found by speech
+    /// recognition have one.
+    pub confidence: Option<f32>,
+}
+
+impl Chapter {
+    pub fn new(start: Duration, title: impl Into<String>, source: ChapterSource) -> Self {
+        Self {
+            start,
+            end: None,
+            title: title.into(),
+            id: None,
+            description: None,
+            parent: None,
+            source,
+            agreement: None,
+            announcement: None,
+            confidence: None,
+        }
+    }
+
+    pub fn with_end(mut self, end: Duration) -> Self {
+        self.end = Some(end);
+        self
+    }
+
+    pub fn with_id(mut self, id: impl Into<String>) -> Self {
+        self.id = Some(id.into());
+        self
+    }
+
+    pub fn with_description(mut self, description: impl Into<String>) -> Self {
+        self.description = Some(description.into());
+        self
+    }
+
+    pub fn with_parent(mut self, parent: impl Into<String>) -> Self {
+        self.parent = Some(parent.into());
+        self
+    }
+
+    pub fn with_announcement(mut self, announcement: impl Into<String>) -> Self {
+        self.announcement = Some(announcement.into());
+        self
+    }
+
+    pub fn with_confidence(mut self, confidence: Option<f32>) -> Self {
+        self.confidence = confidence;
+        self
+    }
+
+    /// Returns the title prefixed with the title of its section, if any, e.g.
+    /// "Part 02 – Chapter 05", for formats that can't nest chapters.
+    pub fn flat_title(&self) -> Cow<'_, str> {
+        match &self.parent {
+            Some(parent) => Cow::Owned(format!("{} – {}", parent, self.title)),
+            None => Cow::Borrowed(&self.title),
+        }
+    }
+}
+
+/// A candidate for a chapter that was rejected, e.g. the word "chapter" without a number after
+/// it. They can be written to some outputs to show what was almost detected.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RejectedCandidate {
+    pub start: Duration,
+    /// The words of the candidate, starting with the word "chapter".
+    pub text: String,
+    /// Why the candidate was rejected, e.g. "no number after \"chapter\"".
+    pub reason: &'static str,
+}
+
+/// The chapters of an audio file, along with the duration of the file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChapterList {
+    pub chapters: Vec<Chapter>,
+    pub duration: Duration,
+}
+
+impl ChapterList {
+    /// Returns an iterator over the chapters paired with their end times. Chapters without an
+    /// explicit end end where the next one starts, or at the end of the file for the last chapter.
+    pub fn iter_with_end(&self) -> impl Iterator<Item = (&Chapter, Duration)> {
+        self.chapters.iter().enumerate().map(|(i, chapter)| {
+            let end = chapter.end.unwrap_or_else(|| {
+                self.chapters
+                    .get(i + 1)
+                    .map(|next| next.start)
+                    .unwrap_or(self.duration)
+            });
+            (chapter, end)
+        })
+    }
+}
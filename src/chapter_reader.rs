@@ -0,0 +1,178 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::{fs, path::Path};
+
+use crate::{
+    chapter::ChapterList,
+    cue::parse_cue,
+    error::{ChapterizerError, IoResultExt, Result},
+    ffmetadata::parse_ffmetadata,
+    json::parse_json,
+    mp4chaps::parse_mp4chaps,
+    vtt::parse_vtt,
+};
+
+/// The formats that chapter files can be read from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChapterFileFormat {
+    Cue,
+    Ffmetadata,
+    Json,
+    Vtt,
+    Mp4chaps,
+}
+
+impl ChapterFileFormat {
+    /// Detects the format from the contents of the chapter file.
+    pub fn sniff(contents: &str) -> Option<Self> {
+        lazy_static! {
+            static ref CUE_COMMAND_REGEX: Regex = Regex::new(
+                r"(?i)^(FILE|REM|TRACK|TITLE|PERFORMER|SONGWRITER|CATALOG|CDTEXTFILE)(\s|$)"
+            )
+            .unwrap();
+            static ref MP4CHAPS_LINE_REGEX: Regex =
+                Regex::new(r"^(\d{1,2}:)?\d{1,2}:\d{1,2}(\.\d+)?(\s|$)").unwrap();
+        }
+
+        let contents = contents.trim_start_matches('\u{feff}').trim_start();
+        if contents.starts_with(";FFMETADATA") {
+            return Some(Self::Ffmetadata);
+        }
+        if contents.starts_with("WEBVTT") {
+            return Some(Self::Vtt);
+        }
+        if contents.starts_with('{') {
+            return Some(Self::Json);
+        }
+
+        let first_line = contents.lines().next()?;
+        if CUE_COMMAND_REGEX.is_match(first_line) {
+            Some(Self::Cue)
+        } else if MP4CHAPS_LINE_REGEX.is_match(first_line) {
+            Some(Self::Mp4chaps)
+        } else {
+            None
+        }
+    }
+
+    /// Guesses the format from the extension of the chapter file.
+    pub fn from_extension(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_string_lossy().to_lowercase();
+        match extension.as_str() {
+            "cue" => Some(Self::Cue),
+            "ffmetadata" | "meta" => Some(Self::Ffmetadata),
+            "json" => Some(Self::Json),
+            "vtt" => Some(Self::Vtt),
+            "txt" => Some(Self::Mp4chaps),
+            _ => None,
+        }
+    }
+}
+
+/// Parses the chapters of a chapter file in the given format.
+pub fn parse_chapters(contents: &str, format: ChapterFileFormat) -> Result<ChapterList> {
+    match format {
+        ChapterFileFormat::Cue => parse_cue(contents),
+        ChapterFileFormat::Ffmetadata => parse_ffmetadata(contents),
+        ChapterFileFormat::Json => parse_json(contents),
+        ChapterFileFormat::Vtt => parse_vtt(contents),
+        ChapterFileFormat::Mp4chaps => parse_mp4chaps(contents),
+    }
+}
+
+/// Reads the chapters from a chapter file. The format is detected from the contents of the file,
+/// falling back to its extension.
+pub fn read_chapter_file(path: &Path) -> Result<ChapterList> {
+    let contents = fs::read_to_string(path).io_context("Failed to read chapter file")?;
+
+    let format = ChapterFileFormat::sniff(&contents)
+        .or_else(|| ChapterFileFormat::from_extension(path))
+        .ok_or(ChapterizerError::UnknownChapterFormat)?;
+    log::debug!("Reading {} as {:?}", path.display(), format);
+
+    parse_chapters(&contents, format)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sniffs_each_format() {
+        let cases = [
+            (";FFMETADATA1\n[CHAPTER]\n", ChapterFileFormat::Ffmetadata),
+            (
+                "WEBVTT\n\n00:00.000 --> 00:10.000\nOne\n",
+                ChapterFileFormat::Vtt,
+            ),
+            ("{\"chapters\": []}", ChapterFileFormat::Json),
+            (
+                "FILE \"book.m4b\" MP4\n  TRACK 1 AUDIO\n",
+                ChapterFileFormat::Cue,
+            ),
+            (
+                "00:00:00.000 Intro\n00:01:00.000 One\n",
+                ChapterFileFormat::Mp4chaps,
+            ),
+            ("1:05 One\n", ChapterFileFormat::Mp4chaps),
+        ];
+        for (contents, format) in cases {
+            assert_eq!(
+                ChapterFileFormat::sniff(contents),
+                Some(format),
+                "{:?}",
+                contents
+            );
+        }
+    }
+
+    #[test]
+    fn sniffs_after_a_byte_order_mark_and_blank_lines() {
+        assert_eq!(
+            ChapterFileFormat::sniff("\u{feff}\r\n\r\nWEBVTT\r\n"),
+            Some(ChapterFileFormat::Vtt)
+        );
+    }
+
+    #[test]
+    fn sniffs_cue_sheets_with_lowercase_commands_or_a_leading_comment() {
+        for contents in [
+            "file \"book.m4b\" MP4\ntrack 1 audio\n",
+            "Title \"Book\"\n",
+            "REM\r\nFILE \"book.m4b\" MP4\r\n",
+            "REM GENRE Audiobook\r\nFILE \"book.m4b\" MP4\r\n",
+        ] {
+            assert_eq!(
+                ChapterFileFormat::sniff(contents),
+                Some(ChapterFileFormat::Cue),
+                "{:?}",
+                contents
+            );
+        }
+    }
+
+    #[test]
+    fn falls_back_to_the_extension() {
+        assert_eq!(ChapterFileFormat::sniff("Intro\nOne\n"), None);
+        assert_eq!(ChapterFileFormat::sniff(""), None);
+        let cases = [
+            ("book.cue", Some(ChapterFileFormat::Cue)),
+            ("book.CUE", Some(ChapterFileFormat::Cue)),
+            ("book.ffmetadata", Some(ChapterFileFormat::Ffmetadata)),
+            ("book.meta", Some(ChapterFileFormat::Ffmetadata)),
+            ("book.json", Some(ChapterFileFormat::Json)),
+            ("book.vtt", Some(ChapterFileFormat::Vtt)),
+            ("book.txt", Some(ChapterFileFormat::Mp4chaps)),
+            ("book.m4b", None),
+            ("book", None),
+        ];
+        for (path, format) in cases {
+            assert_eq!(
+                ChapterFileFormat::from_extension(Path::new(path)),
+                format,
+                "{}",
+                path
+            );
+        }
+    }
+}
@@ -1,9 +1,103 @@
-use std::time::Duration;
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufWriter, ErrorKind, Seek, SeekFrom, Write},
+    path::Path,
+    time::{Duration, Instant},
+};
 
-use color_eyre::eyre;
+use crate::{
+    chapter::{Chapter, RejectedCandidate},
+    error::{IoResultExt, Result},
+};
+
+/// Chapters that start this close to the last chapter already in a reopened output are taken to be
+/// that chapter, since the recognizer may place it slightly differently when the audio is
+/// processed again.
+const RESUME_TOLERANCE: Duration = Duration::from_secs(2);
+
+/// The least time between two syncs of an output file to disk.
+const SYNC_INTERVAL: Duration = Duration::from_secs(30);
 
 pub trait ChapterWriter {
-    fn on_chapter_start(&mut self, start_time: Duration, title: &str) -> eyre::Result<()>;
+    fn on_chapter_start(&mut self, chapter: &Chapter) -> Result<()>;
+
+    fn on_end_of_file(&mut self, file_duration: Duration) -> Result<()>;
+
+    /// Called for each rejected candidate if they're included in the outputs. Formats that can't
+    /// hold them ignore them.
+    fn on_rejected_candidate(&mut self, _candidate: &RejectedCandidate) -> Result<()> {
+        Ok(())
+    }
+
+    /// Passes what was written so far on to the output, so that it's valid and holds the chapters
+    /// written so far if the run is cut off. Formats that are only written at the end of the file
+    /// ignore it.
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// A buffered output file that's synced to disk when it's flushed, unless it was synced less than
+/// [`SYNC_INTERVAL`] ago. A crash or power loss part-way through a long run then loses at most the
+/// last few chapters, without syncing after every one of them.
+pub struct OutputFile {
+    writer: BufWriter<File>,
+    last_sync: Instant,
+}
+
+impl OutputFile {
+    pub fn new(file: File) -> Self {
+        Self {
+            writer: BufWriter::new(file),
+            last_sync: Instant::now(),
+        }
+    }
+}
+
+impl Write for OutputFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writer.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()?;
+        if self.last_sync.elapsed() >= SYNC_INTERVAL {
+            self.writer.get_ref().sync_data()?;
+            self.last_sync = Instant::now();
+        }
+        Ok(())
+    }
+}
+
+/// Reads the contents of an output that's being reopened, or returns an empty string if it
+/// doesn't exist yet.
+pub(crate) fn read_partial_output(path: &Path) -> Result<String> {
+    match std::fs::read(path) {
+        // A write may have been cut off in the middle of a character
+        Ok(contents) => Ok(String::from_utf8_lossy(&contents).into_owned()),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(String::new()),
+        Err(err) => Err(err).io_context("Failed to read existing output"),
+    }
+}
+
+/// Opens the output for appending after cutting it off at complete_len, which drops whatever was
+/// left of a write that was cut off.
+pub(crate) fn reopen_for_append(path: &Path, complete_len: usize) -> Result<File> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)
+        .io_context("Failed to reopen output")?;
+    file.set_len(complete_len as u64)
+        .io_context("Failed to truncate output")?;
+    file.seek(SeekFrom::End(0))
+        .io_context("Failed to seek to end of output")?;
+    Ok(file)
+}
 
-    fn on_end_of_file(&mut self, file_duration: Duration) -> eyre::Result<()>;
+/// Whether what starts at the given time was already written to a reopened output whose last
+/// chapter starts at resume_after.
+pub(crate) fn is_already_written(resume_after: Option<Duration>, start: Duration) -> bool {
+    resume_after.is_some_and(|last_start| start <= last_start + RESUME_TOLERANCE)
 }
@@ -0,0 +1,205 @@
+use itertools::Itertools;
+use std::time::Duration;
+
+use super::{locations::describe_location, results_parser::ParseResult};
+use crate::chapter::{Chapter, ChapterSource};
+
+/// This margin is subtracted from the start timestamp of a chapter when output.
+const PRE_CHAPTER_START_MARGIN: Duration = Duration::from_secs(1);
+
+/// This margin is added to the end timestamp of an "end of chapter N" announcement when output.
+const POST_CHAPTER_END_MARGIN: Duration = Duration::from_secs(1);
+
+/// Chapters announced this close to the start of the file, e.g. a book that opens with "chapter
+/// one", start at 0:00 instead of after an inserted chapter that would only be a few seconds long.
+/// The vocal pause before them isn't checked either, since there may be nothing to pause after.
+pub const START_OF_FILE_WINDOW: Duration = Duration::from_secs(5);
+
+/// Assembles chapters from the results of the results parser.
+pub struct ChapterAssembler {
+    /// The current chapter is only complete once the next chapter starts, so that its end can
+    /// still be set if an "end of chapter N" announcement is found. It's paired with its chapter
+    /// identifier to cross-check the announcement.
+    current_chapter: (String, Chapter),
+    /// Whether the parts of a subdivided chapter are nested in their chapter, rather than being
+    /// separate chapters with merged titles.
+    nest_parts: bool,
+}
+
+impl ChapterAssembler {
+    pub fn new(nest_parts: bool) -> Self {
+        Self {
+            current_chapter: (
+                "00".into(),
+                Chapter::new(Duration::ZERO, "Chapter 00", ChapterSource::Inserted),
+            ),
+            nest_parts,
+        }
+    }
+
+    /// Processes the parse result. If it starts a new chapter, returns the previous chapter, which
+    /// is now complete. The line of the matches file that the result was found in, if any, is
+    /// included in log messages, and the confidence of the result, if known, is kept with the
+    /// chapter.
+    pub fn push(
+        &mut self,
+        parse_result: ParseResult,
+        matches_line: Option<usize>,
+        confidence: Option<f32>,
+    ) -> Option<Chapter> {
+        // TODO: filter out duplicate chapters
+        let (parsed_chapter, is_end_announcement) = match parse_result {
+            ParseResult::Match(parsed_chapter) => (parsed_chapter, false),
+            ParseResult::EndMatch(parsed_chapter) => (parsed_chapter, true),
+            ParseResult::Failure(_) => return None,
+            ParseResult::Incomplete => {
+                unreachable!("Incomplete results should never be sent")
+            }
+        };
+
+        let chapter_id = format_chapter_id(&parsed_chapter.get(1).unwrap().word);
+
+        if is_end_announcement {
+            let chapter_end_duration = parsed_chapter.last().unwrap().end.to_duration();
+            let (current_id, chapter) = &mut self.current_chapter;
+            if chapter_id == *current_id {
+                log::info!(
+                    "Found end of chapter {} at {}",
+                    chapter_id,
+                    describe_location(chapter_end_duration, matches_line)
+                );
+                chapter.end = Some(chapter_end_duration + POST_CHAPTER_END_MARGIN);
+            } else {
+                log::warn!(
+                    "Found end of chapter {} at {}, but the current chapter is {}. \
+                    The start of chapter {} may have been missed.",
+                    chapter_id,
+                    describe_location(chapter_end_duration, matches_line),
+                    current_id,
+                    chapter_id
+                );
+            }
+            return None;
+        }
+
+        let announcement = parsed_chapter.iter().map(|w| w.word.to_string()).join(" ");
+        let chapter_start_duration = parsed_chapter.first().unwrap().start.to_duration();
+
+        log::info!(
+            "Found chapter: {} at {}",
+            announcement,
+            describe_location(chapter_start_duration, matches_line)
+        );
+
+        let replaces_inserted_chapter = chapter_start_duration < START_OF_FILE_WINDOW
+            && self.current_chapter.1.source == ChapterSource::Inserted;
+        let chapter_start = if replaces_inserted_chapter {
+            Duration::ZERO
+        } else {
+            chapter_start_duration.saturating_sub(PRE_CHAPTER_START_MARGIN)
+        };
+        let chapter_title = format!("Chapter {}", chapter_id);
+        let part_number = parsed_chapter
+            .get(3)
+            .and_then(|token| token.word.parse::<f32>().ok());
+        let chapter = match part_number {
+            Some(part_number) if self.nest_parts => Chapter::new(
+                chapter_start,
+                format!("Part {:02}", part_number),
+                ChapterSource::Asr,
+            )
+            .with_parent(chapter_title),
+            Some(part_number) => Chapter::new(
+                chapter_start,
+                format!("{}, Part {:02}", chapter_title, part_number),
+                ChapterSource::Asr,
+            ),
+            None => Chapter::new(chapter_start, chapter_title, ChapterSource::Asr),
+        }
+        .with_id(chapter_id.clone())
+        .with_announcement(announcement)
+        .with_confidence(confidence);
+
+        let (_, mut prev_chapter) =
+            std::mem::replace(&mut self.current_chapter, (chapter_id, chapter));
+        if replaces_inserted_chapter {
+            log::debug!("Chapter is at the start of the file, so no chapter is inserted before it");
+            return None;
+        }
+        // A chapter can't end after the next one starts
+        if prev_chapter.end > Some(self.current_chapter.1.start) {
+            prev_chapter.end = None;
+        }
+        Some(prev_chapter)
+    }
+
+    /// Returns the chapter that's being assembled, i.e. the last one that started.
+    #[cfg(feature = "live-input")]
+    pub fn current_chapter(&self) -> &Chapter {
+        &self.current_chapter.1
+    }
+
+    /// Returns the last chapter, which can't end after the end of the file.
+    pub fn finish(self, file_duration: Duration) -> Chapter {
+        let (_, mut last_chapter) = self.current_chapter;
+        last_chapter.end = last_chapter.end.map(|end| end.min(file_duration));
+        last_chapter
+    }
+}
+
+/// Formats the identifier of a chapter as announced for its title, padding its number to two
+/// digits, e.g. "7" to "07", "7.5" to "07.5" and "12A" to "12A".
+fn format_chapter_id(id: &str) -> String {
+    let number_len = id.find(|c: char| !c.is_ascii_digit()).unwrap_or(id.len());
+    format!("{:0>2}{}", &id[..number_len], &id[number_len..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{chapterize::token::Token, timestamp::AudioTimestamp};
+
+    /// The tokens of an announcement such as "chapter 1" as the results parser matches it.
+    fn announcement(chapter_id: &str, start: f32) -> ParseResult {
+        let token = |word: &str, start: f32| Token {
+            start: AudioTimestamp::from_secs(start),
+            end: AudioTimestamp::from_secs(start + 0.4),
+            word: word.into(),
+            is_replacement: false,
+            max_number_pause: 0.2,
+        };
+        ParseResult::Match(vec![
+            token("chapter", start),
+            token(chapter_id, start + 0.5),
+        ])
+    }
+
+    #[test]
+    fn chapter_at_start_of_file_starts_at_zero() {
+        let mut assembler = ChapterAssembler::new(false);
+        assert!(assembler.push(announcement("1", 0.3), None, None).is_none());
+        let chapter = assembler.finish(Duration::from_secs(60));
+        assert_eq!(chapter.start, Duration::ZERO);
+        assert_eq!(chapter.title, "Chapter 01");
+        assert_eq!(chapter.source, ChapterSource::Asr);
+    }
+
+    #[test]
+    fn chapter_near_start_of_file_replaces_inserted_chapter() {
+        let mut assembler = ChapterAssembler::new(false);
+        assert!(assembler.push(announcement("1", 4.0), None, None).is_none());
+        let first = assembler.push(announcement("2", 30.0), None, None).unwrap();
+        assert_eq!(first.start, Duration::ZERO);
+        assert_eq!(first.title, "Chapter 01");
+    }
+
+    #[test]
+    fn chapter_after_start_of_file_is_preceded_by_inserted_chapter() {
+        let mut assembler = ChapterAssembler::new(false);
+        let inserted = assembler.push(announcement("1", 10.0), None, None).unwrap();
+        assert_eq!(inserted.start, Duration::ZERO);
+        assert_eq!(inserted.source, ChapterSource::Inserted);
+        let chapter = assembler.finish(Duration::from_secs(60));
+        assert_eq!(chapter.start, Duration::from_secs(9));
+    }
+}
@@ -0,0 +1,36 @@
+use crossbeam::channel;
+
+/// A fixed set of sample buffers that are handed back and forth between threads, so that no
+/// buffers are allocated after startup. Cloning the pool shares its buffers.
+#[derive(Clone)]
+pub struct BufferPool {
+    tx: channel::Sender<Vec<i16>>,
+    rx: channel::Receiver<Vec<i16>>,
+}
+
+impl BufferPool {
+    /// Creates a pool of count buffers, each with room for capacity samples.
+    pub fn new(count: usize, capacity: usize) -> Self {
+        let (tx, rx) = channel::bounded(count);
+        for _ in 0..count {
+            tx.send(Vec::with_capacity(capacity)).unwrap();
+        }
+        Self { tx, rx }
+    }
+
+    /// Takes an empty buffer from the pool, waiting for one to be returned if all are in use.
+    pub fn take(&self) -> Vec<i16> {
+        let mut buffer = self
+            .rx
+            .recv()
+            .expect("pool should not be disconnected while it's used");
+        buffer.clear();
+        buffer
+    }
+
+    /// Returns the buffer to the pool.
+    pub fn put(&self, buffer: Vec<i16>) {
+        // The pool only ever holds its own buffers, so it can't be full
+        self.tx.try_send(buffer).unwrap();
+    }
+}
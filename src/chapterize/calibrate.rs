@@ -0,0 +1,123 @@
+use std::{path::Path, time::Duration};
+
+use vosk::{CompleteResult, DecodingState, Model, Recognizer};
+
+use super::{config::DetectionConfig, gimme_audio};
+use crate::{
+    audio_provider::FormatHint,
+    error::{ChapterizerError, Result},
+    status::StatusCalibration,
+    timestamp::SampleOffset,
+};
+
+/// The rate of speech, in words per second of speech, that the default thresholds suit.
+const REFERENCE_SPEECH_RATE: f32 = 2.5;
+
+/// The mean word confidence of clearly recognized speech.
+const REFERENCE_WORD_CONFIDENCE: f32 = 0.9;
+
+/// The most that the pause thresholds are scaled by, either way, so that an odd calibration
+/// can't throw them off completely.
+const MAX_PACE_FACTOR: f32 = 2.0;
+
+/// Calibrating on fewer words than this is too unreliable to adjust anything.
+const MIN_CALIBRATION_WORDS: usize = 100;
+
+/// The number of samples fed to the recognizer at a time.
+const CALIBRATION_CHUNK_LEN: usize = 8 * 1024;
+
+/// What a calibration pass measured about the narrator, and the thresholds adjusted for them.
+#[derive(Clone, Debug)]
+pub struct Calibration {
+    pub num_words: usize,
+    /// The mean confidence of the recognizer in the words it recognized, from 0 to 1.
+    pub mean_word_confidence: f32,
+    /// Words per second, counting only the time spent speaking.
+    pub speech_rate: f32,
+    pub detection_config: DetectionConfig,
+    pub min_confidence: Option<f32>,
+}
+
+impl Calibration {
+    pub fn status(&self) -> StatusCalibration {
+        StatusCalibration {
+            num_words: self.num_words,
+            mean_word_confidence: self.mean_word_confidence,
+            speech_rate: self.speech_rate,
+            min_vocal_pause_before_chapter: self.detection_config.min_vocal_pause_before_chapter,
+            reading_phrase_window: self.detection_config.reading_phrase_window,
+            min_confidence: self.min_confidence,
+        }
+    }
+}
+
+/// Transcribes the start of the audio file to measure how fast the narrator speaks and how well
+/// they're recognized, and adjusts the thresholds to match. Slower narrators pause for longer
+/// between words, so the pause thresholds are scaled up for them, and down for faster ones. The
+/// minimum candidate confidence is lowered for narrators that are recognized poorly, since the
+/// alternatives of the recognizer agree less for them. Returns `None` if too few words were
+/// recognized to go by.
+pub fn calibrate(
+    model: &Model,
+    audio_file_path: &Path,
+    format_hint: &FormatHint,
+    duration: Duration,
+    detection_config: &DetectionConfig,
+    min_confidence: Option<f32>,
+) -> Result<Option<Calibration>> {
+    let mut ap = gimme_audio(audio_file_path, format_hint)?;
+    let sample_rate = ap.sample_rate();
+    let mut recognizer =
+        Recognizer::new(model, sample_rate as f32).ok_or(ChapterizerError::Recognizer)?;
+    recognizer.set_words(true);
+
+    let mut word_confidences = Vec::new();
+    let mut speaking_time = 0.0;
+    let mut add_result = |result: CompleteResult| {
+        let Some(single) = result.single() else {
+            return;
+        };
+        if let (Some(first), Some(last)) = (single.result.first(), single.result.last()) {
+            speaking_time += last.end - first.start;
+        }
+        word_confidences.extend(single.result.iter().map(|word| word.conf));
+    };
+
+    let max_end = SampleOffset::from_duration(duration, sample_rate);
+    let mut buffer = Vec::with_capacity(CALIBRATION_CHUNK_LEN);
+    while ap.position() < max_end && ap.fill_buffer(&mut buffer, CALIBRATION_CHUNK_LEN) > 0 {
+        if let DecodingState::Finalized = recognizer.accept_waveform(&buffer) {
+            add_result(recognizer.result());
+        }
+        buffer.clear();
+    }
+    add_result(recognizer.final_result());
+
+    let num_words = word_confidences.len();
+    if num_words < MIN_CALIBRATION_WORDS || speaking_time <= 0.0 {
+        log::warn!(
+            "Only {} word(s) recognized while calibrating, keeping the thresholds as they are",
+            num_words
+        );
+        return Ok(None);
+    }
+
+    let mean_word_confidence = word_confidences.iter().sum::<f32>() / num_words as f32;
+    let speech_rate = num_words as f32 / speaking_time;
+
+    let pace_factor =
+        (REFERENCE_SPEECH_RATE / speech_rate).clamp(1.0 / MAX_PACE_FACTOR, MAX_PACE_FACTOR);
+    let confidence_factor = (mean_word_confidence / REFERENCE_WORD_CONFIDENCE).clamp(0.5, 1.0);
+
+    let mut detection_config = detection_config.clone();
+    detection_config.min_vocal_pause_before_chapter *= pace_factor;
+    detection_config.reading_phrase_window *= pace_factor;
+
+    Ok(Some(Calibration {
+        num_words,
+        mean_word_confidence,
+        speech_rate,
+        detection_config,
+        min_confidence: min_confidence.map(|min_confidence| min_confidence * confidence_factor),
+    }))
+}
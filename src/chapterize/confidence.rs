@@ -0,0 +1,65 @@
+use vosk::CompleteResultMultiple;
+
+use super::{
+    keywords::Keywords, results_parser::contains_chapter_number, token::Token, window::Transcript,
+};
+use crate::timestamp::AudioTimestamp;
+
+/// Returns the confidence that a recognition result with a candidate holds a chapter
+/// announcement, from 0 to 1. It's the share of the transcripts of the result in which "chapter"
+/// is followed by a number: the alternatives of the recognizer, along with the transcript of the
+/// window transcribed again and the transcripts of the ensemble models, if any. Unlike the
+/// confidence of the recognizer itself, it's comparable between results and models.
+pub fn candidate_confidence(
+    multi: &CompleteResultMultiple,
+    retranscript: Option<&Transcript>,
+    ensemble_transcripts: Option<&[Transcript]>,
+    keywords: &Keywords,
+) -> f32 {
+    let alt_votes = multi.alternatives.iter().map(|alt| {
+        contains_chapter_number(
+            &Transcript::from_alt(alt, AudioTimestamp::ZERO).tokens,
+            keywords,
+        )
+    });
+    let other_votes = retranscript
+        .into_iter()
+        .chain(ensemble_transcripts.unwrap_or_default())
+        .map(|transcript| contains_chapter_number(&transcript.tokens, keywords));
+
+    let (num_votes, num_for) = alt_votes
+        .chain(other_votes)
+        .fold((0, 0), |(num_votes, num_for), vote| {
+            (num_votes + 1, num_for + vote as usize)
+        });
+    if num_votes == 0 {
+        return 0.0;
+    }
+    num_for as f32 / num_votes as f32
+}
+
+/// The confidences of the recognition results with candidates, along with the times they span,
+/// so that the candidates can be looked up once they're parsed.
+#[derive(Debug, Default)]
+pub struct CandidateConfidences {
+    spans: Vec<(AudioTimestamp, AudioTimestamp, f32)>,
+}
+
+impl CandidateConfidences {
+    /// Records the confidence of the recognition result with the tokens.
+    pub fn push(&mut self, tokens: &[Token], confidence: f32) {
+        if let (Some(first), Some(last)) = (tokens.first(), tokens.last()) {
+            self.spans.push((first.start, last.end, confidence));
+        }
+    }
+
+    /// Returns the confidence of the recognition result that the time is in, if it had a
+    /// candidate.
+    pub fn find(&self, time: AudioTimestamp) -> Option<f32> {
+        self.spans
+            .iter()
+            .rev()
+            .find(|(start, end, _)| (*start..=*end).contains(&time))
+            .map(|(_, _, confidence)| *confidence)
+    }
+}
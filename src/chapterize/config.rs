@@ -0,0 +1,191 @@
+use std::{
+    collections::HashMap,
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use super::keywords::AnnouncementLanguage;
+use crate::{
+    error::{ChapterizerError, IoResultExt, Result},
+    sanitize_file_name,
+};
+
+/// Returns "audiobook-chapterizer" in the user's config directory, if it can be found.
+fn config_dir() -> Option<PathBuf> {
+    let config_dir = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("APPDATA").map(PathBuf::from))
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config_dir.join("audiobook-chapterizer"))
+}
+
+/// Returns the directory that narrator profiles are kept in by default, i.e.
+/// "audiobook-chapterizer/profiles" in the user's config directory, if it can be found.
+pub fn default_profiles_dir() -> Option<PathBuf> {
+    Some(config_dir()?.join("profiles"))
+}
+
+/// Returns the path of the user config file, i.e. "audiobook-chapterizer/config.json" in the
+/// user's config directory, if it can be found.
+pub fn default_user_config_path() -> Option<PathBuf> {
+    Some(config_dir()?.join("config.json"))
+}
+
+/// The model and keywords to use for books in a language.
+#[derive(Clone, Debug, Default, PartialEq, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LanguagePreset {
+    /// The Vosk model directory or archive for the language. Relative paths are relative to the
+    /// config file.
+    pub model: Option<PathBuf>,
+    /// The words that announce a chapter. If empty, the usual words of the language are used.
+    pub chapter_words: Vec<String>,
+    /// The reading phrases of the language, see [`DetectionConfig::reading_phrases`]. If not
+    /// given, the reading phrases aren't changed.
+    pub reading_phrases: Option<Vec<String>>,
+}
+
+/// The settings of the user that apply to every run, e.g.
+/// `{"models": {"fr": {"model": "vosk-model-fr-0.22.zip"}}}`, so that they don't have to be passed
+/// every time.
+#[derive(Clone, Debug, Default, PartialEq, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UserConfig {
+    /// The presets of the languages that books are chapterized in, by the code of the language.
+    pub models: HashMap<AnnouncementLanguage, LanguagePreset>,
+}
+
+impl UserConfig {
+    /// Reads the config from a JSON file, resolving the model paths relative to it.
+    pub fn load(path: &Path) -> Result<Self> {
+        let json = fs::read_to_string(path).io_context("Failed to read config file")?;
+        let mut config: Self =
+            serde_json::from_str(&json).map_err(|source| ChapterizerError::Json {
+                context: format!("Failed to parse config file {}", path.display()),
+                source,
+            })?;
+        let config_dir = path.parent().unwrap_or(Path::new(""));
+        for preset in config.models.values_mut() {
+            preset.model = preset.model.take().map(|model| config_dir.join(model));
+        }
+        Ok(config)
+    }
+}
+
+impl DetectionConfig {
+    /// Switches the config to the language, taking the chapter words and reading phrases from the
+    /// preset of the language if there is one.
+    pub fn apply_language(
+        &mut self,
+        language: AnnouncementLanguage,
+        preset: Option<&LanguagePreset>,
+    ) {
+        self.language = language;
+        self.chapter_words = preset
+            .map(|preset| preset.chapter_words.clone())
+            .unwrap_or_default();
+        if let Some(reading_phrases) = preset.and_then(|preset| preset.reading_phrases.clone()) {
+            self.reading_phrases = reading_phrases;
+        }
+    }
+}
+
+/// Returns the path of the file that the narrator profile with the name is kept in. A profile is
+/// a detection config file named after the profile, e.g. "Ray Porter.json".
+pub fn profile_path(profiles_dir: &Path, name: &str) -> PathBuf {
+    profiles_dir.join(format!("{}.json", sanitize_file_name(name)))
+}
+
+/// The thresholds used to detect chapters. The defaults work well for most books, but they can be
+/// tuned for a specific narrator or production using the tune subcommand.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct DetectionConfig {
+    /// The language that chapters are announced in. It decides the language that chapter numbers
+    /// are recognized in, and the chapter words if none are given.
+    pub language: AnnouncementLanguage,
+    /// The words that announce a chapter, e.g. "chapter" and "chapters". When the recognizer isn't
+    /// sure which of them was said, the earlier one is preferred. If empty, the usual words of the
+    /// language are used.
+    pub chapter_words: Vec<String>,
+    /// The minimum pause in seconds before the word "chapter" for it to be considered the start
+    /// of a chapter announcement. With adaptive pauses, it's only used until enough pauses have
+    /// been heard, and bounds the adaptive threshold to between half and twice its value.
+    pub min_vocal_pause_before_chapter: f32,
+    /// Whether to express the pause thresholds as percentiles of the pauses between the words of
+    /// the narrator heard so far, rather than as fixed lengths, so that they suit slow and fast
+    /// narrators alike.
+    pub adaptive_pauses: bool,
+    /// The percentile of the narrator's pauses that the pause before the word "chapter" has to
+    /// reach, with adaptive pauses.
+    pub min_vocal_pause_percentile: f32,
+    /// The percentile of the narrator's pauses that separates the words of two numbers, e.g.
+    /// "chapter one ... two", with adaptive pauses.
+    pub max_number_pause_percentile: f32,
+    /// The percentile of the narrator's pauses that a letter after the chapter number has to be
+    /// followed by to be part of the chapter identifier, e.g. "chapter twelve a", with adaptive
+    /// pauses.
+    pub letter_suffix_pause_percentile: f32,
+    /// Words and phrases that suggest that a chapter of a book within the story is being read
+    /// aloud, e.g. "she opened to chapter five". Candidates preceded by one of them are rejected.
+    /// An empty list turns the check off.
+    pub reading_phrases: Vec<String>,
+    /// How many seconds before the word "chapter" a reading phrase has to end to count. Keep it
+    /// shorter than the pause between chapters, so that a chapter ending in e.g. "read" doesn't
+    /// get the next chapter rejected.
+    pub reading_phrase_window: f32,
+}
+
+impl Default for DetectionConfig {
+    fn default() -> Self {
+        Self {
+            language: AnnouncementLanguage::default(),
+            chapter_words: Vec::new(),
+            min_vocal_pause_before_chapter: 0.25,
+            adaptive_pauses: true,
+            min_vocal_pause_percentile: 90.0,
+            max_number_pause_percentile: 85.0,
+            letter_suffix_pause_percentile: 93.0,
+            reading_phrases: [
+                "read",
+                "reads",
+                "reading",
+                "opened",
+                "opens",
+                "opened to",
+                "turned to",
+                "turns to",
+                "flipped to",
+                "skipped to",
+            ]
+            .map(String::from)
+            .to_vec(),
+            reading_phrase_window: 1.0,
+        }
+    }
+}
+
+impl DetectionConfig {
+    /// Reads the config from a JSON file. Thresholds missing from the file keep their defaults.
+    pub fn load(path: &Path) -> Result<Self> {
+        let json = fs::read_to_string(path).io_context("Failed to read detection config file")?;
+        serde_json::from_str(&json).map_err(|source| ChapterizerError::Json {
+            context: format!("Failed to parse detection config file {}", path.display()),
+            source,
+        })
+    }
+
+    /// Writes the config to a JSON file, creating its directory if needed.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir_path) = path
+            .parent()
+            .filter(|dir_path| !dir_path.as_os_str().is_empty())
+        {
+            fs::create_dir_all(dir_path)
+                .io_context("Failed to create detection config directory")?;
+        }
+        let json = serde_json::to_string_pretty(self).expect("config should be serializable");
+        fs::write(path, json).io_context("Failed to write detection config file")
+    }
+}
@@ -0,0 +1,121 @@
+use std::{
+    io::{self, BufRead, Write},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use super::{locations::seek_hint, window::AudioWindow};
+use crate::{fixed_vec_deque::FixedVecDeque, format_duration, timestamp::AudioTimestamp};
+
+/// The number of clips of recent candidates kept around. A candidate is only parsed a few
+/// recognition results after it was heard, so older clips aren't needed anymore.
+const MAX_CLIPS: usize = 16;
+
+/// The clips of audio around recent candidates, shared between the thread that recognizes the
+/// audio and the thread that asks to confirm the candidates.
+pub type CandidateClips = Arc<Mutex<FixedVecDeque<AudioWindow>>>;
+
+pub fn candidate_clips() -> CandidateClips {
+    Arc::new(Mutex::new(FixedVecDeque::with_max_len(MAX_CLIPS)))
+}
+
+/// Asks in the terminal whether each candidate is a chapter, after playing the audio around it
+/// through the default audio device. Without the confirm-audio feature, a command that plays the
+/// audio is shown instead.
+pub struct Confirmer {
+    clips: CandidateClips,
+    audio_file_path: PathBuf,
+    #[cfg(feature = "confirm-audio")]
+    sample_rate: u32,
+    /// The stream has to be kept alive for as long as audio is played through its handle.
+    #[cfg(feature = "confirm-audio")]
+    output: Option<(rodio::OutputStream, rodio::OutputStreamHandle)>,
+}
+
+impl Confirmer {
+    pub fn new(clips: CandidateClips, audio_file_path: PathBuf, sample_rate: u32) -> Self {
+        #[cfg(not(feature = "confirm-audio"))]
+        let _ = sample_rate;
+        Self {
+            clips,
+            audio_file_path,
+            #[cfg(feature = "confirm-audio")]
+            sample_rate,
+            #[cfg(feature = "confirm-audio")]
+            output: match rodio::OutputStream::try_default() {
+                Ok(output) => Some(output),
+                Err(err) => {
+                    log::warn!("Failed to open the default audio device: {}", err);
+                    None
+                }
+            },
+        }
+    }
+
+    /// Plays the audio around the candidate that starts at the given time and asks whether it's a
+    /// chapter. Answering "r" plays it again. If no answer can be read, e.g.
+    /// because stdin was closed, the candidate is accepted.
+    pub fn confirm(&self, announcement: &str, start: AudioTimestamp) -> bool {
+        let clip = self
+            .clips
+            .lock()
+            .unwrap()
+            .iter()
+            .rev()
+            .find(|clip| clip.offset <= start)
+            .map(|clip| clip.samples.clone());
+        let start = start.to_duration();
+
+        loop {
+            let played = clip.as_deref().is_some_and(|samples| self.play(samples));
+            if !played {
+                eprintln!("  {}", seek_hint(&self.audio_file_path, start));
+            }
+            eprint!(
+                "Is \"{}\" at {} a chapter? [Y/n/r] ",
+                announcement,
+                format_duration(&Some(start))
+            );
+            let _ = io::stderr().flush();
+
+            let mut answer = String::new();
+            match io::stdin().lock().read_line(&mut answer) {
+                Ok(0) | Err(_) => return true,
+                Ok(_) => {}
+            }
+            match answer.trim().to_lowercase().as_str() {
+                "" | "y" | "yes" => return true,
+                "n" | "no" => return false,
+                _ => {}
+            }
+        }
+    }
+
+    /// Plays the samples through the default audio device, returning whether they were played.
+    #[cfg(feature = "confirm-audio")]
+    fn play(&self, samples: &[i16]) -> bool {
+        let Some((_, handle)) = &self.output else {
+            return false;
+        };
+        match rodio::Sink::try_new(handle) {
+            Ok(sink) => {
+                sink.append(rodio::buffer::SamplesBuffer::new(
+                    1,
+                    self.sample_rate,
+                    samples.to_vec(),
+                ));
+                sink.sleep_until_end();
+                true
+            }
+            Err(err) => {
+                log::warn!("Failed to play candidate: {}", err);
+                false
+            }
+        }
+    }
+
+    #[cfg(not(feature = "confirm-audio"))]
+    fn play(&self, _samples: &[i16]) -> bool {
+        false
+    }
+}
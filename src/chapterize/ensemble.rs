@@ -0,0 +1,76 @@
+use super::{
+    keywords::Keywords,
+    results_parser::contains_chapter_number,
+    window::{AudioWindow, Transcript},
+};
+use crate::error::{ChapterizerError, Result};
+use ordered_float::NotNan;
+use vosk::{Model, Recognizer};
+
+/// Additional models that transcribe the candidate windows found by the main model again, so that
+/// the models can vote on whether a window contains a chapter.
+pub struct Ensemble {
+    recognizers: Vec<Recognizer>,
+}
+
+impl Ensemble {
+    pub fn new(models: &[Model], sample_rate: f32) -> Result<Self> {
+        let recognizers = models
+            .iter()
+            .map(|model| {
+                let mut recognizer =
+                    Recognizer::new(model, sample_rate).ok_or(ChapterizerError::Recognizer)?;
+                recognizer.set_max_alternatives(3);
+                recognizer.set_words(true);
+                recognizer.set_partial_words(false);
+                Ok(recognizer)
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self { recognizers })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.recognizers.is_empty()
+    }
+
+    /// Transcribes the window using each of the models.
+    pub fn transcribe_window(
+        &mut self,
+        window: &AudioWindow,
+        keywords: &Keywords,
+    ) -> Vec<Transcript> {
+        self.recognizers
+            .iter_mut()
+            .map(|recognizer| window.transcribe(recognizer, keywords))
+            .collect()
+    }
+}
+
+/// Merges the transcripts of a candidate window by the different models. The majority vote
+/// decides whether the window contains a chapter, and the transcript with the highest confidence
+/// among the majority wins. On a tie, the transcript with the highest confidence overall wins.
+pub fn vote(main: Transcript, others: Vec<Transcript>, keywords: &Keywords) -> Transcript {
+    let (with_chapter, without_chapter): (Vec<_>, Vec<_>) = std::iter::once(main)
+        .chain(others)
+        .partition(|transcript| contains_chapter_number(&transcript.tokens, keywords));
+
+    log::debug!(
+        "Ensemble vote: {} model(s) for a chapter, {} against",
+        with_chapter.len(),
+        without_chapter.len()
+    );
+
+    let candidates = match with_chapter.len().cmp(&without_chapter.len()) {
+        std::cmp::Ordering::Greater => with_chapter,
+        std::cmp::Ordering::Less => without_chapter,
+        std::cmp::Ordering::Equal => with_chapter.into_iter().chain(without_chapter).collect(),
+    };
+
+    // Ties in confidence go to the earliest transcript
+    candidates
+        .into_iter()
+        .rev()
+        .max_by_key(|transcript| NotNan::new(transcript.confidence).unwrap_or_default())
+        .expect("there should be at least one transcript")
+}
@@ -0,0 +1,40 @@
+use std::{str::FromStr, time::Duration};
+
+use crate::{parse_timestamp, timestamp::AudioTimestamp};
+
+/// A time range of the audio file in which no chapters are detected, e.g. samples of other books
+/// at the end that have chapter announcements of their own.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IgnoreRegion {
+    pub start: Duration,
+    /// The end of the range, or `None` if it runs to the end of the file.
+    pub end: Option<Duration>,
+}
+
+impl IgnoreRegion {
+    /// Returns whether the time is in the range.
+    pub fn contains(&self, time: AudioTimestamp) -> bool {
+        let time = time.to_duration();
+        time >= self.start && self.end.is_none_or(|end| time < end)
+    }
+}
+
+impl FromStr for IgnoreRegion {
+    type Err = String;
+
+    /// Parses a range of timestamps in the format [hh:]mm:ss[.fff], e.g. "0:00-2:30", where the
+    /// end can also be "end", e.g. "14:55:00-end".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid time range: {} (expected e.g. 0:00-2:30)", s);
+        let (start, end) = s.split_once('-').ok_or_else(invalid)?;
+        let start = parse_timestamp(start.trim()).ok_or_else(invalid)?;
+        let end = match end.trim() {
+            "end" => None,
+            end => Some(parse_timestamp(end).ok_or_else(invalid)?),
+        };
+        if end.is_some_and(|end| end <= start) {
+            return Err(format!("time range ends before it starts: {}", s));
+        }
+        Ok(Self { start, end })
+    }
+}
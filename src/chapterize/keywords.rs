@@ -0,0 +1,126 @@
+use lazy_static::lazy_static;
+use text2num::Language;
+use vosk::WordInAlternative;
+
+use super::{config::DetectionConfig, token::Token};
+
+lazy_static! {
+    static ref LANG_EN: Language = Language::english();
+    static ref LANG_FR: Language = Language::french();
+    static ref LANG_ES: Language = Language::spanish();
+}
+
+/// A language that chapters can be announced in, written as its ISO 639-1 code, e.g. "en".
+/// Chapter numbers are recognized using text2num, which only knows these languages.
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize,
+)]
+pub enum AnnouncementLanguage {
+    #[default]
+    #[serde(rename = "en")]
+    English,
+    #[serde(rename = "fr")]
+    French,
+    #[serde(rename = "es")]
+    Spanish,
+}
+
+impl AnnouncementLanguage {
+    pub const ALL: [Self; 3] = [Self::English, Self::French, Self::Spanish];
+
+    /// Returns the language with the code, ignoring the region if there is one, e.g. "fr-CA".
+    pub fn from_code(code: &str) -> Option<Self> {
+        let code = code.split(['-', '_']).next().unwrap_or_default();
+        Self::ALL
+            .into_iter()
+            .find(|language| language.code().eq_ignore_ascii_case(code))
+    }
+
+    pub fn code(self) -> &'static str {
+        match self {
+            Self::English => "en",
+            Self::French => "fr",
+            Self::Spanish => "es",
+        }
+    }
+
+    /// Returns the words that announce a chapter in the language, the most common first.
+    pub fn chapter_words(self) -> &'static [&'static str] {
+        match self {
+            Self::English => &["chapter", "chapters"],
+            Self::French => &["chapitre", "chapitres"],
+            Self::Spanish => &["capítulo", "capitulo", "capítulos"],
+        }
+    }
+
+    fn numbers(self) -> &'static Language {
+        match self {
+            Self::English => &LANG_EN,
+            Self::French => &LANG_FR,
+            Self::Spanish => &LANG_ES,
+        }
+    }
+}
+
+/// The words that announce a chapter and the language its number is spoken in, as set in the
+/// detection config.
+#[derive(Clone, Debug)]
+pub struct Keywords {
+    chapter_words: Vec<String>,
+    language: AnnouncementLanguage,
+}
+
+impl Default for Keywords {
+    fn default() -> Self {
+        Self::new(&DetectionConfig::default())
+    }
+}
+
+impl Keywords {
+    pub fn new(config: &DetectionConfig) -> Self {
+        let chapter_words = if config.chapter_words.is_empty() {
+            config
+                .language
+                .chapter_words()
+                .iter()
+                .map(|word| word.to_string())
+                .collect()
+        } else {
+            config
+                .chapter_words
+                .iter()
+                .map(|word| word.to_lowercase())
+                .collect()
+        };
+        Self {
+            chapter_words,
+            language: config.language,
+        }
+    }
+
+    /// Returns the words that announce a chapter, the most common first.
+    pub fn chapter_words(&self) -> &[String] {
+        &self.chapter_words
+    }
+
+    /// Returns the position of the word among the chapter words, or `None` if it isn't one.
+    /// Earlier words are preferred when the recognizer isn't sure which of them was said.
+    pub fn chapter_word_rank(&self, word: &str) -> Option<usize> {
+        self.chapter_words
+            .iter()
+            .position(|chapter_word| chapter_word == word)
+    }
+
+    pub fn is_chapter_token(&self, token: &Token) -> bool {
+        self.chapter_word_rank(&token.word).is_some()
+    }
+
+    pub fn is_chapter_wia(&self, wia: &WordInAlternative) -> bool {
+        self.chapter_word_rank(wia.word).is_some()
+    }
+
+    /// Returns the language that chapter numbers are parsed in.
+    pub fn numbers(&self) -> &'static Language {
+        self.language.numbers()
+    }
+}
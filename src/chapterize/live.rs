@@ -0,0 +1,218 @@
+use std::{
+    path::PathBuf,
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant},
+};
+
+use cpal::{
+    traits::{DeviceTrait, HostTrait, StreamTrait},
+    Sample, SampleFormat, SizedSample,
+};
+use crossbeam::channel;
+use vosk::{CompleteResult, DecodingState, Recognizer};
+
+use super::{
+    assembler::ChapterAssembler,
+    config::DetectionConfig,
+    keywords::Keywords,
+    load_model,
+    results_parser::{get_best_alt, ParseResult, ResultsParser, PRE_CHAPTER_CONTEXT},
+    token::Token,
+    window::Transcript,
+    POST_CHAPTER_CONTEXT,
+};
+use crate::{
+    chapter::{Chapter, ChapterList},
+    error::{ChapterizerError, Result},
+    fixed_vec_deque::FixedVecDeque,
+    timestamp::{AudioTimestamp, SampleOffset},
+};
+
+/// How often the stop flag is checked while waiting for audio.
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+#[derive(Clone, Debug)]
+pub struct LiveOptions {
+    pub model_dir_path: PathBuf,
+    /// The name of the audio input device to capture from, or `None` for the default one.
+    pub device_name: Option<String>,
+    pub detection_config: DetectionConfig,
+    pub nest_chapter_parts: bool,
+    /// Capturing stops after this long, if it hasn't been stopped before then.
+    pub max_runtime: Option<Duration>,
+}
+
+/// Captures audio from an audio input device and detects the chapters announced in it as it's
+/// recorded, until `stop` is set. `on_chapter` is called with each chapter as soon as it's
+/// announced, and again if its end is announced. Times are relative to the start of capturing.
+/// Returns all chapters found, including the one inserted at 0:00.
+pub fn chapterize_live(
+    options: &LiveOptions,
+    stop: &AtomicBool,
+    mut on_chapter: impl FnMut(&Chapter),
+) -> Result<ChapterList> {
+    let host = cpal::default_host();
+    let device = match &options.device_name {
+        Some(device_name) => host
+            .input_devices()
+            .map_err(|err| ChapterizerError::AudioInput(err.to_string()))?
+            .find(|device| device.name().is_ok_and(|name| name == *device_name)),
+        None => host.default_input_device(),
+    }
+    .ok_or_else(|| ChapterizerError::AudioInput("no such input device".into()))?;
+    let supported_config = device
+        .default_input_config()
+        .map_err(|err| ChapterizerError::AudioInput(err.to_string()))?;
+    let sample_format = supported_config.sample_format();
+    let stream_config: cpal::StreamConfig = supported_config.into();
+    let sample_rate = stream_config.sample_rate.0;
+    log::info!(
+        "Capturing from {} at {} Hz",
+        device.name().unwrap_or_else(|_| "the input device".into()),
+        sample_rate
+    );
+
+    let model = load_model(&options.model_dir_path)?;
+    let mut recognizer =
+        Recognizer::new(&model, sample_rate as f32).ok_or(ChapterizerError::Recognizer)?;
+    recognizer.set_max_alternatives(3);
+    recognizer.set_words(true);
+    recognizer.set_partial_words(false);
+
+    let (samples_tx, samples_rx) = channel::unbounded();
+    let stream = match sample_format {
+        SampleFormat::I16 => build_stream::<i16>(&device, &stream_config, samples_tx),
+        SampleFormat::U16 => build_stream::<u16>(&device, &stream_config, samples_tx),
+        SampleFormat::F32 => build_stream::<f32>(&device, &stream_config, samples_tx),
+        _ => Err(ChapterizerError::UnsupportedAudio(
+            "the input device's sample format isn't supported",
+        )),
+    }?;
+    stream
+        .play()
+        .map_err(|err| ChapterizerError::AudioInput(err.to_string()))?;
+
+    let keywords = Keywords::new(&options.detection_config);
+    let (mut results_parser, parse_result_rx) =
+        ResultsParser::new(POST_CHAPTER_CONTEXT, &options.detection_config);
+    let mut last_tokens: FixedVecDeque<Token> = FixedVecDeque::with_max_len(PRE_CHAPTER_CONTEXT);
+    let mut assembler = ChapterAssembler::new(options.nest_chapter_parts);
+    let mut chapters = Vec::new();
+
+    let mut ingest_result = |results_parser: &mut ResultsParser, result: CompleteResult| {
+        let Some(multi) = result.multiple() else {
+            return;
+        };
+        if multi.alternatives.is_empty() {
+            return;
+        }
+        let transcript = Transcript::from_alt(
+            get_best_alt(&multi.alternatives, &keywords),
+            AudioTimestamp::ZERO,
+        );
+        results_parser.ingest_tokens(&mut last_tokens, transcript.tokens);
+    };
+
+    let started = Instant::now();
+    let mut end = SampleOffset::ZERO;
+    while !stop.load(Ordering::Relaxed)
+        && options
+            .max_runtime
+            .is_none_or(|max_runtime| started.elapsed() < max_runtime)
+    {
+        let samples = match samples_rx.recv_timeout(POLL_INTERVAL) {
+            Ok(samples) => samples,
+            Err(channel::RecvTimeoutError::Timeout) => continue,
+            Err(channel::RecvTimeoutError::Disconnected) => break,
+        };
+        end += samples.len() as u64;
+        if let DecodingState::Finalized = recognizer.accept_waveform(&samples) {
+            ingest_result(&mut results_parser, recognizer.result());
+            assemble(
+                &parse_result_rx,
+                &mut assembler,
+                &mut chapters,
+                &mut on_chapter,
+            );
+        }
+    }
+    drop(stream);
+
+    ingest_result(&mut results_parser, recognizer.final_result());
+    results_parser.flush();
+    assemble(
+        &parse_result_rx,
+        &mut assembler,
+        &mut chapters,
+        &mut on_chapter,
+    );
+
+    let duration = end.to_duration(sample_rate);
+    chapters.push(assembler.finish(duration));
+    Ok(ChapterList { chapters, duration })
+}
+
+/// Passes the parse results received so far to the assembler, calling `on_chapter` whenever a
+/// chapter starts or its end is announced.
+fn assemble(
+    parse_result_rx: &channel::Receiver<ParseResult>,
+    assembler: &mut ChapterAssembler,
+    chapters: &mut Vec<Chapter>,
+    on_chapter: &mut impl FnMut(&Chapter),
+) {
+    for parse_result in parse_result_rx.try_iter() {
+        if let ParseResult::Failure(Some(candidate)) = &parse_result {
+            log::debug!(
+                "Rejected candidate \"{}\" at {:.3}s: {}",
+                candidate.text,
+                candidate.start.as_secs_f32(),
+                candidate.reason
+            );
+        }
+        let is_match = matches!(
+            parse_result,
+            ParseResult::Match(_) | ParseResult::EndMatch(_)
+        );
+        if let Some(chapter) = assembler.push(parse_result, None, None) {
+            chapters.push(chapter);
+        }
+        if is_match {
+            on_chapter(assembler.current_chapter());
+        }
+    }
+}
+
+/// Builds a stream that sends the audio captured from the device to the channel, downmixed to
+/// mono 16-bit samples, which is what the recognizer takes.
+fn build_stream<T>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    samples_tx: channel::Sender<Vec<i16>>,
+) -> Result<cpal::Stream>
+where
+    T: SizedSample,
+    i16: cpal::FromSample<T>,
+{
+    let num_channels = config.channels.max(1) as usize;
+    device
+        .build_input_stream(
+            config,
+            move |data: &[T], _: &cpal::InputCallbackInfo| {
+                let samples = data
+                    .chunks(num_channels)
+                    .map(|frame| {
+                        let sum: i32 = frame
+                            .iter()
+                            .map(|&sample| sample.to_sample::<i16>() as i32)
+                            .sum();
+                        (sum / frame.len() as i32) as i16
+                    })
+                    .collect();
+                // The receiver is only gone once capturing has stopped
+                let _ = samples_tx.send(samples);
+            },
+            |err| log::error!("Audio input error: {}", err),
+            None,
+        )
+        .map_err(|err| ChapterizerError::AudioInput(err.to_string()))
+}
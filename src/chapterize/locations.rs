@@ -0,0 +1,62 @@
+use std::{path::Path, time::Duration};
+
+use super::token::Token;
+use crate::{format_duration, timestamp::AudioTimestamp};
+
+/// Seek hints start playing this long before the candidate, so that the pause before it can be
+/// heard too.
+const SEEK_HINT_LEAD_IN: Duration = Duration::from_secs(2);
+
+/// The lines of the matches file that recognition results were written to, along with the times
+/// they span, so that log messages about candidates can refer to them.
+#[derive(Debug, Default)]
+pub struct MatchLines {
+    spans: Vec<(AudioTimestamp, AudioTimestamp, usize)>,
+}
+
+impl MatchLines {
+    /// Records that the recognition result with the tokens was written to the line (counting from
+    /// 1) of the matches file.
+    pub fn push(&mut self, tokens: &[Token], line: usize) {
+        if let (Some(first), Some(last)) = (tokens.first(), tokens.last()) {
+            self.spans.push((first.start, last.end, line));
+        }
+    }
+
+    /// Returns the line of the matches file with the recognition result that the time is in, if it
+    /// was written to the matches file.
+    pub fn find(&self, time: AudioTimestamp) -> Option<usize> {
+        self.spans
+            .iter()
+            .rev()
+            .find(|(start, end, _)| (*start..=*end).contains(&time))
+            .map(|(_, _, line)| *line)
+    }
+}
+
+/// Describes where a candidate was found, e.g. "00:12:34.567 (matches file line 12)".
+pub fn describe_location(start: Duration, matches_line: Option<usize>) -> String {
+    match matches_line {
+        Some(line) => format!(
+            "{} (matches file line {})",
+            format_duration(&Some(start)),
+            line
+        ),
+        None => format_duration(&Some(start)),
+    }
+}
+
+/// Returns an ffplay command that plays the audio file from shortly before the time, ready to be
+/// pasted into a shell to check a candidate by ear.
+pub fn seek_hint(audio_file_path: &Path, start: Duration) -> String {
+    format!(
+        "ffplay -autoexit -ss {} {}",
+        format_duration(&Some(start.saturating_sub(SEEK_HINT_LEAD_IN))),
+        shell_quote(&audio_file_path.to_string_lossy())
+    )
+}
+
+/// Quotes the string for POSIX shells.
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
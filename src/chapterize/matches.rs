@@ -0,0 +1,199 @@
+use std::io::{self, Write};
+
+use vosk::Alternative;
+
+use super::tune::Decision;
+use crate::{
+    error::{ChapterizerError, Result},
+    fixed_vec_deque::FixedVecDeque,
+};
+
+/// The number of recognition results before and after a candidate that are written along with it
+/// as context.
+pub const MATCHES_CONTEXT: usize = 2;
+
+/// A line of a matches file, i.e. a candidate as the recognition result it was found in, along
+/// with the results before and after it as context, as serialized by the recognizer.
+#[derive(Debug)]
+struct Entry {
+    before: Vec<String>,
+    candidate: String,
+    after: Vec<String>,
+}
+
+/// An [`Entry`] with its results parsed, so that it can be written on a single line.
+#[derive(Debug, serde::Serialize)]
+struct EntryOutput {
+    before: Vec<serde_json::Value>,
+    #[serde(rename = "match")]
+    candidate: serde_json::Value,
+    after: Vec<serde_json::Value>,
+}
+
+/// Writes the candidates to a matches file, one per line, each along with up to
+/// [`MATCHES_CONTEXT`] recognition results before and after it. A candidate is only written once
+/// the results after it are known. Each result is written once, so if two candidates are closer
+/// together than that, the results between them are only the context after the first one.
+pub struct MatchesWriter<W: Write> {
+    writer: W,
+    /// The most recent results that weren't written as context after a candidate.
+    recent: FixedVecDeque<String>,
+    /// The candidate that the results after it are still being collected for.
+    pending: Option<Entry>,
+    lines_written: usize,
+}
+
+impl<W: Write> MatchesWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            recent: FixedVecDeque::with_max_len(MATCHES_CONTEXT),
+            pending: None,
+            lines_written: 0,
+        }
+    }
+
+    /// Adds a recognition result that isn't a candidate. Most results are never written, so they're
+    /// only parsed once they are.
+    pub fn push_result(&mut self, result: &str) -> io::Result<()> {
+        let result = result.to_string();
+        let Some(pending) = &mut self.pending else {
+            self.recent.push_back(result);
+            return Ok(());
+        };
+        pending.after.push(result);
+        if pending.after.len() >= MATCHES_CONTEXT {
+            self.write_pending()?;
+        }
+        Ok(())
+    }
+
+    /// Adds a recognition result with a candidate, e.g. with its confidence added. Returns the line
+    /// of the matches file (counting from 1) that it will be written to.
+    pub fn push_candidate(&mut self, result: &str) -> io::Result<usize> {
+        self.write_pending()?;
+        self.pending = Some(Entry {
+            before: self.recent.drain(..).collect(),
+            candidate: result.to_string(),
+            after: Vec::new(),
+        });
+        Ok(self.lines_written + 1)
+    }
+
+    /// Writes the last candidate, with whatever results came after it, and flushes the underlying
+    /// writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.write_pending()?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    fn write_pending(&mut self) -> io::Result<()> {
+        let Some(entry) = self.pending.take() else {
+            return Ok(());
+        };
+        let parse = |results: Vec<String>| {
+            results
+                .iter()
+                .map(|result| serde_json::from_str(result))
+                .collect::<serde_json::Result<Vec<_>>>()
+        };
+        let output = EntryOutput {
+            before: parse(entry.before)?,
+            candidate: serde_json::from_str(&entry.candidate)?,
+            after: parse(entry.after)?,
+        };
+        let line = serde_json::to_string(&output)?;
+        log::trace!("Writing {} bytes to matches file", line.len());
+        self.writer.write_all(line.as_bytes())?;
+        self.writer.write_all(b"\n")?;
+        self.lines_written += 1;
+        Ok(())
+    }
+}
+
+/// A recognition result as read from a matches file. Reviewers annotate candidates by adding a
+/// decision to their line.
+#[derive(Debug, serde::Deserialize)]
+struct ResultLine<'a> {
+    #[serde(borrow)]
+    alternatives: Vec<Alternative<'a>>,
+    #[serde(default)]
+    decision: Option<Decision>,
+}
+
+/// A line of a matches file with a candidate and its context, see [`Entry`]. The decision of a
+/// reviewer can be added to the line itself or to the result of the candidate.
+#[derive(Debug, serde::Deserialize)]
+struct EntryLine<'a> {
+    #[serde(borrow, default)]
+    before: Vec<ResultLine<'a>>,
+    #[serde(borrow, rename = "match")]
+    candidate: ResultLine<'a>,
+    #[serde(borrow, default)]
+    after: Vec<ResultLine<'a>>,
+    #[serde(default)]
+    decision: Option<Decision>,
+}
+
+/// Identifies a recognition result by the start of its first word and the text of its first
+/// alternative, which no two results of a run share.
+fn result_key<'a>(alternatives: &[Alternative<'a>]) -> Option<(f32, &'a str)> {
+    let alt = alternatives.first()?;
+    Some((alt.result.first()?.start, alt.text))
+}
+
+/// Calls the function with the line number, the alternatives and the decision of a reviewer, if
+/// any, of each recognition result in the contents of a matches file, in order. Matches files
+/// written before candidates were grouped with their context, with a result per line, can be read
+/// too. Those repeat results as context if candidates are close together, so results that were
+/// already read in the last few lines are skipped.
+pub fn for_each_result(
+    matches: &str,
+    mut f: impl FnMut(usize, &[Alternative], Option<Decision>),
+) -> Result<()> {
+    // A result is repeated at most this many lines after it was first written
+    let mut recent_keys = FixedVecDeque::with_max_len(2 * MATCHES_CONTEXT + 1);
+    for (index, line) in matches.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let line_number = index + 1;
+        let parse_error = |source| ChapterizerError::Json {
+            context: format!("Failed to parse line {} of matches file", line_number),
+            source,
+        };
+
+        match serde_json::from_str::<EntryLine>(line) {
+            Ok(entry) => {
+                for result in &entry.before {
+                    f(line_number, &result.alternatives, None);
+                }
+                f(
+                    line_number,
+                    &entry.candidate.alternatives,
+                    entry.decision.or(entry.candidate.decision),
+                );
+                for result in &entry.after {
+                    f(line_number, &result.alternatives, None);
+                }
+            }
+            Err(_) => {
+                let result = serde_json::from_str::<ResultLine>(line).map_err(parse_error)?;
+                if let Some(key) = result_key(&result.alternatives) {
+                    if recent_keys.contains(&key) {
+                        log::trace!(
+                            "Skipping repeated result on line {} of matches file",
+                            line_number
+                        );
+                        continue;
+                    }
+                    recent_keys.push_back(key);
+                }
+                f(line_number, &result.alternatives, result.decision);
+            }
+        }
+    }
+
+    Ok(())
+}
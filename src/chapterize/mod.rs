@@ -1,251 +1,1110 @@
 use crate::{
-    audio_provider::AudioProvider,
-    chapter_writer::ChapterWriter,
+    agreement::CrossCheck,
+    audio_provider::{is_stdin, scan_duration, AudioProvider, FormatHint, RawFormat},
+    audiobookshelf::AudiobookshelfWriter,
+    chapter::{Chapter, ChapterList, RejectedCandidate},
+    chapter_writer::{ChapterWriter, OutputFile},
     chapterize::{
-        results_parser::{alt_contains_potential_match, ParseResult, ResultsParser},
+        assembler::ChapterAssembler,
+        buffer_pool::BufferPool,
+        calibrate::calibrate,
+        confidence::{candidate_confidence, CandidateConfidences},
+        confirm::{candidate_clips, Confirmer},
+        ensemble::{vote, Ensemble},
+        keywords::Keywords,
+        locations::{describe_location, seek_hint, MatchLines},
+        matches::MatchesWriter,
+        results_parser::{
+            alt_contains_potential_match, contains_chapter_number, get_best_alt, runner_up_alts,
+            ParseResult, ResultsParser, PRE_CHAPTER_CONTEXT,
+        },
+        segment::{SegmentBounds, Segmenter, SEGMENT_OVERLAP_SECS},
+        speaker::SpeakerChangeDetector,
+        spotting::{spot_keywords, KeywordWindows, WindowEvent},
         token::Token,
+        window::{AudioHistory, Transcript},
     },
-    cue::CueWriter,
+    compression::{self, FileCompression},
+    cue::{disc_file_path, verify_disc_starts, CueWriter, DiscCueWriter},
+    error::{format_error_chain, ChapterizerError, IoResultExt, Result},
+    extract::read_format_tags,
     ffmetadata::FfmetadataWriter,
     fixed_vec_deque::FixedVecDeque,
     format_duration,
+    in_place::{can_embed_chapters, embedded_chapter_writer, InPlaceOptions},
+    json::JsonWriter,
+    matroska::MatroskaWriter,
+    nfo::NfoWriter,
+    output_config::OutputConfig,
+    probe_cache,
+    retime::{ChapterRetimer, Retime},
+    silence::{chapters_at_silences, SilenceDetector, SilenceOptions},
+    split_script::{ScriptShell, SplitScriptWriter},
+    srt::SrtWriter,
+    status::{write_status, ProgressStatus, RunState, StatusChapter},
+    timestamp::{AudioTimestamp, SampleOffset},
+    vtt::VttWriter,
 };
-use arrayvec::ArrayVec;
-use color_eyre::eyre::{self, Context, ContextCompat};
 use crossbeam::channel;
-use itertools::Itertools;
 use std::io::Write;
 use std::path::Path;
 use std::{
-    fs::File,
+    fs::{self, File},
     path::PathBuf,
     sync::{
         atomic::{AtomicU64, Ordering},
-        Arc,
+        Arc, Mutex,
     },
     thread,
-    time::Duration,
+    time::{Duration, Instant},
 };
-use vosk::{CompleteResult, CompleteResultMultiple, Model, Recognizer};
+use vosk::{CompleteResult, CompleteResultMultiple, Recognizer};
 
+mod assembler;
+mod buffer_pool;
+mod calibrate;
+mod confidence;
+mod config;
+mod confirm;
+mod ensemble;
+mod ignore;
+mod keywords;
+#[cfg(feature = "live-input")]
+mod live;
+mod locations;
+mod matches;
+mod model;
+mod pauses;
+mod replay;
 mod results_parser;
+mod segment;
+mod speaker;
+mod spotting;
 mod token;
+mod tune;
+#[cfg(feature = "runtime-vosk")]
+mod vosk_library;
+mod window;
 
-const SAMPLES_BUFFER_SIZE: usize = 8 * 1024; // 8 kb
+pub use self::config::{
+    default_profiles_dir, default_user_config_path, profile_path, DetectionConfig, LanguagePreset,
+    UserConfig,
+};
+pub use self::ignore::IgnoreRegion;
+pub use self::keywords::AnnouncementLanguage;
+#[cfg(feature = "live-input")]
+pub use self::live::{chapterize_live, LiveOptions};
+pub use self::model::{default_model_cache_dir, load_model};
+pub use self::replay::{candidate_times, replay_matches};
+pub use self::tune::{tune, Decision, TuneReport};
 
-/// The number of results before and after a potential match to include as context when writing
-/// potential matches to file.
-const WRITE_POT_MATCH_CONTEXT: usize = 2;
+/// The number of buffers of decoded samples that can wait for the recognizer. Two is enough for
+/// the decoder to fill one while the recognizer consumes the other.
+const DECODE_BUFFERS: usize = 2;
 
 /// 30 tokens should be plenty to capture the chapter number followed by most chapter titles
 const POST_CHAPTER_CONTEXT: usize = 30;
 
+/// The audio written for each candidate includes this many seconds before and after it, so that
+/// the moment can be heard in context.
+const CANDIDATE_AUDIO_MARGIN_SECS: f32 = 3.0;
+
+/// Speaker changes further than this from the nearest chapter are reported as possibly missed
+/// chapters.
+const SPEAKER_CHANGE_TOLERANCE: Duration = Duration::from_secs(30);
+
+/// The number of alternatives considered when transcribing a weak candidate again.
+const RETRANSCRIBE_MAX_ALTERNATIVES: u16 = 10;
+
+/// How long chapterizing may take to stop once --max_runtime is reached before it's abandoned,
+/// which only happens if the decoder or the recognizer hangs in the middle of a buffer.
+const MAX_RUNTIME_GRACE_PERIOD: Duration = Duration::from_secs(30);
+
 const PROGRESS_INTERVAL: Duration = Duration::from_secs(5);
 
 /// Use the average speed factor of the last 5 minutes to calculate the ETA
 const ETA_CALC_WINDOW: usize = 300 / PROGRESS_INTERVAL.as_secs() as usize;
 
-/// This margin is subtracted from the start timestamp of a chapter when output.
-const PRE_CHAPTER_START_MARGIN: Duration = Duration::from_secs(1);
+/// Estimates the total duration of the audio from the size of the file and the average bitrate
+/// of the audio processed so far. Used when the duration of the audio is unknown.
+fn estimate_total_duration(
+    processed_duration: Duration,
+    bytes_read: u64,
+    file_size: Option<u64>,
+) -> Option<Duration> {
+    let file_size = file_size?;
+    if bytes_read == 0 || processed_duration.is_zero() {
+        return None;
+    }
+    Some(processed_duration.mul_f64(file_size as f64 / bytes_read as f64))
+}
 
-pub fn gimme_audio<P>(path: P) -> eyre::Result<AudioProvider>
+pub fn gimme_audio<P>(path: P, format_hint: &FormatHint) -> Result<AudioProvider>
 where
     P: AsRef<Path>,
 {
+    if is_stdin(path.as_ref()) {
+        return match format_hint.raw {
+            Some(raw) => AudioProvider::stdin(raw),
+            None => Err(ChapterizerError::UnsupportedAudio(
+                "Only raw PCM audio can be read from the standard input",
+            )),
+        };
+    }
+
     // Open the media source.
-    let src = std::fs::File::open(&path).wrap_err("Failed to open audio file")?;
+    let src = std::fs::File::open(&path).io_context("Failed to open audio file")?;
+
+    match AudioProvider::new(src, format_hint) {
+        Err(err @ (ChapterizerError::Decode { .. } | ChapterizerError::UnsupportedAudio(_)))
+            if format_hint.ffmpeg_fallback =>
+        {
+            log::warn!(
+                "{}, decoding the audio with ffmpeg instead",
+                format_error_chain(&err)
+            );
+            AudioProvider::ffmpeg(path.as_ref())
+        }
+        result => result,
+    }
+}
+
+/// A recognition result of the main model, passed from the ASR thread to the result processor.
+struct RecognitionMessage {
+    /// The serialized result.
+    result_json: String,
+    /// If the result was a weak candidate, the transcript of its window of audio transcribed again
+    /// with more alternatives.
+    retranscript: Option<Transcript>,
+    /// If the result may contain a chapter, the transcripts of its window of audio by each of the
+    /// models in the ensemble.
+    ensemble_transcripts: Option<Vec<Transcript>>,
+    /// If the result may contain a chapter, the path of the audio file with its window of audio,
+    /// relative to the directory of the matches file.
+    candidate_audio: Option<String>,
+}
 
-    AudioProvider::new(src)
+/// Returns the directory that the audio of the candidates in the matches file is written to, e.g.
+/// "book.candidates" for "book.jsonl".
+pub fn candidate_audio_dir_path(matches_file_path: &Path) -> PathBuf {
+    FileCompression::from_path(matches_file_path)
+        .strip_extension(matches_file_path)
+        .with_extension("candidates")
 }
 
 pub struct ChapterizeOptions {
-    /// The path to the Vosk ASR model directory to use.
+    /// The path to the Vosk ASR model directory to use, or to a zip archive of one.
     pub model_dir_path: PathBuf,
     /// Optionally, a path to a file to write matching recognition results to.
     pub matches_file_path: Option<PathBuf>,
     /// The path to the audio file to chapterize.
     pub audio_file_path: PathBuf,
+    /// The paths to additional Vosk ASR model directories or archives. If any are given, the
+    /// windows of audio that may contain a chapter are transcribed again using each of them, and
+    /// the models vote on whether the window contains a chapter.
+    pub ensemble_model_dir_paths: Vec<PathBuf>,
+    /// The thresholds used to detect chapters.
+    pub detection_config: DetectionConfig,
+    /// Whether to transcribe the windows of audio of weak candidates again with more alternatives.
+    /// A candidate is weak if the word "chapter" was recognized, but no number after it.
+    pub retranscribe_weak_candidates: bool,
+    /// Whether to write a few seconds of audio around each candidate in the matches file to a WAV
+    /// file, so that candidates can be reviewed without the audio file. The WAV files are written
+    /// to the directory returned by [`candidate_audio_dir_path`]. Requires a matches file.
+    pub write_candidate_audio: bool,
+    /// Whether the parts of a subdivided chapter, e.g. "chapter nine, part one", are nested in
+    /// their chapter. If not, each part is a separate chapter with a merged title.
+    pub nest_chapter_parts: bool,
+    /// Whether to write the candidates that were rejected as chapters, e.g. the word "chapter"
+    /// without a number after it, to the outputs that can hold them, along with why they were
+    /// rejected. Useful for finding out why a chapter was missed.
+    pub include_rejected: bool,
+    /// Whether to log an ffplay command with each chapter that plays the audio file from shortly
+    /// before it, to speed up checking the chapters by ear.
+    pub seek_hints: bool,
+    /// Whether to play the audio around each chapter found and ask in the terminal whether it's a
+    /// chapter before it's written. Chapters that aren't confirmed are rejected. The audio is only
+    /// played with the confirm-audio feature; otherwise, a command that plays it is shown.
+    pub confirm_audio: bool,
+    /// The time ranges of the audio file in which candidates are rejected, e.g. samples of other
+    /// books at the end that have chapter announcements of their own.
+    pub ignore_regions: Vec<IgnoreRegion>,
+    /// If set, chapters whose candidate confidence is below this are rejected. The confidence is
+    /// the share of the transcripts of the candidate, from the recognizer's alternatives and any
+    /// ensemble models or transcription again, in which "chapter" is followed by a number. It's
+    /// written to the matches file with each candidate either way.
+    pub min_confidence: Option<f32>,
+    /// If set, this much of the start of the audio file is transcribed before the main pass to
+    /// measure how fast the narrator speaks and how well they're recognized, and the thresholds
+    /// are adjusted to match. The calibration is logged and listed in the status file.
+    pub calibration_duration: Option<Duration>,
+    /// Optionally, a path to write the detection config used to once the run completes, after
+    /// any calibration, so that later runs for the same narrator can load it as a profile.
+    pub save_profile_path: Option<PathBuf>,
+    /// Whether to scan the audio file for its duration before chapterizing it if its metadata
+    /// doesn't specify it, so that progress can be reported.
+    pub prescan_duration: bool,
+    /// If set, the recognizer is restarted for each segment of this length, with some overlap
+    /// between the segments. This keeps the state of the recognizer small on long files. Ignored
+    /// when spotting keywords.
+    pub segment_duration: Option<Duration>,
+    /// Whether to first find where the chapter words are spoken with a recognizer that knows only
+    /// them, and then only recognize the audio around them in full. This is much faster on long
+    /// books, but chapters whose chapter word isn't spotted are missed, and the transcript only
+    /// covers the audio around the chapter words. Needs an audio file, since the audio is read
+    /// twice.
+    pub spot_keywords: bool,
+    /// The number of the highest scoring alternatives of each candidate to consider, at least 1.
+    /// If more than one, the runner-ups among them that read the announcement differently than the
+    /// best one are recorded with the candidate in the matches file, for review. The recognizer
+    /// keeps at least this many alternatives.
+    pub top_alternatives: u16,
+    /// The duration of the chunks of audio fed to the recognizer at a time. Results are only
+    /// checked for between chunks, so shorter chunks let segments and progress end closer to
+    /// where they should, while longer chunks cost less overhead per sample. The chunks are cut
+    /// from the decoded audio, so their length doesn't depend on the packet sizes of the file.
+    pub chunk_duration: Duration,
+    /// If set, chapterizing is stopped once it has run for this long, e.g. because a broken file
+    /// makes the decoder loop. The outputs are finished with the chapters found so far, and a
+    /// [`ChapterizerError::Timeout`] is returned. If the decoder or the recognizer hangs and
+    /// doesn't stop within 30 seconds, it's abandoned with the outputs left unfinished.
+    pub max_runtime: Option<Duration>,
+    /// Whether to detect where the narrator changes, and report changes without a chapter nearby
+    /// as possibly missed chapters.
+    pub detect_speaker_changes: bool,
+    /// Overrides the format of the audio file, given as a file extension, e.g. "mp3". If not set,
+    /// the extension of the audio file is used. Useful for misnamed files.
+    pub format: Option<String>,
+    /// Optionally, the MIME type of the audio file, to help detect its format.
+    pub mime_type: Option<String>,
+    /// If set, the audio file is raw PCM in this format, e.g. audio pre-processed by ffmpeg or sox,
+    /// and isn't probed. The audio file path can then be [`STDIN_PATH`] to read it from the
+    /// standard input.
+    ///
+    /// [`STDIN_PATH`]: crate::audio_provider::STDIN_PATH
+    pub raw: Option<RawFormat>,
+    /// If set, audio that can't be decoded, e.g. because its codec isn't supported, is decoded by
+    /// ffmpeg instead of failing. ffmpeg needs to be installed for this.
+    pub ffmpeg_fallback: bool,
+    /// Optionally, the id of the audio track to chapterize, for files with more than one. Defaults
+    /// to the first audio track with a supported codec.
+    pub track_id: Option<u32>,
+    /// Optionally, a path to a JSON file to write the progress of the run to, including the
+    /// chapters found so far. It's replaced every few seconds while the run goes on, so that long
+    /// runs can be monitored from elsewhere.
+    pub status_file_path: Option<PathBuf>,
+    /// Optionally, a path to a text file to write the full transcript to as it's recognized. Each
+    /// line holds the start time and the words of a recognition result.
+    pub transcript_file_path: Option<PathBuf>,
     /// The path that the output .cue file will be written to.
     pub cue_file_path: Option<PathBuf>,
+    /// The start times of the discs after the first, for books ripped from CDs. If not empty, one
+    /// .cue file is written per disc instead, named after the output .cue file.
+    pub cue_disc_starts: Vec<Duration>,
     /// The path that the output ffmetadata file will be written to.
     pub ffmetadata_file_path: Option<PathBuf>,
+    /// The path that the output JSON file will be written to.
+    pub json_file_path: Option<PathBuf>,
+    /// The path that a script that splits the audio file into its chapters with ffmpeg will be
+    /// written to. It's a PowerShell script if the path ends in .ps1, or a POSIX shell script
+    /// otherwise.
+    pub split_script_file_path: Option<PathBuf>,
+    /// The path that the Audiobookshelf metadata.json file will be written to.
+    pub audiobookshelf_file_path: Option<PathBuf>,
+    /// The path that the Kodi/Jellyfin NFO file will be written to.
+    pub nfo_file_path: Option<PathBuf>,
+    /// The path that the Matroska chapter XML file will be written to.
+    pub matroska_file_path: Option<PathBuf>,
+    /// The path that the WebVTT chapters track will be written to.
+    pub vtt_file_path: Option<PathBuf>,
+    /// The path that the SRT file will be written to.
+    pub srt_file_path: Option<PathBuf>,
+    /// If set, the chapters are also embedded in the audio file itself, which must be an MP4 file,
+    /// e.g. an .m4b, or an MP3 file. The file is changed once all chapters are known.
+    pub embed: Option<InPlaceOptions>,
+    /// The adjustment applied to the timestamps of the chapters when they're written.
+    pub retime: Retime,
+    /// The options of the output formats.
+    pub output_config: OutputConfig,
+    /// The chapters found by other detectors, that the chapters are checked against.
+    pub cross_check: CrossCheck,
 }
 
-pub fn chapterize(options: &ChapterizeOptions) -> Result<(), eyre::Error> {
-    let ap = gimme_audio(&options.audio_file_path)?;
-    let num_channels = 1;
+/// Joins the words of a chapter announcement, e.g. "chapter 7".
+fn announcement_text(tokens: &[Token]) -> String {
+    tokens
+        .iter()
+        .map(|token| token.word.as_str())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Returns the format hint for the audio file, overridden by the format in the options.
+fn options_format_hint(options: &ChapterizeOptions) -> FormatHint {
+    let mut format_hint = FormatHint::from_path(&options.audio_file_path);
+    if let Some(format) = &options.format {
+        format_hint.extension = Some(format.to_lowercase());
+    }
+    format_hint.mime_type = options.mime_type.clone();
+    format_hint.raw = options.raw;
+    format_hint.ffmpeg_fallback = options.ffmpeg_fallback;
+    format_hint.track_id = options.track_id;
+    format_hint
+}
+
+/// The number of samples decoded at a time when scanning for silences.
+const SILENCE_SCAN_BUFFER_SIZE: usize = 8 * 1024;
+
+/// Silences at least this long are taken to be breaks between chapters when falling back to
+/// silence detection. Pauses between paragraphs are rarely this long.
+const SILENCE_CHAPTER_OPTIONS: SilenceOptions = SilenceOptions {
+    threshold_db: -40.0,
+    min_duration: Duration::from_secs(2),
+};
+
+/// Chapters placed at silences are at least this long, so that a few long pauses close together
+/// don't end up as chapters of their own.
+const MIN_SILENCE_CHAPTER_DURATION: Duration = Duration::from_secs(3 * 60);
+
+/// Places chapters at the long silences of the audio file, without speech recognition. This is a
+/// last resort for books in which no chapter announcements were recognized, and the chapters are
+/// not written to any outputs.
+pub fn detect_silence_chapters(options: &ChapterizeOptions) -> Result<ChapterList> {
+    let mut ap = gimme_audio(&options.audio_file_path, &options_format_hint(options))?;
     let sample_rate = ap.sample_rate();
-    let total_duration = ap.total_duration();
-    let total_samples = Arc::new(AtomicU64::new(0));
+    let mut detector = SilenceDetector::new(sample_rate, SILENCE_CHAPTER_OPTIONS);
+    let mut buffer = Vec::with_capacity(SILENCE_SCAN_BUFFER_SIZE);
+    while ap.fill_buffer(&mut buffer, SILENCE_SCAN_BUFFER_SIZE) > 0 {
+        detector.push_samples(&buffer);
+        buffer.clear();
+    }
+
+    let silences = detector.finish();
+    let duration = ap.position().to_duration(sample_rate);
+    let chapter_list = chapters_at_silences(&silences, duration, MIN_SILENCE_CHAPTER_DURATION);
+    log::info!(
+        "Placed {} chapter(s) at {} silence(s)",
+        chapter_list.chapters.len(),
+        silences.len()
+    );
+    Ok(chapter_list)
+}
 
-    let calc_progress_in_secs = move |current_samples: u64| {
-        current_samples as f32 / sample_rate as f32 / num_channels as f32
+/// Chapterizes the audio file using automatic speech recognition and writes the chapters to the
+/// outputs, returning the chapters written.
+pub fn chapterize(options: &ChapterizeOptions) -> Result<ChapterList> {
+    if options.cue_file_path.is_none()
+        && options.ffmetadata_file_path.is_none()
+        && options.json_file_path.is_none()
+        && options.split_script_file_path.is_none()
+        && options.audiobookshelf_file_path.is_none()
+        && options.nfo_file_path.is_none()
+        && options.matroska_file_path.is_none()
+        && options.vtt_file_path.is_none()
+        && options.srt_file_path.is_none()
+        && options.embed.is_none()
+    {
+        return Err(ChapterizerError::InvalidOptions(
+            "no output file paths specified",
+        ));
+    }
+    if options.embed.is_some() && !can_embed_chapters(&options.audio_file_path) {
+        return Err(ChapterizerError::InvalidOptions(
+            "chapters can only be embedded in MP4 and MP3 files",
+        ));
+    }
+    // Retimed chapters belong to another edition of the audio, not to the file they'd be
+    // embedded in
+    if options.embed.is_some() && options.retime != Retime::default() {
+        return Err(ChapterizerError::InvalidOptions(
+            "chapters can't be embedded when they're retimed",
+        ));
+    }
+    verify_disc_starts(&options.cue_disc_starts)?;
+    if options.chunk_duration.is_zero() {
+        return Err(ChapterizerError::InvalidOptions(
+            "chunk duration must be greater than zero",
+        ));
+    }
+    if options
+        .segment_duration
+        .is_some_and(|segment_duration| segment_duration.as_secs_f32() < 2.0 * SEGMENT_OVERLAP_SECS)
+    {
+        return Err(ChapterizerError::InvalidOptions(
+            "segment duration must be at least twice the segment overlap",
+        ));
+    }
+
+    let candidate_audio_dir_path = match (options.write_candidate_audio, &options.matches_file_path)
+    {
+        (false, _) => None,
+        (true, None) => {
+            return Err(ChapterizerError::InvalidOptions(
+                "writing candidate audio requires a matches file",
+            ))
+        }
+        (true, Some(matches_file_path)) => {
+            let dir_path = candidate_audio_dir_path(matches_file_path);
+            fs::create_dir_all(&dir_path)
+                .io_context("Failed to create candidate audio directory")?;
+            Some(dir_path)
+        }
+    };
+
+    let from_stdin = is_stdin(&options.audio_file_path);
+    if from_stdin && options.calibration_duration.is_some() {
+        return Err(ChapterizerError::InvalidOptions(
+            "calibrating needs an audio file, since the standard input can only be read once",
+        ));
+    }
+    if from_stdin && options.spot_keywords {
+        return Err(ChapterizerError::InvalidOptions(
+            "spotting keywords needs an audio file, since the standard input can only be read once",
+        ));
+    }
+
+    let format_hint = options_format_hint(options);
+    let mut ap = gimme_audio(&options.audio_file_path, &format_hint)?;
+    ap.set_metadata_callback(|update| {
+        log::info!(
+            "Metadata updated at {}: {}",
+            format_duration(&update.position),
+            update.title.as_deref().unwrap_or("(no title)")
+        );
+        log::debug!("Updated metadata tags: {:?}", update.tags);
+    });
+    let sample_rate = ap.sample_rate();
+    let total_duration = match ap.total_duration() {
+        Some(total_duration) => Some(total_duration),
+        None if options.prescan_duration && !from_stdin => {
+            log::info!("Audio file metadata does not specify duration, scanning for it");
+            let scan = || {
+                let src =
+                    File::open(&options.audio_file_path).io_context("Failed to open audio file")?;
+                scan_duration(src, &format_hint)
+            };
+            // Only the duration of the default track of a file that isn't raw audio is cached
+            let scanned = match (&format_hint.raw, format_hint.track_id) {
+                (None, None) => probe_cache::scanned_duration(&options.audio_file_path, scan),
+                _ => scan(),
+            };
+            match scanned {
+                Ok(total_duration) => {
+                    log::info!(
+                        "Scanned duration: {}",
+                        format_duration(&Some(total_duration))
+                    );
+                    Some(total_duration)
+                }
+                // The audio may be decoded by ffmpeg because symphonia can't read it
+                Err(err) if options.ffmpeg_fallback => {
+                    log::warn!("Failed to scan for duration: {}", format_error_chain(&err));
+                    None
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        None => None,
     };
+    let chunk_len =
+        ((sample_rate as f64 * options.chunk_duration.as_secs_f64()).round() as usize).max(1);
+    log::debug!(
+        "Feeding the recognizer chunks of {} samples ({} ms)",
+        chunk_len,
+        options.chunk_duration.as_millis()
+    );
+    let total_samples = Arc::new(AtomicU64::new(0));
+
+    let samples_to_duration =
+        move |num_samples: u64| SampleOffset::new(num_samples).to_duration(sample_rate);
 
-    let model = Model::new(options.model_dir_path.to_string_lossy())
-        .wrap_err("Failed to load the model")?;
+    let model = load_model(&options.model_dir_path)?;
     let mut recognizer =
-        Recognizer::new(&model, sample_rate as f32).wrap_err("Failed to create the recognizer")?;
+        Recognizer::new(&model, sample_rate as f32).ok_or(ChapterizerError::Recognizer)?;
 
-    recognizer.set_max_alternatives(3);
+    recognizer.set_max_alternatives(options.top_alternatives.max(3));
     recognizer.set_words(true);
     recognizer.set_partial_words(false);
 
+    let mut detection_config = options.detection_config.clone();
+    let mut min_confidence = options.min_confidence;
+    let calibration = match options.calibration_duration {
+        Some(calibration_duration) => {
+            log::info!(
+                "Calibrating on the first {}",
+                format_duration(&Some(calibration_duration))
+            );
+            calibrate(
+                &model,
+                &options.audio_file_path,
+                &format_hint,
+                calibration_duration,
+                &detection_config,
+                min_confidence,
+            )?
+        }
+        None => None,
+    };
+    if let Some(calibration) = &calibration {
+        log::info!(
+            "Calibrated on {} words: {:.2} words/s, mean word confidence {:.2}",
+            calibration.num_words,
+            calibration.speech_rate,
+            calibration.mean_word_confidence
+        );
+        log::info!(
+            "Calibrated thresholds: min pause before \"chapter\" {:.3}s, reading phrase window {:.2}s{}",
+            calibration.detection_config.min_vocal_pause_before_chapter,
+            calibration.detection_config.reading_phrase_window,
+            match calibration.min_confidence {
+                Some(min_confidence) => format!(", min confidence {:.2}", min_confidence),
+                None => String::new(),
+            }
+        );
+        detection_config = calibration.detection_config.clone();
+        min_confidence = calibration.min_confidence;
+    }
+    let status_calibration = calibration.as_ref().map(|calibration| calibration.status());
+    let profile_config = detection_config.clone();
+    let keywords = Keywords::new(&detection_config);
+    let asr_keywords = keywords.clone();
+
+    let mut keyword_windows = if options.spot_keywords {
+        log::info!("Spotting chapter words");
+        let spotted = spot_keywords(&model, &options.audio_file_path, &format_hint, &keywords)?;
+        let keyword_windows = KeywordWindows::new(&spotted, sample_rate);
+        log::info!(
+            "Spotted {} chapter word(s), recognizing {} of audio in {} window(s) around them",
+            spotted.len(),
+            format_duration(&Some(keyword_windows.total_duration())),
+            keyword_windows.len()
+        );
+        Some(keyword_windows)
+    } else {
+        None
+    };
+
+    let ensemble_models = options
+        .ensemble_model_dir_paths
+        .iter()
+        .map(|model_dir_path| load_model(model_dir_path))
+        .collect::<Result<Vec<_>>>()?;
+    let mut ensemble = Ensemble::new(&ensemble_models, sample_rate as f32)?;
+
+    let mut retranscriber = if options.retranscribe_weak_candidates {
+        let mut retranscriber =
+            Recognizer::new(&model, sample_rate as f32).ok_or(ChapterizerError::Recognizer)?;
+        retranscriber.set_max_alternatives(RETRANSCRIBE_MAX_ALTERNATIVES);
+        retranscriber.set_words(true);
+        retranscriber.set_partial_words(true);
+        Some(retranscriber)
+    } else {
+        None
+    };
+
+    let mut audio_history = AudioHistory::new(
+        sample_rate,
+        !ensemble.is_empty()
+            || retranscriber.is_some()
+            || candidate_audio_dir_path.is_some()
+            || options.confirm_audio,
+    );
+    // The audio around recent candidates, to play when asking to confirm them
+    let confirm_clips = options.confirm_audio.then(candidate_clips);
+    let confirm_clips_clone = confirm_clips.clone();
+    let mut segmenter = Segmenter::new(
+        sample_rate,
+        options
+            .segment_duration
+            .map(|segment_duration| segment_duration.as_secs_f32()),
+    );
+
+    let mut speaker_change_detector = options
+        .detect_speaker_changes
+        .then(|| SpeakerChangeDetector::new(sample_rate as f32));
+
     let start_time = chrono::Local::now();
 
-    let (result_processor_tx, result_processor_rx) = channel::unbounded::<String>();
-    let mut matches_file = match &options.matches_file_path {
-        Some(matches_file_path) => {
-            Some(File::create(matches_file_path).wrap_err("Failed to create matches file")?)
-        }
+    let (result_processor_tx, result_processor_rx) = channel::unbounded::<RecognitionMessage>();
+    let mut matches_writer = match &options.matches_file_path {
+        Some(matches_file_path) => Some(MatchesWriter::new(
+            compression::create_file(matches_file_path)
+                .io_context("Failed to create matches file")?,
+        )),
         None => None,
     };
+    let mut transcript_file = options
+        .transcript_file_path
+        .as_ref()
+        .map(|transcript_file_path| {
+            compression::create_file(transcript_file_path)
+                .io_context("Failed to create transcript file")
+        })
+        .transpose()?;
     let audio_file_path = options.audio_file_path.clone();
+    let cue_disc_starts = options.cue_disc_starts.clone();
+    let retime = options.retime;
+    let cross_check = options.cross_check.clone();
+    let nest_chapter_parts = options.nest_chapter_parts;
+    let include_rejected = options.include_rejected;
+    let seek_hints = options.seek_hints;
+    let top_alternatives = options.top_alternatives;
+    let confirm_audio_file_path = options.audio_file_path.clone();
+    let ignore_regions = options.ignore_regions.clone();
+    let mut output_config = options.output_config.clone();
+    // Raw audio has no tags
+    if !output_config.ffmetadata.copy_tags.is_empty() && options.raw.is_none() {
+        match read_format_tags(&options.audio_file_path) {
+            Ok(tags) => output_config.ffmetadata = output_config.ffmetadata.with_copied_tags(&tags),
+            Err(err) => log::warn!(
+                "Failed to read tags to copy into ffmetadata: {}",
+                format_error_chain(&err)
+            ),
+        }
+    }
 
-    let cue_file = options
+    let cue_files = options
         .cue_file_path
         .as_ref()
-        .map(|cue_file_path| File::create(cue_file_path).wrap_err("Failed to create cue file"))
+        .map(|cue_file_path| {
+            if options.cue_disc_starts.is_empty() {
+                Ok(vec![
+                    File::create(cue_file_path).io_context("Failed to create cue file")?
+                ])
+            } else {
+                (1..=options.cue_disc_starts.len() + 1)
+                    .map(|disc_num| {
+                        File::create(disc_file_path(cue_file_path, disc_num))
+                            .io_context("Failed to create cue file")
+                    })
+                    .collect()
+            }
+        })
         .transpose()?;
     let ffmetadata_file = options
         .ffmetadata_file_path
         .as_ref()
         .map(|ffmetadata_file_path| {
-            File::create(ffmetadata_file_path).wrap_err("Failed to create ffmetadata file")
+            File::create(ffmetadata_file_path).io_context("Failed to create ffmetadata file")
+        })
+        .transpose()?;
+    let json_file = options
+        .json_file_path
+        .as_ref()
+        .map(|json_file_path| File::create(json_file_path).io_context("Failed to create json file"))
+        .transpose()?;
+    let split_script_file = options
+        .split_script_file_path
+        .as_ref()
+        .map(|split_script_file_path| {
+            File::create(split_script_file_path)
+                .io_context("Failed to create split script file")
+                .map(|file| (file, ScriptShell::from_path(split_script_file_path)))
+        })
+        .transpose()?;
+    let audiobookshelf_file = options
+        .audiobookshelf_file_path
+        .as_ref()
+        .map(|audiobookshelf_file_path| {
+            File::create(audiobookshelf_file_path)
+                .io_context("Failed to create Audiobookshelf metadata file")
+        })
+        .transpose()?;
+    let nfo_file = options
+        .nfo_file_path
+        .as_ref()
+        .map(|nfo_file_path| File::create(nfo_file_path).io_context("Failed to create nfo file"))
+        .transpose()?;
+    let matroska_file = options
+        .matroska_file_path
+        .as_ref()
+        .map(|matroska_file_path| {
+            File::create(matroska_file_path).io_context("Failed to create Matroska chapters file")
         })
         .transpose()?;
+    let vtt_file = options
+        .vtt_file_path
+        .as_ref()
+        .map(|vtt_file_path| File::create(vtt_file_path).io_context("Failed to create WebVTT file"))
+        .transpose()?;
+    let srt_file = options
+        .srt_file_path
+        .as_ref()
+        .map(|srt_file_path| File::create(srt_file_path).io_context("Failed to create SRT file"))
+        .transpose()?;
+
+    let embed = options.embed;
+
+    // The chapters written so far, as listed in the status file
+    let status_chapters = Arc::new(Mutex::new(Vec::new()));
+    let status_chapters_clone = status_chapters.clone();
 
     let total_samples_clone = total_samples.clone();
 
-    let result_processor_handle = thread::spawn(move || {
-        let mut write_json_to_matches_file = |json: &str| match &mut matches_file {
-            Some(matches_file) => {
-                log::trace!("Writing {} bytes to matches file", json.len());
-                matches_file
-                    .write_all((format!("{}\n", json)).as_bytes())
-                    .expect("Failed to write buffer to matches file");
-            }
-            None => {
-                log::trace!("No matches file specified, skipped writing");
-            }
-        };
+    // The lines of the matches file that potential matches were written to
+    let match_lines = Arc::new(Mutex::new(MatchLines::default()));
+    let match_lines_clone = match_lines.clone();
 
-        let (mut results_parser, parse_result_rx) = ResultsParser::new(POST_CHAPTER_CONTEXT);
+    // The confidences of the recognition results with candidates
+    let candidate_confidences = Arc::new(Mutex::new(CandidateConfidences::default()));
+    let candidate_confidences_clone = candidate_confidences.clone();
 
-        let parse_result_processor_handle = thread::spawn(move || {
+    // Disconnected once the results are processed, which is the last stage of the pipeline
+    let (pipeline_done_tx, pipeline_done_rx) = channel::bounded::<()>(0);
+    let result_processor_handle = thread::spawn(move || {
+        let _pipeline_done_tx = pipeline_done_tx;
+        let (mut results_parser, parse_result_rx) =
+            ResultsParser::new(POST_CHAPTER_CONTEXT, &detection_config);
+
+        let parse_result_processor_handle = thread::spawn(move || -> Result<ChapterList> {
             let mut chapter_writers = {
-                let mut chapter_writers: Vec<Box<dyn ChapterWriter>> = Vec::with_capacity(2);
+                let mut chapter_writers: Vec<Box<dyn ChapterWriter>> = Vec::with_capacity(3);
 
-                if let Some(cue_file) = cue_file {
-                    let mut cue_writer = CueWriter::new(Box::new(cue_file));
-                    cue_writer.write_header(&audio_file_path).unwrap();
-                    chapter_writers.push(Box::new(cue_writer));
+                if let Some(cue_files) = cue_files {
+                    if cue_disc_starts.is_empty() {
+                        let cue_file = cue_files.into_iter().next().unwrap();
+                        let mut cue_writer =
+                            CueWriter::new(OutputFile::new(cue_file), &output_config.cue);
+                        cue_writer.write_header(&audio_file_path).unwrap();
+                        chapter_writers.push(Box::new(cue_writer));
+                    } else {
+                        let cue_writers = cue_files.into_iter().map(OutputFile::new).collect();
+                        let disc_cue_writer = DiscCueWriter::new(
+                            cue_writers,
+                            &cue_disc_starts,
+                            &audio_file_path,
+                            &output_config.cue,
+                        )?;
+                        chapter_writers.push(Box::new(disc_cue_writer));
+                    }
                 }
 
                 if let Some(ffmetadata_file) = ffmetadata_file {
-                    let mut ffmetadata_writer = FfmetadataWriter::new(Box::new(ffmetadata_file));
+                    let mut ffmetadata_writer = FfmetadataWriter::new(
+                        OutputFile::new(ffmetadata_file),
+                        &output_config.ffmetadata,
+                    );
                     ffmetadata_writer.write_header().unwrap();
                     chapter_writers.push(Box::new(ffmetadata_writer));
                 }
 
+                if let Some(json_file) = json_file {
+                    chapter_writers.push(Box::new(JsonWriter::new(
+                        OutputFile::new(json_file),
+                        &output_config.json,
+                    )));
+                }
+
+                if let Some((split_script_file, shell)) = split_script_file {
+                    chapter_writers.push(Box::new(SplitScriptWriter::new(
+                        OutputFile::new(split_script_file),
+                        shell,
+                        &audio_file_path,
+                        &output_config.split_script,
+                    )));
+                }
+
+                if let Some(audiobookshelf_file) = audiobookshelf_file {
+                    let mut audiobookshelf_writer =
+                        AudiobookshelfWriter::new(OutputFile::new(audiobookshelf_file));
+                    if let Some(total_duration) = total_duration {
+                        audiobookshelf_writer =
+                            audiobookshelf_writer.with_duration(retime.duration(total_duration));
+                    }
+                    chapter_writers.push(Box::new(audiobookshelf_writer));
+                }
+
+                if let Some(nfo_file) = nfo_file {
+                    chapter_writers.push(Box::new(NfoWriter::new(OutputFile::new(nfo_file))));
+                }
+
+                if let Some(matroska_file) = matroska_file {
+                    chapter_writers.push(Box::new(MatroskaWriter::new(OutputFile::new(
+                        matroska_file,
+                    ))));
+                }
+
+                if let Some(vtt_file) = vtt_file {
+                    chapter_writers.push(Box::new(VttWriter::new(OutputFile::new(vtt_file))));
+                }
+
+                if let Some(srt_file) = srt_file {
+                    chapter_writers.push(Box::new(SrtWriter::new(OutputFile::new(srt_file))));
+                }
+
+                if let Some(embed) = &embed {
+                    chapter_writers.push(embedded_chapter_writer(&audio_file_path, embed));
+                }
+
                 chapter_writers
             };
 
-            if chapter_writers.is_empty() {
-                unreachable!(
-                    "No chapter writers specified, cli args validation should have caught this"
-                );
-            }
+            let mut written_chapters = Vec::new();
+            let mut retimer = ChapterRetimer::new(retime);
+            let mut write_chapter = |chapter_writers: &mut Vec<Box<dyn ChapterWriter>>,
+                                     chapter: Chapter,
+                                     end_of_file: Option<Duration>|
+             -> Result<()> {
+                let chapter = cross_check.chapter(&chapter);
+                let mut output_chapters = output_config.finalize_chapter(&mut retimer, &chapter);
+                if let Some(duration) = end_of_file {
+                    output_chapters
+                        .extend(output_config.finalize_last_chapter(&mut retimer, duration));
+                }
+                for output_chapter in output_chapters {
+                    // Flush after each chapter, so that the outputs hold everything processed so
+                    // far if the run is cut off
+                    for chapter_writer in chapter_writers.iter_mut() {
+                        chapter_writer.on_chapter_start(&output_chapter)?;
+                        chapter_writer.flush()?;
+                    }
+                    status_chapters_clone.lock().unwrap().push(StatusChapter {
+                        start: output_chapter.start,
+                        title: output_chapter.flat_title().into_owned(),
+                    });
+                }
+                written_chapters.push(chapter);
+                Ok(())
+            };
 
-            for chapter_writer in chapter_writers.iter_mut() {
-                chapter_writer
-                    .on_chapter_start(Duration::ZERO, "Chapter 00")
-                    .unwrap();
-            }
+            // Audio output can't be moved between threads, so it's opened here
+            let confirmer = confirm_clips_clone
+                .map(|clips| Confirmer::new(clips, confirm_audio_file_path, sample_rate));
 
-            while let Ok(parse_result) = parse_result_rx.recv() {
-                // TODO: filter out duplicate chapters
-                let parsed_chapter = match parse_result {
-                    ParseResult::Match(parsed_chapter) => parsed_chapter,
-                    ParseResult::Failure => continue,
-                    ParseResult::Incomplete => {
-                        unreachable!("Incomplete results should never be sent")
+            let mut assembler = ChapterAssembler::new(nest_chapter_parts);
+            while let Ok(mut parse_result) = parse_result_rx.recv() {
+                if let ParseResult::Match(tokens) | ParseResult::EndMatch(tokens) = &parse_result {
+                    if ignore_regions
+                        .iter()
+                        .any(|region| region.contains(tokens[0].start))
+                    {
+                        parse_result = ParseResult::Failure(Some(RejectedCandidate {
+                            start: tokens[0].start.to_duration(),
+                            text: announcement_text(tokens),
+                            reason: "in an ignored region",
+                        }));
+                    }
+                }
+                let confidence = match &parse_result {
+                    ParseResult::Match(tokens) | ParseResult::EndMatch(tokens) => {
+                        candidate_confidences_clone
+                            .lock()
+                            .unwrap()
+                            .find(tokens[0].start)
                     }
+                    _ => None,
                 };
-
-                let chapter_title = parsed_chapter.iter().map(|w| w.word.to_string()).join(" ");
-                let chapter_start_duration =
-                    Duration::from_secs_f32(parsed_chapter.get(0).unwrap().start);
-
-                log::info!(
-                    "Found chapter: {} at {}",
-                    chapter_title,
-                    format_duration(&Some(chapter_start_duration))
-                );
-
-                for chapter_writer in chapter_writers.iter_mut() {
-                    chapter_writer
-                        .on_chapter_start(
-                            chapter_start_duration.saturating_sub(PRE_CHAPTER_START_MARGIN),
-                            &format!(
-                                "Chapter {:02}",
-                                parsed_chapter.get(1).unwrap().word.parse::<f32>().unwrap()
-                            ),
-                        )
-                        .unwrap();
+                if let (
+                    Some(min_confidence),
+                    ParseResult::Match(tokens) | ParseResult::EndMatch(tokens),
+                ) = (min_confidence, &parse_result)
+                {
+                    if let Some(confidence) = confidence.filter(|&c| c < min_confidence) {
+                        log::debug!(
+                            "Candidate at {:.3}s has confidence {:.2}, below {:.2}",
+                            tokens[0].start.as_secs(),
+                            confidence,
+                            min_confidence
+                        );
+                        parse_result = ParseResult::Failure(Some(RejectedCandidate {
+                            start: tokens[0].start.to_duration(),
+                            text: announcement_text(tokens),
+                            reason: "confidence too low",
+                        }));
+                    }
+                }
+                if let (Some(confirmer), ParseResult::Match(tokens)) = (&confirmer, &parse_result) {
+                    let announcement = announcement_text(tokens);
+                    if !confirmer.confirm(&announcement, tokens[0].start) {
+                        parse_result = ParseResult::Failure(Some(RejectedCandidate {
+                            start: tokens[0].start.to_duration(),
+                            text: announcement,
+                            reason: "not confirmed",
+                        }));
+                    }
+                }
+                let start = parse_result.start();
+                let matches_line =
+                    start.and_then(|start| match_lines_clone.lock().unwrap().find(start));
+                if let ParseResult::Failure(Some(candidate)) = &parse_result {
+                    log::debug!(
+                        "Rejected candidate \"{}\" at {}: {}",
+                        candidate.text,
+                        describe_location(candidate.start, matches_line),
+                        candidate.reason
+                    );
+                    if seek_hints {
+                        log::debug!("  {}", seek_hint(&audio_file_path, candidate.start));
+                    }
+                    if let Some(candidate) = include_rejected
+                        .then(|| retime.rejected_candidate(candidate))
+                        .flatten()
+                    {
+                        for chapter_writer in chapter_writers.iter_mut() {
+                            chapter_writer.on_rejected_candidate(&candidate)?;
+                        }
+                    }
+                }
+                let is_match = matches!(parse_result, ParseResult::Match(_));
+                if let Some(chapter) = assembler.push(parse_result, matches_line, confidence) {
+                    write_chapter(&mut chapter_writers, chapter, None)?;
+                }
+                if let (true, true, Some(start)) = (seek_hints, is_match, start) {
+                    log::info!("  {}", seek_hint(&audio_file_path, start.to_duration()));
                 }
             }
 
             // Since the duration in the file's metadata may be missing or inaccurate, we'll
             // calculate the total file duration based on the number of samples processed.
-            let processed_duration = Duration::from_secs_f32(calc_progress_in_secs(
-                total_samples_clone.load(Ordering::SeqCst),
-            ));
+            let processed_duration =
+                samples_to_duration(total_samples_clone.load(Ordering::Relaxed));
+
+            write_chapter(
+                &mut chapter_writers,
+                assembler.finish(processed_duration),
+                Some(processed_duration),
+            )?;
 
             // TODO: don't call this if parse_result_rx was closed due to CTRL+C
+            let retimed_duration = retime.duration(processed_duration);
             for chapter_writer in chapter_writers.iter_mut() {
-                chapter_writer.on_end_of_file(processed_duration).unwrap();
+                chapter_writer.on_end_of_file(retimed_duration)?;
+                chapter_writer.flush()?;
             }
-        });
 
-        let mut result_index = 0u64;
-        let mut previous_results: FixedVecDeque<String> =
-            FixedVecDeque::with_max_len(WRITE_POT_MATCH_CONTEXT);
-        let mut last_potential_match_index: Option<u64> = None;
+            Ok(ChapterList {
+                chapters: written_chapters,
+                duration: processed_duration,
+            })
+        });
 
-        let mut last_token: Option<Token> = None;
-        while let Ok(msg) = result_processor_rx.recv() {
+        let mut last_tokens: FixedVecDeque<Token> =
+            FixedVecDeque::with_max_len(PRE_CHAPTER_CONTEXT);
+        while let Ok(RecognitionMessage {
+            result_json: msg,
+            retranscript,
+            ensemble_transcripts,
+            candidate_audio,
+        }) = result_processor_rx.recv()
+        {
             let multi: CompleteResultMultiple = serde_json::from_str(&msg).unwrap();
 
-            if multi.alternatives.iter().any(alt_contains_potential_match) {
-                // Write previous N results as context
-                for prev_result in previous_results.iter().take(WRITE_POT_MATCH_CONTEXT) {
-                    write_json_to_matches_file(prev_result);
+            let mut matches_line = None;
+            let mut confidence = None;
+            if multi
+                .alternatives
+                .iter()
+                .any(|alt| alt_contains_potential_match(alt, &keywords))
+            {
+                confidence = Some(candidate_confidence(
+                    &multi,
+                    retranscript.as_ref(),
+                    ensemble_transcripts.as_deref(),
+                    &keywords,
+                ));
+                // Write potential match result, along with its confidence and a reference to its
+                // audio, and the results around it as context
+                if let Some(matches_writer) = &mut matches_writer {
+                    let mut result: serde_json::Map<String, serde_json::Value> =
+                        serde_json::from_str(&msg).unwrap();
+                    result.insert("confidence".into(), confidence.into());
+                    if let Some(candidate_audio) = candidate_audio {
+                        result.insert("audio".into(), candidate_audio.into());
+                    }
+                    let runner_ups =
+                        runner_up_alts(&multi.alternatives, &keywords, top_alternatives.into());
+                    if !runner_ups.is_empty() {
+                        result.insert(
+                            "runner_ups".into(),
+                            runner_ups
+                                .iter()
+                                .map(|runner_up| {
+                                    serde_json::json!({
+                                        "alternative": runner_up.index,
+                                        "text": runner_up.alt.text,
+                                        "score": runner_up.score.into_inner(),
+                                    })
+                                })
+                                .collect(),
+                        );
+                    }
+                    matches_line = Some(
+                        matches_writer
+                            .push_candidate(&serde_json::to_string(&result).unwrap())
+                            .expect("Failed to write to matches file"),
+                    );
                 }
-                // Write potential match result
-                write_json_to_matches_file(&msg);
+            } else if let Some(matches_writer) = &mut matches_writer {
+                matches_writer
+                    .push_result(&msg)
+                    .expect("Failed to write to matches file");
+            }
 
-                last_potential_match_index.replace(result_index);
-            } else if let Some(lpmi) = last_potential_match_index {
-                // Write next N results following a potential match as context
-                if (result_index - lpmi) <= WRITE_POT_MATCH_CONTEXT as u64 {
-                    write_json_to_matches_file(&msg);
+            let main_transcript = retranscript.unwrap_or_else(|| {
+                Transcript::from_alt(
+                    get_best_alt(&multi.alternatives, &keywords),
+                    AudioTimestamp::ZERO,
+                )
+            });
+            let transcript = match ensemble_transcripts {
+                Some(ensemble_transcripts) => {
+                    vote(main_transcript, ensemble_transcripts, &keywords)
                 }
+                None => main_transcript,
+            };
+            if let (Some(transcript_file), Some(first_token)) =
+                (&mut transcript_file, transcript.tokens.first())
+            {
+                let line = format!(
+                    "{}\t{}\n",
+                    format_duration(&Some(first_token.start.to_duration())),
+                    transcript
+                        .tokens
+                        .iter()
+                        .map(|token| token.word.as_str())
+                        .collect::<Vec<_>>()
+                        .join(" ")
+                );
+                transcript_file
+                    .write_all(line.as_bytes())
+                    .expect("Failed to write to transcript file");
             }
-
-            results_parser.ingest_results(&mut last_token, &multi);
-
-            previous_results.push_back(msg);
-            result_index += 1;
+            if let Some(confidence) = confidence {
+                candidate_confidences
+                    .lock()
+                    .unwrap()
+                    .push(&transcript.tokens, confidence);
+            }
+            if let Some(matches_line) = matches_line {
+                match_lines
+                    .lock()
+                    .unwrap()
+                    .push(&transcript.tokens, matches_line);
+            }
+            results_parser.ingest_tokens(&mut last_tokens, transcript.tokens);
+        }
+        if let Some(matches_writer) = matches_writer {
+            matches_writer
+                .finish()
+                .expect("Failed to write to matches file");
         }
 
         results_parser.flush();
-        parse_result_processor_handle.join().unwrap();
+        parse_result_processor_handle.join().unwrap()
     });
 
-    assert!(ETA_CALC_WINDOW > 0);
+    let bytes_read = ap.bytes_read_counter();
+    let file_size = std::fs::metadata(&options.audio_file_path)
+        .map(|metadata| metadata.len())
+        .ok();
+
+    const { assert!(ETA_CALC_WINDOW > 0) };
     let (progress_reporter_stop_tx, progress_reporter_stop_rx) = channel::unbounded::<()>();
     let total_samples_clone = total_samples.clone();
+    let status_file_path = options.status_file_path.clone();
+    let status_audio_file_path = options.audio_file_path.clone();
+    let status_chapters_clone = status_chapters.clone();
+    let status_calibration_clone = status_calibration.clone();
     let progress_reporter_handle = thread::spawn(move || {
         let mut speed_factors: FixedVecDeque<f32> = FixedVecDeque::with_max_len(ETA_CALC_WINDOW);
         let mut last_time = chrono::Local::now();
@@ -254,25 +1113,31 @@ pub fn chapterize(options: &ChapterizeOptions) -> Result<(), eyre::Error> {
             // Wait at most PROGRESS_INTERVAL for a stop message
             match progress_reporter_stop_rx.recv_timeout(PROGRESS_INTERVAL) {
                 Ok(_) => break,
-                Err(err) if matches!(err, channel::RecvTimeoutError::Disconnected) => break,
+                Err(channel::RecvTimeoutError::Disconnected) => break,
                 _ => (),
             }
             let current_time = chrono::Local::now();
-            let current_samples = total_samples_clone.load(Ordering::SeqCst);
+            let current_samples = total_samples_clone.load(Ordering::Relaxed);
 
             let time_delta = (current_time - last_time).to_std().unwrap();
-            let processed_duration =
-                Duration::from_secs_f32(calc_progress_in_secs(current_samples));
-            let processed_duration_delta =
-                Duration::from_secs_f32(calc_progress_in_secs(current_samples - last_samples));
+            let processed_duration = samples_to_duration(current_samples);
+            let processed_duration_delta = samples_to_duration(current_samples - last_samples);
+
+            // If the duration is unknown, estimate it so that progress and ETA can be reported.
+            // Only actual estimates are marked, values that can't be estimated are shown as "??".
+            let (total_duration, estimate_marker) = match total_duration {
+                Some(total_duration) => (Some(total_duration), ""),
+                None => {
+                    let estimate = estimate_total_duration(
+                        processed_duration,
+                        bytes_read.load(Ordering::Relaxed),
+                        file_size,
+                    );
+                    (estimate, if estimate.is_some() { "~" } else { "" })
+                }
+            };
             let progress_percent = total_duration.map(|td| {
-                f32::min(
-                    f32::max(
-                        processed_duration.as_secs_f32() / td.as_secs_f32() * 100.0,
-                        0.0,
-                    ),
-                    100.0,
-                )
+                (processed_duration.as_secs_f32() / td.as_secs_f32() * 100.0).clamp(0.0, 100.0)
             });
             let speed_factor = processed_duration_delta.as_secs_f32() / time_delta.as_secs_f32();
             speed_factors.push_back(speed_factor);
@@ -295,72 +1160,365 @@ pub fn chapterize(options: &ChapterizeOptions) -> Result<(), eyre::Error> {
             };
 
             log::info!(
-                "Progress: {} @ {} of {}\tSpeed: {:.2}x\tTime left: {}\tETA: {})",
+                "Progress: {} @ {} of {}\tSpeed: {:.2}x\tTime left: {}\tETA: {}){}",
                 match progress_percent {
-                    Some(pct) => format!("{:05.2}%", pct),
+                    Some(pct) => format!("{}{:05.2}%", estimate_marker, pct),
                     None => "??%".into(),
                 },
                 format_duration(&Some(processed_duration)),
-                format_duration(&total_duration),
+                format!("{}{}", estimate_marker, format_duration(&total_duration)),
                 speed_factor,
-                format_duration(&remaining_wall_time),
+                format!(
+                    "{}{}",
+                    estimate_marker,
+                    format_duration(&remaining_wall_time)
+                ),
                 match eta {
-                    Some(eta) => eta.format("%a %e %b %Y %T").to_string(),
+                    Some(eta) => format!("{}{}", estimate_marker, eta.format("%a %e %b %Y %T")),
                     None => "??".into(),
+                },
+                if estimate_marker.is_empty() {
+                    ""
+                } else {
+                    "\t(duration estimated from file size)"
                 }
             );
 
+            if let Some(status_file_path) = &status_file_path {
+                let status = ProgressStatus {
+                    audio_file: status_audio_file_path.clone(),
+                    state: RunState::Running,
+                    updated_at: current_time.to_rfc3339(),
+                    position: processed_duration,
+                    total_duration,
+                    duration_is_estimate: !estimate_marker.is_empty(),
+                    percent: progress_percent,
+                    speed: avg_speed_factor,
+                    eta: eta.map(|eta| eta.to_rfc3339()),
+                    chapters: status_chapters_clone.lock().unwrap().clone(),
+                    calibration: status_calibration_clone.clone(),
+                };
+                if let Err(err) = write_status(status_file_path, &status) {
+                    log::warn!("Failed to write status: {}", format_error_chain(&err));
+                }
+            }
+
             last_time = current_time;
             last_samples = current_samples;
         }
     });
 
+    // Decode in a separate thread, so that the next buffer of samples is decoded while the
+    // recognizer consumes the previous one. Buffers are returned to the pool once consumed.
+    let buffer_pool = BufferPool::new(DECODE_BUFFERS + 1, chunk_len);
+    let (filled_buffer_tx, filled_buffer_rx) = channel::bounded::<Vec<i16>>(DECODE_BUFFERS);
+    let decoder_buffer_pool = buffer_pool.clone();
+    let deadline = options
+        .max_runtime
+        .map(|max_runtime| Instant::now() + max_runtime);
+    // Returns whether the deadline was reached. Stopping the decoder stops the recognizer once it
+    // has consumed what was decoded, so that the outputs are still finished.
+    let decoder_handle = thread::spawn(move || loop {
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            return true;
+        }
+        let mut buffer = decoder_buffer_pool.take();
+        if ap.fill_buffer(&mut buffer, chunk_len) == 0 || filled_buffer_tx.send(buffer).is_err() {
+            return false;
+        }
+    });
+
     let total_samples_clone = total_samples.clone();
     let asr_handle = thread::spawn(move || {
-        let process_result = |result: CompleteResult| {
-            let multi = result.multiple().unwrap();
+        let process_result = |result: CompleteResult,
+                              bounds: SegmentBounds,
+                              audio_history: &AudioHistory,
+                              ensemble: &mut Ensemble,
+                              retranscriber: &mut Option<Recognizer>| {
+            let mut multi = result.multiple().unwrap();
+            bounds.apply(&mut multi);
+
+            let (mut retranscript, mut ensemble_transcripts, mut candidate_audio) =
+                (None, None, None);
+            if multi
+                .alternatives
+                .iter()
+                .any(|alt| alt_contains_potential_match(alt, &asr_keywords))
+            {
+                let words = multi.alternatives.iter().flat_map(|alt| alt.result.iter());
+                let start = AudioTimestamp::from_secs(
+                    words.clone().map(|wia| wia.start).fold(f32::MAX, f32::min),
+                );
+                let end = AudioTimestamp::from_secs(words.map(|wia| wia.end).fold(0.0, f32::max));
+                let window = audio_history.window(start, end);
+
+                // If the chapter number wasn't recognized, try again with more alternatives
+                if let Some(retranscriber) = retranscriber {
+                    let best_alt = get_best_alt(&multi.alternatives, &asr_keywords);
+                    if !contains_chapter_number(
+                        &Transcript::from_alt(best_alt, AudioTimestamp::ZERO).tokens,
+                        &asr_keywords,
+                    ) {
+                        let transcript = window.transcribe(retranscriber, &asr_keywords);
+                        let is_improved =
+                            contains_chapter_number(&transcript.tokens, &asr_keywords);
+                        log::debug!(
+                            "Transcribed weak candidate at {:.2}s again, {}",
+                            start.as_secs(),
+                            if is_improved {
+                                "found chapter number"
+                            } else {
+                                "still no chapter number"
+                            }
+                        );
+                        retranscript = is_improved.then_some(transcript);
+                    }
+                }
+
+                // Have the ensemble transcribe the window of audio again
+                if !ensemble.is_empty() {
+                    ensemble_transcripts = Some(ensemble.transcribe_window(&window, &asr_keywords));
+                }
+
+                if let Some(confirm_clips) = &confirm_clips {
+                    confirm_clips
+                        .lock()
+                        .unwrap()
+                        .push_back(audio_history.window_with_margin(
+                            start,
+                            end,
+                            CANDIDATE_AUDIO_MARGIN_SECS,
+                        ));
+                }
+
+                if let Some(dir_path) = &candidate_audio_dir_path {
+                    let clip =
+                        audio_history.window_with_margin(start, end, CANDIDATE_AUDIO_MARGIN_SECS);
+                    let file_name = format!("{:09}.wav", (clip.offset.as_secs() * 1000.0) as u64);
+                    match clip.write_wav(&dir_path.join(&file_name), sample_rate) {
+                        Ok(()) => {
+                            let dir_name = dir_path.file_name().unwrap_or_default();
+                            candidate_audio =
+                                Some(format!("{}/{}", dir_name.to_string_lossy(), file_name));
+                        }
+                        Err(err) => log::warn!(
+                            "Failed to write audio of candidate at {:.2}s: {}",
+                            start.as_secs(),
+                            format_error_chain(&err)
+                        ),
+                    }
+                }
+            }
+
             // The prediction result contains borrowed data which depends on the recognizer.
             // We serialize the data before passing it between threads to work around this.
             let msg = serde_json::to_string(&multi).unwrap();
-            result_processor_tx.send(msg).unwrap();
+            result_processor_tx
+                .send(RecognitionMessage {
+                    result_json: msg,
+                    retranscript,
+                    ensemble_transcripts,
+                    candidate_audio,
+                })
+                .unwrap();
         };
 
-        let mut buffer: ArrayVec<i16, SAMPLES_BUFFER_SIZE> = ArrayVec::new();
-        // TODO: is there a faster way to keep reading the samples into a buffer?
-        for chunk in ap.into_iter().chunks(SAMPLES_BUFFER_SIZE).into_iter() {
-            let mut chunk_size = 0usize;
-            for sample in chunk {
-                buffer.push(sample);
-                chunk_size += 1;
+        // The bounds of the results of the window of audio around spotted keywords being
+        // recognized, if spotting keywords
+        let mut window_bounds = segmenter.bounds();
+        for buffer in filled_buffer_rx {
+            // Only this thread writes the counter, and readers only need an approximate value
+            total_samples_clone.fetch_add(buffer.len() as u64, Ordering::Relaxed);
+
+            audio_history.push_samples(&buffer);
+            segmenter.push_samples(&buffer);
+            if let Some(detector) = &mut speaker_change_detector {
+                detector.push_samples(&buffer);
+            }
+
+            if let Some(keyword_windows) = &mut keyword_windows {
+                for event in keyword_windows.push_samples(&buffer) {
+                    match event {
+                        WindowEvent::Start(bounds) => {
+                            recognizer.reset();
+                            window_bounds = bounds;
+                        }
+                        WindowEvent::Samples(samples) => {
+                            if let vosk::DecodingState::Finalized =
+                                recognizer.accept_waveform(samples)
+                            {
+                                process_result(
+                                    recognizer.result(),
+                                    window_bounds,
+                                    &audio_history,
+                                    &mut ensemble,
+                                    &mut retranscriber,
+                                );
+                            }
+                        }
+                        WindowEvent::End => process_result(
+                            recognizer.final_result(),
+                            window_bounds,
+                            &audio_history,
+                            &mut ensemble,
+                            &mut retranscriber,
+                        ),
+                    }
+                }
+                buffer_pool.put(buffer);
+                continue;
             }
-            total_samples_clone.store(
-                total_samples_clone.load(Ordering::SeqCst) + chunk_size as u64,
-                Ordering::SeqCst,
-            );
 
             if let vosk::DecodingState::Finalized = recognizer.accept_waveform(&buffer) {
-                process_result(recognizer.result());
+                process_result(
+                    recognizer.result(),
+                    segmenter.bounds(),
+                    &audio_history,
+                    &mut ensemble,
+                    &mut retranscriber,
+                );
             }
 
-            buffer.clear();
+            if segmenter.is_segment_full() {
+                let (ended_bounds, overlap) = segmenter.start_next_segment();
+                process_result(
+                    recognizer.final_result(),
+                    ended_bounds,
+                    &audio_history,
+                    &mut ensemble,
+                    &mut retranscriber,
+                );
+                recognizer.reset();
+                if let vosk::DecodingState::Finalized = recognizer.accept_waveform(&overlap) {
+                    process_result(
+                        recognizer.result(),
+                        segmenter.bounds(),
+                        &audio_history,
+                        &mut ensemble,
+                        &mut retranscriber,
+                    );
+                }
+            }
+
+            buffer_pool.put(buffer);
+        }
+        match &keyword_windows {
+            // The audio ended in a window
+            Some(keyword_windows) if keyword_windows.is_in_window() => process_result(
+                recognizer.final_result(),
+                window_bounds,
+                &audio_history,
+                &mut ensemble,
+                &mut retranscriber,
+            ),
+            Some(_) => {}
+            None => process_result(
+                recognizer.final_result(),
+                segmenter.bounds(),
+                &audio_history,
+                &mut ensemble,
+                &mut retranscriber,
+            ),
         }
-        process_result(recognizer.final_result());
         progress_reporter_stop_tx.send(()).unwrap();
+        speaker_change_detector.map(SpeakerChangeDetector::finish)
     });
 
-    asr_handle.join().unwrap();
-    result_processor_handle.join().unwrap();
+    // The decoder only checks the deadline between buffers, so if it or the recognizer hangs
+    // within a buffer, the pipeline is abandoned after a grace period instead of waited for. Its
+    // threads are left running, and the outputs only hold the chapters written so far.
+    if let Some(deadline) = deadline {
+        let abandon_at = deadline + MAX_RUNTIME_GRACE_PERIOD;
+        if let Err(channel::RecvTimeoutError::Timeout) = pipeline_done_rx.recv_deadline(abandon_at)
+        {
+            log::error!(
+                "Chapterizing didn't stop within {} seconds of the maximum runtime, abandoning it",
+                MAX_RUNTIME_GRACE_PERIOD.as_secs()
+            );
+            return Err(ChapterizerError::Timeout {
+                max_runtime: options.max_runtime.unwrap_or_default(),
+                position: samples_to_duration(total_samples.load(Ordering::Relaxed)),
+            });
+        }
+    }
+
+    // The decoder is done once the recognizer is, since the recognizer stops when it stops
+    let speaker_changes = asr_handle.join().unwrap();
+    let timed_out = decoder_handle.join().unwrap();
+    let chapter_list = result_processor_handle.join().unwrap();
     progress_reporter_handle.join().unwrap();
+    let chapter_list = chapter_list?;
+
+    for change in speaker_changes.unwrap_or_default() {
+        let has_nearby_chapter = chapter_list
+            .chapters
+            .iter()
+            .any(|chapter| chapter.start.abs_diff(change) <= SPEAKER_CHANGE_TOLERANCE);
+        if !has_nearby_chapter {
+            log::warn!(
+                "The narrator seems to change at {} without a chapter nearby. A chapter may have \
+                been missed.",
+                format_duration(&Some(change))
+            );
+        }
+    }
 
     let end_time = chrono::Local::now();
-    let secs_processed = calc_progress_in_secs(total_samples.load(Ordering::SeqCst));
+    let samples_processed = total_samples.load(Ordering::Relaxed);
+    let secs_processed = samples_to_duration(samples_processed).as_secs_f32();
     let time_elasped = (end_time - start_time).to_std().unwrap();
+
+    if let Some(status_file_path) = &options.status_file_path {
+        let status = ProgressStatus {
+            audio_file: options.audio_file_path.clone(),
+            state: if timed_out {
+                RunState::Aborted
+            } else {
+                RunState::Finished
+            },
+            updated_at: end_time.to_rfc3339(),
+            position: chapter_list.duration,
+            total_duration: if timed_out {
+                total_duration
+            } else {
+                Some(chapter_list.duration)
+            },
+            duration_is_estimate: false,
+            percent: match total_duration {
+                Some(total_duration) if timed_out => {
+                    Some(chapter_list.duration.as_secs_f32() / total_duration.as_secs_f32() * 100.0)
+                }
+                Some(_) => Some(100.0),
+                None => None,
+            },
+            speed: secs_processed / time_elasped.as_secs_f32(),
+            eta: None,
+            chapters: status_chapters.lock().unwrap().clone(),
+            calibration: status_calibration,
+        };
+        if let Err(err) = write_status(status_file_path, &status) {
+            log::warn!("Failed to write status: {}", format_error_chain(&err));
+        }
+    }
     log::info!(
-        "Processed {:.2} seconds of audio in {:.2} seconds ({:.2}x RT)",
+        "Processed {:.2} seconds of audio in {:.2} seconds ({:.2}x RT, {:.0} samples/s)",
         secs_processed,
         time_elasped.as_secs_f32(),
-        secs_processed / time_elasped.as_secs_f32()
+        secs_processed / time_elasped.as_secs_f32(),
+        samples_processed as f64 / time_elasped.as_secs_f64()
     );
 
-    Ok(())
+    if let (Some(save_profile_path), false) = (&options.save_profile_path, timed_out) {
+        profile_config.save(save_profile_path)?;
+        log::info!("Saved profile to {}", save_profile_path.display());
+    }
+
+    if timed_out {
+        return Err(ChapterizerError::Timeout {
+            max_runtime: options.max_runtime.unwrap_or_default(),
+            position: chapter_list.duration,
+        });
+    }
+
+    Ok(chapter_list)
 }
@@ -0,0 +1,192 @@
+use std::{
+    fs::{self, File},
+    io,
+    path::{Path, PathBuf},
+    process,
+    time::UNIX_EPOCH,
+};
+
+use vosk::Model;
+
+use crate::{
+    error::{ChapterizerError, IoResultExt, Result},
+    user_cache_dir,
+};
+
+/// The file that records which archive a cached model was extracted from. It's written last, so
+/// that a cut off extraction isn't mistaken for a complete one.
+const SOURCE_FILE_NAME: &str = ".source";
+
+/// Returns the directory that models distributed as zip archives are extracted to by default, i.e.
+/// "audiobook-chapterizer/models" in the user's cache directory, if it can be found.
+pub fn default_model_cache_dir() -> Option<PathBuf> {
+    Some(user_cache_dir()?.join("models"))
+}
+
+/// Loads the Vosk model at the path, which is either a model directory or a zip archive of one,
+/// as models are distributed. Archives are extracted to the model cache directory the first time
+/// they're used, and again if they change.
+pub fn load_model(path: &Path) -> Result<Model> {
+    ensure_vosk_loaded()?;
+    let model_dir_path = if is_zip(path) {
+        extract_model(path)?
+    } else {
+        path.to_path_buf()
+    };
+    Model::new(model_dir_path.to_string_lossy()).ok_or_else(|| ChapterizerError::ModelLoad {
+        path: path.to_path_buf(),
+    })
+}
+
+/// Returns an error if the native Vosk library can't be loaded. With the runtime-vosk feature, it's
+/// loaded here the first time it's needed.
+#[cfg(feature = "runtime-vosk")]
+fn ensure_vosk_loaded() -> Result<()> {
+    super::vosk_library::load()
+}
+
+/// Returns an error if the native Vosk library can't be loaded. On Windows with MSVC, the DLL is
+/// only loaded once it's first used (see build.rs), which would otherwise abort the run.
+#[cfg(all(
+    windows,
+    target_env = "msvc",
+    not(any(feature = "static-vosk", feature = "runtime-vosk"))
+))]
+fn ensure_vosk_loaded() -> Result<()> {
+    const DLL_NAME: &str = "libvosk.dll";
+    extern "system" {
+        // From delayimp, returns a negative HRESULT if the DLL or any of its imports is missing
+        fn __HrLoadAllImportsForDll(dll_name: *const std::ffi::c_char) -> i32;
+    }
+    // SAFETY: the name is a nul-terminated string
+    let hresult = unsafe { __HrLoadAllImportsForDll(c"libvosk.dll".as_ptr()) };
+    if hresult < 0 {
+        return Err(ChapterizerError::VoskLibrary(DLL_NAME));
+    }
+    Ok(())
+}
+
+/// Elsewhere the library is loaded along with the executable, or linked into it, and the dynamic
+/// loader refuses to start the executable without it, naming the missing library.
+#[cfg(not(any(
+    feature = "runtime-vosk",
+    all(windows, target_env = "msvc", not(feature = "static-vosk"))
+)))]
+fn ensure_vosk_loaded() -> Result<()> {
+    Ok(())
+}
+
+fn is_zip(path: &Path) -> bool {
+    path.is_file()
+        && path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("zip"))
+}
+
+/// Identifies the archive by its path, size and modification time, so that an archive that's
+/// replaced by a newer version of the model is extracted again.
+fn archive_source(archive_path: &Path) -> Result<String> {
+    let metadata = fs::metadata(archive_path).io_context("Failed to read model archive")?;
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map(|modified| modified.as_secs())
+        .unwrap_or_default();
+    let archive_path = fs::canonicalize(archive_path).unwrap_or(archive_path.to_path_buf());
+    Ok(format!(
+        "{}\n{}\n{}\n",
+        archive_path.display(),
+        metadata.len(),
+        modified
+    ))
+}
+
+/// Extracts the model archive to the model cache directory, unless it already was, and returns
+/// the directory of the model.
+fn extract_model(archive_path: &Path) -> Result<PathBuf> {
+    let cache_dir = default_model_cache_dir().ok_or(ChapterizerError::InvalidOptions(
+        "no cache directory to extract the model to",
+    ))?;
+    let name = archive_path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "model".into());
+    let extract_dir = cache_dir.join(&name);
+    let source = archive_source(archive_path)?;
+
+    if is_extracted_from(&extract_dir, &source) {
+        log::debug!("Using the model extracted to {}", extract_dir.display());
+        return Ok(model_root(&extract_dir));
+    }
+
+    log::info!(
+        "Extracting {} to {}",
+        archive_path.display(),
+        extract_dir.display()
+    );
+    // Each run extracts to a directory of its own, which is then moved into place, so that runs
+    // extracting the same archive at the same time don't extract over each other
+    let temp_dir = cache_dir.join(format!(".{}.{}.tmp", name, process::id()));
+    let result = extract_archive(archive_path, &temp_dir, &source).and_then(|()| {
+        if is_extracted_from(&extract_dir, &source) {
+            log::debug!("Another run extracted the model first, using its extraction");
+            return Ok(());
+        }
+        if let Err(err) = fs::remove_dir_all(&extract_dir) {
+            if err.kind() != io::ErrorKind::NotFound {
+                return Err(err).io_context("Failed to remove outdated extracted model");
+            }
+        }
+        match fs::rename(&temp_dir, &extract_dir) {
+            Err(_) if is_extracted_from(&extract_dir, &source) => Ok(()),
+            result => result.io_context("Failed to move the extracted model into place"),
+        }
+    });
+    if temp_dir.exists() {
+        let _ = fs::remove_dir_all(&temp_dir);
+    }
+    result?;
+
+    Ok(model_root(&extract_dir))
+}
+
+/// Returns whether the directory holds a complete extraction of the archive with the source.
+fn is_extracted_from(extract_dir: &Path, source: &str) -> bool {
+    fs::read_to_string(extract_dir.join(SOURCE_FILE_NAME)).is_ok_and(|cached| cached == source)
+}
+
+/// Extracts the model archive to the directory, recording the source of the archive last.
+fn extract_archive(archive_path: &Path, extract_dir: &Path, source: &str) -> Result<()> {
+    fs::create_dir_all(extract_dir).io_context("Failed to create model cache directory")?;
+    let archive = File::open(archive_path).io_context("Failed to open model archive")?;
+    zip::ZipArchive::new(archive)
+        .and_then(|mut archive| archive.extract(extract_dir))
+        .map_err(|source| ChapterizerError::ModelExtract {
+            path: archive_path.to_path_buf(),
+            source,
+        })?;
+    fs::write(extract_dir.join(SOURCE_FILE_NAME), source)
+        .io_context("Failed to write model cache file")
+}
+
+/// Returns the directory of the model in the extracted archive. Models are distributed with all
+/// of their files in a directory named after the model, e.g. "vosk-model-en-us-0.22/conf", so
+/// that's the model directory if the archive holds nothing else.
+fn model_root(extract_dir: &Path) -> PathBuf {
+    let entries = fs::read_dir(extract_dir)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| {
+                    path.file_name()
+                        .is_some_and(|name| name != SOURCE_FILE_NAME)
+                })
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    match entries.as_slice() {
+        [dir] if dir.is_dir() => dir.clone(),
+        _ => extract_dir.to_path_buf(),
+    }
+}
@@ -0,0 +1,54 @@
+/// The width of the bins of the pause histogram, in seconds.
+const BIN_SECS: f32 = 0.01;
+
+/// The number of bins of the pause histogram. Longer pauses are counted in the last bin, since
+/// only the lower percentiles are of interest.
+const NUM_BINS: usize = 300;
+
+/// Percentiles aren't taken until this many pauses have been heard, since they'd jump around too
+/// much before then.
+const MIN_PAUSES: usize = 200;
+
+/// The distribution of the pauses between the words of the narrator, so that pause thresholds
+/// can be expressed as percentiles of it and adapt to slow and fast narrators. Pauses are kept
+/// in a histogram, so that the distribution of a whole book takes constant memory.
+#[derive(Debug)]
+pub struct PauseStats {
+    bins: Vec<u32>,
+    count: usize,
+}
+
+impl Default for PauseStats {
+    fn default() -> Self {
+        Self {
+            bins: vec![0; NUM_BINS],
+            count: 0,
+        }
+    }
+}
+
+impl PauseStats {
+    /// Records the pause (in seconds) between two words.
+    pub fn push(&mut self, pause: f32) {
+        let bin = ((pause.max(0.0) / BIN_SECS) as usize).min(NUM_BINS - 1);
+        self.bins[bin] += 1;
+        self.count += 1;
+    }
+
+    /// Returns the pause (in seconds) that the percentage of pauses are shorter than, e.g. 90 for
+    /// the 90th percentile, or `None` if too few pauses have been heard yet.
+    pub fn percentile(&self, percentage: f32) -> Option<f32> {
+        if self.count < MIN_PAUSES {
+            return None;
+        }
+        let rank = ((percentage / 100.0).clamp(0.0, 1.0) * self.count as f32).ceil() as u32;
+        let mut seen = 0;
+        for (bin, &count) in self.bins.iter().enumerate() {
+            seen += count;
+            if seen >= rank.max(1) {
+                return Some((bin + 1) as f32 * BIN_SECS);
+            }
+        }
+        Some(NUM_BINS as f32 * BIN_SECS)
+    }
+}
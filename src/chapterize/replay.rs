@@ -0,0 +1,104 @@
+use std::{path::Path, time::Duration};
+
+use super::{
+    assembler::ChapterAssembler,
+    config::DetectionConfig,
+    keywords::Keywords,
+    locations::MatchLines,
+    matches::for_each_result,
+    results_parser::{get_best_alt, ResultsParser, PRE_CHAPTER_CONTEXT},
+    token::Token,
+    POST_CHAPTER_CONTEXT,
+};
+use crate::{
+    chapter::ChapterList,
+    compression,
+    error::{IoResultExt, Result},
+    fixed_vec_deque::FixedVecDeque,
+    timestamp::AudioTimestamp,
+};
+
+/// Calls the function with the line number and the tokens of the best alternative of each
+/// recognition result in the matches file.
+fn for_each_transcript(
+    matches_file_path: &Path,
+    keywords: &Keywords,
+    mut f: impl FnMut(usize, Vec<Token>),
+) -> Result<()> {
+    let matches =
+        compression::read_to_string(matches_file_path).io_context("Failed to read matches file")?;
+
+    for_each_result(&matches, |line_number, alternatives, _| {
+        if alternatives.is_empty() {
+            return;
+        }
+
+        f(
+            line_number,
+            get_best_alt(alternatives, keywords)
+                .result
+                .iter()
+                .map(Token::from)
+                .collect(),
+        );
+    })
+}
+
+/// Returns the times at which the word "chapter" was recognized in the matches file, i.e. the
+/// candidates for the start of a chapter, whether or not they were accepted. The chapter words
+/// are taken from the config.
+pub fn candidate_times(
+    matches_file_path: &Path,
+    config: &DetectionConfig,
+) -> Result<Vec<Duration>> {
+    let keywords = Keywords::new(config);
+    let mut times = Vec::new();
+    for_each_transcript(matches_file_path, &keywords, |_, tokens| {
+        times.extend(
+            tokens
+                .iter()
+                .filter(|token| keywords.is_chapter_token(token))
+                .map(|token| token.start.to_duration()),
+        );
+    })?;
+    // The matches file repeats results as context around each candidate
+    times.sort();
+    times.dedup();
+    Ok(times)
+}
+
+/// Detects chapters in the recognition results of a matches file instead of an audio file, so
+/// that detection can be repeated quickly, e.g. with different thresholds. Since the matches file
+/// doesn't record the duration of the audio, the duration of the chapter list is taken to be the
+/// end of the last recognized word. Parts of subdivided chapters are separate chapters.
+pub fn replay_matches(matches_file_path: &Path, config: &DetectionConfig) -> Result<ChapterList> {
+    let (mut results_parser, parse_result_rx) = ResultsParser::new(POST_CHAPTER_CONTEXT, config);
+    let mut last_tokens: FixedVecDeque<Token> = FixedVecDeque::with_max_len(PRE_CHAPTER_CONTEXT);
+    let mut end = AudioTimestamp::ZERO;
+    let mut match_lines = MatchLines::default();
+    for_each_transcript(matches_file_path, &Keywords::new(config), |line, tokens| {
+        if let Some(last_token) = tokens.last() {
+            if last_token.end > end {
+                end = last_token.end;
+            }
+        }
+        match_lines.push(&tokens, line);
+        results_parser.ingest_tokens(&mut last_tokens, tokens);
+    })?;
+    results_parser.flush();
+
+    let duration = end.to_duration();
+    let mut assembler = ChapterAssembler::new(false);
+    let mut chapters: Vec<_> = parse_result_rx
+        .into_iter()
+        .filter_map(|parse_result| {
+            let matches_line = parse_result
+                .start()
+                .and_then(|start| match_lines.find(start));
+            assembler.push(parse_result, matches_line, None)
+        })
+        .collect();
+    chapters.push(assembler.finish(duration));
+
+    Ok(ChapterList { chapters, duration })
+}
@@ -1,85 +1,289 @@
-use super::token::{is_chapter_token, Token};
+use super::{
+    assembler::START_OF_FILE_WINDOW,
+    config::DetectionConfig,
+    keywords::Keywords,
+    pauses::PauseStats,
+    token::{Token, DEFAULT_MAX_NUMBER_PAUSE},
+};
+use crate::{
+    chapter::RejectedCandidate, fixed_vec_deque::FixedVecDeque, timestamp::AudioTimestamp,
+};
 use crossbeam::channel;
 use itertools::Itertools;
-use lazy_static::lazy_static;
 use ordered_float::NotNan;
-use text2num::{rewrite_numbers, word_to_digit::find_numbers_iter, Language};
-use vosk::{Alternative, CompleteResultMultiple};
+use text2num::{
+    rewrite_numbers,
+    word_to_digit::{find_numbers_iter, Replace},
+};
+use vosk::Alternative;
+
+/// The number of tokens before the chapter token to keep in the buffer. Two tokens are needed to
+/// recognize "end of chapter N" announcements, and a few more to find reading phrases, e.g. "she
+/// opened the book to chapter N".
+pub const PRE_CHAPTER_CONTEXT: usize = 6;
+
+/// The number of tokens, starting with the chapter token, to include in the text of a rejected
+/// candidate.
+const REJECTED_CANDIDATE_TOKENS: usize = 4;
+
+/// The maximum number of digits read one at a time, e.g. "chapter one two" for chapter 12, that
+/// are combined into a chapter number.
+const MAX_DIGIT_SEQUENCE_LEN: usize = 3;
+
+/// A letter after the chapter number, e.g. "chapter twelve a", is only taken to be part of the
+/// chapter identifier if it's followed by a vocal pause of at least this many seconds, since it's
+/// more likely to be the start of the title otherwise, e.g. "chapter twelve a new beginning".
+/// With adaptive pauses, it's only used until enough pauses have been heard.
+const LETTER_SUFFIX_MIN_PAUSE: f32 = 0.3;
+
+/// Subtracted from the score of an Alternative whose chapter number is read as a sequence of
+/// digits, so that an Alternative in which the number was recognized as a whole is preferred.
+const DIGIT_SEQUENCE_PENALTY: f32 = 1.5;
+
+pub fn alt_contains_potential_match<'a>(alt: &'a Alternative<'a>, keywords: &Keywords) -> bool {
+    alt.result.iter().any(|wia| keywords.is_chapter_wia(wia))
+}
 
-const MIN_VOCAL_PAUSE_BEFORE_CHAPTER: f32 = 0.25;
+/// Returns whether the tokens contain a chapter token that is directly followed by a number.
+pub fn contains_chapter_number(tokens: &[Token], keywords: &Keywords) -> bool {
+    tokens
+        .iter()
+        .positions(|token| keywords.is_chapter_token(token))
+        .any(|index| {
+            find_numbers_iter(tokens[index + 1..].iter(), keywords.numbers(), 0.0)
+                .next()
+                .is_some_and(|occ| occ.start == 0)
+        })
+}
 
-lazy_static! {
-    static ref LANG_EN: Language = Language::english();
+/// Returns the number of single digits at the start of the tokens, which have already had their
+/// numbers rewritten, if they're read as one digit sequence, e.g. "chapter one two" for chapter
+/// 12. Returns 0 if there's no such sequence. Digits separated by a vocal pause aren't combined.
+fn digit_sequence_len(tokens: &[Token]) -> usize {
+    let is_digit = |token: &Token| {
+        token.is_replacement && token.word.len() == 1 && token.word.as_bytes()[0].is_ascii_digit()
+    };
+    let mut len = 0;
+    while len < MAX_DIGIT_SEQUENCE_LEN
+        && tokens.get(len).is_some_and(is_digit)
+        && (len == 0 || !text2num::Token::nt_separated(&&tokens[len], &&tokens[len - 1]))
+    {
+        len += 1;
+    }
+    if len > 1 {
+        len
+    } else {
+        0
+    }
 }
 
-pub fn alt_contains_potential_match<'a>(alt: &'a Alternative<'a>) -> bool {
-    alt.result.iter().any(is_chapter_token)
+/// What follows the chapter number in an announcement, if it's part of the chapter identifier.
+enum IdSuffix {
+    None,
+    /// More tokens are needed to tell.
+    Incomplete,
+    /// The first `len` tokens make the identifier fractional or lettered, e.g. "and a half".
+    Suffix {
+        len: usize,
+        text: String,
+    },
 }
 
-/// Given several Alternatives, returns "best" one according to several criteria.
-pub fn get_best_alt<'a>(alts: &'a [Alternative<'a>]) -> &'a Alternative<'a> {
-    let mut pot_matches = alts
+/// Parses the suffix of a fractional or lettered chapter identifier, e.g. "and a half" in
+/// "chapter seven and a half" or "a" in "chapter twelve a", from the tokens after the number. A
+/// letter has to be followed by a pause of at least `letter_pause` seconds.
+fn parse_id_suffix(tokens: &[Token], is_end: bool, letter_pause: f32) -> IdSuffix {
+    const HALF: [&str; 3] = ["and", "a", "half"];
+    let words = tokens
         .iter()
-        .filter(|alt| alt_contains_potential_match(alt))
+        .take(HALF.len())
+        .map(|token| token.word.as_str())
         .collect::<Vec<_>>();
+    if words == HALF {
+        return IdSuffix::Suffix {
+            len: HALF.len(),
+            text: ".5".into(),
+        };
+    }
+    if !words.is_empty() && HALF.starts_with(&words) {
+        return if is_end {
+            IdSuffix::None
+        } else {
+            IdSuffix::Incomplete
+        };
+    }
 
-    // If this set of Alternatives does not contain any potential matches, just return the highest
-    // confidence Alternative (the first one, since they're sorted by confidence)
-    if pot_matches.is_empty() {
-        return alts.get(0).expect("expected at least 1 Alternative");
+    let Some(letter) = tokens.first() else {
+        return IdSuffix::None;
+    };
+    let mut chars = letter.word.chars();
+    let (Some(c), None) = (chars.next(), chars.next()) else {
+        return IdSuffix::None;
+    };
+    if !c.is_ascii_lowercase() {
+        return IdSuffix::None;
+    }
+    let is_suffix = match tokens.get(1) {
+        Some(next) => next.start.secs_since(letter.end) >= letter_pause,
+        None if is_end => true,
+        None => return IdSuffix::Incomplete,
+    };
+    if is_suffix {
+        IdSuffix::Suffix {
+            len: 1,
+            text: c.to_ascii_uppercase().to_string(),
+        }
+    } else {
+        IdSuffix::None
     }
+}
 
-    let score_alt = |alt: &Alternative| {
-        // Prefer higher confidence
-        let mut score = alt.confidence;
+/// Scores an alternative with a potential match by how likely it is to hold the announcement of a
+/// chapter as it was spoken, or returns None if it has no potential match. Higher is better: the
+/// score is the confidence of the alternative, plus a bonus for more common chapter words and for
+/// chapter numbers right after the chapter word, with more words read as the number counting for
+/// more. A number read as a sequence of digits is penalized.
+pub fn score_alt(alt: &Alternative, keywords: &Keywords) -> Option<NotNan<f32>> {
+    // Prefer higher confidence
+    let mut score = alt.confidence;
+
+    let (chap_index, chap_rank) = alt
+        .result
+        .iter()
+        .enumerate()
+        .find_map(|(index, wia)| Some((index, keywords.chapter_word_rank(wia.word)?)))?;
 
-        let (chap_index, chap_word) = alt
-            .result
-            .iter()
-            .find_position(|wia| is_chapter_token(wia))
-            .unwrap();
-
-        // Slightly prefer "chapter" over "chapters"
-        if chap_word.word == "chapter" {
-            score += 1.0;
-        } else if chap_word.word == "chapters" {
-            score += 0.9;
+    // Slightly prefer the more common chapter words, e.g. "chapter" over "chapters"
+    score += 1.0 - 0.1 * chap_rank as f32;
+
+    let following_words = alt
+        .result
+        .iter()
+        .skip(chap_index + 1)
+        .map(Token::from)
+        .collect::<Vec<_>>();
+
+    if let Some(occ) = find_numbers_iter(following_words.iter(), keywords.numbers(), 0.0).next() {
+        // Only consider the number if it's right after the chapter word
+        if occ.start == 0 {
+            log::trace!("Occ after chapter word: {:#?}", occ);
+            // The more words it was successfully able to parse into a number, the better
+            score += occ.text.split(' ').count() as f32;
         } else {
-            unreachable!()
+            log::trace!("Occ NOT after chapter word: {:#?}", occ);
         }
+    }
 
-        let following_words = alt
-            .result
-            .iter()
-            .skip(chap_index + 1)
-            .map(Token::from)
-            .collect::<Vec<_>>();
+    let digits = digit_sequence_len(&rewrite_numbers(following_words, keywords.numbers(), 0.0));
+    if digits > 0 {
+        log::trace!("Chapter number read as a sequence of {} digits", digits);
+        score -= DIGIT_SEQUENCE_PENALTY;
+    }
 
-        if let Some(occ) = find_numbers_iter(following_words.iter(), &*LANG_EN, 0.0).next() {
-            // Only consider the number if it's right after the chapter word
-            if occ.start == 0 {
-                log::trace!("Occ after chapter word: {:#?}", occ);
-                // The more words it was successfully able to parse into a number, the better
-                score += occ.text.split(' ').count() as f32;
-            } else {
-                log::trace!("Occ NOT after chapter word: {:#?}", occ);
-            }
-        }
+    // TODO: log how score was determined
+    NotNan::new(score).ok()
+}
+
+/// An alternative with a potential match, with its score as given by [`score_alt`].
+#[derive(Clone, Copy, Debug)]
+pub struct RankedAlt<'a> {
+    /// The index of the alternative in the recognition result.
+    pub index: usize,
+    pub alt: &'a Alternative<'a>,
+    pub score: NotNan<f32>,
+}
+
+/// Returns the alternatives with a potential match, from the highest score to the lowest. Of
+/// alternatives with the same score, the one that comes last in the recognition result, i.e. the
+/// one the recognizer is least confident about, is ranked first.
+pub fn rank_alts<'a>(alts: &'a [Alternative<'a>], keywords: &Keywords) -> Vec<RankedAlt<'a>> {
+    let mut ranked = alts
+        .iter()
+        .enumerate()
+        .filter(|(_, alt)| alt_contains_potential_match(alt, keywords))
+        .filter_map(|(index, alt)| {
+            Some(RankedAlt {
+                index,
+                alt,
+                score: score_alt(alt, keywords)?,
+            })
+        })
+        .collect::<Vec<_>>();
+    ranked.sort_by_key(|ranked_alt| ranked_alt.score);
+    ranked.reverse();
+    ranked
+}
+
+/// The words of the alternative from its first chapter word on, i.e. how it reads the
+/// announcement of a chapter.
+fn announcement<'a>(alt: &Alternative<'a>, keywords: &Keywords) -> Vec<&'a str> {
+    alt.result
+        .iter()
+        .skip_while(|wia| !keywords.is_chapter_wia(wia))
+        .map(|wia| wia.word)
+        .collect()
+}
 
-        // TODO: log how score was determined
-        NotNan::new(score).unwrap()
+/// Returns the runner-ups among the k highest ranked alternatives, see [`rank_alts`], that read
+/// the announcement differently than the best one, from the highest ranked to the lowest. If
+/// there are none, the candidate isn't ambiguous, as the alternatives only differ before the
+/// chapter word.
+pub fn runner_up_alts<'a>(
+    alts: &'a [Alternative<'a>],
+    keywords: &Keywords,
+    k: usize,
+) -> Vec<RankedAlt<'a>> {
+    let ranked = rank_alts(alts, keywords);
+    let Some(best) = ranked.first() else {
+        return Vec::new();
     };
+    let best_announcement = announcement(best.alt, keywords);
+    ranked
+        .iter()
+        .take(k)
+        .skip(1)
+        .filter(|ranked_alt| announcement(ranked_alt.alt, keywords) != best_announcement)
+        .copied()
+        .collect()
+}
 
-    pot_matches.sort_by_cached_key(|alt| score_alt(alt));
-    // Return the highest scoring Alternative
-    pot_matches.last().unwrap()
+/// Given several Alternatives, returns "best" one according to several criteria: the highest
+/// ranked one with a potential match, see [`rank_alts`], or the one with the highest confidence if
+/// none of them has one.
+pub fn get_best_alt<'a>(alts: &'a [Alternative<'a>], keywords: &Keywords) -> &'a Alternative<'a> {
+    match rank_alts(alts, keywords).first() {
+        Some(best) => best.alt,
+        // The alternatives are sorted by confidence
+        None => alts.first().expect("expected at least 1 Alternative"),
+    }
 }
 
 #[derive(Debug)]
 pub enum ParseResult {
+    /// The start of a chapter was announced, e.g. "chapter seven". If the chapter is subdivided,
+    /// the announcement includes the part, e.g. "chapter seven part two".
     Match(Vec<Token>),
+    /// The end of a chapter was announced, e.g. "end of chapter seven".
+    EndMatch(Vec<Token>),
     Incomplete,
-    Failure,
+    /// The buffer doesn't contain a chapter announcement. If it contained the word "chapter", the
+    /// rejected candidate is included.
+    Failure(Option<RejectedCandidate>),
+}
+
+impl ParseResult {
+    /// The time at which the word "chapter" of the result was recognized, if any.
+    pub fn start(&self) -> Option<AudioTimestamp> {
+        match self {
+            ParseResult::Match(tokens) | ParseResult::EndMatch(tokens) => {
+                tokens.first().map(|token| token.start)
+            }
+            ParseResult::Failure(Some(candidate)) => {
+                Some(AudioTimestamp::from_duration(candidate.start))
+            }
+            ParseResult::Failure(None) | ParseResult::Incomplete => None,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -87,17 +291,45 @@ pub struct ResultsParser {
     parse_result_tx: channel::Sender<ParseResult>,
     buffer: Vec<Token>,
     capacity: usize,
+    min_vocal_pause_before_chapter: f32,
+    /// The pauses between the words of the narrator heard so far.
+    pauses: PauseStats,
+    adaptive_pauses: bool,
+    min_vocal_pause_percentile: f32,
+    max_number_pause_percentile: f32,
+    letter_suffix_pause_percentile: f32,
+    /// The reading phrases, split into their words.
+    reading_phrases: Vec<Vec<String>>,
+    reading_phrase_window: f32,
+    keywords: Keywords,
 }
 
 impl ResultsParser {
-    pub fn new(post_match_context: usize) -> (Self, channel::Receiver<ParseResult>) {
+    pub fn new(
+        post_match_context: usize,
+        config: &DetectionConfig,
+    ) -> (Self, channel::Receiver<ParseResult>) {
         let (tx, rx) = channel::unbounded();
-        let capacity = 2 + post_match_context;
+        let capacity = PRE_CHAPTER_CONTEXT + 1 + post_match_context;
 
         (
             Self {
                 buffer: Vec::with_capacity(capacity),
                 capacity,
+                min_vocal_pause_before_chapter: config.min_vocal_pause_before_chapter,
+                pauses: PauseStats::default(),
+                adaptive_pauses: config.adaptive_pauses,
+                min_vocal_pause_percentile: config.min_vocal_pause_percentile,
+                max_number_pause_percentile: config.max_number_pause_percentile,
+                letter_suffix_pause_percentile: config.letter_suffix_pause_percentile,
+                reading_phrases: config
+                    .reading_phrases
+                    .iter()
+                    .map(|phrase| phrase.split_whitespace().map(str::to_lowercase).collect())
+                    .filter(|words: &Vec<String>| !words.is_empty())
+                    .collect(),
+                reading_phrase_window: config.reading_phrase_window,
+                keywords: Keywords::new(config),
                 parse_result_tx: tx,
             },
             rx,
@@ -116,27 +348,45 @@ impl ResultsParser {
         self.buffer.len() == self.capacity
     }
 
-    /// Ingests a batch of prediction results. Keeps the prev_token Option up to date with to the
-    /// last token in the batch.
-    pub fn ingest_results(
+    /// Returns the pause threshold at the percentile of the narrator's pauses, bounded to between
+    /// half and twice the fixed threshold. Returns the fixed threshold if pauses aren't adaptive
+    /// or too few have been heard yet.
+    fn pause_threshold(&self, percentile: f32, fixed: f32) -> f32 {
+        if !self.adaptive_pauses {
+            return fixed;
+        }
+        self.pauses
+            .percentile(percentile)
+            .map_or(fixed, |pause| pause.clamp(fixed / 2.0, fixed * 2.0))
+    }
+
+    /// Ingests the transcript picked from a batch of prediction results. Keeps prev_tokens up to
+    /// date with the last PRE_CHAPTER_CONTEXT tokens in the transcript. The pauses between the
+    /// words of the transcript are added to the narrator's pauses.
+    pub fn ingest_tokens(
         &mut self,
-        prev_token: &mut Option<Token>,
-        multi: &CompleteResultMultiple,
+        prev_tokens: &mut FixedVecDeque<Token>,
+        tokens: impl IntoIterator<Item = Token>,
     ) {
-        let best_alt = get_best_alt(&multi.alternatives);
-        let alt_token_iter = best_alt.result.iter();
-        for token in alt_token_iter.map(Token::from) {
-            if self.has_data() || token.is_chapter_token() {
-                // If this is a new match, first push the token before the chapter token
-                if self.is_empty() && token.is_chapter_token() {
-                    if let Some(ref prev_token) = prev_token {
+        let mut prev_end = None;
+        for token in tokens {
+            if let Some(prev_end) = prev_end {
+                self.pauses.push(token.start.secs_since(prev_end));
+            }
+            prev_end = Some(token.end);
+
+            let is_chapter_token = self.keywords.is_chapter_token(&token);
+            if self.has_data() || is_chapter_token {
+                // If this is a new match, first push the tokens before the chapter token
+                if self.is_empty() && is_chapter_token {
+                    for prev_token in prev_tokens.iter() {
                         self.push(prev_token.clone());
                     }
                 }
 
                 self.push(token.clone());
             }
-            prev_token.replace(token);
+            prev_tokens.push_back(token);
         }
     }
 
@@ -162,7 +412,7 @@ impl ResultsParser {
         }
 
         match parse_result {
-            ParseResult::Match(_) | ParseResult::Failure => {
+            ParseResult::Match(_) | ParseResult::EndMatch(_) | ParseResult::Failure(_) => {
                 self.buffer.clear();
             }
             ParseResult::Incomplete => {
@@ -181,52 +431,113 @@ impl ResultsParser {
         }
     }
 
+    /// Returns a failure for the candidate starting at the chapter token in the buffer.
+    fn reject(&self, chapter_token_index: usize, reason: &'static str) -> ParseResult {
+        let tokens = &self.buffer[chapter_token_index..];
+        ParseResult::Failure(Some(RejectedCandidate {
+            start: tokens[0].start.to_duration(),
+            text: tokens
+                .iter()
+                .take(REJECTED_CANDIDATE_TOKENS)
+                .map(|token| token.word.as_str())
+                .join(" "),
+            reason,
+        }))
+    }
+
+    /// Returns the reading phrase that ends shortly before the chapter token in the buffer, if
+    /// any, e.g. "opened to" in "she opened to chapter five".
+    fn find_reading_phrase(&self, chapter_token_index: usize) -> Option<String> {
+        let chapter_start = self.buffer[chapter_token_index].start;
+        let words = self.buffer[..chapter_token_index]
+            .iter()
+            .skip_while(|token| token.end < chapter_start.add_secs(-self.reading_phrase_window))
+            .map(|token| token.word.to_lowercase())
+            .collect::<Vec<_>>();
+
+        self.reading_phrases
+            .iter()
+            .find(|phrase| words.windows(phrase.len()).any(|window| window == *phrase))
+            .map(|phrase| phrase.join(" "))
+    }
+
     fn parse_chapter(&self, is_end: bool) -> ParseResult {
         log::debug!("Parsing chapter with match buffer:\n{:#?}", self);
 
-        let (chapter_token_index, chapter_token) =
-            match self.buffer.iter().find_position(|t| t.is_chapter_token()) {
-                Some(tuple) => tuple,
-                None => {
-                    return if is_end {
-                        log::debug!("ParseResult::Failure: no chapter token");
-                        ParseResult::Failure
-                    } else {
-                        log::debug!("ParseResult::Incomplete: waiting for chapter token");
-                        ParseResult::Incomplete
-                    }
+        let (chapter_token_index, chapter_token) = match self
+            .buffer
+            .iter()
+            .find_position(|t| self.keywords.is_chapter_token(t))
+        {
+            Some(tuple) => tuple,
+            None => {
+                return if is_end {
+                    log::debug!("ParseResult::Failure: no chapter token");
+                    ParseResult::Failure(None)
+                } else {
+                    log::debug!("ParseResult::Incomplete: waiting for chapter token");
+                    ParseResult::Incomplete
                 }
-            };
-
-        if let Some(prev_token) = chapter_token_index
+            }
+        };
+
+        let is_end_announcement = chapter_token_index >= 2
+            && self.buffer[chapter_token_index - 2].word == "end"
+            && self.buffer[chapter_token_index - 1].word == "of";
+
+        // Chapter ends are announced as one phrase, so there is no pause before the chapter token
+        if is_end_announcement {
+            log::debug!("Chapter token is preceded by \"end of\"");
+        } else if chapter_token.start < AudioTimestamp::from_duration(START_OF_FILE_WINDOW) {
+            // Whatever comes before it at the very start of the file is likely noise or a title
+            log::debug!("Chapter token is at the start of the file, so the pause isn't checked");
+        } else if let Some(prev_token) = chapter_token_index
             .checked_sub(1)
             .and_then(|index| self.buffer.get(index))
         {
-            let vocal_pause_len = chapter_token.start - prev_token.end;
-            if vocal_pause_len < MIN_VOCAL_PAUSE_BEFORE_CHAPTER {
+            let vocal_pause_len = chapter_token.start.secs_since(prev_token.end);
+            let min_vocal_pause = self.pause_threshold(
+                self.min_vocal_pause_percentile,
+                self.min_vocal_pause_before_chapter,
+            );
+            if vocal_pause_len < min_vocal_pause {
                 log::debug!(
-                    "ParseResult::Failure: vocal pause before chapter token not long enough at {:.3}s",
-                    vocal_pause_len
+                    "ParseResult::Failure: vocal pause before chapter token not long enough at {:.3}s (minimum {:.3}s)",
+                    vocal_pause_len,
+                    min_vocal_pause
                 );
-                return ParseResult::Failure;
+                return self.reject(chapter_token_index, "pause before \"chapter\" too short");
             }
         }
 
+        if let Some(phrase) = self.find_reading_phrase(chapter_token_index) {
+            log::debug!(
+                "ParseResult::Failure: chapter token is preceded by reading phrase \"{}\"",
+                phrase
+            );
+            return self.reject(chapter_token_index, "preceded by a reading phrase");
+        }
+
         if self.buffer.iter().skip(chapter_token_index + 1).count() == 0 {
             return if is_end {
                 log::debug!("ParseResult::Failure: no token after chapter");
-                ParseResult::Failure
+                self.reject(chapter_token_index, "nothing after \"chapter\"")
             } else {
                 log::debug!("ParseResult::Incomplete: waiting for token after chapter token");
                 ParseResult::Incomplete
             };
         }
 
+        let max_number_pause =
+            self.pause_threshold(self.max_number_pause_percentile, DEFAULT_MAX_NUMBER_PAUSE);
         let tokens = self
             .buffer
             .iter()
             .skip(chapter_token_index)
-            .cloned()
+            .map(|token| Token {
+                max_number_pause,
+                ..token.clone()
+            })
             .collect::<Vec<_>>();
 
         // Sanity check
@@ -234,12 +545,31 @@ impl ResultsParser {
             assert!(!token.is_replacement);
         }
 
-        let mut tokens = rewrite_numbers(tokens, &*LANG_EN, 0.0);
+        let mut tokens = rewrite_numbers(tokens, self.keywords.numbers(), 0.0);
+
+        // Narrators sometimes read the chapter number one digit at a time, e.g. "chapter one two",
+        // which isn't a number as a whole
+        let digits = digit_sequence_len(&tokens[1..]);
+        if digits > 0 {
+            if tokens.len() == 1 + digits && !is_end {
+                log::debug!("ParseResult::Incomplete: waiting for token after digit sequence");
+                return ParseResult::Incomplete;
+            }
+            let sequence = tokens.drain(1..1 + digits).collect::<Vec<_>>();
+            let number = sequence.iter().map(|token| token.word.as_str()).collect();
+            let number_token = Token::replace(sequence.into_iter(), number);
+            log::info!(
+                "Reading the digits after \"chapter\" at {:.3}s as chapter {}",
+                number_token.start.as_secs(),
+                number_token.word
+            );
+            tokens.insert(1, number_token);
+        }
 
-        let chapter_token = tokens.get(0).unwrap();
+        let chapter_token = tokens.first().unwrap();
 
         // Sanity check
-        assert!(chapter_token.is_chapter_token());
+        assert!(self.keywords.is_chapter_token(chapter_token));
         assert!(!chapter_token.is_replacement);
 
         let chapter_number_token = tokens.get(1).unwrap();
@@ -248,7 +578,25 @@ impl ResultsParser {
                 "ParseResult::Failure: token after chapter is not a number: {:#?}",
                 chapter_number_token
             );
-            return ParseResult::Failure;
+            return self.reject(chapter_token_index, "no number after \"chapter\"");
+        }
+
+        let letter_pause =
+            self.pause_threshold(self.letter_suffix_pause_percentile, LETTER_SUFFIX_MIN_PAUSE);
+        match parse_id_suffix(&tokens[2..], is_end, letter_pause) {
+            IdSuffix::None => {}
+            IdSuffix::Incomplete => {
+                log::debug!(
+                    "ParseResult::Incomplete: waiting for the rest of the chapter identifier"
+                );
+                return ParseResult::Incomplete;
+            }
+            IdSuffix::Suffix { len, text } => {
+                let id_tokens = tokens.drain(1..2 + len).collect::<Vec<_>>();
+                let id = format!("{}{}", id_tokens[0].word, text);
+                log::debug!("Chapter identifier is {}", id);
+                tokens.insert(1, Token::replace(id_tokens.into_iter(), id));
+            }
         }
 
         let token_after_chapter_number = tokens.get(2);
@@ -258,12 +606,96 @@ impl ResultsParser {
             return ParseResult::Incomplete;
         }
 
+        // Subdivided chapters are announced as e.g. "chapter nine, part one"
+        let has_part = match (tokens.get(2), tokens.get(3)) {
+            (Some(part_token), Some(part_number_token))
+                if part_token.word == "part" && part_number_token.is_replacement =>
+            {
+                if tokens.get(4).is_none() && !is_end {
+                    log::debug!("ParseResult::Incomplete: waiting for token after part number");
+                    return ParseResult::Incomplete;
+                }
+                true
+            }
+            (Some(part_token), None) if part_token.word == "part" && !is_end => {
+                log::debug!("ParseResult::Incomplete: waiting for token after part token");
+                return ParseResult::Incomplete;
+            }
+            _ => false,
+        };
+
         // TODO: attempt to extract chapter title using vocal pause
 
-        tokens.drain(2..);
+        tokens.drain(if has_part { 4 } else { 2 }..);
 
-        let parse_result = ParseResult::Match(tokens);
-        log::debug!("ParseResult::Match: {:#?}", parse_result);
+        let parse_result = if is_end_announcement {
+            ParseResult::EndMatch(tokens)
+        } else {
+            ParseResult::Match(tokens)
+        };
+        log::debug!("{:#?}", parse_result);
         parse_result
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token(word: &str, start: f32) -> Token {
+        Token {
+            start: AudioTimestamp::from_secs(start),
+            end: AudioTimestamp::from_secs(start + 0.4),
+            word: word.into(),
+            is_replacement: false,
+            max_number_pause: DEFAULT_MAX_NUMBER_PAUSE,
+        }
+    }
+
+    /// Parses the words, given with their start times, as a single transcript.
+    fn parse(words: &[(&str, f32)]) -> Vec<ParseResult> {
+        let (mut parser, rx) = ResultsParser::new(2, &DetectionConfig::default());
+        let mut prev_tokens = FixedVecDeque::with_max_len(PRE_CHAPTER_CONTEXT);
+        parser.ingest_tokens(
+            &mut prev_tokens,
+            words.iter().map(|&(word, start)| token(word, start)),
+        );
+        parser.flush();
+        rx.try_iter().collect()
+    }
+
+    fn is_match(parse_result: &ParseResult) -> bool {
+        matches!(parse_result, ParseResult::Match(_))
+    }
+
+    #[test]
+    fn chapter_at_start_of_file_is_matched() {
+        let results = parse(&[("chapter", 0.0), ("one", 0.5), ("it", 1.5), ("was", 1.9)]);
+        assert!(results.iter().any(is_match), "{:?}", results);
+    }
+
+    #[test]
+    fn chapter_at_start_of_file_is_matched_without_pause() {
+        // Whatever comes before the chapter word at the very start is likely a title
+        let results = parse(&[
+            ("the", 0.0),
+            ("book", 0.4),
+            ("chapter", 0.8),
+            ("one", 1.3),
+            ("it", 2.3),
+        ]);
+        assert!(results.iter().any(is_match), "{:?}", results);
+    }
+
+    #[test]
+    fn chapter_without_pause_later_in_file_is_rejected() {
+        let results = parse(&[
+            ("the", 60.0),
+            ("book", 60.4),
+            ("chapter", 60.8),
+            ("one", 61.3),
+            ("it", 62.3),
+        ]);
+        assert!(!results.iter().any(is_match), "{:?}", results);
+    }
+}
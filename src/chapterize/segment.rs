@@ -0,0 +1,125 @@
+use crate::{
+    fixed_vec_deque::FixedVecDeque,
+    timestamp::{AudioTimestamp, SampleOffset},
+};
+use vosk::CompleteResultMultiple;
+
+/// The amount of audio at the end of a segment that is fed to the recognizer again at the start
+/// of the next segment, so that words cut off at the boundary are recognized in full.
+pub const SEGMENT_OVERLAP_SECS: f32 = 2.0;
+
+/// Where the words of a recognition result lie in the audio file, and which of them to keep.
+#[derive(Clone, Copy, Debug)]
+pub struct SegmentBounds {
+    /// The time in the audio file at which the recognizer started recognizing.
+    pub offset: AudioTimestamp,
+    /// Words whose midpoint lies before this time belong to the previous segment.
+    pub keep_from: AudioTimestamp,
+    /// Words whose midpoint lies at or after this time belong to the next segment.
+    pub keep_until: AudioTimestamp,
+}
+
+impl SegmentBounds {
+    /// Moves the words of the result to their time in the audio file, and drops the words that
+    /// belong to a neighboring segment. The text of the alternatives is left as is.
+    pub fn apply(&self, multi: &mut CompleteResultMultiple) {
+        for alt in &mut multi.alternatives {
+            for word in &mut alt.result {
+                word.start += self.offset.as_secs();
+                word.end += self.offset.as_secs();
+            }
+            alt.result.retain(|word| {
+                let midpoint = AudioTimestamp::from_secs(word.start)
+                    .midpoint(AudioTimestamp::from_secs(word.end));
+                midpoint >= self.keep_from && midpoint < self.keep_until
+            });
+        }
+    }
+}
+
+/// Splits the audio into segments, restarting the recognizer for each one. This bounds the state
+/// that the recognizer accumulates over a long file.
+///
+/// The segments overlap, so that a word spoken at a boundary is recognized in full by at least
+/// one of them. The words in the overlap are split between the segments at its midpoint. Since the
+/// results of all segments are fed to the same results parser in order, the tokens before a
+/// boundary are carried over as context for the next segment, and a chapter announced across a
+/// boundary is parsed as one.
+pub struct Segmenter {
+    sample_rate: u32,
+    /// The length of a segment in samples, if the audio is split into segments.
+    segment_len: Option<u64>,
+    /// The most recent samples, to feed to the recognizer again at the start of the next segment.
+    overlap: FixedVecDeque<i16>,
+    samples_pushed: SampleOffset,
+    /// Where the current segment started.
+    segment_start: SampleOffset,
+    bounds: SegmentBounds,
+}
+
+impl Segmenter {
+    /// Creates a segmenter for segments of the given length in seconds. If None, the audio isn't
+    /// split.
+    pub fn new(sample_rate: u32, segment_secs: Option<f32>) -> Self {
+        let overlap_len = match segment_secs {
+            Some(_) => (SEGMENT_OVERLAP_SECS * sample_rate as f32) as usize,
+            None => 0,
+        };
+        Self {
+            sample_rate,
+            segment_len: segment_secs.map(|secs| (secs * sample_rate as f32) as u64),
+            overlap: FixedVecDeque::with_max_len(overlap_len),
+            samples_pushed: SampleOffset::ZERO,
+            segment_start: SampleOffset::ZERO,
+            bounds: SegmentBounds {
+                offset: AudioTimestamp::ZERO,
+                keep_from: AudioTimestamp::ZERO,
+                keep_until: AudioTimestamp::END,
+            },
+        }
+    }
+
+    pub fn push_samples(&mut self, samples: &[i16]) {
+        if self.segment_len.is_some() {
+            for &sample in samples {
+                self.overlap.push_back(sample);
+            }
+        }
+        self.samples_pushed += samples.len() as u64;
+    }
+
+    /// The bounds of the results of the current segment.
+    pub fn bounds(&self) -> SegmentBounds {
+        self.bounds
+    }
+
+    /// Whether the current segment is complete, and the next one should be started.
+    pub fn is_segment_full(&self) -> bool {
+        self.segment_len
+            .is_some_and(|segment_len| self.samples_pushed - self.segment_start >= segment_len)
+    }
+
+    /// Ends the current segment and starts the next one. Returns the bounds of the final result of
+    /// the segment that just ended, and the samples to feed to the restarted recognizer first.
+    pub fn start_next_segment(&mut self) -> (SegmentBounds, Vec<i16>) {
+        let now = self.samples_pushed.to_timestamp(self.sample_rate);
+        let overlap_start =
+            (self.samples_pushed - self.overlap.len() as u64).to_timestamp(self.sample_rate);
+        let cut = overlap_start.midpoint(now);
+
+        let ended = SegmentBounds {
+            keep_until: cut,
+            ..self.bounds
+        };
+        log::debug!("Starting new recognizer segment at {:.2}s", cut.as_secs());
+
+        self.segment_start = self.samples_pushed;
+        self.bounds = SegmentBounds {
+            offset: overlap_start,
+            keep_from: cut,
+            keep_until: AudioTimestamp::END,
+        };
+
+        (ended, self.overlap.iter().copied().collect())
+    }
+}
@@ -0,0 +1,184 @@
+use std::time::Duration;
+
+/// The audio is decimated to roughly this sample rate before estimating pitch, which keeps the
+/// detector cheap compared to the recognizer.
+const ANALYSIS_SAMPLE_RATE: f32 = 4000.0;
+
+/// The length of the frames that the pitch is estimated for.
+const FRAME_SECS: f32 = 0.05;
+
+/// The range of voice pitches considered, in Hz.
+const MIN_PITCH: f32 = 60.0;
+const MAX_PITCH: f32 = 400.0;
+
+/// Frames quieter than this RMS are treated as silence.
+const MIN_VOICED_RMS: f32 = 300.0;
+
+/// The minimum normalized autocorrelation for a frame to count as voiced.
+const MIN_VOICING: f32 = 0.5;
+
+/// The minimum number of voiced frames for a second of audio to get a pitch.
+const MIN_VOICED_FRAMES_PER_SEC: usize = 4;
+
+/// The length of the windows on either side of a potential change point that are compared.
+const WINDOW_SECS: usize = 20;
+
+/// The minimum change in average pitch between the windows, as a ratio, for a change point. A
+/// change of 20% is about three semitones, which is more than a single narrator's pitch drifts.
+const MIN_PITCH_RATIO: f32 = 1.2;
+
+/// Finds the points where the narrator likely changes, by comparing the average voice pitch of the
+/// audio before and after each second. Multi-narrator books often switch narrators at chapter
+/// boundaries, so change points without a chapter nearby may be missed chapters.
+pub struct SpeakerChangeDetector {
+    decimation: usize,
+    analysis_rate: f32,
+    /// The sum of the samples of the current decimated sample, and how many have been added.
+    decimation_acc: (f32, usize),
+    frame: Vec<f32>,
+    frame_len: usize,
+    frames_per_sec: usize,
+    /// The pitches of the voiced frames of the current second.
+    second_pitches: Vec<f32>,
+    frames_in_second: usize,
+    /// The median pitch of each second of audio, if enough of it was voiced.
+    pitches: Vec<Option<f32>>,
+}
+
+impl SpeakerChangeDetector {
+    pub fn new(sample_rate: f32) -> Self {
+        let decimation = (sample_rate / ANALYSIS_SAMPLE_RATE).round().max(1.0) as usize;
+        let analysis_rate = sample_rate / decimation as f32;
+        let frame_len = (analysis_rate * FRAME_SECS) as usize;
+        Self {
+            decimation,
+            analysis_rate,
+            decimation_acc: (0.0, 0),
+            frame: Vec::with_capacity(frame_len),
+            frame_len,
+            frames_per_sec: (1.0 / FRAME_SECS).round() as usize,
+            second_pitches: Vec::new(),
+            frames_in_second: 0,
+            pitches: Vec::new(),
+        }
+    }
+
+    pub fn push_samples(&mut self, samples: &[i16]) {
+        for &sample in samples {
+            let (sum, count) = &mut self.decimation_acc;
+            *sum += sample as f32;
+            *count += 1;
+            if *count < self.decimation {
+                continue;
+            }
+            // Averaging the samples acts as a crude low-pass filter before decimating
+            self.frame.push(*sum / *count as f32);
+            self.decimation_acc = (0.0, 0);
+
+            if self.frame.len() == self.frame_len {
+                if let Some(pitch) = self.estimate_pitch() {
+                    self.second_pitches.push(pitch);
+                }
+                self.frame.clear();
+                self.finish_frame();
+            }
+        }
+    }
+
+    /// Estimates the pitch of the current frame using autocorrelation. Returns None if the frame
+    /// is silent or unvoiced.
+    fn estimate_pitch(&self) -> Option<f32> {
+        let frame = &self.frame;
+        let energy = frame.iter().map(|x| x * x).sum::<f32>();
+        if (energy / frame.len() as f32).sqrt() < MIN_VOICED_RMS {
+            return None;
+        }
+
+        let rate = self.analysis_rate;
+        let min_lag = (rate / MAX_PITCH) as usize;
+        let max_lag = ((rate / MIN_PITCH) as usize).min(frame.len() - 1);
+        let (best_lag, best_corr) = (min_lag.max(1)..=max_lag)
+            .map(|lag| {
+                let corr = frame
+                    .iter()
+                    .zip(&frame[lag..])
+                    .map(|(a, b)| a * b)
+                    .sum::<f32>();
+                (lag, corr / energy)
+            })
+            .fold((0, f32::MIN), |best, candidate| {
+                if candidate.1 > best.1 {
+                    candidate
+                } else {
+                    best
+                }
+            });
+
+        (best_corr >= MIN_VOICING).then(|| rate / best_lag as f32)
+    }
+
+    fn finish_frame(&mut self) {
+        self.frames_in_second += 1;
+        if self.frames_in_second < self.frames_per_sec {
+            return;
+        }
+
+        let pitch = (self.second_pitches.len() >= MIN_VOICED_FRAMES_PER_SEC)
+            .then(|| median(&mut self.second_pitches));
+        self.pitches.push(pitch);
+        self.second_pitches.clear();
+        self.frames_in_second = 0;
+    }
+
+    /// Returns the times of the change points, in order.
+    pub fn finish(self) -> Vec<Duration> {
+        // The geometric mean, since pitch is perceived logarithmically. Unlike the median, it
+        // peaks right at a change point.
+        let window_mean = |range: std::ops::Range<usize>| {
+            let pitches = self.pitches[range].iter().flatten().collect::<Vec<_>>();
+            (pitches.len() >= WINDOW_SECS / 2).then(|| {
+                let log_sum = pitches.iter().map(|pitch| pitch.ln()).sum::<f32>();
+                (log_sum / pitches.len() as f32).exp()
+            })
+        };
+
+        // The pitch ratio between the windows before and after each second
+        let mut candidates = (WINDOW_SECS..self.pitches.len().saturating_sub(WINDOW_SECS))
+            .filter_map(|second| {
+                let before = window_mean(second - WINDOW_SECS..second)?;
+                let after = window_mean(second..second + WINDOW_SECS)?;
+                let ratio = before.max(after) / before.min(after);
+                (ratio >= MIN_PITCH_RATIO).then_some((second, ratio))
+            })
+            .collect::<Vec<_>>();
+
+        // Keep only the strongest change point within each window, since a change shows up in
+        // the ratios of all seconds around it
+        candidates.sort_by(|a, b| b.1.total_cmp(&a.1));
+        let mut change_points: Vec<usize> = Vec::new();
+        for (second, ratio) in candidates {
+            if change_points
+                .iter()
+                .all(|&other| second.abs_diff(other) >= WINDOW_SECS)
+            {
+                log::debug!(
+                    "Speaker change at {}s: pitch changes by a factor of {:.2}",
+                    second,
+                    ratio
+                );
+                change_points.push(second);
+            }
+        }
+
+        change_points.sort_unstable();
+        change_points
+            .into_iter()
+            .map(|second| Duration::from_secs(second as u64))
+            .collect()
+    }
+}
+
+fn median(values: &mut [f32]) -> f32 {
+    values.sort_by(f32::total_cmp);
+    values[values.len() / 2]
+}
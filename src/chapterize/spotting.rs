@@ -0,0 +1,179 @@
+use std::{path::Path, time::Duration};
+
+use vosk::{CompleteResult, DecodingState, Model, Recognizer};
+
+use super::{gimme_audio, keywords::Keywords, segment::SegmentBounds};
+use crate::{
+    audio_provider::FormatHint,
+    error::{ChapterizerError, Result},
+    timestamp::{AudioTimestamp, SampleOffset},
+};
+
+/// The number of samples fed to the keyword spotter at a time.
+const SPOTTING_CHUNK_LEN: usize = 8 * 1024;
+
+/// The audio this long before a spotted chapter word is recognized in full too, so that the pause
+/// before the announcement can be measured.
+const WINDOW_BEFORE_SECS: f32 = 5.0;
+
+/// The audio this long after a spotted chapter word is recognized in full too, which leaves room
+/// for the chapter number and most titles.
+const WINDOW_AFTER_SECS: f32 = 15.0;
+
+/// Chapter words spotted with a lower confidence than this are ignored. It's kept low, since a
+/// chapter word that isn't spotted is never recognized in full.
+const MIN_SPOTTED_CONFIDENCE: f32 = 0.3;
+
+/// Runs a recognizer whose grammar only holds the chapter words over the whole audio file, and
+/// returns the times at which it heard one of them, in order. Such a recognizer is much faster
+/// than one with the full language model of the model, but also hears the chapter words in words
+/// that merely sound like them.
+pub fn spot_keywords(
+    model: &Model,
+    audio_file_path: &Path,
+    format_hint: &FormatHint,
+    keywords: &Keywords,
+) -> Result<Vec<AudioTimestamp>> {
+    let mut ap = gimme_audio(audio_file_path, format_hint)?;
+    let sample_rate = ap.sample_rate();
+    // Everything that isn't a chapter word is recognized as [unk]
+    let mut grammar = keywords.chapter_words().to_vec();
+    grammar.push("[unk]".into());
+    let mut recognizer = Recognizer::new_with_grammar(model, sample_rate as f32, &grammar)
+        .ok_or(ChapterizerError::Recognizer)?;
+    recognizer.set_words(true);
+
+    let mut spotted = Vec::new();
+    let mut add_result = |result: CompleteResult| {
+        let Some(single) = result.single() else {
+            return;
+        };
+        spotted.extend(
+            single
+                .result
+                .iter()
+                .filter(|word| {
+                    keywords.chapter_word_rank(word.word).is_some()
+                        && word.conf >= MIN_SPOTTED_CONFIDENCE
+                })
+                .map(|word| AudioTimestamp::from_secs(word.start)),
+        );
+    };
+
+    let mut buffer = Vec::with_capacity(SPOTTING_CHUNK_LEN);
+    while ap.fill_buffer(&mut buffer, SPOTTING_CHUNK_LEN) > 0 {
+        if let DecodingState::Finalized = recognizer.accept_waveform(&buffer) {
+            add_result(recognizer.result());
+        }
+        buffer.clear();
+    }
+    add_result(recognizer.final_result());
+
+    Ok(spotted)
+}
+
+/// What to do with the recognizer for the samples passed to [`KeywordWindows::push_samples`].
+pub enum WindowEvent<'a> {
+    /// A window starts, so the recognizer is restarted, and its results have these bounds.
+    Start(SegmentBounds),
+    /// Samples in the current window, to feed to the recognizer.
+    Samples(&'a [i16]),
+    /// The current window ends, so the final result of the recognizer is taken.
+    End,
+}
+
+/// The windows of audio around the spotted chapter words, which are the only audio recognized in
+/// full when spotting keywords. Windows that overlap are merged. The recognizer is restarted for
+/// each window, like it is for each segment, so the words it recognizes are moved to their time in
+/// the audio file by the bounds of the window.
+pub struct KeywordWindows {
+    /// The start and end of each window, in order.
+    windows: Vec<(SampleOffset, SampleOffset)>,
+    sample_rate: u32,
+    /// The index of the window that the next samples are in or before.
+    next: usize,
+    in_window: bool,
+    position: SampleOffset,
+}
+
+impl KeywordWindows {
+    pub fn new(spotted: &[AudioTimestamp], sample_rate: u32) -> Self {
+        let mut windows: Vec<(SampleOffset, SampleOffset)> = Vec::new();
+        for &time in spotted {
+            let start =
+                SampleOffset::from_timestamp(time.add_secs(-WINDOW_BEFORE_SECS), sample_rate);
+            let end = SampleOffset::from_timestamp(time.add_secs(WINDOW_AFTER_SECS), sample_rate);
+            match windows.last_mut() {
+                Some((_, last_end)) if start <= *last_end => *last_end = (*last_end).max(end),
+                _ => windows.push((start, end)),
+            }
+        }
+        Self {
+            windows,
+            sample_rate,
+            next: 0,
+            in_window: false,
+            position: SampleOffset::ZERO,
+        }
+    }
+
+    /// The number of windows, after merging those that overlap.
+    pub fn len(&self) -> usize {
+        self.windows.len()
+    }
+
+    /// The total duration of the windows.
+    pub fn total_duration(&self) -> Duration {
+        let total_samples = self.windows.iter().map(|&(start, end)| end - start).sum();
+        SampleOffset::new(total_samples).to_duration(self.sample_rate)
+    }
+
+    /// Whether the last samples pushed were in a window that hasn't ended yet, e.g. because the
+    /// audio ended in it.
+    pub fn is_in_window(&self) -> bool {
+        self.in_window
+    }
+
+    /// Returns what to do with the recognizer for the samples, which follow those pushed before.
+    pub fn push_samples<'a>(&mut self, samples: &'a [i16]) -> Vec<WindowEvent<'a>> {
+        let buffer_start = self.position;
+        let buffer_end = buffer_start + samples.len() as u64;
+        let slice = |from: SampleOffset, to: SampleOffset| {
+            &samples[(from - buffer_start) as usize..(to - buffer_start) as usize]
+        };
+
+        let mut events = Vec::new();
+        let mut position = buffer_start;
+        while position < buffer_end {
+            let Some(&(start, end)) = self.windows.get(self.next) else {
+                break;
+            };
+            if !self.in_window {
+                if start >= buffer_end {
+                    break;
+                }
+                position = position.max(start);
+                self.in_window = true;
+                events.push(WindowEvent::Start(SegmentBounds {
+                    offset: position.to_timestamp(self.sample_rate),
+                    keep_from: AudioTimestamp::ZERO,
+                    keep_until: AudioTimestamp::END,
+                }));
+            }
+
+            let samples_end = end.min(buffer_end);
+            if samples_end > position {
+                events.push(WindowEvent::Samples(slice(position, samples_end)));
+                position = samples_end;
+            }
+            if position >= end {
+                events.push(WindowEvent::End);
+                self.in_window = false;
+                self.next += 1;
+            }
+        }
+
+        self.position = buffer_end;
+        events
+    }
+}
@@ -1,34 +1,39 @@
 use text2num::word_to_digit;
 use vosk::WordInAlternative;
 
+use crate::timestamp::AudioTimestamp;
+
+/// The longest vocal pause in seconds between words for them to be part of a single number, unless
+/// the results parser has adapted it to the narrator.
+pub const DEFAULT_MAX_NUMBER_PAUSE: f32 = 0.2;
+
 #[derive(Clone, Debug)]
 pub struct Token {
-    /// Time in seconds when the word starts.
-    pub start: f32,
+    /// Time when the word starts.
+    pub start: AudioTimestamp,
 
-    /// Time in seconds when the word ends.
-    pub end: f32,
+    /// Time when the word ends.
+    pub end: AudioTimestamp,
 
     /// The transcribed word.
     pub word: String,
 
     /// Indicates that this Token replaced other Token(s)
     pub is_replacement: bool,
-}
 
-impl Token {
-    pub fn is_chapter_token(&self) -> bool {
-        self.word == "chapter" || self.word == "chapters"
-    }
+    /// The longest vocal pause in seconds before this word for it to be part of a single number
+    /// with the word before it.
+    pub max_number_pause: f32,
 }
 
 impl<'a> From<&'a WordInAlternative<'a>> for Token {
     fn from(wia: &'a WordInAlternative<'a>) -> Self {
         Self {
-            start: wia.start,
-            end: wia.end,
+            start: AudioTimestamp::from_secs(wia.start),
+            end: AudioTimestamp::from_secs(wia.end),
             word: wia.word.into(),
             is_replacement: false,
+            max_number_pause: DEFAULT_MAX_NUMBER_PAUSE,
         }
     }
 }
@@ -44,9 +49,9 @@ impl text2num::Token for &'_ Token {
     }
 
     fn nt_separated(&self, previous: &Self) -> bool {
-        // if there is a voice pause of more than 200ms between words, we can assume that they are
-        // not part of a single number
-        self.start - previous.end > 0.2f32
+        // if there is a long enough voice pause between words, we can assume that they are not
+        // part of a single number
+        self.start.secs_since(previous.end) > self.max_number_pause
     }
 }
 
@@ -63,11 +68,7 @@ impl word_to_digit::Replace for Token {
             end,
             word: data,
             is_replacement: true,
+            max_number_pause: start_word.max_number_pause,
         }
     }
 }
-
-// TODO: refactor/deduplicate this
-pub fn is_chapter_token<'a>(wia: &'a WordInAlternative<'a>) -> bool {
-    wia.word == "chapter" || wia.word == "chapters"
-}
@@ -0,0 +1,161 @@
+use std::path::Path;
+
+use super::{
+    config::DetectionConfig, keywords::Keywords, matches::for_each_result,
+    results_parser::get_best_alt, token::Token,
+};
+use crate::{
+    compression,
+    error::{ChapterizerError, IoResultExt, Result},
+};
+
+/// The decision made about a candidate when reviewing a matches file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Decision {
+    /// The candidate is the start of a chapter.
+    Accepted,
+    /// The candidate is not the start of a chapter.
+    Rejected,
+}
+
+/// A reviewed candidate, along with the values of the features that the thresholds apply to.
+#[derive(Clone, Copy, Debug)]
+struct ReviewedCandidate {
+    decision: Decision,
+    /// The pause before the word "chapter", or infinity if nothing was said before it.
+    vocal_pause: f32,
+}
+
+/// The outcome of tuning the detection thresholds.
+#[derive(Clone, Debug)]
+pub struct TuneReport {
+    /// The number of reviewed candidates that the thresholds were tuned on.
+    pub num_candidates: usize,
+    /// The fraction of candidates decided correctly using the original thresholds.
+    pub original_accuracy: f64,
+    /// The fraction of candidates decided correctly using the tuned thresholds.
+    pub tuned_accuracy: f64,
+    /// The tuned thresholds.
+    pub config: DetectionConfig,
+}
+
+fn pause_accuracy(candidates: &[ReviewedCandidate], min_vocal_pause: f32) -> f64 {
+    let num_correct = candidates
+        .iter()
+        .filter(|candidate| {
+            let accepted = candidate.vocal_pause >= min_vocal_pause;
+            accepted == (candidate.decision == Decision::Accepted)
+        })
+        .count();
+    num_correct as f64 / candidates.len() as f64
+}
+
+/// Reads the reviewed candidates from an annotated matches file.
+fn read_reviewed_candidates(
+    matches_file_path: &Path,
+    keywords: &Keywords,
+) -> Result<Vec<ReviewedCandidate>> {
+    let matches =
+        compression::read_to_string(matches_file_path).io_context("Failed to read matches file")?;
+
+    let mut candidates = Vec::new();
+    let mut prev_token: Option<Token> = None;
+    for_each_result(&matches, |line_number, alternatives, decision| {
+        if alternatives.is_empty() {
+            return;
+        }
+
+        let tokens = get_best_alt(alternatives, keywords)
+            .result
+            .iter()
+            .map(Token::from)
+            .collect::<Vec<_>>();
+
+        if let Some(decision) = decision {
+            match tokens
+                .iter()
+                .position(|token| keywords.is_chapter_token(token))
+            {
+                Some(chapter_index) => {
+                    let chapter_token = &tokens[chapter_index];
+                    let before = match chapter_index {
+                        0 => prev_token.as_ref(),
+                        _ => tokens.get(chapter_index - 1),
+                    };
+                    candidates.push(ReviewedCandidate {
+                        decision,
+                        vocal_pause: before
+                            .map(|before| chapter_token.start.secs_since(before.end))
+                            .unwrap_or(f32::INFINITY),
+                    });
+                }
+                None => log::warn!(
+                    "Skipping reviewed line {} of matches file: no chapter token in best alternative",
+                    line_number
+                ),
+            }
+        }
+
+        if let Some(last_token) = tokens.last() {
+            prev_token = Some(last_token.clone());
+        }
+    })?;
+
+    Ok(candidates)
+}
+
+/// Suggests detection thresholds that would have decided the reviewed candidates in the matches
+/// file as accurately as possible, starting from the original config. Only the thresholds that
+/// can be derived from the matches file are tuned.
+pub fn tune(matches_file_path: &Path, original: &DetectionConfig) -> Result<TuneReport> {
+    let candidates = read_reviewed_candidates(matches_file_path, &Keywords::new(original))?;
+    if candidates.is_empty() {
+        return Err(ChapterizerError::InvalidOptions(
+            "matches file contains no reviewed candidates",
+        ));
+    }
+
+    // The accuracy only changes between observed pauses, so try the midpoints between them,
+    // rounded to milliseconds to keep the config readable
+    let mut pauses = candidates
+        .iter()
+        .map(|candidate| candidate.vocal_pause)
+        .filter(|pause| pause.is_finite())
+        .collect::<Vec<_>>();
+    pauses.sort_by(f32::total_cmp);
+    pauses.dedup();
+    let thresholds = std::iter::once(0.0)
+        .chain(
+            pauses
+                .windows(2)
+                .map(|pair| ((pair[0] + pair[1]) / 2.0 * 1000.0).round() / 1000.0),
+        )
+        .chain(pauses.last().map(|max| max + 0.01));
+
+    // On a tie, prefer the threshold closest to the original
+    let original_pause = original.min_vocal_pause_before_chapter;
+    let best_pause = thresholds
+        .max_by(|a, b| {
+            pause_accuracy(&candidates, *a)
+                .total_cmp(&pause_accuracy(&candidates, *b))
+                .then_with(|| {
+                    (b - original_pause)
+                        .abs()
+                        .total_cmp(&(a - original_pause).abs())
+                })
+        })
+        .expect("there should be at least one threshold");
+
+    let mut config = original.clone();
+    config.min_vocal_pause_before_chapter = best_pause;
+    // The pause was tuned as a fixed length, so it shouldn't be adapted to the narrator
+    config.adaptive_pauses = false;
+
+    Ok(TuneReport {
+        num_candidates: candidates.len(),
+        original_accuracy: pause_accuracy(&candidates, original_pause),
+        tuned_accuracy: pause_accuracy(&candidates, best_pause),
+        config,
+    })
+}
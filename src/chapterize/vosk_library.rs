@@ -0,0 +1,155 @@
+use std::{
+    env,
+    ffi::{c_char, c_int, c_short, c_void},
+    sync::OnceLock,
+};
+
+use libloading::Library;
+
+use crate::error::{ChapterizerError, Result};
+
+/// The file name of the native Vosk library on the target platform.
+#[cfg(windows)]
+const LIBRARY_FILE_NAME: &str = "libvosk.dll";
+#[cfg(target_os = "macos")]
+const LIBRARY_FILE_NAME: &str = "libvosk.dylib";
+#[cfg(not(any(windows, target_os = "macos")))]
+const LIBRARY_FILE_NAME: &str = "libvosk.so";
+
+/// The functions of the library once it's loaded.
+static FUNCTIONS: OnceLock<Functions> = OnceLock::new();
+
+/// Declares the functions of the Vosk C API, as the vosk crate links against them. Opaque pointers
+/// are passed as `c_void` pointers, which have the same representation. Each function is defined
+/// here under its own name, so that the vosk crate links against these definitions instead of the
+/// library itself (see build.rs), and calls the function of the loaded library.
+macro_rules! vosk_functions {
+    ($(fn $name:ident($($arg:ident: $arg_type:ty),*) $(-> $ret:ty)?;)*) => {
+        struct Functions {
+            /// Keeps the library loaded for as long as its functions may be called.
+            _library: Library,
+            $($name: unsafe extern "C" fn($($arg_type),*) $(-> $ret)?,)*
+        }
+
+        impl Functions {
+            /// Looks up each of the functions in the library.
+            ///
+            /// # Safety
+            ///
+            /// The library must be the Vosk library, whose functions have these signatures.
+            unsafe fn get(library: Library) -> std::result::Result<Self, libloading::Error> {
+                Ok(Self {
+                    $($name: *library.get(concat!(stringify!($name), "\0").as_bytes())?,)*
+                    _library: library,
+                })
+            }
+        }
+
+        $(
+            #[no_mangle]
+            unsafe extern "C" fn $name($($arg: $arg_type),*) $(-> $ret)? {
+                (functions().$name)($($arg),*)
+            }
+        )*
+    };
+}
+
+vosk_functions! {
+    fn vosk_model_new(model_path: *const c_char) -> *mut c_void;
+    fn vosk_model_free(model: *mut c_void);
+    fn vosk_model_find_word(model: *mut c_void, word: *const c_char) -> c_int;
+    fn vosk_spk_model_new(model_path: *const c_char) -> *mut c_void;
+    fn vosk_spk_model_free(model: *mut c_void);
+    fn vosk_recognizer_new(model: *mut c_void, sample_rate: f32) -> *mut c_void;
+    fn vosk_recognizer_new_spk(
+        model: *mut c_void,
+        sample_rate: f32,
+        spk_model: *mut c_void
+    ) -> *mut c_void;
+    fn vosk_recognizer_new_grm(
+        model: *mut c_void,
+        sample_rate: f32,
+        grammar: *const c_char
+    ) -> *mut c_void;
+    fn vosk_recognizer_set_spk_model(recognizer: *mut c_void, spk_model: *mut c_void);
+    fn vosk_recognizer_set_max_alternatives(recognizer: *mut c_void, max_alternatives: c_int);
+    fn vosk_recognizer_set_words(recognizer: *mut c_void, words: c_int);
+    fn vosk_recognizer_set_partial_words(recognizer: *mut c_void, partial_words: c_int);
+    fn vosk_recognizer_set_nlsml(recognizer: *mut c_void, nlsml: c_int);
+    fn vosk_recognizer_accept_waveform(
+        recognizer: *mut c_void,
+        data: *const c_char,
+        length: c_int
+    ) -> c_int;
+    fn vosk_recognizer_accept_waveform_s(
+        recognizer: *mut c_void,
+        data: *const c_short,
+        length: c_int
+    ) -> c_int;
+    fn vosk_recognizer_accept_waveform_f(
+        recognizer: *mut c_void,
+        data: *const f32,
+        length: c_int
+    ) -> c_int;
+    fn vosk_recognizer_result(recognizer: *mut c_void) -> *const c_char;
+    fn vosk_recognizer_partial_result(recognizer: *mut c_void) -> *const c_char;
+    fn vosk_recognizer_final_result(recognizer: *mut c_void) -> *const c_char;
+    fn vosk_recognizer_reset(recognizer: *mut c_void);
+    fn vosk_recognizer_free(recognizer: *mut c_void);
+    fn vosk_set_log_level(log_level: c_int);
+    fn vosk_gpu_init();
+    fn vosk_gpu_thread_init();
+    fn vosk_batch_model_new() -> *mut c_void;
+    fn vosk_batch_model_free(model: *mut c_void);
+    fn vosk_batch_model_wait(model: *mut c_void);
+    fn vosk_batch_recognizer_new(model: *mut c_void, sample_rate: f32) -> *mut c_void;
+    fn vosk_batch_recognizer_free(recognizer: *mut c_void);
+    fn vosk_batch_recognizer_accept_waveform(
+        recognizer: *mut c_void,
+        data: *const c_char,
+        length: c_int
+    );
+    fn vosk_batch_recognizer_set_nlsml(recognizer: *mut c_void, nlsml: c_int);
+    fn vosk_batch_recognizer_finish_stream(recognizer: *mut c_void);
+    fn vosk_batch_recognizer_front_result(recognizer: *mut c_void) -> *const c_char;
+    fn vosk_batch_recognizer_pop(recognizer: *mut c_void);
+    fn vosk_batch_recognizer_get_pending_chunks(recognizer: *mut c_void) -> c_int;
+}
+
+fn functions() -> &'static Functions {
+    FUNCTIONS
+        .get()
+        .expect("the Vosk library is used before it's loaded")
+}
+
+/// Loads the Vosk library, unless it already was, from the directory of the executable or else
+/// from the directories that are searched for libraries. Returns an error naming the library if
+/// it's missing, which is only an error for runs that use speech recognition.
+pub fn load() -> Result<()> {
+    if FUNCTIONS.get().is_some() {
+        return Ok(());
+    }
+
+    let next_to_executable = env::current_exe()
+        .ok()
+        .map(|path| path.with_file_name(LIBRARY_FILE_NAME))
+        .filter(|path| path.is_file());
+    // SAFETY: loading the library runs its initializers, which is what linking it would do too
+    let library = match next_to_executable {
+        Some(path) => unsafe { Library::new(path) },
+        None => unsafe { Library::new(LIBRARY_FILE_NAME) },
+    };
+    // SAFETY: the library is the Vosk library, whose functions have the declared signatures
+    let functions = library.and_then(|library| unsafe { Functions::get(library) });
+    match functions {
+        Ok(functions) => {
+            // If another thread loaded it in the meantime, its functions are used instead
+            let _ = FUNCTIONS.set(functions);
+            Ok(())
+        }
+        Err(err) => {
+            log::debug!("Failed to load {}: {}", LIBRARY_FILE_NAME, err);
+            Err(ChapterizerError::VoskLibrary(LIBRARY_FILE_NAME))
+        }
+    }
+}
@@ -0,0 +1,170 @@
+use super::{keywords::Keywords, results_parser::get_best_alt, token::Token};
+use crate::{
+    error::{IoResultExt, Result},
+    fixed_vec_deque::FixedVecDeque,
+    timestamp::{AudioTimestamp, SampleOffset},
+};
+use std::{fs, path::Path};
+use vosk::{Alternative, CompleteResult, DecodingState, Recognizer};
+
+/// The amount of recent audio kept around so that candidate windows can be transcribed again.
+const HISTORY_SECS: f32 = 60.0;
+
+/// The margin of audio around a candidate window given to the recognizer as acoustic context.
+const WINDOW_MARGIN_SECS: f32 = 0.5;
+
+/// A transcript of a window of audio.
+#[derive(Clone, Debug)]
+pub struct Transcript {
+    pub confidence: f32,
+    pub tokens: Vec<Token>,
+}
+
+impl Transcript {
+    /// Creates a transcript from the alternative, whose words are timed relative to the offset.
+    pub fn from_alt(alt: &Alternative, offset: AudioTimestamp) -> Self {
+        Self {
+            confidence: alt.confidence,
+            tokens: alt
+                .result
+                .iter()
+                .map(|wia| {
+                    let mut token = Token::from(wia);
+                    token.start = token.start.relative_to(offset);
+                    token.end = token.end.relative_to(offset);
+                    token
+                })
+                .collect(),
+        }
+    }
+}
+
+/// A window of audio, along with where it starts in the file.
+pub struct AudioWindow {
+    pub samples: Vec<i16>,
+    pub offset: AudioTimestamp,
+}
+
+impl AudioWindow {
+    /// Transcribes the window from scratch using the recognizer, picking the best alternative of
+    /// each result.
+    pub fn transcribe(&self, recognizer: &mut Recognizer, keywords: &Keywords) -> Transcript {
+        recognizer.reset();
+
+        let mut transcripts = Vec::new();
+        let mut add_result = |result: CompleteResult| {
+            let multi = result.multiple().unwrap();
+            if !multi.alternatives.is_empty() {
+                transcripts.push(Transcript::from_alt(
+                    get_best_alt(&multi.alternatives, keywords),
+                    self.offset,
+                ));
+            }
+        };
+        if let DecodingState::Finalized = recognizer.accept_waveform(&self.samples) {
+            add_result(recognizer.result());
+        }
+        add_result(recognizer.final_result());
+
+        // The recognizer may split the window into several results, so join them up again
+        let confidence =
+            transcripts.iter().map(|t| t.confidence).sum::<f32>() / transcripts.len().max(1) as f32;
+        Transcript {
+            confidence,
+            tokens: transcripts.into_iter().flat_map(|t| t.tokens).collect(),
+        }
+    }
+
+    /// Writes the window to a 16-bit mono WAV file.
+    pub fn write_wav(&self, path: &Path, sample_rate: u32) -> Result<()> {
+        let data_len = (self.samples.len() * 2) as u32;
+        let mut wav = Vec::with_capacity(44 + data_len as usize);
+        wav.extend_from_slice(b"RIFF");
+        wav.extend_from_slice(&(36 + data_len).to_le_bytes());
+        wav.extend_from_slice(b"WAVEfmt ");
+        wav.extend_from_slice(&16u32.to_le_bytes());
+        // PCM, mono
+        wav.extend_from_slice(&1u16.to_le_bytes());
+        wav.extend_from_slice(&1u16.to_le_bytes());
+        wav.extend_from_slice(&sample_rate.to_le_bytes());
+        // Byte rate and block align
+        wav.extend_from_slice(&(sample_rate * 2).to_le_bytes());
+        wav.extend_from_slice(&2u16.to_le_bytes());
+        wav.extend_from_slice(&16u16.to_le_bytes());
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&data_len.to_le_bytes());
+        for sample in &self.samples {
+            wav.extend_from_slice(&sample.to_le_bytes());
+        }
+        fs::write(path, wav).io_context("Failed to write WAV file")
+    }
+}
+
+/// Keeps the most recent audio around, so that windows of it can be transcribed again.
+pub struct AudioHistory {
+    enabled: bool,
+    sample_rate: u32,
+    history: FixedVecDeque<i16>,
+    /// The total number of samples pushed, including the ones no longer in the history.
+    samples_pushed: SampleOffset,
+}
+
+impl AudioHistory {
+    /// Creates a history of the most recent audio. If not enabled, no audio is kept.
+    pub fn new(sample_rate: u32, enabled: bool) -> Self {
+        let max_len = if enabled {
+            (HISTORY_SECS * sample_rate as f32) as usize
+        } else {
+            0
+        };
+        Self {
+            enabled,
+            sample_rate,
+            history: FixedVecDeque::with_max_len(max_len),
+            samples_pushed: SampleOffset::ZERO,
+        }
+    }
+
+    pub fn push_samples(&mut self, samples: &[i16]) {
+        if !self.enabled {
+            return;
+        }
+        for &sample in samples {
+            self.history.push_back(sample);
+        }
+        self.samples_pushed += samples.len() as u64;
+    }
+
+    /// Returns the window between start and end, plus a margin. Only the most recent audio is
+    /// kept, so the window is cut off if it starts too long ago.
+    pub fn window(&self, start: AudioTimestamp, end: AudioTimestamp) -> AudioWindow {
+        self.window_with_margin(start, end, WINDOW_MARGIN_SECS)
+    }
+
+    /// Like [`AudioHistory::window`], but with the given margin (in seconds).
+    pub fn window_with_margin(
+        &self,
+        start: AudioTimestamp,
+        end: AudioTimestamp,
+        margin: f32,
+    ) -> AudioWindow {
+        let history_start = self.samples_pushed - self.history.len() as u64;
+        let to_sample_offset = |timestamp: AudioTimestamp| {
+            SampleOffset::from_timestamp(timestamp, self.sample_rate)
+                .clamp(history_start, self.samples_pushed)
+        };
+        let start_offset = to_sample_offset(start.add_secs(-margin));
+        let end_offset = to_sample_offset(end.add_secs(margin));
+
+        AudioWindow {
+            samples: self
+                .history
+                .range(
+                    (start_offset - history_start) as usize..(end_offset - history_start) as usize,
+                )
+                .copied()
+                .collect(),
+            offset: start_offset.to_timestamp(self.sample_rate),
+        }
+    }
+}
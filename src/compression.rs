@@ -0,0 +1,65 @@
+use std::{
+    fs::File,
+    io::{self, BufReader, Read, Write},
+    path::{Path, PathBuf},
+};
+
+/// How a sidecar file, e.g. a matches or transcript file, is compressed. These files get large on
+/// long books, so they're compressed if their path ends in .gz or .zst.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileCompression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl FileCompression {
+    /// Returns the compression that the extension of the path stands for.
+    pub fn from_path(path: &Path) -> Self {
+        match path
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_lowercase())
+            .as_deref()
+        {
+            Some("gz") => Self::Gzip,
+            Some("zst") => Self::Zstd,
+            _ => Self::None,
+        }
+    }
+
+    /// Returns the path without the extension of the compression, e.g. "book.jsonl" for
+    /// "book.jsonl.gz".
+    pub fn strip_extension(self, path: &Path) -> PathBuf {
+        match self {
+            Self::None => path.to_path_buf(),
+            Self::Gzip | Self::Zstd => path.with_extension(""),
+        }
+    }
+}
+
+/// Creates the file, compressing what's written to it if its path ends in .gz or .zst. The
+/// compressed stream is finished when the writer is dropped.
+pub fn create_file(path: &Path) -> io::Result<Box<dyn Write + Send>> {
+    let file = File::create(path)?;
+    Ok(match FileCompression::from_path(path) {
+        FileCompression::None => Box::new(file),
+        FileCompression::Gzip => Box::new(flate2::write::GzEncoder::new(
+            file,
+            flate2::Compression::default(),
+        )),
+        FileCompression::Zstd => Box::new(zstd::Encoder::new(file, 0)?.auto_finish()),
+    })
+}
+
+/// Reads the file into a string, decompressing it if its path ends in .gz or .zst.
+pub fn read_to_string(path: &Path) -> io::Result<String> {
+    let file = BufReader::new(File::open(path)?);
+    let mut reader: Box<dyn Read> = match FileCompression::from_path(path) {
+        FileCompression::None => Box::new(file),
+        FileCompression::Gzip => Box::new(flate2::bufread::MultiGzDecoder::new(file)),
+        FileCompression::Zstd => Box::new(zstd::Decoder::with_buffer(file)?),
+    };
+    let mut contents = String::new();
+    reader.read_to_string(&mut contents)?;
+    Ok(contents)
+}
@@ -0,0 +1,121 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::{
+    error::{ChapterizerError, IoResultExt, Result},
+    evaluate::{ratio, EvaluationReport},
+    stats::DurationStats,
+};
+
+/// An entry of a corpus manifest.
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct CorpusEntry {
+    /// The audio file to detect chapters in, or a matches file (ending in .jsonl) to detect
+    /// chapters in the recognition results of.
+    pub input: PathBuf,
+    /// The chapter file with the correct chapters.
+    pub reference: PathBuf,
+}
+
+impl CorpusEntry {
+    pub fn is_matches_file(&self) -> bool {
+        self.input.extension().is_some_and(|ext| ext == "jsonl")
+    }
+}
+
+/// Reads a corpus manifest, which is a JSON array of entries. Relative paths in the manifest are
+/// relative to the directory of the manifest.
+pub fn read_manifest(manifest_file_path: &Path) -> Result<Vec<CorpusEntry>> {
+    let manifest =
+        fs::read_to_string(manifest_file_path).io_context("Failed to read corpus manifest")?;
+    let entries: Vec<CorpusEntry> =
+        serde_json::from_str(&manifest).map_err(|source| ChapterizerError::Json {
+            context: "Failed to parse corpus manifest".into(),
+            source,
+        })?;
+
+    let base_dir = manifest_file_path.parent().unwrap_or(Path::new(""));
+    Ok(entries
+        .into_iter()
+        .map(|entry| CorpusEntry {
+            input: base_dir.join(entry.input),
+            reference: base_dir.join(entry.reference),
+        })
+        .collect())
+}
+
+/// The score of a single corpus entry.
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct EntryScore {
+    pub input: PathBuf,
+    pub num_detected: usize,
+    pub num_reference: usize,
+    pub num_matched: usize,
+    pub precision: f64,
+    pub recall: f64,
+    pub start_error: Option<DurationStats>,
+    /// The error that prevented the entry from being scored, if any.
+    pub error: Option<String>,
+}
+
+/// The scores of all entries in a corpus, along with the aggregate scores. The aggregate precision
+/// and recall are calculated over all chapters in the corpus, so that books with many chapters
+/// weigh more than books with few.
+#[derive(Clone, Debug, Default, serde::Serialize)]
+pub struct Scoreboard {
+    pub entries: Vec<EntryScore>,
+    pub num_failed: usize,
+    pub num_detected: usize,
+    pub num_reference: usize,
+    pub num_matched: usize,
+    pub precision: f64,
+    pub recall: f64,
+    pub start_error: Option<DurationStats>,
+    #[serde(skip)]
+    start_errors: Vec<Duration>,
+}
+
+impl Scoreboard {
+    pub fn add(&mut self, input: PathBuf, report: &EvaluationReport) {
+        self.entries.push(EntryScore {
+            input,
+            num_detected: report.num_detected,
+            num_reference: report.num_reference,
+            num_matched: report.num_matched,
+            precision: report.precision,
+            recall: report.recall,
+            start_error: report.start_error,
+            error: None,
+        });
+
+        self.num_detected += report.num_detected;
+        self.num_reference += report.num_reference;
+        self.num_matched += report.num_matched;
+        self.start_errors.extend_from_slice(&report.start_errors);
+        self.update_aggregates();
+    }
+
+    /// Adds an entry that couldn't be scored. It isn't included in the aggregate scores.
+    pub fn add_failure(&mut self, input: PathBuf, error: String) {
+        self.entries.push(EntryScore {
+            input,
+            num_detected: 0,
+            num_reference: 0,
+            num_matched: 0,
+            precision: 0.0,
+            recall: 0.0,
+            start_error: None,
+            error: Some(error),
+        });
+        self.num_failed += 1;
+    }
+
+    fn update_aggregates(&mut self) {
+        self.precision = ratio(self.num_matched, self.num_detected);
+        self.recall = ratio(self.num_matched, self.num_reference);
+        self.start_error = DurationStats::from_durations(&self.start_errors);
+    }
+}
@@ -1,10 +1,15 @@
-use lazy_static::lazy_static;
-use regex::Regex;
-use std::{io::Write, path::Path, time::Duration};
+use std::{
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
-use color_eyre::eyre::{self, eyre, Context};
-
-use crate::chapter_writer::ChapterWriter;
+use crate::{
+    chapter::{Chapter, ChapterList, ChapterSource, RejectedCandidate},
+    chapter_writer::{is_already_written, read_partial_output, reopen_for_append, ChapterWriter},
+    error::{ChapterizerError, IoResultExt, Result},
+};
 
 /// There are 75 frames in one second
 const CUE_FRAMES_PER_SECOND: f32 = 75.0;
@@ -17,36 +22,255 @@ pub fn duration_to_cue_index(duration: Duration) -> String {
     format!("{}:{}:{:02}", minutes, seconds, frames)
 }
 
-pub struct CueWriter {
-    writer: Box<dyn Write>,
+/// Parses a cue index in the format mm:ss:ff, e.g. "12:34:56".
+pub fn cue_index_to_duration(index: &str) -> Option<Duration> {
+    let mut parts = index.split(':');
+    let minutes = parts.next()?.parse::<u64>().ok()?;
+    let seconds = parts.next()?.parse::<u64>().ok()?;
+    let frames = parts.next()?.parse::<u32>().ok()?;
+    if parts.next().is_some() || seconds >= 60 || frames as f32 >= CUE_FRAMES_PER_SECOND {
+        return None;
+    }
+
+    Some(
+        Duration::from_secs(minutes * 60 + seconds)
+            + Duration::from_secs_f32(frames as f32 / CUE_FRAMES_PER_SECOND),
+    )
+}
+
+/// Quotes the string for a cue sheet, escaping double quotes and backslashes with a backslash as
+/// most cue sheet readers expect. Line breaks and other control characters can't be represented,
+/// so line breaks are replaced with spaces and the others are removed, with a warning. Tabs are
+/// replaced with spaces.
+fn quote_string(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    let mut has_control_chars = false;
+    for c in s.trim().chars() {
+        match c {
+            '"' | '\\' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            '\r' | '\n' => {
+                has_control_chars = true;
+                // Collapse CRLF into a single space
+                if !quoted.ends_with(' ') {
+                    quoted.push(' ');
+                }
+            }
+            '\t' => quoted.push(' '),
+            c if c.is_control() => has_control_chars = true,
+            c => quoted.push(c),
+        }
+    }
+    if has_control_chars {
+        log::warn!(
+            "Removed line breaks or control characters from \"{}\" in cue sheet, since they \
+            can't be represented",
+            quoted.trim_end()
+        );
+    }
+    format!("\"{}\"", quoted.trim_end())
+}
+
+/// Parses a string in a cue sheet, which is either quoted, with backslash escapes, or a single
+/// unquoted word.
+fn unquote_string(s: &str) -> String {
+    let Some(quoted) = s.strip_prefix('"') else {
+        return s.to_string();
+    };
+    let mut unquoted = String::with_capacity(quoted.len());
+    let mut chars = quoted.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(escaped @ ('"' | '\\')) => unquoted.push(escaped),
+                // Other backslashes are literal, e.g. in Windows paths
+                Some(other) => {
+                    unquoted.push('\\');
+                    unquoted.push(other);
+                }
+                None => unquoted.push('\\'),
+            },
+            '"' => break,
+            c => unquoted.push(c),
+        }
+    }
+    unquoted
+}
+
+/// Parses the tracks of a cue sheet as chapters. Cue sheets don't record the duration of the
+/// audio, so the duration of the chapter list is taken to be the start of the last track.
+pub fn parse_cue(contents: &str) -> Result<ChapterList> {
+    let parse_error = |line: usize, message: String| ChapterizerError::ChapterParse {
+        format: "cue",
+        line,
+        message,
+    };
+
+    let mut chapters: Vec<Chapter> = Vec::new();
+    // The title and start of the current track, which are only known once all its lines are read
+    let mut track: Option<(Option<String>, Option<Duration>)> = None;
+    let mut push_track =
+        |track: Option<(Option<String>, Option<Duration>)>, line: usize| match track {
+            Some((title, Some(start))) => {
+                let title = title.unwrap_or_else(|| format!("Track {}", chapters.len() + 1));
+                chapters.push(Chapter::new(start, title, ChapterSource::UserEdit));
+                Ok(())
+            }
+            Some((_, None)) => Err(parse_error(line, "track has no INDEX 01".into())),
+            None => Ok(()),
+        };
+
+    for (index, line) in contents.lines().enumerate() {
+        let line_num = index + 1;
+        let (command, args) = line.trim().split_once(' ').unwrap_or((line.trim(), ""));
+        // Commands are case-insensitive
+        let command = command.to_ascii_uppercase();
+        if command == "TRACK" {
+            push_track(track.replace((None, None)), line_num)?;
+            continue;
+        }
+        // Titles and indices before the first track apply to the whole disc
+        let Some((title, start)) = &mut track else {
+            continue;
+        };
+        match command.as_str() {
+            "TITLE" => *title = Some(unquote_string(args.trim())),
+            "INDEX" => {
+                if let Some(("01", index)) = args.trim().split_once(' ') {
+                    *start = Some(cue_index_to_duration(index.trim()).ok_or_else(|| {
+                        parse_error(line_num, format!("invalid index: {}", index.trim()))
+                    })?);
+                }
+            }
+            _ => {}
+        }
+    }
+    push_track(track, contents.lines().count())?;
+
+    let duration = chapters
+        .last()
+        .map(|chapter| chapter.start)
+        .unwrap_or_default();
+    Ok(ChapterList { chapters, duration })
+}
+
+/// The text encoding of cue sheets.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Deserialize)]
+pub enum CueEncoding {
+    #[default]
+    #[serde(rename = "utf8")]
+    Utf8,
+    /// UTF-8 with a byte order mark, which some players need to detect that the cue sheet isn't
+    /// in a legacy encoding.
+    #[serde(rename = "utf8-bom")]
+    Utf8Bom,
+}
+
+/// Which of the fields of the chapters that cue sheets have no commands for are written as `REM`
+/// comments under their tracks, so that they aren't lost when the cue sheet is the only output.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CueRemFields {
+    #[default]
+    None,
+    /// Where the chapter came from and, for chapters found by speech recognition, the confidence
+    /// of their announcement, e.g. `REM SOURCE asr` and `REM CONFIDENCE 0.83`.
+    Basic,
+    /// The basic fields, along with the announcement of the chapter as it was recognized and
+    /// which detectors agree on it if it was cross-checked, e.g. `REM ANNOUNCEMENT "chapter 7"`
+    /// and `REM AGREEMENT 0.67 "asr silence"`.
+    Full,
+}
+
+/// The options of the cue writer.
+#[derive(Clone, Debug, Default, PartialEq, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CueOptions {
+    pub encoding: CueEncoding,
+    pub rem_fields: CueRemFields,
+}
+
+pub struct CueWriter<W: Write> {
+    writer: W,
+    options: CueOptions,
     track_num: usize,
     header_written: bool,
+    /// The start of the last chapter that was already in the file when it was reopened.
+    resume_after: Option<Duration>,
 }
 
 // TODO: double check encoding, is ASCII required or is UTF8 ok?
-impl CueWriter {
-    pub fn new(writer: Box<dyn Write>) -> Self {
+impl<W: Write> CueWriter<W> {
+    pub fn new(writer: W, options: &CueOptions) -> Self {
         Self {
             writer,
+            options: options.clone(),
             track_num: 1,
             header_written: false,
+            resume_after: None,
         }
     }
 
-    fn sanitize_string<T: AsRef<str>>(s: T) -> String {
-        lazy_static! {
-            static ref SANITIZE_STRING_REGEX: Regex = Regex::new("[\r\n\"\\\\]+").unwrap();
+    /// Returns the underlying writer, without flushing it.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    /// Flushes the underlying writer and returns it.
+    pub fn finalize(mut self) -> Result<W> {
+        self.writer.flush().io_context("Failed to flush cue file")?;
+        Ok(self.writer)
+    }
+}
+
+impl CueWriter<File> {
+    /// Reopens a cue sheet written by a previous run that may have been cut off, to continue
+    /// appending to it. Whatever was written of a track that's incomplete is dropped, and the
+    /// header is written if it's missing. Returns the writer along with the tracks that were
+    /// already in the file. Chapters up to the last of those are skipped, so that the chapters can
+    /// be passed to the writer from the start again. If the file doesn't exist, it's created.
+    pub fn reopen(
+        path: &Path,
+        options: &CueOptions,
+        audio_file_path: &Path,
+    ) -> Result<(Self, ChapterList)> {
+        let contents = read_partial_output(path)?;
+
+        // The file is complete up to the end of the header or of the last track's index
+        let mut complete_len = 0;
+        let mut offset = 0;
+        for line in contents.split_inclusive('\n') {
+            offset += line.len();
+            let line = line.trim_start_matches('\u{feff}').trim();
+            let is_complete_line = contents[..offset].ends_with('\n');
+            if is_complete_line && (line.starts_with("FILE ") || line.starts_with("INDEX 01 ")) {
+                complete_len = offset;
+            }
+        }
+        let chapter_list = parse_cue(&contents[..complete_len])?;
+
+        let file = reopen_for_append(path, complete_len)?;
+        let mut cue_writer = Self::new(file, options);
+        if complete_len == 0 {
+            cue_writer.write_header(audio_file_path)?;
+        } else {
+            cue_writer.header_written = true;
         }
+        cue_writer.track_num = chapter_list.chapters.len() + 1;
+        cue_writer.resume_after = chapter_list.chapters.last().map(|chapter| chapter.start);
 
-        SANITIZE_STRING_REGEX
-            .replace_all(s.as_ref(), "")
-            .trim()
-            .to_string()
+        Ok((cue_writer, chapter_list))
     }
+}
 
-    pub fn write_header(&mut self, audio_file_path: &Path) -> eyre::Result<()> {
+impl<W: Write> CueWriter<W> {
+    pub fn write_header(&mut self, audio_file_path: &Path) -> Result<()> {
         if self.header_written {
-            return Err(eyre!("Failed to write cue header: header already written"));
+            return Err(ChapterizerError::InvalidState(
+                "Failed to write cue header: header already written",
+            ));
         }
 
         let file_name = audio_file_path.file_name().unwrap().to_string_lossy();
@@ -61,53 +285,378 @@ impl CueWriter {
             None => "BINARY",
         };
 
-        let cue_header = &format!(
-            "FILE \"{}\" {}",
-            &Self::sanitize_string(file_name),
-            file_type
-        );
+        let cue_header = &format!("FILE {} {}", quote_string(&file_name), file_type);
+
+        if self.options.encoding == CueEncoding::Utf8Bom {
+            self.writer
+                .write_all("\u{feff}".as_bytes())
+                .io_context("Failed to write cue header")?;
+        }
 
         self.writer
             .write_all((format!("{}\n", cue_header)).as_bytes())
-            .wrap_err("Failed to write cue header")?;
+            .io_context("Failed to write cue header")?;
 
         self.header_written = true;
 
         Ok(())
     }
 
-    pub fn write_track(&mut self, start_time: Duration, title: &str) -> eyre::Result<()> {
+    pub fn write_track(&mut self, start_time: Duration, title: &str) -> Result<()> {
+        self.write_track_with_comments(start_time, title, &[])
+    }
+
+    /// Writes a track with the comments as `REM` lines. They're written before its index, so that
+    /// the track is complete once its index is written, as [`CueWriter::reopen`] expects.
+    pub fn write_track_with_comments(
+        &mut self,
+        start_time: Duration,
+        title: &str,
+        comments: &[String],
+    ) -> Result<()> {
         if !self.header_written {
-            return Err(eyre!("Failed to write cue track: must write header first"));
+            return Err(ChapterizerError::InvalidState(
+                "Failed to write cue track: must write header first",
+            ));
         }
 
-        let cue_track = unindent::unindent(&format!(
-            "
-                TRACK {} AUDIO
-                    TITLE \"{}\"
-                    INDEX 01 {}
-            ",
+        let mut cue_track = format!(
+            "TRACK {} AUDIO\n    TITLE {}\n",
             self.track_num,
-            &Self::sanitize_string(title),
-            duration_to_cue_index(start_time),
+            quote_string(title)
+        );
+        for comment in comments {
+            cue_track.push_str(&format!("    REM {}\n", comment));
+        }
+        cue_track.push_str(&format!(
+            "    INDEX 01 {}\n",
+            duration_to_cue_index(start_time)
         ));
 
         self.writer
             .write_all(cue_track.as_bytes())
-            .wrap_err("Failed to write cue track")?;
+            .io_context("Failed to write cue track")?;
 
         self.track_num += 1;
 
         Ok(())
     }
+
+    /// Returns the fields of the chapter that are written as comments under its track, as set by
+    /// the rem_fields option.
+    fn chapter_comments(&self, chapter: &Chapter) -> Vec<String> {
+        let mut comments = Vec::new();
+        if self.options.rem_fields >= CueRemFields::Basic {
+            comments.push(format!("SOURCE {}", chapter.source));
+            if let Some(confidence) = chapter.confidence {
+                comments.push(format!("CONFIDENCE {:.2}", confidence));
+            }
+        }
+        if self.options.rem_fields >= CueRemFields::Full {
+            if let Some(announcement) = &chapter.announcement {
+                comments.push(format!("ANNOUNCEMENT {}", quote_string(announcement)));
+            }
+            if let Some(agreement) = &chapter.agreement {
+                let sources = agreement
+                    .sources
+                    .iter()
+                    .map(ChapterSource::to_string)
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                comments.push(format!(
+                    "AGREEMENT {:.2} {}",
+                    agreement.confidence,
+                    quote_string(&sources)
+                ));
+            }
+        }
+        comments
+    }
+
+    /// Writes a rejected candidate as a comment, e.g.
+    /// `REM REJECTED 12:34:56 "chapter the" "no number after \"chapter\""`.
+    pub fn write_rejected_candidate(
+        &mut self,
+        start_time: Duration,
+        text: &str,
+        reason: &str,
+    ) -> Result<()> {
+        if !self.header_written {
+            return Err(ChapterizerError::InvalidState(
+                "Failed to write cue comment: must write header first",
+            ));
+        }
+
+        let indent = if self.track_num > 1 { "    " } else { "" };
+        let comment = format!(
+            "{}REM REJECTED {} {} {}\n",
+            indent,
+            duration_to_cue_index(start_time),
+            quote_string(text),
+            quote_string(reason)
+        );
+        self.writer
+            .write_all(comment.as_bytes())
+            .io_context("Failed to write cue comment")
+    }
+}
+
+impl<W: Write> ChapterWriter for CueWriter<W> {
+    fn on_chapter_start(&mut self, chapter: &Chapter) -> Result<()> {
+        if is_already_written(self.resume_after, chapter.start) {
+            return Ok(());
+        }
+        let comments = self.chapter_comments(chapter);
+        self.write_track_with_comments(chapter.start, &chapter.flat_title(), &comments)
+    }
+
+    fn on_end_of_file(&mut self, _file_duration: Duration) -> Result<()> {
+        Ok(())
+    }
+
+    fn on_rejected_candidate(&mut self, candidate: &RejectedCandidate) -> Result<()> {
+        if is_already_written(self.resume_after, candidate.start) {
+            return Ok(());
+        }
+        self.write_rejected_candidate(candidate.start, &candidate.text, candidate.reason)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.writer.flush().io_context("Failed to flush cue file")
+    }
 }
 
-impl ChapterWriter for CueWriter {
-    fn on_chapter_start(&mut self, start_time: Duration, title: &str) -> eyre::Result<()> {
-        self.write_track(start_time, title)
+/// Returns the path of the file for the given disc (starting at 1), e.g. "Book - Disc 02.cue" for
+/// "Book.cue".
+pub fn disc_file_path(path: &Path, disc_num: usize) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let file_name = match path.extension() {
+        Some(ext) => format!("{} - Disc {:02}.{}", stem, disc_num, ext.to_string_lossy()),
+        None => format!("{} - Disc {:02}", stem, disc_num),
+    };
+    path.with_file_name(file_name)
+}
+
+/// Checks that the start times of the discs after the first are after 0:00 and strictly
+/// increasing.
+pub fn verify_disc_starts(disc_starts: &[Duration]) -> Result<()> {
+    let mut prev_start = Duration::ZERO;
+    for &start in disc_starts {
+        if start <= prev_start {
+            return Err(ChapterizerError::InvalidOptions(
+                "disc start times must be after 0:00 and strictly increasing",
+            ));
+        }
+        prev_start = start;
     }
+    Ok(())
+}
+
+/// Writes one cue sheet per disc, for books ripped from CDs. Each cue sheet holds the tracks of its
+/// disc, numbered from 1. They all refer to the whole audio file, since the audio isn't split into
+/// discs, so the tracks are timed relative to the start of the file. If a disc starts in the
+/// middle of a chapter, the chapter is continued as the first track of the disc, starting at the
+/// start of the disc.
+pub struct DiscCueWriter<W: Write> {
+    /// The start time of each disc, paired with the writer of its cue sheet.
+    discs: Vec<(Duration, CueWriter<W>)>,
+    current_disc: usize,
+    current_title: Option<String>,
+}
+
+impl<W: Write> DiscCueWriter<W> {
+    /// Creates a writer for each disc and writes their headers. The first disc starts at 0:00, the
+    /// others at disc_starts, which must be strictly increasing. There must be one writer per disc.
+    pub fn new(
+        writers: Vec<W>,
+        disc_starts: &[Duration],
+        audio_file_path: &Path,
+        options: &CueOptions,
+    ) -> Result<Self> {
+        if writers.len() != disc_starts.len() + 1 {
+            return Err(ChapterizerError::InvalidOptions(
+                "number of cue writers does not match number of discs",
+            ));
+        }
+        verify_disc_starts(disc_starts)?;
+        let starts = std::iter::once(Duration::ZERO).chain(disc_starts.iter().copied());
+
+        let mut discs = Vec::with_capacity(writers.len());
+        for (start, writer) in starts.zip(writers) {
+            let mut cue_writer = CueWriter::new(writer, options);
+            cue_writer.write_header(audio_file_path)?;
+            discs.push((start, cue_writer));
+        }
+
+        Ok(Self {
+            discs,
+            current_disc: 0,
+            current_title: None,
+        })
+    }
+
+    /// Returns the underlying writers in the order of the discs, without flushing them.
+    pub fn into_inner(self) -> Vec<W> {
+        self.discs
+            .into_iter()
+            .map(|(_, cue_writer)| cue_writer.into_inner())
+            .collect()
+    }
+
+    /// Flushes the underlying writers and returns them in the order of the discs.
+    pub fn finalize(self) -> Result<Vec<W>> {
+        self.discs
+            .into_iter()
+            .map(|(_, cue_writer)| cue_writer.finalize())
+            .collect()
+    }
+
+    /// Moves on to the next disc while it starts before the given time (or at it, if inclusive).
+    fn advance_discs(&mut self, time: Duration, inclusive: bool) -> Result<()> {
+        while let Some((start, cue_writer)) = self.discs.get_mut(self.current_disc + 1) {
+            if *start > time || (*start == time && !inclusive) {
+                break;
+            }
+            self.current_disc += 1;
+
+            // The disc starts in the middle of the current chapter, so continue it on this disc
+            if *start < time {
+                if let Some(title) = &self.current_title {
+                    cue_writer.write_track(*start, title)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> ChapterWriter for DiscCueWriter<W> {
+    fn on_chapter_start(&mut self, chapter: &Chapter) -> Result<()> {
+        self.advance_discs(chapter.start, true)?;
+
+        let (_, cue_writer) = &mut self.discs[self.current_disc];
+        let title = chapter.flat_title().into_owned();
+        let comments = cue_writer.chapter_comments(chapter);
+        cue_writer.write_track_with_comments(chapter.start, &title, &comments)?;
+        self.current_title = Some(title);
 
-    fn on_end_of_file(&mut self, _file_duration: Duration) -> eyre::Result<()> {
         Ok(())
     }
+
+    fn on_end_of_file(&mut self, file_duration: Duration) -> Result<()> {
+        self.advance_discs(file_duration, false)
+    }
+
+    fn on_rejected_candidate(&mut self, candidate: &RejectedCandidate) -> Result<()> {
+        // Candidates are only found after the chapter they're in has started, so the current disc
+        // is usually the right one, unless a later disc started since
+        let (_, cue_writer) = self
+            .discs
+            .iter_mut()
+            .rev()
+            .find(|(start, _)| *start <= candidate.start)
+            .expect("the first disc starts at 0:00");
+        cue_writer.write_rejected_candidate(candidate.start, &candidate.text, candidate.reason)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        for (_, cue_writer) in &mut self.discs {
+            cue_writer.flush()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chapters(titles: &[&str]) -> Vec<Chapter> {
+        titles
+            .iter()
+            .enumerate()
+            .map(|(index, title)| {
+                let start = Duration::from_secs(60 * index as u64);
+                Chapter::new(start, *title, ChapterSource::UserEdit)
+            })
+            .collect()
+    }
+
+    fn titles(chapter_list: &ChapterList) -> Vec<&str> {
+        chapter_list
+            .chapters
+            .iter()
+            .map(|chapter| chapter.title.as_str())
+            .collect()
+    }
+
+    /// Quotes the title for a cue sheet and parses it back.
+    fn round_trip(title: &str) -> String {
+        let contents = format!(
+            "FILE \"book.m4b\" MP4\nTRACK 01 AUDIO\n    TITLE {}\n    INDEX 01 00:00:00\n",
+            quote_string(title)
+        );
+        parse_cue(&contents).unwrap().chapters.remove(0).title
+    }
+
+    #[test]
+    fn quotes_and_backslashes_round_trip() {
+        let title = r#"Bob's "Plan" \ C:\x"#;
+        assert_eq!(round_trip(title), title);
+    }
+
+    #[test]
+    fn line_breaks_and_tabs_are_replaced_with_spaces() {
+        assert_eq!(
+            round_trip("Part One\r\nThe Beginning"),
+            "Part One The Beginning"
+        );
+        assert_eq!(round_trip("Part Two\nThe End"), "Part Two The End");
+        assert_eq!(round_trip("Part\tThree"), "Part Three");
+    }
+
+    #[test]
+    fn parses_lowercase_commands() {
+        let contents = "rem\r\nfile \"book.m4b\" MP4\r\ntrack 01 audio\r\n  title \"One\"\r\n  \
+                        index 01 00:01:00\r\n";
+        let chapter_list = parse_cue(contents).unwrap();
+        assert_eq!(titles(&chapter_list), ["One"]);
+        assert_eq!(chapter_list.chapters[0].start, Duration::from_secs(1));
+    }
+
+    #[test]
+    fn reopen_continues_a_cut_off_cue_sheet() {
+        let audio_file_path = Path::new("book.m4b");
+        let chapters = chapters(&["One", "Two", "Three", "Four"]);
+        let mut writer = CueWriter::new(Vec::new(), &CueOptions::default());
+        writer.write_header(audio_file_path).unwrap();
+        for chapter in &chapters[..3] {
+            writer.on_chapter_start(chapter).unwrap();
+        }
+        let contents = String::from_utf8(writer.finalize().unwrap()).unwrap();
+        // Cut the file off in the middle of the index of the third track
+        let cut_off = &contents[..contents.rfind("INDEX 01").unwrap() + 5];
+
+        let path = std::env::temp_dir().join(format!(
+            "audiobook-chapterizer-reopen-{}.cue",
+            std::process::id()
+        ));
+        std::fs::write(&path, cut_off).unwrap();
+        let (mut writer, written) =
+            CueWriter::reopen(&path, &CueOptions::default(), audio_file_path).unwrap();
+        assert_eq!(titles(&written), ["One", "Two"]);
+        for chapter in &chapters {
+            writer.on_chapter_start(chapter).unwrap();
+        }
+        writer.finalize().unwrap();
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            titles(&parse_cue(&contents).unwrap()),
+            ["One", "Two", "Three", "Four"]
+        );
+        assert_eq!(contents.matches("FILE ").count(), 1);
+        assert_eq!(contents.matches("TRACK 3 ").count(), 1);
+    }
 }
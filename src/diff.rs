@@ -0,0 +1,145 @@
+use std::{fmt, time::Duration};
+
+use crate::{
+    chapter::{Chapter, ChapterList},
+    evaluate::{match_chapters, start_difference},
+    format_duration,
+};
+
+/// How far apart the starts of an existing chapter and a new one can be for the new one to count
+/// as the same chapter, moved.
+pub const DEFAULT_DIFF_TOLERANCE: Duration = Duration::from_secs(5);
+
+/// How far apart the starts of the same chapter can be for it to count as not moved. Cue sheets
+/// only hold times in frames of 1/75 s, so the times read back from them are slightly off.
+const UNMOVED_TOLERANCE: Duration = Duration::from_millis(50);
+
+/// A difference between the existing chapters and the new ones.
+#[derive(Clone, Debug)]
+pub enum ChapterChange {
+    /// A new chapter that doesn't match any existing one.
+    Added(Chapter),
+    /// An existing chapter that doesn't match any new one.
+    Removed(Chapter),
+    /// An existing chapter whose start or title differs from the new chapter it matches.
+    Changed { old: Chapter, new: Chapter },
+}
+
+impl ChapterChange {
+    /// The start of the chapter, for sorting the changes. The new start for changed chapters.
+    fn start(&self) -> Duration {
+        match self {
+            ChapterChange::Added(chapter) | ChapterChange::Removed(chapter) => chapter.start,
+            ChapterChange::Changed { new, .. } => new.start,
+        }
+    }
+}
+
+impl fmt::Display for ChapterChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ChapterChange::Added(chapter) => write!(
+                f,
+                "+ {} {:?}",
+                format_duration(&Some(chapter.start)),
+                chapter.flat_title()
+            ),
+            ChapterChange::Removed(chapter) => write!(
+                f,
+                "- {} {:?}",
+                format_duration(&Some(chapter.start)),
+                chapter.flat_title()
+            ),
+            ChapterChange::Changed { old, new } => {
+                write!(f, "~ {}", format_duration(&Some(old.start)))?;
+                if start_difference(old, new) > UNMOVED_TOLERANCE {
+                    write!(f, " -> {}", format_duration(&Some(new.start)))?;
+                }
+                write!(f, " {:?}", old.flat_title())?;
+                if old.flat_title() != new.flat_title() {
+                    write!(f, " -> {:?}", new.flat_title())?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// How the new chapters differ from the existing ones.
+#[derive(Clone, Debug)]
+pub struct ChapterDiff {
+    /// The changes, sorted by start time.
+    pub changes: Vec<ChapterChange>,
+    /// The number of existing chapters that match a new chapter with the same start and title.
+    pub num_unchanged: usize,
+}
+
+impl ChapterDiff {
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// Returns a one-line summary of the numbers of changes.
+    pub fn summary(&self) -> String {
+        let count = |f: fn(&ChapterChange) -> bool| self.changes.iter().filter(|c| f(c)).count();
+        format!(
+            "{} added, {} removed, {} changed, {} unchanged",
+            count(|c| matches!(c, ChapterChange::Added(_))),
+            count(|c| matches!(c, ChapterChange::Removed(_))),
+            count(|c| matches!(c, ChapterChange::Changed { .. })),
+            self.num_unchanged
+        )
+    }
+}
+
+/// Compares the new chapters against the existing ones. A new chapter is the same chapter as an
+/// existing one if their start times are at most the tolerance apart, matching the closest pairs
+/// first. Titles are compared including the section, and chapter ends aren't compared at all, as
+/// not every format holds them.
+pub fn diff_chapters(
+    existing: &ChapterList,
+    new: &ChapterList,
+    tolerance: Duration,
+) -> ChapterDiff {
+    let matches = match_chapters(&existing.chapters, &new.chapters, tolerance);
+
+    let mut changes = Vec::new();
+    let mut num_unchanged = 0;
+    let mut existing_matched = vec![false; existing.chapters.len()];
+    let mut new_matched = vec![false; new.chapters.len()];
+    for (e, n) in matches {
+        existing_matched[e] = true;
+        new_matched[n] = true;
+        let (old, new) = (&existing.chapters[e], &new.chapters[n]);
+        if start_difference(old, new) <= UNMOVED_TOLERANCE && old.flat_title() == new.flat_title() {
+            num_unchanged += 1;
+        } else {
+            changes.push(ChapterChange::Changed {
+                old: old.clone(),
+                new: new.clone(),
+            });
+        }
+    }
+
+    changes.extend(
+        existing
+            .chapters
+            .iter()
+            .zip(&existing_matched)
+            .filter(|(_, &matched)| !matched)
+            .map(|(chapter, _)| ChapterChange::Removed(chapter.clone())),
+    );
+    changes.extend(
+        new.chapters
+            .iter()
+            .zip(&new_matched)
+            .filter(|(_, &matched)| !matched)
+            .map(|(chapter, _)| ChapterChange::Added(chapter.clone())),
+    );
+    changes.sort_by_key(|change| change.start());
+
+    ChapterDiff {
+        changes,
+        num_unchanged,
+    }
+}
@@ -0,0 +1,144 @@
+use std::{error::Error, io, path::PathBuf, time::Duration};
+
+use crate::extract::FfProbeError;
+
+pub type Result<T, E = ChapterizerError> = std::result::Result<T, E>;
+
+/// The error type returned by the library.
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum ChapterizerError {
+    /// An I/O operation failed. The context describes what was being attempted.
+    #[error("{context}")]
+    Io {
+        context: &'static str,
+        #[source]
+        source: io::Error,
+    },
+    /// The audio file could not be probed or decoded.
+    #[cfg(feature = "asr")]
+    #[error("{context}")]
+    Decode {
+        context: &'static str,
+        #[source]
+        source: symphonia::core::errors::Error,
+    },
+    /// The audio file was read successfully, but can't be used.
+    #[error("{0}")]
+    UnsupportedAudio(&'static str),
+    /// The native Vosk library could not be loaded.
+    #[error(
+        "Failed to load the Vosk library {0}. Put it next to the executable or in a directory \
+        that's searched for libraries"
+    )]
+    VoskLibrary(&'static str),
+    /// The Vosk model could not be loaded.
+    #[error("Failed to load the model at {}", path.display())]
+    ModelLoad { path: PathBuf },
+    /// The zip archive of a Vosk model could not be extracted.
+    #[cfg(feature = "asr")]
+    #[error("Failed to extract the model archive {}", path.display())]
+    ModelExtract {
+        path: PathBuf,
+        #[source]
+        source: zip::result::ZipError,
+    },
+    /// The audio input device could not be opened or captured from.
+    #[cfg(feature = "live-input")]
+    #[error("Failed to capture from the audio input device: {0}")]
+    AudioInput(String),
+    /// The Vosk recognizer could not be created from the loaded model.
+    #[error("Failed to create the recognizer")]
+    Recognizer,
+    /// Running or parsing the output of ffprobe failed.
+    #[error("Failed to read metadata using ffprobe")]
+    Ffprobe(#[from] FfProbeError),
+    /// The audio file's metadata contains no chapters.
+    #[error("Metadata contains no chapters")]
+    NoChapters,
+    /// A notification about the outcome of a run could not be sent.
+    #[error("Failed to send notification: {0}")]
+    Notification(String),
+    /// The options passed to the library are invalid.
+    #[error("Invalid options: {0}")]
+    InvalidOptions(&'static str),
+    /// A JSON file could not be parsed. The context describes which file.
+    #[error("{context}")]
+    Json {
+        context: String,
+        #[source]
+        source: serde_json::Error,
+    },
+    /// A chapter file could not be parsed.
+    #[error("Failed to parse {format} chapter file: line {line}: {message}")]
+    ChapterParse {
+        format: &'static str,
+        line: usize,
+        message: String,
+    },
+    /// The format of a chapter file could not be detected from its contents or extension.
+    #[error("Could not detect the format of the chapter file")]
+    UnknownChapterFormat,
+    /// A file written by the chapterizer does not match what was intended, e.g. because the muxer
+    /// dropped chapters. The original file is left untouched.
+    #[error("Verification of {} failed: {message}", path.display())]
+    Verification { path: PathBuf, message: String },
+    /// Chapterizing the audio file took longer than allowed, e.g. because a broken file made the
+    /// decoder loop. The outputs contain the chapters found up to the position reached.
+    #[error(
+        "Gave up after the maximum runtime of {}s, at {} of the audio",
+        max_runtime.as_secs_f32(),
+        crate::format_duration(&Some(*position))
+    )]
+    Timeout {
+        max_runtime: Duration,
+        position: Duration,
+    },
+    /// The chapters of an MP4 file could not be read or written.
+    #[error("{context}")]
+    Mp4 {
+        context: &'static str,
+        #[source]
+        source: mp4ameta::Error,
+    },
+    /// The ID3 tag of an MP3 file could not be read or written.
+    #[error("{context}")]
+    Id3 {
+        context: &'static str,
+        #[source]
+        source: id3::Error,
+    },
+    /// The ID3 chapters of a tag are inconsistent, e.g. a table of contents refers to a chapter
+    /// that doesn't exist.
+    #[error("Invalid ID3 chapters: {0}")]
+    Id3Chapters(String),
+    /// ffmpeg exited with an error, which it printed.
+    #[error("ffmpeg failed: {0}")]
+    Ffmpeg(String),
+    /// A writer method was called at the wrong time, e.g. writing a chapter before the header.
+    #[error("{0}")]
+    InvalidState(&'static str),
+}
+
+/// Formats the error along with all of its sources, e.g. "Failed to x: Failed to y: No such file".
+pub fn format_error_chain(err: &dyn Error) -> String {
+    let mut formatted = err.to_string();
+    let mut source = err.source();
+    while let Some(err) = source {
+        formatted.push_str(": ");
+        formatted.push_str(&err.to_string());
+        source = err.source();
+    }
+    formatted
+}
+
+pub(crate) trait IoResultExt<T> {
+    /// Wraps the error in a [`ChapterizerError::Io`] with the given context.
+    fn io_context(self, context: &'static str) -> Result<T>;
+}
+
+impl<T> IoResultExt<T> for io::Result<T> {
+    fn io_context(self, context: &'static str) -> Result<T> {
+        self.map_err(|source| ChapterizerError::Io { context, source })
+    }
+}
@@ -0,0 +1,117 @@
+use std::time::Duration;
+
+use crate::{
+    chapter::{Chapter, ChapterList},
+    stats::DurationStats,
+};
+
+/// How well the detected chapters match the reference chapters.
+#[derive(Clone, Debug)]
+pub struct EvaluationReport {
+    pub num_detected: usize,
+    pub num_reference: usize,
+    /// The number of detected chapters that were matched to a reference chapter.
+    pub num_matched: usize,
+    /// The fraction of detected chapters that match a reference chapter, or 1 if no chapters were
+    /// detected.
+    pub precision: f64,
+    /// The fraction of reference chapters that were detected, or 1 if there are no reference
+    /// chapters.
+    pub recall: f64,
+    /// The differences between the start times of the matched chapters.
+    pub start_errors: Vec<Duration>,
+    /// The statistics of the start_errors, or None if no chapters were matched.
+    pub start_error: Option<DurationStats>,
+    /// The reference chapters that weren't detected.
+    pub missed: Vec<Chapter>,
+    /// The detected chapters that don't match any reference chapter.
+    pub spurious: Vec<Chapter>,
+}
+
+pub(crate) fn ratio(num: usize, den: usize) -> f64 {
+    if den == 0 {
+        1.0
+    } else {
+        num as f64 / den as f64
+    }
+}
+
+/// Returns the difference between the start times of the chapters.
+pub(crate) fn start_difference(a: &Chapter, b: &Chapter) -> Duration {
+    a.start.max(b.start) - a.start.min(b.start)
+}
+
+/// Matches the chapters of a to those of b whose start times are at most the tolerance apart.
+/// Each chapter is matched at most once, with the closest pairs being matched first. Returns the
+/// indices of the matched pairs, sorted by the index in a.
+pub(crate) fn match_chapters(
+    a: &[Chapter],
+    b: &[Chapter],
+    tolerance: Duration,
+) -> Vec<(usize, usize)> {
+    let mut pairs = Vec::new();
+    for (i, a_chapter) in a.iter().enumerate() {
+        for (j, b_chapter) in b.iter().enumerate() {
+            if start_difference(a_chapter, b_chapter) <= tolerance {
+                pairs.push((i, j));
+            }
+        }
+    }
+    pairs.sort_by_key(|&(i, j)| start_difference(&a[i], &b[j]));
+
+    let mut a_matched = vec![false; a.len()];
+    let mut b_matched = vec![false; b.len()];
+    let mut matches = Vec::new();
+    for (i, j) in pairs {
+        if a_matched[i] || b_matched[j] {
+            continue;
+        }
+        a_matched[i] = true;
+        b_matched[j] = true;
+        matches.push((i, j));
+    }
+    matches.sort();
+    matches
+}
+
+/// Compares the detected chapters against the reference chapters. A detected chapter matches a
+/// reference chapter if their start times are at most the tolerance apart. Each chapter is matched
+/// at most once, with the closest pairs being matched first.
+pub fn evaluate(
+    detected: &ChapterList,
+    reference: &ChapterList,
+    tolerance: Duration,
+) -> EvaluationReport {
+    let mut detected_matched = vec![false; detected.chapters.len()];
+    let mut reference_matched = vec![false; reference.chapters.len()];
+    let mut start_errors = Vec::new();
+    for (d, r) in match_chapters(&detected.chapters, &reference.chapters, tolerance) {
+        detected_matched[d] = true;
+        reference_matched[r] = true;
+        start_errors.push(start_difference(
+            &detected.chapters[d],
+            &reference.chapters[r],
+        ));
+    }
+
+    let unmatched = |chapters: &[Chapter], matched: &[bool]| {
+        chapters
+            .iter()
+            .zip(matched)
+            .filter(|(_, &matched)| !matched)
+            .map(|(chapter, _)| chapter.clone())
+            .collect()
+    };
+
+    EvaluationReport {
+        num_detected: detected.chapters.len(),
+        num_reference: reference.chapters.len(),
+        num_matched: start_errors.len(),
+        precision: ratio(start_errors.len(), detected.chapters.len()),
+        recall: ratio(start_errors.len(), reference.chapters.len()),
+        start_error: DurationStats::from_durations(&start_errors),
+        missed: unmatched(&reference.chapters, &reference_matched),
+        spurious: unmatched(&detected.chapters, &detected_matched),
+        start_errors,
+    }
+}
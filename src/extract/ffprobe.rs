@@ -1,6 +1,5 @@
 // Copied/adapted from https://github.com/theduke/ffprobe-rs, MIT License
 
-use num_traits::ToPrimitive;
 use serde_with::{serde_as, DisplayFromStr};
 use std::{
     collections::HashMap,
@@ -17,7 +16,15 @@ pub fn ffprobe(path: impl AsRef<Path>) -> Result<FfProbe, FfProbeError> {
     let mut cmd = Command::new("ffprobe");
 
     // Default args.
-    cmd.args(["-v", "quiet", "-show_chapters", "-print_format", "json"]);
+    cmd.args([
+        "-v",
+        "quiet",
+        "-show_chapters",
+        "-show_format",
+        "-show_streams",
+        "-print_format",
+        "json",
+    ]);
 
     cmd.arg(path);
 
@@ -27,7 +34,29 @@ pub fn ffprobe(path: impl AsRef<Path>) -> Result<FfProbe, FfProbeError> {
         return Err(FfProbeError::Status(out));
     }
 
-    serde_json::from_slice::<FfProbe>(&out.stdout).map_err(FfProbeError::Deserialize)
+    serde_json::from_slice::<FfProbe>(&out.stdout).map_err(|source| FfProbeError::Deserialize {
+        snippet: snippet_at(&out.stdout, source.line(), source.column()),
+        source,
+    })
+}
+
+/// The number of bytes of JSON to show on either side of the position of a deserialization error.
+const SNIPPET_RADIUS: usize = 40;
+
+/// Returns the JSON around the given 1-based line and column, on a single line.
+fn snippet_at(json: &[u8], line: usize, column: usize) -> String {
+    let line_start = json
+        .split_inclusive(|&b| b == b'\n')
+        .take(line.saturating_sub(1))
+        .map(<[u8]>::len)
+        .sum::<usize>();
+    let position = (line_start + column.saturating_sub(1)).min(json.len());
+    let start = position.saturating_sub(SNIPPET_RADIUS);
+    let end = (position + SNIPPET_RADIUS).min(json.len());
+    String::from_utf8_lossy(&json[start..end])
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
 }
 
 #[derive(Debug)]
@@ -35,7 +64,11 @@ pub fn ffprobe(path: impl AsRef<Path>) -> Result<FfProbe, FfProbeError> {
 pub enum FfProbeError {
     Io(io::Error),
     Status(process::Output),
-    Deserialize(serde_json::Error),
+    /// The output of ffprobe couldn't be parsed. The snippet is the output around the error.
+    Deserialize {
+        source: serde_json::Error,
+        snippet: String,
+    },
 }
 
 impl fmt::Display for FfProbeError {
@@ -50,7 +83,9 @@ impl fmt::Display for FfProbeError {
                     String::from_utf8_lossy(&o.stderr)
                 )
             }
-            FfProbeError::Deserialize(e) => e.fmt(f),
+            FfProbeError::Deserialize { source, snippet } => {
+                write!(f, "{} near `{}`", source, snippet)
+            }
         }
     }
 }
@@ -59,17 +94,113 @@ impl error::Error for FfProbeError {}
 
 #[derive(Default, Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub struct FfProbe {
+    #[serde(default)]
     pub chapters: Vec<Chapter>,
+    #[serde(default)]
+    pub format: Option<Format>,
+    #[serde(default)]
+    pub streams: Vec<Stream>,
+}
+
+impl FfProbe {
+    /// Returns the audio streams, in the order of their index.
+    pub fn audio_streams(&self) -> impl Iterator<Item = &Stream> {
+        self.streams
+            .iter()
+            .filter(|stream| stream.codec_type.as_deref() == Some("audio"))
+    }
+}
+
+/// ffprobe prints durations as seconds in a string, e.g. "3723.456000".
+fn parse_secs(secs: &Option<String>) -> Option<Duration> {
+    secs.as_ref()
+        .and_then(|secs| secs.parse::<f64>().ok())
+        .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+}
+
+/// The container of the file.
+#[derive(Default, Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Format {
+    /// The short names of the container format, separated by commas, e.g. "mov,mp4,m4a".
+    #[serde(default)]
+    pub format_name: Option<String>,
+    #[serde(default)]
+    pub nb_streams: Option<u32>,
+    pub tags: Option<HashMap<String, String>>,
+    #[serde(default)]
+    duration: Option<String>,
+    #[serde(default)]
+    bit_rate: Option<String>,
+}
+
+impl Format {
+    pub fn duration(&self) -> Option<Duration> {
+        parse_secs(&self.duration)
+    }
+
+    /// The overall bitrate in bits per second.
+    pub fn bit_rate(&self) -> Option<u64> {
+        self.bit_rate.as_ref().and_then(|rate| rate.parse().ok())
+    }
+}
+
+/// A stream of the file, e.g. its audio or its cover art.
+#[derive(Default, Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Stream {
+    pub index: usize,
+    /// The kind of stream, e.g. "audio", "video" for cover art, or "data" for chapter tracks.
+    #[serde(default)]
+    pub codec_type: Option<String>,
+    /// The short name of the codec, e.g. "aac" or "mp3".
+    #[serde(default)]
+    pub codec_name: Option<String>,
+    #[serde(default)]
+    pub channels: Option<u32>,
+    #[serde(default)]
+    pub channel_layout: Option<String>,
+    #[serde(default)]
+    pub tags: Option<HashMap<String, String>>,
+    #[serde(default)]
+    sample_rate: Option<String>,
+    #[serde(default)]
+    duration: Option<String>,
+    #[serde(default)]
+    bit_rate: Option<String>,
+}
+
+impl Stream {
+    pub fn sample_rate(&self) -> Option<u32> {
+        self.sample_rate.as_ref().and_then(|rate| rate.parse().ok())
+    }
+
+    pub fn duration(&self) -> Option<Duration> {
+        parse_secs(&self.duration)
+    }
+
+    /// The bitrate in bits per second.
+    pub fn bit_rate(&self) -> Option<u64> {
+        self.bit_rate.as_ref().and_then(|rate| rate.parse().ok())
+    }
+
+    /// The language of the stream, e.g. "eng", if it's tagged.
+    pub fn language(&self) -> Option<&str> {
+        self.tags
+            .as_ref()
+            .and_then(|tags| tags.get("language"))
+            .map(|x| &**x)
+    }
 }
 
 #[serde_as]
 #[derive(Default, Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub struct Chapter {
+    #[serde(default)]
     pub id: i64,
     #[serde_as(as = "DisplayFromStr")]
     time_base: num_rational::Rational32,
     start: i64,
     end: i64,
+    #[serde(default)]
     tags: Option<HashMap<String, String>>,
 }
 
@@ -88,11 +219,23 @@ impl Chapter {
             .map(|x| &**x)
     }
 
+    pub fn time_base(&self) -> num_rational::Rational32 {
+        self.time_base
+    }
+
+    /// Converts the timestamp to a duration using integer math, so that it can be converted back
+    /// to the same timestamp exactly.
+    fn to_duration(&self, ts: i64) -> Duration {
+        let nanos = ts as i128 * *self.time_base.numer() as i128 * 1_000_000_000
+            / *self.time_base.denom() as i128;
+        Duration::from_nanos(nanos.clamp(0, u64::MAX as i128) as u64)
+    }
+
     pub fn start(&self) -> Duration {
-        Duration::from_secs_f64(self.start as f64 * self.time_base.to_f64().unwrap())
+        self.to_duration(self.start)
     }
 
     pub fn end(&self) -> Duration {
-        Duration::from_secs_f64(self.end as f64 * self.time_base.to_f64().unwrap())
+        self.to_duration(self.end)
     }
 }
@@ -1,19 +1,70 @@
-use self::ffprobe::ffprobe;
 use crate::{
-    chapter_writer::ChapterWriter, cue::CueWriter, ffmetadata::FfmetadataWriter, format_duration,
+    agreement::CrossCheck,
+    audiobookshelf::AudiobookshelfWriter,
+    chapter::{Chapter, ChapterList, ChapterSource},
+    chapter_writer::ChapterWriter,
+    cue::{disc_file_path, verify_disc_starts, CueWriter, DiscCueWriter},
+    error::{ChapterizerError, IoResultExt, Result},
+    ffmetadata::{FfmetadataTimebase, FfmetadataWriter},
+    format_duration,
+    in_place::{can_embed_chapters, embedded_chapter_writer, InPlaceOptions},
+    json::JsonWriter,
+    matroska::MatroskaWriter,
+    nfo::NfoWriter,
+    output_config::OutputConfig,
+    probe_cache,
+    retime::{ChapterRetimer, Retime},
+    split_script::{ScriptShell, SplitScriptWriter},
+    srt::SrtWriter,
+    vtt::VttWriter,
+};
+use std::{
+    collections::HashMap,
+    fs::File,
+    path::{Path, PathBuf},
+    time::Duration,
 };
-use color_eyre::{eyre::Context, Result};
-use std::{fs::File, path::PathBuf, time::Duration};
 
 mod ffprobe;
 
+pub(crate) use self::ffprobe::ffprobe;
+pub use self::ffprobe::{Chapter as FfProbeChapter, FfProbe, FfProbeError, Format, Stream};
+
 pub struct ExtractOptions {
     /// The path to the audio file to chapterize.
     pub audio_file_path: PathBuf,
     /// The path that the output .cue file will be written to.
     pub cue_file_path: Option<PathBuf>,
+    /// The start times of the discs after the first, for books ripped from CDs. If not empty, one
+    /// .cue file is written per disc instead, named after the output .cue file.
+    pub cue_disc_starts: Vec<Duration>,
     /// The path that the output ffmetadata file will be written to.
     pub ffmetadata_file_path: Option<PathBuf>,
+    /// The path that the output JSON file will be written to.
+    pub json_file_path: Option<PathBuf>,
+    /// The path that a script that splits the audio file into its chapters with ffmpeg will be
+    /// written to. It's a PowerShell script if the path ends in .ps1, or a POSIX shell script
+    /// otherwise.
+    pub split_script_file_path: Option<PathBuf>,
+    /// The path that the Audiobookshelf metadata.json file will be written to.
+    pub audiobookshelf_file_path: Option<PathBuf>,
+    /// The path that the Kodi/Jellyfin NFO file will be written to.
+    pub nfo_file_path: Option<PathBuf>,
+    /// The path that the Matroska chapter XML file will be written to.
+    pub matroska_file_path: Option<PathBuf>,
+    /// The path that the WebVTT chapters track will be written to.
+    pub vtt_file_path: Option<PathBuf>,
+    /// The path that the SRT file will be written to.
+    pub srt_file_path: Option<PathBuf>,
+    /// If set, the chapters are also embedded in the audio file itself, which must be an MP4 file,
+    /// e.g. an .m4b, or an MP3 file. The file is changed once all chapters are known.
+    pub embed: Option<InPlaceOptions>,
+    /// The adjustment applied to the timestamps of the chapters when they're written.
+    pub retime: Retime,
+    /// The options of the output formats.
+    pub output_config: OutputConfig,
+    /// The chapters found by other detectors, that the chapters are checked against.
+    pub cross_check: CrossCheck,
 }
 
 /// For some reason, ffprobe reports durations that are exactly 25 ms later than what ffmpeg
@@ -24,65 +75,87 @@ fn ffprobe_duration_difference_workaround(duration: Duration) -> Duration {
     duration.saturating_sub(Duration::from_millis(25))
 }
 
-pub fn extract_chapters(options: &ExtractOptions) -> Result<bool> {
-    let chapters = ffprobe(&options.audio_file_path)?.chapters;
-    if chapters.is_empty() {
-        log::debug!("Metadata contains no chapters");
-        return Ok(false);
-    }
+/// Reads the chapters, container and streams of the audio file using ffprobe, or from the probe
+/// cache if it's enabled and the file was probed before.
+pub fn probe(audio_file_path: &Path) -> Result<FfProbe> {
+    Ok(probe_cache::ffprobe(audio_file_path, || {
+        ffprobe(audio_file_path)
+    })?)
+}
 
-    // TODO: dedupe/abstract chapter writers setup and usage
+/// Returns the number of chapters in the audio file's metadata.
+pub fn count_metadata_chapters(audio_file_path: &Path) -> Result<usize> {
+    Ok(probe(audio_file_path)?.chapters.len())
+}
 
-    let cue_file = options
-        .cue_file_path
-        .as_ref()
-        .map(|cue_file_path| File::create(cue_file_path).wrap_err("Failed to create cue file"))
-        .transpose()?;
-    let ffmetadata_file = options
-        .ffmetadata_file_path
-        .as_ref()
-        .map(|ffmetadata_file_path| {
-            File::create(ffmetadata_file_path).wrap_err("Failed to create ffmetadata file")
-        })
-        .transpose()?;
+/// Returns the tags of the audio file's container, e.g. its title and artist.
+pub fn read_format_tags(audio_file_path: &Path) -> Result<HashMap<String, String>> {
+    Ok(probe(audio_file_path)?
+        .format
+        .and_then(|format| format.tags)
+        .unwrap_or_default())
+}
 
-    let mut chapter_writers = {
-        let mut chapter_writers: Vec<Box<dyn ChapterWriter>> = Vec::with_capacity(2);
+/// Returns the chapters in the audio file's metadata, without writing them anywhere.
+pub fn read_metadata_chapters(audio_file_path: &Path) -> Result<Vec<Chapter>> {
+    Ok(probe(audio_file_path)?
+        .chapters
+        .iter()
+        .map(|chapter| {
+            Chapter::new(
+                ffprobe_duration_difference_workaround(chapter.start()),
+                chapter.title().unwrap_or("Untitled"),
+                ChapterSource::Metadata,
+            )
+        })
+        .collect())
+}
 
-        if let Some(cue_file) = cue_file {
-            let mut cue_writer = CueWriter::new(Box::new(cue_file));
-            cue_writer.write_header(&options.audio_file_path).unwrap();
-            chapter_writers.push(Box::new(cue_writer));
-        }
+/// Extracts the chapters from the audio file's metadata and writes them to the outputs, returning
+/// the chapters written. Returns [`ChapterizerError::NoChapters`] if the metadata contains no
+/// chapters, in which case no outputs are written.
+pub fn extract_chapters(options: &ExtractOptions) -> Result<ChapterList> {
+    verify_outputs(options)?;
 
-        if let Some(ffmetadata_file) = ffmetadata_file {
-            let mut ffmetadata_writer = FfmetadataWriter::new(Box::new(ffmetadata_file));
-            ffmetadata_writer.write_header().unwrap();
-            chapter_writers.push(Box::new(ffmetadata_writer));
-        }
+    let ffprobe = probe(&options.audio_file_path)?;
+    let chapters = ffprobe.chapters;
+    if chapters.is_empty() {
+        log::debug!("Metadata contains no chapters");
+        return Err(ChapterizerError::NoChapters);
+    }
 
-        chapter_writers
-    };
+    let format_tags = ffprobe
+        .format
+        .and_then(|format| format.tags)
+        .unwrap_or_default();
+    let time_base = chapters[0].time_base();
+    let mut chapter_writers = create_chapter_writers(
+        options,
+        &format_tags,
+        Some((*time_base.numer() as u64, *time_base.denom() as u64)),
+    )?;
 
-    if chapter_writers.is_empty() {
-        unreachable!("No chapter writers specified, cli args validation should have caught this");
-    }
+    let mut written_chapters = Vec::with_capacity(chapters.len() + 1);
+    let mut retimer = ChapterRetimer::new(options.retime);
 
     // Ensure that the first chapter in the output starts at 0:00:00.00
     let first_chapter = chapters.first().unwrap();
     if ffprobe_duration_difference_workaround(first_chapter.start()) != Duration::ZERO {
         log::debug!("Adding 0th chapter @ 0:00:00.00");
 
-        for chapter_writer in chapter_writers.iter_mut() {
-            chapter_writer
-                .on_chapter_start(Duration::ZERO, "Chapter 00")
-                .unwrap();
-        }
+        let chapter = Chapter::new(Duration::ZERO, "Chapter 00", ChapterSource::Inserted);
+        let output_chapters = options
+            .output_config
+            .finalize_chapter(&mut retimer, &chapter);
+        write_chapters(&mut chapter_writers, output_chapters)?;
+        written_chapters.push(chapter);
     }
 
     for chapter in &chapters {
         let title = chapter.title().unwrap_or("Untitled");
+        let description = chapter.description();
         let start = ffprobe_duration_difference_workaround(chapter.start());
+        let end = ffprobe_duration_difference_workaround(chapter.end());
 
         log::debug!(
             "Extracted chapter {} @ {}: \"{}\"",
@@ -91,18 +164,323 @@ pub fn extract_chapters(options: &ExtractOptions) -> Result<bool> {
             title
         );
 
-        for chapter_writer in chapter_writers.iter_mut() {
-            chapter_writer.on_chapter_start(start, title).unwrap();
+        // Keep the end time from the metadata, since there may be gaps between chapters
+        let mut chapter = Chapter::new(start, title, ChapterSource::Metadata).with_end(end);
+        if let Some(description) = description {
+            chapter = chapter.with_description(description);
         }
+        let chapter = options.cross_check.chapter(&chapter);
+        let output_chapters = options
+            .output_config
+            .finalize_chapter(&mut retimer, &chapter);
+        write_chapters(&mut chapter_writers, output_chapters)?;
+        written_chapters.push(chapter);
     }
 
     let last_chapter = chapters.last().unwrap();
+    let duration = ffprobe_duration_difference_workaround(last_chapter.end());
+
+    let last_output_chapter = options
+        .output_config
+        .finalize_last_chapter(&mut retimer, duration);
+    write_chapters(&mut chapter_writers, last_output_chapter)?;
 
+    let retimed_duration = options.retime.duration(duration);
     for chapter_writer in chapter_writers.iter_mut() {
-        chapter_writer
-            .on_end_of_file(ffprobe_duration_difference_workaround(last_chapter.end()))
-            .unwrap();
+        chapter_writer.on_end_of_file(retimed_duration)?;
+    }
+
+    Ok(ChapterList {
+        chapters: written_chapters,
+        duration,
+    })
+}
+
+/// Writes the chapters of a chapter file, e.g. a text file of "HH:MM:SS Title" lines jotted down
+/// while listening, to the outputs, returning the chapters written. The audio file is probed for
+/// its duration and tags, since chapter files don't necessarily record them. If it can't be, the
+/// duration of the chapter list is used instead. Chapters that start
+/// after the end of the audio file are dropped. Returns [`ChapterizerError::NoChapters`] if the
+/// chapter file contains no chapters, in which case no outputs are written.
+pub fn import_chapters(
+    options: &ExtractOptions,
+    chapter_list: &ChapterList,
+) -> Result<ChapterList> {
+    verify_outputs(options)?;
+
+    let mut chapters = chapter_list.chapters.clone();
+    if chapters.is_empty() {
+        log::debug!("Chapter file contains no chapters");
+        return Err(ChapterizerError::NoChapters);
+    }
+    if !chapters.is_sorted_by_key(|chapter| chapter.start) {
+        log::warn!("Chapters in the chapter file are out of order, sorting them by start time");
+        chapters.sort_by_key(|chapter| chapter.start);
+    }
+
+    let format = match probe(&options.audio_file_path) {
+        Ok(ffprobe) => ffprobe.format.unwrap_or_default(),
+        Err(err) => {
+            log::warn!("Failed to probe audio file: {}", err);
+            Format::default()
+        }
+    };
+    let duration = match format.duration() {
+        Some(duration) => ffprobe_duration_difference_workaround(duration),
+        None => chapter_list.duration.max(chapters.last().unwrap().start),
+    };
+    chapters.retain(|chapter| {
+        let is_in_file = chapter.start < duration;
+        if !is_in_file {
+            log::warn!(
+                "Dropping chapter \"{}\" @ {}, which starts after the end of the audio file",
+                chapter.flat_title(),
+                format_duration(&Some(chapter.start))
+            );
+        }
+        is_in_file
+    });
+    if chapters.is_empty() {
+        return Err(ChapterizerError::NoChapters);
     }
 
-    Ok(true)
+    let mut chapter_writers =
+        create_chapter_writers(options, &format.tags.unwrap_or_default(), None)?;
+
+    let mut written_chapters = Vec::with_capacity(chapters.len() + 1);
+    let mut retimer = ChapterRetimer::new(options.retime);
+    if chapters[0].start != Duration::ZERO {
+        log::debug!("Adding 0th chapter @ 0:00:00.00");
+        chapters.insert(
+            0,
+            Chapter::new(Duration::ZERO, "Chapter 00", ChapterSource::Inserted),
+        );
+    }
+    for chapter in chapters {
+        let chapter = options.cross_check.chapter(&chapter);
+        let output_chapters = options
+            .output_config
+            .finalize_chapter(&mut retimer, &chapter);
+        write_chapters(&mut chapter_writers, output_chapters)?;
+        written_chapters.push(chapter);
+    }
+
+    let last_output_chapter = options
+        .output_config
+        .finalize_last_chapter(&mut retimer, duration);
+    write_chapters(&mut chapter_writers, last_output_chapter)?;
+
+    let retimed_duration = options.retime.duration(duration);
+    for chapter_writer in chapter_writers.iter_mut() {
+        chapter_writer.on_end_of_file(retimed_duration)?;
+    }
+
+    Ok(ChapterList {
+        chapters: written_chapters,
+        duration,
+    })
+}
+
+/// Passes the chapters to each of the chapter writers, in order.
+fn write_chapters(
+    chapter_writers: &mut [Box<dyn ChapterWriter>],
+    chapters: impl IntoIterator<Item = Chapter>,
+) -> Result<()> {
+    for chapter in chapters {
+        for chapter_writer in chapter_writers.iter_mut() {
+            chapter_writer.on_chapter_start(&chapter)?;
+        }
+    }
+    Ok(())
+}
+
+fn verify_outputs(options: &ExtractOptions) -> Result<()> {
+    if options.cue_file_path.is_none()
+        && options.ffmetadata_file_path.is_none()
+        && options.json_file_path.is_none()
+        && options.split_script_file_path.is_none()
+        && options.audiobookshelf_file_path.is_none()
+        && options.nfo_file_path.is_none()
+        && options.matroska_file_path.is_none()
+        && options.vtt_file_path.is_none()
+        && options.srt_file_path.is_none()
+        && options.embed.is_none()
+    {
+        return Err(ChapterizerError::InvalidOptions(
+            "no output file paths specified",
+        ));
+    }
+    if options.embed.is_some() && !can_embed_chapters(&options.audio_file_path) {
+        return Err(ChapterizerError::InvalidOptions(
+            "chapters can only be embedded in MP4 and MP3 files",
+        ));
+    }
+    // Retimed chapters belong to another edition of the audio, not to the file they'd be
+    // embedded in
+    if options.embed.is_some() && options.retime != Retime::default() {
+        return Err(ChapterizerError::InvalidOptions(
+            "chapters can't be embedded when they're retimed",
+        ));
+    }
+    verify_disc_starts(&options.cue_disc_starts)
+}
+
+/// Creates the outputs and their chapter writers. The tags are copied into the ffmetadata output
+/// if configured, and the timebase of the chapters in the metadata, if any, is used for it if its
+/// timebase is set to the source's.
+fn create_chapter_writers(
+    options: &ExtractOptions,
+    format_tags: &HashMap<String, String>,
+    source_timebase: Option<(u64, u64)>,
+) -> Result<Vec<Box<dyn ChapterWriter>>> {
+    // TODO: dedupe/abstract chapter writers setup and usage
+    let cue_files = options
+        .cue_file_path
+        .as_ref()
+        .map(|cue_file_path| {
+            if options.cue_disc_starts.is_empty() {
+                Ok(vec![
+                    File::create(cue_file_path).io_context("Failed to create cue file")?
+                ])
+            } else {
+                (1..=options.cue_disc_starts.len() + 1)
+                    .map(|disc_num| {
+                        File::create(disc_file_path(cue_file_path, disc_num))
+                            .io_context("Failed to create cue file")
+                    })
+                    .collect()
+            }
+        })
+        .transpose()?;
+    let ffmetadata_file = options
+        .ffmetadata_file_path
+        .as_ref()
+        .map(|ffmetadata_file_path| {
+            File::create(ffmetadata_file_path).io_context("Failed to create ffmetadata file")
+        })
+        .transpose()?;
+    let json_file = options
+        .json_file_path
+        .as_ref()
+        .map(|json_file_path| File::create(json_file_path).io_context("Failed to create json file"))
+        .transpose()?;
+    let split_script_file = options
+        .split_script_file_path
+        .as_ref()
+        .map(|split_script_file_path| {
+            File::create(split_script_file_path)
+                .io_context("Failed to create split script file")
+                .map(|file| (file, ScriptShell::from_path(split_script_file_path)))
+        })
+        .transpose()?;
+    let audiobookshelf_file = options
+        .audiobookshelf_file_path
+        .as_ref()
+        .map(|audiobookshelf_file_path| {
+            File::create(audiobookshelf_file_path)
+                .io_context("Failed to create Audiobookshelf metadata file")
+        })
+        .transpose()?;
+    let nfo_file = options
+        .nfo_file_path
+        .as_ref()
+        .map(|nfo_file_path| File::create(nfo_file_path).io_context("Failed to create nfo file"))
+        .transpose()?;
+    let matroska_file = options
+        .matroska_file_path
+        .as_ref()
+        .map(|matroska_file_path| {
+            File::create(matroska_file_path).io_context("Failed to create Matroska chapters file")
+        })
+        .transpose()?;
+    let vtt_file = options
+        .vtt_file_path
+        .as_ref()
+        .map(|vtt_file_path| File::create(vtt_file_path).io_context("Failed to create WebVTT file"))
+        .transpose()?;
+    let srt_file = options
+        .srt_file_path
+        .as_ref()
+        .map(|srt_file_path| File::create(srt_file_path).io_context("Failed to create SRT file"))
+        .transpose()?;
+
+    let chapter_writers = {
+        let mut chapter_writers: Vec<Box<dyn ChapterWriter>> = Vec::with_capacity(3);
+
+        if let Some(cue_files) = cue_files {
+            if options.cue_disc_starts.is_empty() {
+                let cue_file = cue_files.into_iter().next().unwrap();
+                let mut cue_writer = CueWriter::new(cue_file, &options.output_config.cue);
+                cue_writer.write_header(&options.audio_file_path)?;
+                chapter_writers.push(Box::new(cue_writer));
+            } else {
+                let disc_cue_writer = DiscCueWriter::new(
+                    cue_files,
+                    &options.cue_disc_starts,
+                    &options.audio_file_path,
+                    &options.output_config.cue,
+                )?;
+                chapter_writers.push(Box::new(disc_cue_writer));
+            }
+        }
+
+        if let Some(ffmetadata_file) = ffmetadata_file {
+            let mut ffmetadata_options = options
+                .output_config
+                .ffmetadata
+                .with_copied_tags(format_tags);
+            if let (FfmetadataTimebase::Source, Some((num, den))) =
+                (&ffmetadata_options.timebase, source_timebase)
+            {
+                ffmetadata_options.timebase = FfmetadataTimebase::Fixed(num, den);
+            }
+            let mut ffmetadata_writer = FfmetadataWriter::new(ffmetadata_file, &ffmetadata_options);
+            ffmetadata_writer.write_header()?;
+            chapter_writers.push(Box::new(ffmetadata_writer));
+        }
+
+        if let Some(json_file) = json_file {
+            chapter_writers.push(Box::new(JsonWriter::new(
+                json_file,
+                &options.output_config.json,
+            )));
+        }
+
+        if let Some((split_script_file, shell)) = split_script_file {
+            chapter_writers.push(Box::new(SplitScriptWriter::new(
+                split_script_file,
+                shell,
+                &options.audio_file_path,
+                &options.output_config.split_script,
+            )));
+        }
+
+        if let Some(audiobookshelf_file) = audiobookshelf_file {
+            chapter_writers.push(Box::new(AudiobookshelfWriter::new(audiobookshelf_file)));
+        }
+
+        if let Some(nfo_file) = nfo_file {
+            chapter_writers.push(Box::new(NfoWriter::new(nfo_file)));
+        }
+
+        if let Some(matroska_file) = matroska_file {
+            chapter_writers.push(Box::new(MatroskaWriter::new(matroska_file)));
+        }
+
+        if let Some(vtt_file) = vtt_file {
+            chapter_writers.push(Box::new(VttWriter::new(vtt_file)));
+        }
+
+        if let Some(srt_file) = srt_file {
+            chapter_writers.push(Box::new(SrtWriter::new(srt_file)));
+        }
+
+        if let Some(embed) = &options.embed {
+            chapter_writers.push(embedded_chapter_writer(&options.audio_file_path, embed));
+        }
+
+        chapter_writers
+    };
+
+    Ok(chapter_writers)
 }
@@ -1,50 +1,272 @@
-use lazy_static::lazy_static;
-use regex::Regex;
-use std::{io::Write, time::Duration};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::File,
+    io::Write,
+    path::Path,
+    time::Duration,
+};
 
-use color_eyre::eyre::{self, eyre, Context};
+use crate::{
+    chapter::{Chapter, ChapterList, ChapterSource},
+    chapter_writer::{is_already_written, read_partial_output, reopen_for_append, ChapterWriter},
+    error::{ChapterizerError, IoResultExt, Result},
+};
 
-use crate::chapter_writer::ChapterWriter;
+/// The timebase that ffmpeg uses for a chapter that doesn't specify one, as (numerator, denominator).
+const DEFAULT_TIMEBASE: (u64, u64) = (1, 1_000_000_000);
 
-pub struct FfmetadataWriter {
-    writer: Box<dyn Write>,
+/// The timebase that chapter timestamps are written in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Deserialize)]
+#[serde(try_from = "String")]
+pub enum FfmetadataTimebase {
+    /// The timebase of the chapters in the audio file's metadata, so that they're written at the
+    /// precision that ffprobe reported them at. Falls back to milliseconds for chapters from other
+    /// sources.
+    Source,
+    /// A fixed timebase, as (numerator, denominator).
+    Fixed(u64, u64),
+}
+
+/// The timebase that chapters are written in by default, i.e. milliseconds.
+const DEFAULT_WRITE_TIMEBASE: (u64, u64) = (1, 1000);
+
+impl Default for FfmetadataTimebase {
+    fn default() -> Self {
+        Self::Fixed(DEFAULT_WRITE_TIMEBASE.0, DEFAULT_WRITE_TIMEBASE.1)
+    }
+}
+
+impl TryFrom<String> for FfmetadataTimebase {
+    type Error = String;
+
+    /// Parses "source" or a fraction such as "1/1000".
+    fn try_from(s: String) -> std::result::Result<Self, Self::Error> {
+        if s == "source" {
+            return Ok(Self::Source);
+        }
+        s.split_once('/')
+            .and_then(|(num, den)| Some((num.trim().parse().ok()?, den.trim().parse().ok()?)))
+            .filter(|&(num, den)| num != 0 && den != 0)
+            .map(|(num, den)| Self::Fixed(num, den))
+            .ok_or_else(|| {
+                format!(
+                    "invalid timebase \"{}\", expected e.g. \"1/1000\" or \"source\"",
+                    s
+                )
+            })
+    }
+}
+
+impl FfmetadataTimebase {
+    /// Returns the timebase as (numerator, denominator), using the default for
+    /// [`FfmetadataTimebase::Source`] if it wasn't resolved to the source's timebase.
+    fn ratio(&self) -> (u64, u64) {
+        match self {
+            Self::Source => DEFAULT_WRITE_TIMEBASE,
+            Self::Fixed(num, den) => (*num, *den),
+        }
+    }
+
+    /// Converts the time to the nearest number of ticks of the timebase.
+    fn to_ticks(self, time: Duration) -> u128 {
+        let (num, den) = self.ratio();
+        let tick_nanos = num as u128 * 1_000_000_000;
+        (time.as_nanos() * den as u128 + tick_nanos / 2) / tick_nanos
+    }
+}
+
+/// How line breaks in titles and metadata values are written.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Deserialize)]
+pub enum FfmetadataNewlines {
+    /// Line breaks are replaced with a space, since players show chapter titles on one line.
+    #[default]
+    #[serde(rename = "space")]
+    Space,
+    /// Line breaks are escaped with a backslash, which ffmpeg reads as a line break in the value.
+    #[serde(rename = "escape")]
+    Escape,
+}
+
+/// The options of the ffmetadata writer.
+#[derive(Clone, Debug, Default, PartialEq, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FfmetadataOptions {
+    /// The timebase that chapter timestamps are written in, e.g. "1/1000" for milliseconds.
+    pub timebase: FfmetadataTimebase,
+    /// The keys of the tags to copy from the audio file into the global metadata, e.g. "title"
+    /// and "artist". ffmpeg replaces all metadata of the audio file when the ffmetadata file is
+    /// used as its metadata source, so tags that aren't copied are lost.
+    pub copy_tags: Vec<String>,
+    /// The global metadata to write, e.g. {"album": "..."}. Takes precedence over copied tags.
+    pub metadata: BTreeMap<String, String>,
+    /// How line breaks in titles and metadata values are written. Carriage returns are treated as
+    /// line breaks, and a CRLF is a single line break.
+    pub newlines: FfmetadataNewlines,
+}
+
+impl FfmetadataOptions {
+    /// Returns the options with the tags listed in copy_tags added to the global metadata, unless
+    /// the metadata already has a value for them. Tag keys are matched case-insensitively, since
+    /// their case differs between containers.
+    pub fn with_copied_tags(&self, tags: &HashMap<String, String>) -> Self {
+        let mut options = self.clone();
+        for key in &self.copy_tags {
+            let value = tags
+                .iter()
+                .find(|(tag_key, _)| tag_key.eq_ignore_ascii_case(key))
+                .map(|(_, value)| value);
+            match value {
+                Some(value) => {
+                    options
+                        .metadata
+                        .entry(key.clone())
+                        .or_insert_with(|| value.clone());
+                }
+                None => log::debug!("Audio file has no {} tag to copy", key),
+            }
+        }
+        options
+    }
+}
+
+pub struct FfmetadataWriter<W: Write> {
+    writer: W,
+    options: FfmetadataOptions,
     header_written: bool,
-    /// A tuple of (start_time, title). We still need the end time to actually write the chapter.
-    partial_chapter: Option<(Duration, String)>,
+    /// If the chapter has no explicit end, we still need the start time of the next chapter to
+    /// actually write it.
+    partial_chapter: Option<Chapter>,
+    /// The start of the last chapter that was already in the file when it was reopened.
+    resume_after: Option<Duration>,
 }
 
-impl FfmetadataWriter {
-    pub fn new(writer: Box<dyn Write>) -> Self {
+impl<W: Write> FfmetadataWriter<W> {
+    pub fn new(writer: W, options: &FfmetadataOptions) -> Self {
         Self {
             writer,
+            options: options.clone(),
             header_written: false,
             partial_chapter: None,
+            resume_after: None,
         }
     }
 
-    // ffmpeg docs 22.9: Metadata keys or values containing special characters (‘=’, ‘;’, ‘#’, ‘\’ and a newline) must be escaped with a backslash ‘\’.
-    fn sanitize_string<T: AsRef<str>>(s: T) -> String {
-        lazy_static! {
-            static ref CR_REGEX: Regex = Regex::new("\r+").unwrap();
-            static ref SPECIAL_CHARS_REGEX: Regex = Regex::new("[\n=;#\\\\]").unwrap();
+    /// Returns the underlying writer, without flushing it. The last chapter is only written at
+    /// the end of the file.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    /// Flushes the underlying writer and returns it. The last chapter is only written at the end
+    /// of the file, so that should be passed to the writer first.
+    pub fn finalize(mut self) -> Result<W> {
+        self.writer
+            .flush()
+            .io_context("Failed to flush ffmetadata file")?;
+        Ok(self.writer)
+    }
+}
+
+impl FfmetadataWriter<File> {
+    /// Reopens an ffmetadata file written by a previous run that may have been cut off, to
+    /// continue appending to it. Whatever was written of a chapter that's incomplete is dropped,
+    /// and if no chapter is complete, the file is started over from the header. Returns the writer
+    /// along with the chapters that were already in the file. Chapters up to the last of those are
+    /// skipped, so that the chapters can be passed to the writer from the start again. If the file
+    /// doesn't exist, it's created.
+    pub fn reopen(path: &Path, options: &FfmetadataOptions) -> Result<(Self, ChapterList)> {
+        let contents = read_partial_output(path)?;
+
+        // The file is complete up to the end of the title of the last chapter, which is the last
+        // key written for each chapter. Escaped newlines don't end a line.
+        let mut complete_len = 0;
+        let mut line_start = 0;
+        let mut in_chapter = false;
+        let mut escaped = false;
+        for (index, c) in contents.char_indices() {
+            if c == '\n' && !escaped {
+                let line = contents[line_start..index].trim_end_matches('\r');
+                if line.starts_with('[') {
+                    in_chapter = line == "[CHAPTER]";
+                } else if in_chapter && line.starts_with("title=") {
+                    complete_len = index + 1;
+                }
+                line_start = index + 1;
+            }
+            escaped = c == '\\' && !escaped;
+        }
+        let chapter_list = if complete_len == 0 {
+            ChapterList {
+                chapters: Vec::new(),
+                duration: Duration::ZERO,
+            }
+        } else {
+            parse_ffmetadata(&contents[..complete_len])?
+        };
+
+        let file = reopen_for_append(path, complete_len)?;
+        let mut ffmetadata_writer = Self::new(file, options);
+        if complete_len == 0 {
+            ffmetadata_writer.write_header()?;
+        } else {
+            ffmetadata_writer.header_written = true;
         }
+        ffmetadata_writer.resume_after = chapter_list.chapters.last().map(|chapter| chapter.start);
 
-        SPECIAL_CHARS_REGEX
-            .replace_all(CR_REGEX.replace_all(s.as_ref(), "").as_ref(), "\\$0")
-            .trim()
-            .to_string()
+        Ok((ffmetadata_writer, chapter_list))
+    }
+}
+
+impl<W: Write> FfmetadataWriter<W> {
+    // ffmpeg docs 22.9: Metadata keys or values containing special characters (‘=’, ‘;’, ‘#’, ‘\’ and a newline) must be escaped with a backslash ‘\’.
+    fn escape_string(&self, s: &str) -> String {
+        let mut escaped = String::with_capacity(s.len());
+        let mut chars = s.trim().chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '\r' | '\n' => {
+                    if c == '\r' {
+                        chars.next_if_eq(&'\n');
+                    }
+                    match self.options.newlines {
+                        FfmetadataNewlines::Space => {
+                            if !escaped.ends_with(' ') {
+                                escaped.push(' ');
+                            }
+                        }
+                        FfmetadataNewlines::Escape => escaped.push_str("\\\n"),
+                    }
+                }
+                '=' | ';' | '#' | '\\' => {
+                    escaped.push('\\');
+                    escaped.push(c);
+                }
+                c => escaped.push(c),
+            }
+        }
+        escaped
     }
 
-    pub fn write_header(&mut self) -> eyre::Result<()> {
+    /// Writes the header, followed by the global metadata from the options, if any.
+    pub fn write_header(&mut self) -> Result<()> {
         if self.header_written {
-            return Err(eyre!(
-                "Failed to write ffmetadata header: header already written"
+            return Err(ChapterizerError::InvalidState(
+                "Failed to write ffmetadata header: header already written",
+            ));
+        }
+
+        let mut header = format!("{}\n", ";FFMETADATA1");
+        for (key, value) in &self.options.metadata {
+            header.push_str(&format!(
+                "{}={}\n",
+                self.escape_string(key),
+                self.escape_string(value)
             ));
         }
 
         self.writer
-            .write_all((format!("{}\n", ";FFMETADATA1")).as_bytes())
-            .wrap_err("Failed to write ffmetadata header")?;
+            .write_all(header.as_bytes())
+            .io_context("Failed to write ffmetadata header")?;
 
         self.header_written = true;
 
@@ -56,50 +278,332 @@ impl FfmetadataWriter {
         start_time: Duration,
         end_time: Duration,
         title: &str,
-    ) -> eyre::Result<()> {
+    ) -> Result<()> {
         if !self.header_written {
-            return Err(eyre!(
-                "Failed to write ffmetadata chapter: must write header first"
+            return Err(ChapterizerError::InvalidState(
+                "Failed to write ffmetadata chapter: must write header first",
             ));
         }
 
-        let chapter_data = unindent::unindent(&format!(
+        let timebase = self.options.timebase;
+        let (num, den) = timebase.ratio();
+        let mut chapter_data = unindent::unindent(&format!(
             "
                 [CHAPTER]
-                TIMEBASE=1/1000
+                TIMEBASE={}/{}
                 START={}
                 END={}
-                title={}
             ",
-            start_time.as_millis(),
-            end_time.as_millis(),
-            &Self::sanitize_string(title),
+            num,
+            den,
+            timebase.to_ticks(start_time),
+            timebase.to_ticks(end_time),
         ));
+        // The title is added after unindenting, since the lines after an escaped line break in it
+        // aren't indented
+        chapter_data.push_str(&format!("title={}\n", self.escape_string(title)));
 
         self.writer
             .write_all(chapter_data.as_bytes())
-            .wrap_err("Failed to write ffmetadata chapter")?;
+            .io_context("Failed to write ffmetadata chapter")?;
 
         Ok(())
     }
 }
 
-impl ChapterWriter for FfmetadataWriter {
-    fn on_chapter_start(&mut self, start_time: Duration, title: &str) -> eyre::Result<()> {
-        if let Some((prev_start_time, prev_title)) = self.partial_chapter.take() {
-            self.write_chapter(prev_start_time, start_time, &prev_title)?;
+impl<W: Write> ChapterWriter for FfmetadataWriter<W> {
+    fn on_chapter_start(&mut self, chapter: &Chapter) -> Result<()> {
+        if is_already_written(self.resume_after, chapter.start) {
+            return Ok(());
+        }
+        if let Some(prev_chapter) = self.partial_chapter.take() {
+            let prev_end = prev_chapter.end.unwrap_or(chapter.start);
+            self.write_chapter(prev_chapter.start, prev_end, &prev_chapter.flat_title())?;
         }
 
-        self.partial_chapter = Some((start_time, title.to_string()));
+        self.partial_chapter = Some(chapter.clone());
 
         Ok(())
     }
 
-    fn on_end_of_file(&mut self, file_duration: Duration) -> eyre::Result<()> {
-        if let Some((start_time, title)) = self.partial_chapter.take() {
-            self.write_chapter(start_time, file_duration, &title)?;
+    fn on_end_of_file(&mut self, file_duration: Duration) -> Result<()> {
+        if let Some(chapter) = self.partial_chapter.take() {
+            let end = chapter.end.unwrap_or(file_duration);
+            self.write_chapter(chapter.start, end, &chapter.flat_title())?;
         }
 
         Ok(())
     }
+
+    fn flush(&mut self) -> Result<()> {
+        self.writer
+            .flush()
+            .io_context("Failed to flush ffmetadata file")
+    }
+}
+
+/// A chapter whose keys are still being read.
+struct PartialChapter {
+    line: usize,
+    timebase: (u64, u64),
+    start: Option<u64>,
+    end: Option<u64>,
+    title: Option<String>,
+}
+
+impl PartialChapter {
+    fn to_duration(&self, ticks: u64) -> Duration {
+        let (num, den) = self.timebase;
+        let nanos = ticks as u128 * num as u128 * 1_000_000_000 / den as u128;
+        Duration::from_nanos(nanos.try_into().unwrap_or(u64::MAX))
+    }
+}
+
+/// Splits the contents into lines, joining lines that end in an escaped newline. Escapes are kept,
+/// so that an escaped '=' can still be told apart from the one separating the key and value. Each
+/// line is paired with its line number.
+fn logical_lines(contents: &str) -> Vec<(usize, String)> {
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    let mut line_num = 1;
+    let mut start_line_num = 1;
+    let mut escaped = false;
+    for c in contents.chars().filter(|&c| c != '\r') {
+        if c == '\n' {
+            line_num += 1;
+            if !escaped {
+                lines.push((start_line_num, std::mem::take(&mut line)));
+                start_line_num = line_num;
+                continue;
+            }
+        }
+        escaped = c == '\\' && !escaped;
+        line.push(c);
+    }
+    if !line.is_empty() {
+        lines.push((start_line_num, line));
+    }
+    lines
+}
+
+/// Splits the line into its key and value at the first unescaped '=', removing the escapes.
+fn split_key_value(line: &str) -> Option<(String, String)> {
+    let mut key = String::new();
+    let mut value = None;
+    let mut escaped = false;
+    for c in line.chars() {
+        if !escaped && c == '\\' {
+            escaped = true;
+            continue;
+        }
+        match &mut value {
+            None if !escaped && c == '=' => value = Some(String::new()),
+            None => key.push(c),
+            Some(value) => value.push(c),
+        }
+        escaped = false;
+    }
+    value.map(|value| (key, value))
+}
+
+/// Parses the chapters of an ffmetadata file. Metadata other than chapter titles is ignored. The
+/// duration of the chapter list is taken to be the end of the last chapter.
+pub fn parse_ffmetadata(contents: &str) -> Result<ChapterList> {
+    let parse_error = |line: usize, message: String| ChapterizerError::ChapterParse {
+        format: "ffmetadata",
+        line,
+        message,
+    };
+
+    let lines = logical_lines(contents);
+    if !lines
+        .first()
+        .is_some_and(|(_, line)| line.starts_with(";FFMETADATA"))
+    {
+        return Err(parse_error(1, "missing ;FFMETADATA header".into()));
+    }
+
+    let mut chapters: Vec<Chapter> = Vec::new();
+    let mut push_chapter = |partial: Option<PartialChapter>| -> Result<()> {
+        let Some(partial) = partial else {
+            return Ok(());
+        };
+        let start = partial
+            .start
+            .ok_or_else(|| parse_error(partial.line, "chapter has no START".into()))?;
+        let title = partial
+            .title
+            .clone()
+            .unwrap_or_else(|| format!("Chapter {}", chapters.len() + 1));
+        let mut chapter = Chapter::new(partial.to_duration(start), title, ChapterSource::UserEdit);
+        chapter.end = partial.end.map(|end| partial.to_duration(end));
+        chapters.push(chapter);
+        Ok(())
+    };
+
+    let mut chapter: Option<PartialChapter> = None;
+    for (line_num, line) in lines.into_iter().skip(1) {
+        if line.is_empty() || line.starts_with(';') || line.starts_with('#') {
+            continue;
+        }
+        if line.starts_with('[') {
+            push_chapter(chapter.take())?;
+            if line == "[CHAPTER]" {
+                chapter = Some(PartialChapter {
+                    line: line_num,
+                    timebase: DEFAULT_TIMEBASE,
+                    start: None,
+                    end: None,
+                    title: None,
+                });
+            }
+            continue;
+        }
+        // Keys outside of chapter sections are global or stream metadata
+        let Some(chapter) = &mut chapter else {
+            continue;
+        };
+        let Some((key, value)) = split_key_value(&line) else {
+            return Err(parse_error(
+                line_num,
+                format!("expected key=value: {}", line),
+            ));
+        };
+        let parse_ticks = |value: &str| {
+            value
+                .trim()
+                .parse::<u64>()
+                .map_err(|_| parse_error(line_num, format!("invalid {}: {}", key, value)))
+        };
+        match key.as_str() {
+            "TIMEBASE" => {
+                chapter.timebase = value
+                    .trim()
+                    .split_once('/')
+                    .and_then(|(num, den)| Some((num.parse().ok()?, den.parse().ok()?)))
+                    .filter(|&(_, den)| den != 0)
+                    .ok_or_else(|| parse_error(line_num, format!("invalid TIMEBASE: {}", value)))?;
+            }
+            "START" => chapter.start = Some(parse_ticks(&value)?),
+            "END" => chapter.end = Some(parse_ticks(&value)?),
+            "title" => chapter.title = Some(value),
+            _ => {}
+        }
+    }
+    push_chapter(chapter)?;
+
+    let duration = chapters
+        .iter()
+        .map(|chapter| chapter.end.unwrap_or(chapter.start))
+        .max()
+        .unwrap_or_default();
+    Ok(ChapterList { chapters, duration })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes chapters with the titles to an ffmetadata file and parses it back.
+    fn round_trip(titles: &[&str], newlines: FfmetadataNewlines) -> Vec<String> {
+        let options = FfmetadataOptions {
+            newlines,
+            ..Default::default()
+        };
+        let mut writer = FfmetadataWriter::new(Vec::new(), &options);
+        writer.write_header().unwrap();
+        for (index, title) in titles.iter().enumerate() {
+            let start = Duration::from_secs(60 * index as u64);
+            let chapter = Chapter::new(start, *title, ChapterSource::UserEdit);
+            writer.on_chapter_start(&chapter).unwrap();
+        }
+        writer
+            .on_end_of_file(Duration::from_secs(60 * titles.len() as u64))
+            .unwrap();
+        let contents = String::from_utf8(writer.finalize().unwrap()).unwrap();
+
+        parse_ffmetadata(&contents)
+            .unwrap()
+            .chapters
+            .into_iter()
+            .map(|chapter| chapter.title)
+            .collect()
+    }
+
+    #[test]
+    fn special_characters_round_trip() {
+        let titles = [
+            "1 = 2",
+            "Chapter 1; The Start",
+            "#1 Fan",
+            "C:\\Books\\",
+            "Ends with a backslash\\",
+        ];
+        assert_eq!(round_trip(&titles, FfmetadataNewlines::Space), titles);
+    }
+
+    #[test]
+    fn escaped_newlines_round_trip() {
+        let titles = ["Part One\nThe Beginning", "A\\\nB"];
+        assert_eq!(round_trip(&titles, FfmetadataNewlines::Escape), titles);
+    }
+
+    #[test]
+    fn newlines_are_replaced_with_spaces() {
+        let titles = ["Part One\nThe Beginning", "Part Two\r\n\r\nThe End"];
+        assert_eq!(
+            round_trip(&titles, FfmetadataNewlines::Space),
+            ["Part One The Beginning", "Part Two The End"]
+        );
+    }
+
+    #[test]
+    fn reopen_continues_a_cut_off_file() {
+        let chapters = ["One", "Two", "Three", "Four"]
+            .iter()
+            .enumerate()
+            .map(|(index, title)| {
+                let start = Duration::from_secs(60 * index as u64);
+                Chapter::new(start, *title, ChapterSource::UserEdit)
+            })
+            .collect::<Vec<_>>();
+        let options = FfmetadataOptions::default();
+        let mut writer = FfmetadataWriter::new(Vec::new(), &options);
+        writer.write_header().unwrap();
+        for chapter in &chapters {
+            writer.on_chapter_start(chapter).unwrap();
+        }
+        let contents = String::from_utf8(writer.finalize().unwrap()).unwrap();
+        // Cut the file off in the middle of the title of the third chapter
+        let cut_off = &contents[..contents.rfind("title=").unwrap() + 8];
+
+        let path = std::env::temp_dir().join(format!(
+            "audiobook-chapterizer-reopen-{}.ffmetadata",
+            std::process::id()
+        ));
+        std::fs::write(&path, cut_off).unwrap();
+        let (mut writer, written) = FfmetadataWriter::reopen(&path, &options).unwrap();
+        let written_titles = written
+            .chapters
+            .iter()
+            .map(|chapter| chapter.title.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(written_titles, ["One", "Two"]);
+        for chapter in &chapters {
+            writer.on_chapter_start(chapter).unwrap();
+        }
+        writer.on_end_of_file(Duration::from_secs(240)).unwrap();
+        writer.finalize().unwrap();
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let chapter_list = parse_ffmetadata(&contents).unwrap();
+        let titles = chapter_list
+            .chapters
+            .iter()
+            .map(|chapter| chapter.title.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(titles, ["One", "Two", "Three", "Four"]);
+        assert_eq!(contents.matches(";FFMETADATA1").count(), 1);
+    }
 }
@@ -0,0 +1,409 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use id3::{
+    frame::{Chapter as ChapFrame, Content, TableOfContents},
+    Frame, Tag, TagLike, Version,
+};
+
+use crate::{
+    chapter::{Chapter, ChapterList, ChapterSource},
+    chapter_writer::ChapterWriter,
+    error::{ChapterizerError, IoResultExt, Result},
+    in_place::{replace_in_place, InPlaceOptions},
+};
+
+/// The offsets of CHAP frames are set to this to mark them as unused, so that players go by the
+/// times instead.
+const UNUSED_OFFSET: u32 = 0xFFFFFFFF;
+
+/// Replaces the chapter list of the tag with the chapters, rather than appending them to whatever
+/// chapters the tag already has.
+///
+/// The chapter list is the top-level CTOC frame along with the CHAP and CTOC frames it refers to,
+/// directly or through nested CTOC frames. It's removed and rebuilt as a single ordered top-level
+/// CTOC frame. CHAP frames that no CTOC frame refers to are removed too, since players that
+/// ignore CTOC frames would list them alongside the new chapters. Other CTOC frames, the CHAP
+/// frames they refer to and all frames that aren't about chapters are kept.
+///
+/// Sections are flattened into the titles of their chapters, since many players don't support
+/// nested tables of contents. The CHAP frames are added in the order of the chapters, for players
+/// that list them in the order they appear in the tag.
+pub fn merge_chapters(tag: &mut Tag, chapter_list: &ChapterList) -> Result<()> {
+    let tables = tag
+        .tables_of_contents()
+        .map(|toc| (toc.element_id.clone(), toc.clone()))
+        .collect::<HashMap<_, _>>();
+
+    // The elements of the old chapter list
+    let mut stale = HashSet::new();
+    let mut pending = tables
+        .values()
+        .filter(|toc| toc.top_level)
+        .map(|toc| toc.element_id.clone())
+        .collect::<Vec<_>>();
+    while let Some(element_id) = pending.pop() {
+        if !stale.insert(element_id.clone()) {
+            continue;
+        }
+        if let Some(toc) = tables.get(&element_id) {
+            pending.extend(toc.elements.iter().cloned());
+        }
+    }
+    let kept_tables = tables
+        .values()
+        .filter(|toc| !stale.contains(&toc.element_id))
+        .collect::<Vec<_>>();
+    let referenced = kept_tables
+        .iter()
+        .flat_map(|toc| toc.elements.iter())
+        .collect::<HashSet<_>>();
+    for chapter in tag.chapters() {
+        if !referenced.contains(&chapter.element_id) && !stale.contains(&chapter.element_id) {
+            log::warn!(
+                "Removing ID3 chapter {:?}, which isn't in any table of contents",
+                chapter.element_id
+            );
+            stale.insert(chapter.element_id.clone());
+        }
+    }
+
+    let kept_ids = tag
+        .chapters()
+        .map(|chapter| &chapter.element_id)
+        .chain(tables.keys())
+        .filter(|element_id| !stale.contains(*element_id))
+        .cloned()
+        .collect::<HashSet<_>>();
+
+    // Rebuild the frames without the old chapter list. Tables of contents that are kept may refer
+    // to elements of it, which are removed from them
+    let frames = tag
+        .frames()
+        .filter_map(|frame| match frame.content() {
+            Content::Chapter(chapter) if stale.contains(&chapter.element_id) => None,
+            Content::TableOfContents(toc) if stale.contains(&toc.element_id) => None,
+            Content::TableOfContents(toc) => {
+                let mut toc = toc.clone();
+                toc.elements.retain(|element| !stale.contains(element));
+                Some(Frame::from(toc))
+            }
+            _ => Some(frame.clone()),
+        })
+        .collect::<Vec<_>>();
+    *tag = Tag::with_version(tag.version());
+    for frame in frames {
+        tag.add_frame(frame);
+    }
+
+    let mut next_id = 0;
+    let mut unique_id = |prefix: &str| loop {
+        let element_id = format!("{}{}", prefix, next_id);
+        next_id += 1;
+        if !kept_ids.contains(&element_id) {
+            break element_id;
+        }
+    };
+
+    let mut elements = Vec::with_capacity(chapter_list.chapters.len());
+    let mut chap_frames = Vec::with_capacity(chapter_list.chapters.len());
+    for (index, chapter) in chapter_list.chapters.iter().enumerate() {
+        let end = chapter.end.unwrap_or_else(|| {
+            chapter_list
+                .chapters
+                .get(index + 1)
+                .map_or(chapter_list.duration, |next| next.start)
+        });
+        let mut frames = vec![Frame::text("TIT2", chapter.flat_title())];
+        if let Some(description) = &chapter.description {
+            frames.push(Frame::text("TIT3", description.as_str()));
+        }
+        let element_id = unique_id("chp");
+        elements.push(element_id.clone());
+        chap_frames.push(ChapFrame {
+            element_id,
+            start_time: millis(chapter.start),
+            end_time: millis(end),
+            start_offset: UNUSED_OFFSET,
+            end_offset: UNUSED_OFFSET,
+            frames,
+        });
+    }
+
+    tag.add_frame(TableOfContents {
+        element_id: unique_id("toc"),
+        top_level: true,
+        ordered: true,
+        elements,
+        frames: Vec::new(),
+    });
+    for chap_frame in chap_frames {
+        tag.add_frame(chap_frame);
+    }
+
+    validate(tag)
+}
+
+/// Returns whether the path has the extension of an MP3 file, which chapters can be embedded in.
+pub fn is_mp3_path(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("mp3"))
+}
+
+/// Writes the chapters into the ID3v2 tag of the MP3 file as CHAP frames with a CTOC frame
+/// listing them, see [`merge_chapters`], which is what most podcast players read. If existing
+/// chapters are to be stripped, all CHAP and CTOC frames are removed first. A tag is added if the
+/// file has none. Only the tag of a copy of the file is rewritten, so the audio is left as it is,
+/// and the copy replaces the file once it's verified to have the chapters, see
+/// [`replace_in_place`].
+pub fn embed_chapters(
+    path: &Path,
+    chapter_list: &ChapterList,
+    options: &InPlaceOptions,
+) -> Result<()> {
+    let mut tag = match Tag::read_from_path(path) {
+        Ok(tag) => tag,
+        Err(err) if matches!(err.kind, id3::ErrorKind::NoTag) => Tag::with_version(Version::Id3v24),
+        Err(source) => {
+            return Err(ChapterizerError::Id3 {
+                context: "Failed to read ID3 tag",
+                source,
+            })
+        }
+    };
+    if options.strip_existing {
+        tag.remove_all_chapters();
+        tag.remove_all_tables_of_contents();
+    }
+    merge_chapters(&mut tag, chapter_list)?;
+
+    // ID3v2.2 has no chapter frames
+    let version = match tag.version() {
+        Version::Id3v22 => Version::Id3v23,
+        version => version,
+    };
+    replace_in_place(path, chapter_list, options, |temp_path| {
+        fs::copy(path, temp_path).io_context("Failed to copy MP3 file")?;
+        tag.write_to_path(temp_path, version)
+            .map_err(|source| ChapterizerError::Id3 {
+                context: "Failed to write ID3 tag",
+                source,
+            })
+    })
+}
+
+/// Embeds the chapters in the ID3 tag of the MP3 file at the end of the file, see
+/// [`embed_chapters`]. The file is only changed once all chapters are known, so a run that's cut
+/// off leaves it untouched.
+pub struct Id3ChapterWriter {
+    path: PathBuf,
+    options: InPlaceOptions,
+    chapters: Vec<Chapter>,
+}
+
+impl Id3ChapterWriter {
+    pub fn new(path: &Path, options: &InPlaceOptions) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            options: *options,
+            chapters: Vec::new(),
+        }
+    }
+}
+
+impl ChapterWriter for Id3ChapterWriter {
+    fn on_chapter_start(&mut self, chapter: &Chapter) -> Result<()> {
+        self.chapters.push(chapter.clone());
+        Ok(())
+    }
+
+    fn on_end_of_file(&mut self, file_duration: Duration) -> Result<()> {
+        let chapter_list = ChapterList {
+            chapters: std::mem::take(&mut self.chapters),
+            duration: file_duration,
+        };
+        embed_chapters(&self.path, &chapter_list, &self.options)?;
+        log::info!(
+            "Embedded {} chapter(s) in {}",
+            chapter_list.chapters.len(),
+            self.path.display()
+        );
+        Ok(())
+    }
+}
+
+/// Checks that the chapters of the tag are consistent, with common player quirks in mind: there's
+/// exactly one top-level table of contents, every element a table of contents refers to exists,
+/// element IDs are unique, and the chapters of the top-level table of contents are in order and
+/// don't overlap.
+pub fn validate(tag: &Tag) -> Result<()> {
+    let fail = |message: String| Err(ChapterizerError::Id3Chapters(message));
+
+    let mut element_ids = HashSet::new();
+    for element_id in tag
+        .chapters()
+        .map(|chapter| &chapter.element_id)
+        .chain(tag.tables_of_contents().map(|toc| &toc.element_id))
+    {
+        if element_id.is_empty() || !element_id.is_ascii() {
+            return fail(format!(
+                "element ID {:?} is not a non-empty ASCII string",
+                element_id
+            ));
+        }
+        if !element_ids.insert(element_id) {
+            return fail(format!(
+                "element ID {:?} is used more than once",
+                element_id
+            ));
+        }
+    }
+
+    let top_level = tag
+        .tables_of_contents()
+        .filter(|toc| toc.top_level)
+        .collect::<Vec<_>>();
+    let [top_level] = top_level.as_slice() else {
+        return fail(format!(
+            "expected one top-level table of contents, found {}",
+            top_level.len()
+        ));
+    };
+
+    for toc in tag.tables_of_contents() {
+        if let Some(element) = toc
+            .elements
+            .iter()
+            .find(|element| !element_ids.contains(element))
+        {
+            return fail(format!(
+                "table of contents {:?} refers to {:?}, which doesn't exist",
+                toc.element_id, element
+            ));
+        }
+    }
+
+    let chapters = tag
+        .chapters()
+        .map(|chapter| (&chapter.element_id, chapter))
+        .collect::<HashMap<_, _>>();
+    let mut prev_end = 0;
+    for element in &top_level.elements {
+        let Some(chapter) = chapters.get(element) else {
+            continue;
+        };
+        if chapter.start_time >= chapter.end_time {
+            return fail(format!("chapter {:?} is empty", chapter.element_id));
+        }
+        if chapter.start_time < prev_end {
+            return fail(format!(
+                "chapter {:?} starts before the previous chapter ends",
+                chapter.element_id
+            ));
+        }
+        prev_end = chapter.end_time;
+    }
+
+    Ok(())
+}
+
+/// Reads the chapters from the CHAP frames of the tag, in the order of the top-level CTOC frame
+/// if there is one, or by start time otherwise. The titles of nested CTOC frames become the
+/// parents of the chapters they refer to. The end of a chapter is only kept if it leaves a gap
+/// before the next chapter.
+pub fn read_chapters(tag: &Tag) -> Vec<Chapter> {
+    let frames = tag
+        .chapters()
+        .map(|chapter| (&chapter.element_id, chapter))
+        .collect::<HashMap<_, _>>();
+    let tables = tag
+        .tables_of_contents()
+        .map(|toc| (&toc.element_id, toc))
+        .collect::<HashMap<_, _>>();
+
+    let mut chapters = Vec::with_capacity(frames.len());
+    let mut seen = HashSet::new();
+    match tag.tables_of_contents().find(|toc| toc.top_level) {
+        Some(top_level) => {
+            // Elements are visited depth-first, along with the title of their section
+            let mut pending = top_level
+                .elements
+                .iter()
+                .rev()
+                .map(|element| (element, None))
+                .collect::<Vec<_>>();
+            while let Some((element_id, parent)) = pending.pop() {
+                if !seen.insert(element_id) {
+                    continue;
+                }
+                if let Some(frame) = frames.get(element_id) {
+                    let mut chapter = read_chapter(frame);
+                    chapter.parent = parent;
+                    chapters.push(chapter);
+                } else if let Some(toc) = tables.get(element_id) {
+                    let title = frame_title(&toc.frames);
+                    pending.extend(
+                        toc.elements
+                            .iter()
+                            .rev()
+                            .map(|element| (element, title.clone().or(parent.clone()))),
+                    );
+                }
+            }
+        }
+        None => {
+            chapters.extend(frames.values().map(|frame| read_chapter(frame)));
+            chapters.sort_by_key(|chapter| chapter.start);
+        }
+    }
+
+    let next_starts = chapters
+        .iter()
+        .skip(1)
+        .map(|chapter| Some(chapter.start))
+        .chain([None])
+        .collect::<Vec<_>>();
+    for (chapter, next_start) in chapters.iter_mut().zip(next_starts) {
+        if next_start.is_some_and(|next_start| chapter.end >= Some(next_start)) {
+            chapter.end = None;
+        }
+    }
+
+    chapters
+}
+
+/// Reads a chapter from a CHAP frame, with an explicit end. It's titled after its element ID if
+/// it has no TIT2 sub-frame.
+fn read_chapter(frame: &ChapFrame) -> Chapter {
+    let title = frame_title(&frame.frames).unwrap_or_else(|| frame.element_id.clone());
+    let mut chapter = Chapter::new(
+        Duration::from_millis(frame.start_time.into()),
+        title,
+        ChapterSource::Id3,
+    );
+    chapter.end = Some(Duration::from_millis(frame.end_time.into()));
+    chapter.description = frame_text(&frame.frames, "TIT3");
+    chapter
+}
+
+/// Returns the text of the TIT2 sub-frame, if any.
+fn frame_title(frames: &[Frame]) -> Option<String> {
+    frame_text(frames, "TIT2")
+}
+
+/// Returns the text of the sub-frame with the ID, if any.
+fn frame_text(frames: &[Frame], id: &str) -> Option<String> {
+    frames
+        .iter()
+        .find(|frame| frame.id() == id)
+        .and_then(|frame| frame.content().text())
+        .map(str::to_string)
+}
+
+fn millis(duration: Duration) -> u32 {
+    duration.as_millis().try_into().unwrap_or(u32::MAX)
+}
@@ -0,0 +1,311 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    time::Duration,
+};
+
+use crate::{
+    chapter::ChapterList,
+    chapter_writer::ChapterWriter,
+    error::{ChapterizerError, IoResultExt, Result},
+    extract::{ffprobe, FfProbe},
+    ffmetadata::parse_ffmetadata,
+    id3_chapters::{is_mp3_path, Id3ChapterWriter},
+    mp4_chapters::{is_mp4_path, Mp4ChapterWriter},
+};
+
+/// The maximum difference between the durations of the original and the rewritten file.
+const MAX_DURATION_DIFFERENCE: Duration = Duration::from_secs(1);
+
+/// The maximum difference between the intended and the written start of a chapter. Containers
+/// store timestamps in their own timebase, so they're rarely exactly the same.
+const MAX_START_DIFFERENCE: Duration = Duration::from_millis(50);
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct InPlaceOptions {
+    /// Whether to keep the original file next to the rewritten one, with `.bak` appended to its
+    /// name.
+    pub keep_backup: bool,
+    /// Whether to remove whatever chapters the file already has, rather than only replacing its
+    /// chapter list. Some containers keep chapters in more than one place, e.g. an .m4b may have
+    /// both a chapter list and a QuickTime chapter track, and some players keep showing stale
+    /// publisher chapters from the one that wasn't replaced.
+    pub strip_existing: bool,
+}
+
+/// Returns the ffmpeg arguments that select what's copied from the original file, as input 0,
+/// when it's remuxed with the chapters of an ffmetadata file, as input 1. The global tags of the
+/// original file are kept, and those in the ffmetadata file, e.g. set with --metadata, are applied
+/// over them.
+pub fn remux_map_args(options: &InPlaceOptions) -> Vec<&'static str> {
+    let mut args = if options.strip_existing {
+        // Only the audio and the cover art are copied, which leaves out any chapter tracks
+        vec!["-map", "0:a", "-map", "0:v?"]
+    } else {
+        vec!["-map", "0"]
+    };
+    args.extend([
+        "-map_metadata",
+        "0",
+        "-map_metadata",
+        "1",
+        "-map_chapters",
+        "1",
+    ]);
+    args
+}
+
+/// Removes the temporary file when dropped, unless it has been moved into place.
+struct TempFile {
+    path: PathBuf,
+    persisted: bool,
+}
+
+impl TempFile {
+    /// Moves the temporary file to the path, replacing whatever file is there.
+    fn persist(mut self, path: &Path) -> Result<()> {
+        fs::rename(&self.path, path).io_context("Failed to move the new file into place")?;
+        self.persisted = true;
+        Ok(())
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if !self.persisted && self.path.exists() {
+            if let Err(err) = fs::remove_file(&self.path) {
+                log::warn!(
+                    "Failed to remove temporary file {}: {}",
+                    self.path.display(),
+                    err
+                );
+            }
+        }
+    }
+}
+
+/// Returns the path of the temporary file to write a new version of the file to. It's in the same
+/// directory so that it can be renamed over the original atomically, and keeps the extension so
+/// that tools can tell the container format from it.
+fn temp_path(path: &Path) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let file_name = match path.extension() {
+        Some(ext) => format!(".{}.chapterizer-tmp.{}", stem, ext.to_string_lossy()),
+        None => format!(".{}.chapterizer-tmp", stem),
+    };
+    path.with_file_name(file_name)
+}
+
+/// Returns the path the original file is kept at, e.g. "book.m4b.bak".
+pub fn backup_path(path: &Path) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".bak");
+    path.with_file_name(file_name)
+}
+
+/// Returns whether chapters can be embedded in the file by rewriting its metadata, which is the
+/// case for MP4 and MP3 files.
+pub fn can_embed_chapters(path: &Path) -> bool {
+    is_mp4_path(path) || is_mp3_path(path)
+}
+
+/// Returns the writer that embeds the chapters in the file, which must be one that
+/// [`can_embed_chapters`] accepts.
+pub fn embedded_chapter_writer(path: &Path, options: &InPlaceOptions) -> Box<dyn ChapterWriter> {
+    if is_mp3_path(path) {
+        Box::new(Id3ChapterWriter::new(path, options))
+    } else {
+        Box::new(Mp4ChapterWriter::new(path, options))
+    }
+}
+
+/// Reads the chapters back from the file and compares them against the intended chapters, to
+/// catch container quirks such as players or muxers ignoring some kinds of chapters.
+pub fn verify_chapters(path: &Path, expected: &ChapterList) -> Result<()> {
+    verify_probed_chapters(path, &ffprobe(path)?, expected)
+}
+
+fn verify_probed_chapters(path: &Path, probe: &FfProbe, expected: &ChapterList) -> Result<()> {
+    let fail = |message: String| {
+        Err(ChapterizerError::Verification {
+            path: path.to_path_buf(),
+            message,
+        })
+    };
+
+    if probe.chapters.len() != expected.chapters.len() {
+        return fail(format!(
+            "expected {} chapters, found {}",
+            expected.chapters.len(),
+            probe.chapters.len()
+        ));
+    }
+
+    for (i, (actual, expected)) in probe.chapters.iter().zip(&expected.chapters).enumerate() {
+        if actual.start().abs_diff(expected.start) > MAX_START_DIFFERENCE {
+            return fail(format!(
+                "chapter {} starts at {}, expected {}",
+                i + 1,
+                crate::format_duration(&Some(actual.start())),
+                crate::format_duration(&Some(expected.start))
+            ));
+        }
+        let title = actual.title().unwrap_or_default();
+        if title != expected.flat_title() {
+            return fail(format!(
+                "chapter {} is titled {:?}, expected {:?}",
+                i + 1,
+                title,
+                expected.flat_title()
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks that the rewritten file has the expected chapters and the same duration as the
+/// original, to catch muxers that silently drop chapters or truncate the audio.
+fn verify(original_path: &Path, new_path: &Path, expected: &ChapterList) -> Result<()> {
+    let fail = |message: String| {
+        Err(ChapterizerError::Verification {
+            path: new_path.to_path_buf(),
+            message,
+        })
+    };
+
+    let original = ffprobe(original_path)?;
+    let new = ffprobe(new_path)?;
+
+    verify_probed_chapters(new_path, &new, expected)?;
+
+    let duration = |probe: &FfProbe| probe.format.as_ref()?.duration();
+    match (duration(&original), duration(&new)) {
+        (Some(original), Some(new)) => {
+            if new.abs_diff(original) > MAX_DURATION_DIFFERENCE {
+                return fail(format!(
+                    "duration changed from {:.3}s to {:.3}s",
+                    original.as_secs_f64(),
+                    new.as_secs_f64()
+                ));
+            }
+        }
+        (Some(_), None) => return fail("could not read the duration".into()),
+        // Nothing to compare against
+        (None, _) => log::warn!(
+            "Could not read the duration of {}, skipping duration check",
+            original_path.display()
+        ),
+    }
+
+    Ok(())
+}
+
+/// Replaces the file with a new version written by `write`, e.g. a remux with chapters embedded.
+/// The new version is written to a temporary file and verified to have the expected chapters and
+/// the same duration before it atomically replaces the original. If anything fails,
+/// the original is left untouched and the temporary file is removed.
+pub fn replace_in_place(
+    path: &Path,
+    expected: &ChapterList,
+    options: &InPlaceOptions,
+    write: impl FnOnce(&Path) -> Result<()>,
+) -> Result<()> {
+    let backup_path = backup_path(path);
+    // Don't overwrite an earlier backup, as it may be the only copy of the original
+    if options.keep_backup && backup_path.exists() {
+        return Err(ChapterizerError::Io {
+            context: "Backup file already exists",
+            source: std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                backup_path.display().to_string(),
+            ),
+        });
+    }
+
+    let temp_file = write_verified(path, path, expected, write)?;
+
+    if options.keep_backup {
+        // A hard link keeps the original without copying it, but isn't supported everywhere
+        if fs::hard_link(path, &backup_path).is_err() {
+            fs::copy(path, &backup_path).io_context("Failed to back up the original file")?;
+        }
+        log::info!("Kept the original file at {}", backup_path.display());
+    }
+
+    temp_file.persist(path)
+}
+
+/// Writes a new version of the original file with `write` to a temporary file next to the
+/// destination, and verifies that it has the expected chapters and the same duration as the
+/// original. The temporary file is removed if it isn't persisted.
+fn write_verified(
+    original_path: &Path,
+    dest_path: &Path,
+    expected: &ChapterList,
+    write: impl FnOnce(&Path) -> Result<()>,
+) -> Result<TempFile> {
+    let temp_file = TempFile {
+        path: temp_path(dest_path),
+        persisted: false,
+    };
+    write(&temp_file.path)?;
+    verify(original_path, &temp_file.path, expected)?;
+    Ok(temp_file)
+}
+
+/// Remuxes the audio file with the chapters of the ffmetadata file into the output file with
+/// ffmpeg, copying the streams rather than encoding them again. What's copied from the audio file
+/// besides its audio depends on the options, see [`remux_map_args`].
+pub fn remux_with_chapters(
+    audio_file_path: &Path,
+    ffmetadata_file_path: &Path,
+    output_file_path: &Path,
+    options: &InPlaceOptions,
+) -> Result<()> {
+    let output = Command::new("ffmpeg")
+        .args(["-v", "error", "-nostdin", "-y", "-i"])
+        .arg(audio_file_path)
+        .arg("-i")
+        .arg(ffmetadata_file_path)
+        .args(remux_map_args(options))
+        .args(["-c", "copy"])
+        .arg(output_file_path)
+        .stdin(Stdio::null())
+        .output()
+        .io_context("Failed to run ffmpeg")?;
+    if !output.status.success() {
+        return Err(ChapterizerError::Ffmpeg(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Embeds the chapters of the ffmetadata file in the audio file by remuxing it with ffmpeg, see
+/// [`remux_with_chapters`]. The chapterized copy is written to the output path, or replaces the
+/// audio file if there is none, see [`replace_in_place`]. Either way, it's only moved into place
+/// once it's verified to have the chapters of the ffmetadata file and the same duration as the
+/// audio file.
+pub fn apply_chapters(
+    audio_file_path: &Path,
+    ffmetadata_file_path: &Path,
+    output_file_path: Option<&Path>,
+    options: &InPlaceOptions,
+) -> Result<()> {
+    let contents =
+        fs::read_to_string(ffmetadata_file_path).io_context("Failed to read ffmetadata file")?;
+    let expected = parse_ffmetadata(&contents)?;
+    let remux = |temp_path: &Path| {
+        remux_with_chapters(audio_file_path, ffmetadata_file_path, temp_path, options)
+    };
+
+    match output_file_path {
+        Some(output_file_path) => {
+            write_verified(audio_file_path, output_file_path, &expected, remux)?
+                .persist(output_file_path)
+        }
+        None => replace_in_place(audio_file_path, &expected, options, remux),
+    }
+}
@@ -0,0 +1,261 @@
+use serde_with::{serde_as, DurationSecondsWithFrac};
+use std::{
+    io::{self, Write},
+    time::Duration,
+};
+
+use crate::{
+    chapter::{Agreement, Chapter, ChapterList, ChapterSource, RejectedCandidate},
+    chapter_writer::ChapterWriter,
+    error::{ChapterizerError, IoResultExt, Result},
+};
+
+/// Chapters in JSON files written by hand don't need to specify their source.
+fn default_source() -> ChapterSource {
+    ChapterSource::UserEdit
+}
+
+#[serde_as]
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct JsonChapter {
+    #[serde_as(as = "DurationSecondsWithFrac<f64>")]
+    start: Duration,
+    #[serde_as(as = "DurationSecondsWithFrac<f64>")]
+    end: Duration,
+    title: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    #[serde(default = "default_source")]
+    source: ChapterSource,
+    /// The detectors that found the chapter, if it was cross-checked.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    sources: Vec<ChapterSource>,
+    /// The share of the detectors that ran that found the chapter, if it was cross-checked.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    confidence: Option<f32>,
+    /// The chapters of a section, e.g. of "Part 02". Sections span their chapters.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    chapters: Vec<JsonChapter>,
+}
+
+impl JsonChapter {
+    fn from_chapter(chapter: Chapter, end: Duration) -> Self {
+        let (sources, confidence) = match chapter.agreement {
+            Some(agreement) => (agreement.sources, Some(agreement.confidence)),
+            None => (Vec::new(), None),
+        };
+        Self {
+            start: chapter.start,
+            end: chapter.end.unwrap_or(end),
+            title: chapter.title,
+            id: chapter.id,
+            description: chapter.description,
+            source: chapter.source,
+            sources,
+            confidence,
+            chapters: Vec::new(),
+        }
+    }
+
+    /// Flattens the chapter into the list, replacing sections by their chapters.
+    fn flatten_into(self, parent: Option<&str>, chapters: &mut Vec<Chapter>) {
+        if !self.chapters.is_empty() {
+            for chapter in self.chapters {
+                chapter.flatten_into(Some(&self.title), chapters);
+            }
+            return;
+        }
+
+        let mut chapter = Chapter::new(self.start, self.title, self.source).with_end(self.end);
+        chapter.id = self.id;
+        chapter.description = self.description;
+        chapter.parent = parent.map(str::to_string);
+        chapter.agreement = self.confidence.map(|confidence| Agreement {
+            sources: self.sources,
+            confidence,
+        });
+        chapters.push(chapter);
+    }
+}
+
+#[serde_as]
+#[derive(Debug, serde::Serialize)]
+struct JsonRejected {
+    #[serde_as(as = "DurationSecondsWithFrac<f64>")]
+    start: Duration,
+    text: String,
+    reason: &'static str,
+}
+
+#[serde_as]
+#[derive(Debug, serde::Serialize)]
+struct JsonOutput<'a> {
+    #[serde_as(as = "DurationSecondsWithFrac<f64>")]
+    duration: Duration,
+    chapters: &'a [JsonChapter],
+    /// The rejected candidates, if they're included in the outputs.
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    rejected: &'a [JsonRejected],
+}
+
+#[serde_as]
+#[derive(Debug, serde::Deserialize)]
+struct JsonInput {
+    #[serde_as(as = "DurationSecondsWithFrac<f64>")]
+    duration: Duration,
+    chapters: Vec<JsonChapter>,
+}
+
+/// Parses the chapters of a JSON document in the format written by [`JsonWriter`].
+pub fn parse_json(contents: &str) -> Result<ChapterList> {
+    let input: JsonInput =
+        serde_json::from_str(contents).map_err(|source| ChapterizerError::Json {
+            context: "Failed to parse json chapter file".into(),
+            source,
+        })?;
+
+    let mut chapters = Vec::with_capacity(input.chapters.len());
+    for chapter in input.chapters {
+        chapter.flatten_into(None, &mut chapters);
+    }
+
+    Ok(ChapterList {
+        chapters,
+        duration: input.duration,
+    })
+}
+
+/// The options of the JSON writer.
+#[derive(Clone, Debug, PartialEq, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct JsonOptions {
+    /// Whether to indent the document. If not, it's written on a single line.
+    pub pretty: bool,
+}
+
+impl Default for JsonOptions {
+    fn default() -> Self {
+        Self { pretty: true }
+    }
+}
+
+/// Writes the chapters as a single JSON document. Since the document can only be written once the
+/// end of the file is known, chapters are collected in memory until then. Chapters that belong to
+/// a section are nested in it.
+pub struct JsonWriter<W: Write> {
+    writer: W,
+    options: JsonOptions,
+    chapters: Vec<JsonChapter>,
+    rejected: Vec<JsonRejected>,
+    /// If the chapter has no explicit end, we still need the start time of the next chapter to
+    /// actually add it.
+    partial_chapter: Option<Chapter>,
+}
+
+impl<W: Write> JsonWriter<W> {
+    pub fn new(writer: W, options: &JsonOptions) -> Self {
+        Self {
+            writer,
+            options: options.clone(),
+            chapters: Vec::new(),
+            rejected: Vec::new(),
+            partial_chapter: None,
+        }
+    }
+
+    /// Returns the underlying writer, without flushing it. Nothing is written to it before the
+    /// end of the file.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    /// Flushes the underlying writer and returns it. The JSON is written at the end of the file,
+    /// so that should be passed to the writer first.
+    pub fn finalize(mut self) -> Result<W> {
+        self.writer
+            .flush()
+            .io_context("Failed to flush json file")?;
+        Ok(self.writer)
+    }
+
+    /// Adds the chapter, using the given end time if it doesn't have an explicit end.
+    fn push_chapter(&mut self, mut chapter: Chapter, end: Duration) {
+        let Some(parent) = chapter.parent.take() else {
+            self.chapters.push(JsonChapter::from_chapter(chapter, end));
+            return;
+        };
+        let chapter = JsonChapter::from_chapter(chapter, end);
+
+        // Add the chapter to its section, starting a new section if the previous chapter belongs
+        // to a different one
+        match self.chapters.last_mut() {
+            Some(section) if !section.chapters.is_empty() && section.title == parent => {
+                section.end = section.end.max(chapter.end);
+                section.chapters.push(chapter);
+            }
+            _ => self.chapters.push(JsonChapter {
+                start: chapter.start,
+                end: chapter.end,
+                title: parent,
+                id: None,
+                description: None,
+                source: chapter.source,
+                sources: Vec::new(),
+                confidence: None,
+                chapters: vec![chapter],
+            }),
+        }
+    }
+}
+
+impl<W: Write> ChapterWriter for JsonWriter<W> {
+    fn on_chapter_start(&mut self, chapter: &Chapter) -> Result<()> {
+        if let Some(prev_chapter) = self.partial_chapter.take() {
+            self.push_chapter(prev_chapter, chapter.start);
+        }
+
+        self.partial_chapter = Some(chapter.clone());
+
+        Ok(())
+    }
+
+    fn on_end_of_file(&mut self, file_duration: Duration) -> Result<()> {
+        if let Some(chapter) = self.partial_chapter.take() {
+            self.push_chapter(chapter, file_duration);
+        }
+
+        let output = JsonOutput {
+            duration: file_duration,
+            chapters: &self.chapters,
+            rejected: &self.rejected,
+        };
+
+        if self.options.pretty {
+            serde_json::to_writer_pretty(&mut self.writer, &output)
+        } else {
+            serde_json::to_writer(&mut self.writer, &output)
+        }
+        .map_err(io::Error::from)
+        .io_context("Failed to write json chapters")?;
+        self.writer
+            .write_all(b"\n")
+            .io_context("Failed to write json chapters")?;
+
+        Ok(())
+    }
+
+    fn on_rejected_candidate(&mut self, candidate: &RejectedCandidate) -> Result<()> {
+        self.rejected.push(JsonRejected {
+            start: candidate.start,
+            text: candidate.text.clone(),
+            reason: candidate.reason,
+        });
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.writer.flush().io_context("Failed to flush json file")
+    }
+}
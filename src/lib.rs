@@ -1,12 +1,59 @@
-use std::time::Duration;
+use std::{env, path::PathBuf, time::Duration};
 
+pub mod agreement;
+#[cfg(feature = "asr")]
 pub mod audio_provider;
+pub mod audiobookshelf;
+pub mod chapter;
+pub mod chapter_reader;
 pub mod chapter_writer;
+#[cfg(feature = "asr")]
 pub mod chapterize;
+#[cfg(feature = "asr")]
+pub mod compression;
+pub mod corpus;
 pub mod cue;
+pub mod diff;
+pub mod error;
+pub mod evaluate;
 pub mod extract;
 pub mod ffmetadata;
 pub mod fixed_vec_deque;
+pub mod id3_chapters;
+pub mod in_place;
+pub mod json;
+pub mod lock;
+pub mod matroska;
+pub mod mp4_chapters;
+pub mod mp4chaps;
+pub mod nfo;
+pub mod notify;
+pub mod output_config;
+pub mod probe_cache;
+pub mod report;
+pub mod retime;
+pub mod sanity;
+pub mod silence;
+pub mod split_script;
+pub mod srt;
+pub mod stats;
+pub mod status;
+pub mod streams;
+pub mod timestamp;
+pub mod titles;
+pub mod visualize;
+pub mod vtt;
+
+/// Returns the directory of the caches of the application, i.e. "audiobook-chapterizer" in the
+/// user's cache directory, if it can be found.
+pub fn user_cache_dir() -> Option<PathBuf> {
+    let cache_dir = env::var_os("XDG_CACHE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("LOCALAPPDATA").map(PathBuf::from))
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))?;
+    Some(cache_dir.join("audiobook-chapterizer"))
+}
 
 pub fn format_duration(duration: &Option<Duration>) -> String {
     let duration = match duration {
@@ -27,3 +74,58 @@ pub fn format_duration(duration: &Option<Duration>) -> String {
         millis / 10
     )
 }
+
+/// Replaces the characters that aren't allowed in file names on common platforms.
+pub fn sanitize_file_name(name: &str) -> String {
+    name.chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect::<String>()
+        .trim_end_matches(['.', ' '])
+        .to_string()
+}
+
+/// Escapes the characters that have a special meaning in XML text and attribute values.
+pub fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Parses a timestamp in the format [hh:]mm:ss[.fff], e.g. "01:02:03.450" or "02:03".
+pub fn parse_timestamp(s: &str) -> Option<Duration> {
+    let (whole, frac) = match s.split_once('.') {
+        Some((whole, frac)) => (whole, Some(frac)),
+        None => (s, None),
+    };
+
+    let parts = whole
+        .split(':')
+        .map(|part| match part.len() {
+            1..=2 if part.chars().all(|c| c.is_ascii_digit()) => part.parse::<u64>().ok(),
+            _ => None,
+        })
+        .collect::<Option<Vec<_>>>()?;
+    let (hours, minutes, seconds) = match parts[..] {
+        [minutes, seconds] => (0, minutes, seconds),
+        [hours, minutes, seconds] => (hours, minutes, seconds),
+        _ => return None,
+    };
+    if minutes >= 60 || seconds >= 60 {
+        return None;
+    }
+
+    let frac = match frac {
+        Some(frac) if !frac.is_empty() && frac.chars().all(|c| c.is_ascii_digit()) => {
+            format!("0.{}", frac).parse::<f64>().ok()?
+        }
+        Some(_) => return None,
+        None => 0.0,
+    };
+
+    Some(Duration::from_secs(hours * 3600 + minutes * 60 + seconds) + Duration::from_secs_f64(frac))
+}
@@ -0,0 +1,58 @@
+use std::{
+    fs::{File, OpenOptions, TryLockError},
+    path::{Path, PathBuf},
+};
+
+use crate::error::{IoResultExt, Result};
+
+/// An advisory lock on a file, held until dropped, so that concurrent invocations don't process or
+/// write the same file at the same time. The lock is taken on a separate lock file next to the
+/// file, since the file itself may not exist yet or may be replaced while it's locked.
+///
+/// The operating system releases the lock when the process exits, so a crashed run never leaves
+/// a file locked. The lock file itself is left in place, since removing it could let two
+/// invocations hold locks on different lock files for the same file at once.
+#[derive(Debug)]
+pub struct FileLock {
+    _file: File,
+}
+
+impl FileLock {
+    /// Locks the file, unless another process holds its lock, in which case None is returned.
+    pub fn try_acquire(path: &Path) -> Result<Option<FileLock>> {
+        let lock_path = lock_path(path);
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&lock_path)
+            .io_context("Failed to open lock file")?;
+        match file.try_lock() {
+            Ok(()) => Ok(Some(FileLock { _file: file })),
+            Err(TryLockError::WouldBlock) => Ok(None),
+            Err(TryLockError::Error(err)) => Err(err).io_context("Failed to lock file"),
+        }
+    }
+}
+
+/// Returns the path of the lock file of the file, e.g. ".book.m4b.chapterizer-lock".
+pub fn lock_path(path: &Path) -> PathBuf {
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!(".{}.chapterizer-lock", file_name))
+}
+
+/// Locks all of the files, or none of them if another process holds any of their locks. Returns
+/// the path of the first file that's locked by another process in that case.
+pub fn try_lock_all<'a>(
+    paths: impl IntoIterator<Item = &'a Path>,
+) -> Result<Result<Vec<FileLock>, PathBuf>> {
+    let mut locks = Vec::new();
+    for path in paths {
+        match FileLock::try_acquire(path)? {
+            Some(lock) => locks.push(lock),
+            // The locks taken so far are released when dropped
+            None => return Ok(Err(path.to_path_buf())),
+        }
+    }
+    Ok(Ok(locks))
+}
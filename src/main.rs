@@ -1,24 +1,134 @@
+#[cfg(feature = "asr")]
+use audiobook_chapterizer::chapterize::{
+    candidate_times, chapterize, default_profiles_dir, default_user_config_path,
+    detect_silence_chapters, gimme_audio, profile_path, replay_matches, tune, AnnouncementLanguage,
+    ChapterizeOptions, DetectionConfig, IgnoreRegion, UserConfig,
+};
+#[cfg(feature = "live-input")]
+use audiobook_chapterizer::chapterize::{chapterize_live, LiveOptions};
+use audiobook_chapterizer::{
+    agreement::CrossCheck,
+    chapter::ChapterList,
+    chapter_reader::read_chapter_file,
+    cue::disc_file_path,
+    diff::{diff_chapters, DEFAULT_DIFF_TOLERANCE},
+    error::{format_error_chain, ChapterizerError},
+    evaluate::evaluate,
+    extract::{count_metadata_chapters, extract_chapters, import_chapters, ExtractOptions},
+    format_duration,
+    in_place::{apply_chapters, can_embed_chapters, InPlaceOptions},
+    lock::try_lock_all,
+    notify::{post_summary, show_desktop_notification, RunSummary},
+    output_config::OutputConfig,
+    probe_cache::{self, default_probe_cache_dir},
+    report::{write_report, FileReport},
+    retime::{Retime, TimeOffset},
+    status::{format_status, read_status},
+    titles::chapter_number,
+};
+#[cfg(feature = "asr")]
 use audiobook_chapterizer::{
-    chapterize::{chapterize, ChapterizeOptions},
-    extract::{extract_chapters, ExtractOptions},
+    agreement::DEFAULT_AGREEMENT_TOLERANCE,
+    audio_provider::{
+        inspect_audio, is_stdin, AudioProperties, FormatHint, RawFormat, RawSampleFormat,
+    },
+    chapter::ChapterSource,
+    compression::FileCompression,
+    corpus::{read_manifest, CorpusEntry, Scoreboard},
+    extract::read_metadata_chapters,
+    sanitize_file_name,
+    silence::{write_silences_csv, write_silences_json, SilenceDetector, SilenceOptions},
+    visualize::{render_svg, LoudnessEnvelope, Markers},
 };
 use clap::{
     builder::{OsStringValueParser, TypedValueParser},
-    ArgAction, Args, Parser,
+    ArgAction, Args, Parser, Subcommand, ValueEnum,
 };
-use color_eyre::eyre;
+use color_eyre::eyre::{self, eyre, Context};
 use log::LevelFilter;
 use std::{
     ffi::{OsStr, OsString},
-    path::PathBuf,
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
 };
 
 // TODO: find a way to parallelize the workload
 
+#[cfg(feature = "asr")]
 fn verify_jsonl_ext(os: OsString) -> Result<PathBuf, &'static str> {
     let path = PathBuf::from(os);
-    if path.extension() != Some(OsStr::new("jsonl")) {
-        return Err("path must end in .jsonl");
+    let uncompressed_path = FileCompression::from_path(&path).strip_extension(&path);
+    if uncompressed_path.extension() != Some(OsStr::new("jsonl")) {
+        return Err("path must end in .jsonl, .jsonl.gz or .jsonl.zst");
+    }
+    Ok(path)
+}
+
+fn parse_seconds(s: &str) -> Result<Duration, String> {
+    s.parse::<f64>()
+        .ok()
+        .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+        .ok_or_else(|| format!("invalid number of seconds: {}", s))
+}
+
+#[cfg(feature = "asr")]
+fn parse_milliseconds(s: &str) -> Result<Duration, String> {
+    s.parse::<u64>()
+        .ok()
+        .filter(|millis| *millis > 0)
+        .map(Duration::from_millis)
+        .ok_or_else(|| format!("must be a positive whole number of milliseconds: {}", s))
+}
+
+fn parse_tempo_ratio(s: &str) -> Result<f64, String> {
+    s.parse::<f64>()
+        .ok()
+        .filter(|ratio| ratio.is_finite() && *ratio > 0.0)
+        .ok_or_else(|| format!("tempo ratio must be a positive number: {}", s))
+}
+
+#[cfg(feature = "asr")]
+fn parse_confidence(s: &str) -> Result<f32, String> {
+    s.parse::<f32>()
+        .ok()
+        .filter(|confidence| (0.0..=1.0).contains(confidence))
+        .ok_or_else(|| format!("confidence must be a number from 0 to 1: {}", s))
+}
+
+#[cfg(feature = "asr")]
+fn parse_language(s: &str) -> Result<AnnouncementLanguage, String> {
+    AnnouncementLanguage::from_code(s).ok_or_else(|| {
+        format!(
+            "unsupported language: {} (chapter numbers can be recognized in en, fr and es)",
+            s
+        )
+    })
+}
+
+#[cfg(feature = "asr")]
+fn parse_raw_sample_format(s: &str) -> Result<RawSampleFormat, String> {
+    RawSampleFormat::from_name(s).ok_or_else(|| {
+        format!(
+            "unsupported raw sample format: {} (supported are s16le, s16be and f32le)",
+            s
+        )
+    })
+}
+
+fn parse_key_value(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((key, value)) if !key.trim().is_empty() => {
+            Ok((key.trim().to_string(), value.to_string()))
+        }
+        _ => Err(format!("expected key=value: {}", s)),
+    }
+}
+
+fn verify_report_ext(os: OsString) -> Result<PathBuf, &'static str> {
+    let path = PathBuf::from(os);
+    if path.extension() != Some(OsStr::new("json")) && path.extension() != Some(OsStr::new("csv")) {
+        return Err("path must end in .json or .csv");
     }
     Ok(path)
 }
@@ -33,49 +143,2401 @@ struct Outputs {
     /// The path that the output ffmetadata file will be written to (if any).
     #[arg(value_name = "ffmetadata_file", long = "output_ffmetadata")]
     ffmetadata_file_path: Option<PathBuf>,
+    /// The path that the output JSON file will be written to (if any).
+    #[arg(value_name = "json_file", long = "output_json")]
+    json_file_path: Option<PathBuf>,
+    /// The path that a script that splits the audio file into one file per chapter with ffmpeg
+    /// will be written to (if any). It's a PowerShell script if the path ends in .ps1, or a POSIX
+    /// shell script otherwise.
+    #[arg(value_name = "script_file", long = "output_split_script")]
+    split_script_file_path: Option<PathBuf>,
+    /// The path that an Audiobookshelf metadata.json file will be written to (if any). Placed in
+    /// the folder of a book in an Audiobookshelf library, its chapters are applied on the next
+    /// library scan.
+    #[arg(value_name = "metadata_file", long = "output_audiobookshelf")]
+    audiobookshelf_file_path: Option<PathBuf>,
+    /// The path that an NFO file with the chapters as bookmarks, as Kodi and Jellyfin read them,
+    /// will be written to (if any).
+    #[arg(value_name = "nfo_file", long = "output_nfo")]
+    nfo_file_path: Option<PathBuf>,
+    /// The path that a Matroska chapter XML file will be written to (if any), which mkvmerge can
+    /// mux into .mka and .mkv files with --chapters.
+    #[arg(value_name = "xml_file", long = "output_matroska")]
+    matroska_file_path: Option<PathBuf>,
+    /// The path that a WebVTT chapters track will be written to (if any), which web players show
+    /// as chapters when it's added to the audio with a <track kind="chapters"> element.
+    #[arg(value_name = "vtt_file", long = "output_vtt")]
+    vtt_file_path: Option<PathBuf>,
+    /// The path that an SRT file with a cue per chapter will be written to (if any), for players
+    /// and car head units that show subtitles but not chapters.
+    #[arg(value_name = "srt_file", long = "output_srt")]
+    srt_file_path: Option<PathBuf>,
+    /// Embed the chapters in the audio file itself, which must be an MP4 file, e.g. an .m4b, or an
+    /// MP3 file. MP4 files get both a Nero chapter list and a QuickTime chapter track, and MP3
+    /// files get ID3v2 CHAP frames with a CTOC table of contents. Only the metadata of the file is
+    /// rewritten, so this is much faster than remuxing it with ffmpeg.
+    #[arg(long = "embed")]
+    embed: bool,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum OutputFormat {
+    Cue,
+    Ffmetadata,
+    Json,
+    /// A POSIX shell script that splits the audio file into its chapters.
+    #[value(name = "split_script")]
+    SplitScript,
+    /// An Audiobookshelf metadata.json file, which has to be renamed to metadata.json and moved
+    /// into the folder of the book in the library.
+    Audiobookshelf,
+    /// An NFO file for Kodi and Jellyfin.
+    Nfo,
+    /// A Matroska chapter XML file for mkvmerge.
+    Matroska,
+    /// A WebVTT chapters track.
+    Vtt,
+    /// An SRT file with a cue per chapter.
+    Srt,
+    /// Embeds the chapters in the audio file itself instead of writing a file, if it's an MP4
+    /// file, e.g. an .m4b, or an MP3 file.
+    Embed,
+}
+
+/// What to do when speech recognition finds no chapters in an audio file.
+#[cfg(feature = "asr")]
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum NoChaptersAction {
+    /// Write a single chapter spanning the whole file, with a warning.
+    Single,
+    /// Fail the file, removing its outputs, so that the run exits with a non-zero code.
+    Fail,
+    /// Place chapters at long silences instead.
+    Silence,
+}
+
+/// The sources that chapters can be taken from.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Detector {
+    /// The chapters in the audio file's metadata.
+    #[value(alias = "embedded")]
+    Metadata,
+    /// Automatic speech recognition.
+    #[cfg(feature = "asr")]
+    Asr,
+}
+
+#[derive(Args, Clone, Debug)]
+struct BatchArgs {
+    /// A file listing the paths of the audio files to chapterize, one per line. Empty lines and
+    /// lines starting with # are skipped.
+    #[arg(value_name = "list_file")]
+    list_file_path: PathBuf,
+    /// The directory that the outputs will be written to. The outputs for each audio file are
+    /// written to a subdirectory named after the audio file.
+    #[arg(value_name = "output_dir", long = "output_dir", default_value = ".")]
+    output_dir_path: PathBuf,
+    /// The output formats to write for each audio file.
+    #[arg(
+        value_name = "formats",
+        long = "formats",
+        value_delimiter = ',',
+        default_value = "cue,ffmetadata"
+    )]
+    formats: Vec<OutputFormat>,
+    /// Also write matching recognition results for each audio file.
+    #[cfg(feature = "asr")]
+    #[arg(long = "write_matches")]
+    write_matches: bool,
+    /// Also write the full transcript of each audio file, with the start time of each line.
+    #[cfg(feature = "asr")]
+    #[arg(long = "write_transcripts")]
+    write_transcripts: bool,
+    /// Also write the progress of each audio file to a status file while it's chapterized, which
+    /// the status subcommand can read.
+    #[cfg(feature = "asr")]
+    #[arg(long = "write_status")]
+    write_status: bool,
+    /// Skip audio files whose metadata already contains at least this many chapters. Audio files
+    /// with fewer chapters are chapterized as if their metadata contained no chapters.
+    #[arg(value_name = "min_chapters", long = "skip_if_chaptered")]
+    skip_if_chaptered: Option<usize>,
+    /// Lock each audio file and its outputs while it's chapterized, and skip audio files that
+    /// another invocation has locked. Useful when several invocations work on a shared library.
+    /// The locks are advisory lock files next to the locked files.
+    #[arg(long = "lock")]
+    lock: bool,
+    /// Skip audio files for which all outputs already exist. Ignored with --diff.
+    #[arg(long = "skip_if_output_exists")]
+    skip_if_output_exists: bool,
+    /// Continue the chapter numbers of each audio file from the highest chapter number of the
+    /// audio file before it in the list, for the volumes of a set whose chapters are numbered
+    /// throughout, e.g. so that "Chapter 01" of the second volume becomes "Chapter 14". Only
+    /// titles such as "Chapter 05" are renumbered. The numbers of an audio file that's skipped
+    /// because its outputs exist are read from them. If the chapters of an audio file are
+    /// unknown otherwise, e.g. because it failed, the numbering isn't continued after it.
+    #[arg(long = "continue_numbering")]
+    continue_numbering: bool,
+    /// Put the volume number of each audio file, i.e. its position in the list, before the titles
+    /// of its chapters, along with this label, e.g. "Book" for titles such as
+    /// "Book 2, Chapter 14".
+    #[arg(value_name = "label", long = "volume_label")]
+    volume_label: Option<String>,
+    /// Optionally, a path to a file to write a summary of the batch run to. The path must end in
+    /// .json or .csv
+    #[arg(
+        value_name = "report_file",
+        long = "report",
+        value_parser = OsStringValueParser::new().try_map(verify_report_ext)
+    )]
+    report_file_path: Option<PathBuf>,
+}
+
+#[cfg(feature = "asr")]
+#[derive(Args, Clone, Debug)]
+struct TuneArgs {
+    /// A matches file in which the candidates were reviewed, by adding "decision": "accepted" or
+    /// "decision": "rejected" to the line of each candidate.
+    #[arg(value_name = "matches_file")]
+    matches_file_path: PathBuf,
+    /// Optionally, a path to write the suggested detection config to.
+    #[arg(value_name = "config_file", long = "output_config")]
+    output_config_path: Option<PathBuf>,
+}
+
+#[derive(Args, Clone, Debug)]
+struct EvaluateArgs {
+    /// The chapter file with the detected chapters, e.g. as written by a previous run.
+    #[arg(value_name = "detected_file")]
+    detected_file_path: PathBuf,
+    /// The chapter file with the correct chapters. Chapter files can be cue, ffmetadata, JSON,
+    /// WebVTT or mp4chaps text files. The format is detected from the contents of the file,
+    /// falling back to its extension.
+    #[arg(value_name = "reference_file")]
+    reference_file_path: PathBuf,
+    /// The maximum difference in seconds between the start times of a detected chapter and a
+    /// reference chapter for them to match.
+    #[arg(
+        value_name = "seconds",
+        long = "tolerance",
+        default_value = "5",
+        value_parser = parse_seconds
+    )]
+    tolerance: Duration,
+}
+
+#[cfg(feature = "asr")]
+#[derive(Args, Clone, Debug)]
+struct CorpusArgs {
+    /// A JSON file listing the entries of the corpus, e.g.
+    /// [{"input": "book.mp3", "reference": "book.cue"}]. The input is either an audio file, or a
+    /// matches file ending in .jsonl to detect chapters in its recognition results instead, which
+    /// is much faster. Relative paths are relative to the directory of the manifest.
+    #[arg(value_name = "manifest_file")]
+    manifest_file_path: PathBuf,
+    /// The directory that the chapters detected in audio files are written to, as JSON files named
+    /// after the audio files.
+    #[arg(value_name = "output_dir", long = "output_dir", default_value = ".")]
+    output_dir_path: PathBuf,
+    /// The maximum difference in seconds between the start times of a detected chapter and a
+    /// reference chapter for them to match.
+    #[arg(
+        value_name = "seconds",
+        long = "tolerance",
+        default_value = "5",
+        value_parser = parse_seconds
+    )]
+    tolerance: Duration,
+    /// Optionally, a path to write the scoreboard to as JSON, e.g. to compare it against the
+    /// scoreboard of a later run.
+    #[arg(value_name = "scoreboard_file", long = "scoreboard")]
+    scoreboard_file_path: Option<PathBuf>,
+}
+
+#[cfg(feature = "asr")]
+#[derive(Args, Clone, Debug)]
+struct InspectArgs {
+    /// The path to the audio file to inspect.
+    #[arg(value_name = "audio_file")]
+    audio_file_path: PathBuf,
+}
+
+#[cfg(feature = "asr")]
+#[derive(Args, Clone, Debug)]
+struct SilencesArgs {
+    /// The path to the audio file to scan for silences.
+    #[arg(value_name = "audio_file")]
+    audio_file_path: PathBuf,
+    /// The path to write the silences to, as CSV if it ends in .csv and as JSON otherwise. If not
+    /// given, they're printed to stdout as JSON.
+    #[arg(value_name = "output_file", long = "output")]
+    output_file_path: Option<PathBuf>,
+    /// Audio quieter than this many dBFS is silent.
+    #[arg(
+        value_name = "db",
+        long = "silence_threshold",
+        default_value = "-40",
+        allow_hyphen_values = true
+    )]
+    threshold_db: f32,
+    /// The minimum duration of a silence in seconds.
+    #[arg(
+        value_name = "seconds",
+        long = "min_silence",
+        default_value = "0.5",
+        value_parser = parse_seconds
+    )]
+    min_duration: Duration,
+}
+
+#[cfg(feature = "asr")]
+#[derive(Args, Clone, Debug)]
+struct VisualizeArgs {
+    /// The path to the audio file to draw.
+    #[arg(value_name = "audio_file")]
+    audio_file_path: PathBuf,
+    /// The path to write the SVG drawing to.
+    #[arg(value_name = "svg_file", long = "output")]
+    output_file_path: PathBuf,
+    /// Optionally, a chapter file with the chapters to mark, e.g. as detected by a previous run.
+    #[arg(value_name = "chapter_file", long = "chapters")]
+    chapter_file_path: Option<PathBuf>,
+    /// Optionally, the matches file of a previous run. Candidates in it that didn't become one of
+    /// the chapters are marked as rejected.
+    #[arg(
+        value_name = "matches_file",
+        long = "matches",
+        requires = "chapter_file_path",
+        value_parser = OsStringValueParser::new().try_map(verify_jsonl_ext)
+    )]
+    matches_file_path: Option<PathBuf>,
+}
+
+#[derive(Args, Clone, Debug)]
+struct StatusArgs {
+    /// The status file written by a run.
+    #[arg(value_name = "status_file")]
+    status_file_path: PathBuf,
+}
+
+#[derive(Args, Clone, Debug)]
+struct ApplyArgs {
+    /// The audio file to embed the chapters in.
+    #[arg(value_name = "audio_file")]
+    audio_file_path: PathBuf,
+    /// The ffmetadata file with the chapters, e.g. as written with --output_ffmetadata.
+    #[arg(value_name = "ffmetadata_file")]
+    ffmetadata_file_path: PathBuf,
+    /// The path to write the chapterized copy of the audio file to.
+    #[arg(
+        value_name = "output_file",
+        short = 'o',
+        long = "output",
+        required_unless_present = "in_place"
+    )]
+    output_file_path: Option<PathBuf>,
+    /// Replace the audio file with the chapterized copy instead of writing it elsewhere. The copy
+    /// is written to a temporary file next to it first, and only replaces it once it's verified
+    /// to have the chapters and the same duration. Use --keep_backup to keep the original.
+    #[arg(long = "in_place", conflicts_with = "output_file_path")]
+    in_place: bool,
+    /// Only copy the audio and the cover art of the audio file, leaving out whatever chapters it
+    /// already has, e.g. the chapter track of an .m4b, which some players keep showing otherwise.
+    #[arg(long = "strip_existing")]
+    strip_existing: bool,
+}
+
+#[derive(Subcommand, Clone, Debug)]
+enum Command {
+    /// Chapterizes each of the audio files listed in a file.
+    Batch(BatchArgs),
+    /// Suggests detection thresholds that would have decided the reviewed candidates in a matches
+    /// file as accurately as possible.
+    #[cfg(feature = "asr")]
+    Tune(TuneArgs),
+    /// Compares detected chapters against a reference chapter file and reports precision, recall
+    /// and the errors in the start times of the chapters.
+    Evaluate(EvaluateArgs),
+    /// Detects chapters in each entry of a corpus and evaluates them against the entry's reference
+    /// chapter file, reporting the scores of each entry and of the corpus as a whole.
+    #[cfg(feature = "asr")]
+    Corpus(CorpusArgs),
+    /// Prints the properties of an audio file as the chapterizer sees them, e.g. to check that it
+    /// can be decoded before starting a long run.
+    #[cfg(feature = "asr")]
+    Inspect(InspectArgs),
+    /// Scans an audio file for silences and writes their start, duration and loudness, without
+    /// running speech recognition. Useful for chapterizing by hand.
+    #[cfg(feature = "asr")]
+    Silences(SilencesArgs),
+    /// Draws the loudness of an audio file over time as an SVG strip, with silences, chapters and
+    /// rejected candidates marked, to spot structural problems at a glance.
+    #[cfg(feature = "asr")]
+    Visualize(VisualizeArgs),
+    /// Prints the progress of a run from the status file it writes, e.g. to check on a long run
+    /// from another terminal.
+    Status(StatusArgs),
+    /// Embeds the chapters of an ffmetadata file in a copy of an audio file, or in the audio file
+    /// itself, by remuxing it with ffmpeg without encoding it again. Works for any container that
+    /// ffmpeg can write chapters to. ffmpeg and ffprobe need to be installed.
+    Apply(ApplyArgs),
 }
 
 #[derive(Parser, Clone, Debug)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Cli {
     /// Makes logging more verbose. Pass once for debug log level, twice for trace log level.
     #[arg(short, action = ArgAction::Count, global = true)]
     verbose: u8,
-    /// The path to the Vosk ASR model directory to use.
-    #[arg(value_name = "model_dir", long = "model", default_value = "./model")]
-    model_dir_path: PathBuf,
-    /// Optionally, a path to a file to write matching recognition results to. The path must end in
-    /// .jsonl
+    /// The path to the Vosk ASR model directory to use, or to a .zip of one as the models are
+    /// distributed. Archives are extracted to the user's cache directory the first time they're
+    /// used. Defaults to the model of the language given with --language, if the config file has
+    /// one, or else to ./model.
+    #[cfg(feature = "asr")]
+    #[arg(value_name = "model_dir", long = "model", global = true)]
+    model_dir_path: Option<PathBuf>,
+    /// The language that chapters are announced in: en, fr or es. Chapter numbers are recognized
+    /// in the language, and the model and chapter words are taken from the language's entry in
+    /// the models section of the config file, if it has one, e.g.
+    /// {"models": {"fr": {"model": "vosk-model-fr-0.22.zip", "chapter_words": ["chapitre"]}}}.
+    #[cfg(feature = "asr")]
+    #[arg(
+        value_name = "code",
+        long = "language",
+        value_parser = parse_language,
+        global = true
+    )]
+    language: Option<AnnouncementLanguage>,
+    /// The config file with the settings that apply to every run, such as the model to use for
+    /// each language. Defaults to "audiobook-chapterizer/config.json" in the user's config
+    /// directory, if it exists.
+    #[cfg(feature = "asr")]
+    #[arg(value_name = "config_file", long = "config", global = true)]
+    user_config_path: Option<PathBuf>,
+    /// The path to an additional Vosk ASR model directory or .zip. Can be passed multiple times.
+    /// The windows of audio that may contain a chapter are transcribed again using each additional
+    /// model, and the models vote on whether the window contains a chapter. This reduces missed
+    /// chapters at the cost of runtime.
+    #[cfg(feature = "asr")]
+    #[arg(value_name = "model_dir", long = "ensemble_model", global = true)]
+    ensemble_model_dir_paths: Vec<PathBuf>,
+    /// If the word "chapter" is recognized but no number after it, transcribe that part of the
+    /// audio again with more alternatives to try to find the chapter number.
+    #[cfg(feature = "asr")]
+    #[arg(long = "retranscribe_weak", global = true)]
+    retranscribe_weak_candidates: bool,
+    /// Write the parts of a subdivided chapter, e.g. "chapter nine, part one", as sub-chapters of
+    /// the chapter in formats that support nesting. By default, each part is a separate chapter
+    /// titled e.g. "Chapter 09, Part 01".
+    #[cfg(feature = "asr")]
+    #[arg(long = "nest_chapter_parts", global = true)]
+    nest_chapter_parts: bool,
+    /// Write the candidates that were rejected as chapters, e.g. "chapter" without a number after
+    /// it, with the reason they were rejected. They're written as REM lines in .cue files and in
+    /// a "rejected" array in JSON files.
+    #[cfg(feature = "asr")]
+    #[arg(long = "include_rejected", global = true)]
+    include_rejected: bool,
+    /// Log an ffplay command with each chapter found that plays the audio file from shortly before
+    /// it, ready to be pasted into a shell to check the chapter by ear.
+    #[cfg(feature = "asr")]
+    #[arg(long = "seek_hints", global = true)]
+    seek_hints: bool,
+    /// A time range of the audio file in which no chapters are detected, e.g. "0:00-2:30" or
+    /// "14:55:00-end". Useful for books with samples of other books at the end that have chapter
+    /// announcements of their own. Can be given more than once.
+    #[cfg(feature = "asr")]
+    #[arg(value_name = "start-end", long = "ignore", global = true)]
+    ignore_regions: Vec<IgnoreRegion>,
+    /// Reject chapters whose confidence is below this, from 0 to 1. The confidence of a candidate
+    /// is the share of its transcripts in which "chapter" is followed by a number, counting the
+    /// recognizer's alternatives and any ensemble models. Raise it for fewer false chapters, lower
+    /// it for fewer missed ones. Candidates are written to the matches file with their confidence
+    /// either way.
+    #[cfg(feature = "asr")]
+    #[arg(
+        value_name = "confidence",
+        long = "min_confidence",
+        value_parser = parse_confidence,
+        global = true
+    )]
+    min_confidence: Option<f32>,
+    /// Before chapterizing an audio file, transcribe this many seconds of its start to measure how
+    /// fast the narrator speaks and how well they're recognized, and adjust the pause thresholds
+    /// and the minimum confidence to match. The calibration is logged and listed in the status
+    /// file.
+    #[cfg(feature = "asr")]
+    #[arg(
+        value_name = "seconds",
+        long = "calibrate",
+        value_parser = parse_seconds,
+        global = true
+    )]
+    calibration_duration: Option<Duration>,
+    /// Write a few seconds of audio around each candidate in the matches file to a WAV file in a
+    /// directory next to it, e.g. "book.candidates" for "book.jsonl", so that candidates can be
+    /// reviewed without the audio file. Requires a matches file.
+    #[cfg(feature = "asr")]
+    #[arg(long = "candidate_audio", global = true)]
+    write_candidate_audio: bool,
+    /// Optionally, a path to a JSON file with the thresholds used to detect chapters, e.g. as
+    /// written by the tune subcommand.
+    #[cfg(feature = "asr")]
+    #[arg(value_name = "config_file", long = "detection_config", global = true)]
+    detection_config_path: Option<PathBuf>,
+    /// Optionally, the name of a narrator profile to load the thresholds used to detect chapters
+    /// from, as saved by a previous run with --save_profile, e.g. "Ray Porter".
+    #[cfg(feature = "asr")]
+    #[arg(
+        value_name = "name",
+        long = "profile",
+        conflicts_with = "detection_config_path",
+        global = true
+    )]
+    profile: Option<String>,
+    /// Optionally, the name of a narrator profile to save the thresholds used to detect chapters
+    /// to once the run completes, including any calibration, so that books by the same narrator
+    /// can be chapterized with the same thresholds using --profile.
+    #[cfg(feature = "asr")]
+    #[arg(value_name = "name", long = "save_profile", global = true)]
+    save_profile: Option<String>,
+    /// The directory that narrator profiles are kept in. Defaults to
+    /// "audiobook-chapterizer/profiles" in the user's config directory.
+    #[cfg(feature = "asr")]
+    #[arg(value_name = "dir", long = "profile_dir", global = true)]
+    profile_dir_path: Option<PathBuf>,
+    /// Optionally, a URL to POST a JSON summary to when the run completes or fails.
+    #[arg(value_name = "url", long = "notify_url", global = true)]
+    notify_url: Option<String>,
+    /// Cache what's read about each audio file by probing it, i.e. the output of ffprobe, its
+    /// audio properties and any scanned duration, in the user's cache directory, so that later
+    /// runs on the same files don't probe them again. A file is probed again once its size or
+    /// modification time changes.
+    #[arg(long = "probe_cache", global = true)]
+    probe_cache: bool,
+    /// If an audio file's metadata doesn't specify its duration, scan it for its duration before
+    /// chapterizing it so that progress and ETA can be reported. This is common for VBR MP3 files.
+    #[cfg(feature = "asr")]
+    #[arg(long = "prescan_duration", global = true)]
+    prescan_duration: bool,
+    /// Restart the recognizer for each segment of this many seconds, with some overlap between the
+    /// segments. This keeps the memory usage of the recognizer down on long files.
+    #[cfg(feature = "asr")]
+    #[arg(
+        value_name = "seconds",
+        long = "segment_duration",
+        value_parser = parse_seconds,
+        global = true
+    )]
+    segment_duration: Option<Duration>,
+    /// Before speech recognition, find where the chapter words are spoken with a recognizer that
+    /// knows only them, and then only recognize the audio around them in full. This is much
+    /// faster on long books, but chapters whose chapter word isn't spotted are missed, and the
+    /// transcript only covers the audio around the chapter words.
+    #[cfg(feature = "asr")]
+    #[arg(
+        long = "spot_keywords",
+        conflicts_with = "segment_duration",
+        global = true
+    )]
+    spot_keywords: bool,
+    /// Consider this many of the highest scoring alternatives of each candidate. Those that read
+    /// the announcement differently than the best one are recorded with the candidate in the
+    /// matches file as "runner_ups", so that ambiguous candidates can be reviewed.
+    #[cfg(feature = "asr")]
+    #[arg(
+        value_name = "count",
+        long = "top_alternatives",
+        default_value_t = 1,
+        value_parser = clap::value_parser!(u16).range(1..),
+        global = true
+    )]
+    top_alternatives: u16,
+    /// Feed the recognizer chunks of this many milliseconds of audio at a time. Shorter chunks
+    /// make segments and progress reports more precise, longer chunks are slightly faster.
+    #[cfg(feature = "asr")]
+    #[arg(
+        value_name = "milliseconds",
+        long = "chunk_ms",
+        value_parser = parse_milliseconds,
+        default_value = "500",
+        global = true
+    )]
+    chunk_duration: Duration,
+    /// Give up on an audio file once it has been chapterized for this many seconds, e.g. because a
+    /// broken file makes the decoder loop. The outputs get the chapters found so far, and batch
+    /// runs move on to the next file. If the decoder or the recognizer hangs, the file is abandoned
+    /// 30 seconds later, with the outputs left as they were.
+    #[cfg(feature = "asr")]
+    #[arg(
+        value_name = "seconds",
+        long = "max_runtime",
+        value_parser = parse_seconds,
+        global = true
+    )]
+    max_runtime: Option<Duration>,
+    /// Detect where the narrator changes, e.g. in books with several narrators, and warn about
+    /// changes without a chapter nearby, since they may be missed chapters.
+    #[cfg(feature = "asr")]
+    #[arg(long = "speaker_changes", global = true)]
+    detect_speaker_changes: bool,
+    /// What to do when speech recognition finds no chapters in an audio file: write a single
+    /// chapter spanning the whole file, fail the file, or place chapters at long silences instead.
+    #[cfg(feature = "asr")]
+    #[arg(
+        value_name = "action",
+        long = "on_no_chapters",
+        default_value = "single",
+        global = true
+    )]
+    on_no_chapters: NoChaptersAction,
+    /// Checks the chapters against the chapters in the metadata and the chapters at long silences,
+    /// and writes which of them agree on each chapter, along with the share of them that do, to
+    /// the JSON output. Books whose detectors mostly agree can be accepted as they are, and the
+    /// others reviewed.
+    #[cfg(feature = "asr")]
+    #[arg(long = "cross_check", global = true)]
+    cross_check: bool,
+    /// Overrides the format of the audio files, given as a file extension, e.g. "mp3". By default,
+    /// the format is detected from the contents and extension of the files. Useful for misnamed
+    /// files.
+    #[cfg(feature = "asr")]
+    #[arg(value_name = "extension", long = "format", global = true)]
+    format: Option<String>,
+    /// The MIME type of the audio files, e.g. "audio/mpeg", to help detect their format.
+    #[cfg(feature = "asr")]
+    #[arg(value_name = "mime_type", long = "mime", global = true)]
+    mime_type: Option<String>,
+    /// Reads the audio files as raw PCM with this sample format, e.g. "s16le", instead of detecting
+    /// their format, so that audio pre-processed with ffmpeg or sox can be passed straight in.
+    /// Requires --rate and --channels. The audio file can then be "-" to pipe the audio in through
+    /// the standard input, except with --chapter_list or --on_no_chapters silence.
+    #[cfg(feature = "asr")]
+    #[arg(
+        value_name = "sample_format",
+        long = "raw",
+        value_parser = parse_raw_sample_format,
+        requires_all = ["raw_sample_rate", "raw_num_channels"],
+        global = true
+    )]
+    raw: Option<RawSampleFormat>,
+    /// The sample rate of the raw PCM audio read with --raw, in Hz.
+    #[cfg(feature = "asr")]
+    #[arg(
+        value_name = "hz",
+        long = "rate",
+        value_parser = clap::value_parser!(u32).range(1..),
+        requires = "raw",
+        global = true
+    )]
+    raw_sample_rate: Option<u32>,
+    /// The number of channels of the raw PCM audio read with --raw. They're mixed down to mono.
+    #[cfg(feature = "asr")]
+    #[arg(
+        value_name = "count",
+        long = "channels",
+        value_parser = clap::value_parser!(u16).range(1..),
+        requires = "raw",
+        global = true
+    )]
+    raw_num_channels: Option<u16>,
+    /// If an audio file is in a format or codec that can't be decoded, e.g. some HE-AAC files,
+    /// decode it with ffmpeg instead of giving up. The audio is then resampled to 16 kHz by
+    /// ffmpeg, which needs to be installed.
+    #[cfg(feature = "asr")]
+    #[arg(long = "ffmpeg_fallback", global = true)]
+    ffmpeg_fallback: bool,
+    /// Chapterize each audio track of files with more than one, e.g. releases with a track per
+    /// language, instead of only the first. The outputs of each track are written separately,
+    /// with the id of the track, and its language if known, in their file names, e.g.
+    /// "book.track2-eng.cue". Chapters from the metadata of the file are the same for each track.
+    #[cfg(feature = "asr")]
+    #[arg(long = "all_tracks", global = true)]
+    all_tracks: bool,
+    /// Keep the original audio file next to it, with .bak appended to its name, when embedding
+    /// chapters in it.
+    #[arg(long = "keep_backup", global = true)]
+    keep_backup: bool,
+    /// Instead of writing the outputs, compare the chapters that would be written against those
+    /// in the outputs that already exist, and log what would change. Only cue sheets, unless
+    /// split into discs, ffmetadata, JSON and WebVTT files can be compared.
+    #[arg(long = "diff", global = true)]
+    diff: bool,
+    /// Show a desktop notification when the run completes or fails.
+    #[arg(long = "notify_desktop", global = true)]
+    notify_desktop: bool,
+    /// The sources to try to take chapters from, in order. The first source that yields chapters is
+    /// used.
+    #[arg(
+        value_name = "sources",
+        long = "source_order",
+        value_delimiter = ',',
+        global = true
+    )]
+    #[cfg_attr(feature = "asr", arg(default_value = "metadata,asr"))]
+    #[cfg_attr(not(feature = "asr"), arg(default_value = "metadata"))]
+    source_order: Vec<Detector>,
+    /// If the metadata of an audio file can't be read, e.g. because it's corrupt, move on to the
+    /// next source in the source order instead of failing the file.
+    #[arg(long = "ignore_metadata_errors", global = true)]
+    ignore_metadata_errors: bool,
+    /// Split the cue output into one .cue file per disc, for books ripped from CDs. Takes the start
+    /// times of the discs after the first, in seconds. Each .cue file holds the tracks of its
+    /// disc, numbered from 1, and refers to the whole audio file, with the track times in it.
+    #[arg(
+        value_name = "seconds",
+        long = "cue_disc_starts",
+        value_delimiter = ',',
+        value_parser = parse_seconds,
+        global = true
+    )]
+    cue_disc_starts: Vec<Duration>,
+    /// Shift all chapter timestamps in the outputs by this many seconds, which may be negative.
+    /// Useful when the outputs are meant for a version of the audio file that has its intro
+    /// trimmed off (negative) or an intro added (positive). Chapters that would start before 0:00
+    /// start at 0:00 instead.
+    #[arg(
+        value_name = "seconds",
+        long = "offset",
+        allow_hyphen_values = true,
+        default_value = "0",
+        global = true
+    )]
+    offset: TimeOffset,
+    /// Scale all chapter timestamps in the outputs for a version of the audio file with a
+    /// different speed, e.g. 1.25 for a version that was re-encoded at 1.25x speed, or 0.8 to go
+    /// back from that version to the original. Applied before the offset.
+    #[arg(
+        value_name = "ratio",
+        long = "tempo_ratio",
+        default_value = "1",
+        value_parser = parse_tempo_ratio,
+        global = true
+    )]
+    tempo_ratio: f64,
+    /// Optionally, a path to a JSON file with options for each output format, e.g.
+    /// {"cue": {"encoding": "utf8-bom"}, "json": {"pretty": false}}.
+    #[arg(value_name = "config_file", long = "output_config", global = true)]
+    output_config_path: Option<PathBuf>,
+    /// Global metadata to write to the ffmetadata output, as key=value, e.g. "artist=Jane Doe".
+    /// Can be passed multiple times. Takes precedence over the metadata in the output config.
+    #[arg(
+        value_name = "key=value",
+        long = "metadata",
+        value_parser = parse_key_value,
+        global = true
+    )]
+    metadata: Vec<(String, String)>,
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Optionally, a path to a file to write matching recognition results to, one candidate per
+    /// line as a JSON object with the result it was found in as "match" and the results around it
+    /// as "before" and "after". The path must end in .jsonl, or in .jsonl.gz or .jsonl.zst to
+    /// compress the file with gzip or zstd.
+    #[cfg(feature = "asr")]
     #[arg(
         value_name = "matches_file",
         long = "write_matches",
         value_parser = OsStringValueParser::new().try_map(verify_jsonl_ext)
     )]
     matches_file_path: Option<PathBuf>,
+    /// Optionally, a path to a JSON file to write the progress of the run to every few seconds,
+    /// including the chapters found so far, so that long runs can be monitored from elsewhere.
+    #[cfg(feature = "asr")]
+    #[arg(value_name = "status_file", long = "status_file")]
+    status_file_path: Option<PathBuf>,
+    /// Optionally, a path to a text file to write the full transcript to as it's recognized, with
+    /// the start time of each line. Unlike the matches file, it covers all of the audio, so it's
+    /// useful even if no chapters are found. It's compressed with gzip or zstd if the path ends in
+    /// .gz or .zst.
+    #[cfg(feature = "asr")]
+    #[arg(value_name = "transcript_file", long = "write_transcript")]
+    transcript_file_path: Option<PathBuf>,
+    /// Play the audio around each chapter found and ask whether it's a chapter before writing it,
+    /// for quick one-off jobs. Chapters that aren't confirmed are rejected. The audio is only
+    /// played if built with the confirm-audio feature; otherwise, an ffplay command is shown.
+    #[cfg(feature = "asr")]
+    #[arg(long = "confirm_audio")]
+    confirm_audio: bool,
+    /// Optionally, a chapter file to take the chapters from instead of the sources in the source
+    /// order, e.g. a text file of "HH:MM:SS Title" lines jotted down while listening. The chapters
+    /// are written to all outputs, with the duration of the audio file. Chapter files can also be
+    /// cue, ffmetadata, JSON, WebVTT or mp4chaps text files.
+    #[arg(value_name = "chapter_file", long = "chapter_list")]
+    chapter_list_file_path: Option<PathBuf>,
+    /// Experimental: detect chapters in the audio captured from an audio input device, e.g. a
+    /// microphone, as it's recorded, instead of in the audio file. Each chapter is printed as a
+    /// line of JSON as soon as it's announced, and again if its end is announced. Press Enter to
+    /// stop; the chapters are then written to the outputs. The audio file is the one that the
+    /// narration is being recorded to, which the outputs refer to, and isn't read.
+    #[cfg(feature = "live-input")]
+    #[arg(long = "live", conflicts_with = "chapter_list_file_path")]
+    live: bool,
+    /// The name of the audio input device to capture from with --live. Defaults to the default
+    /// input device of the system.
+    #[cfg(feature = "live-input")]
+    #[arg(value_name = "device", long = "input_device", requires = "live")]
+    input_device: Option<String>,
     /// The path to the audio file to chapterize.
-    #[arg(value_name = "audio_file", short = 'i')]
-    audio_file_path: PathBuf,
+    #[arg(value_name = "audio_file", short = 'i', required = true)]
+    audio_file_path: Option<PathBuf>,
     #[command(flatten)]
     outputs: Outputs,
 }
 
-impl From<Cli> for ChapterizeOptions {
-    fn from(val: Cli) -> Self {
+/// Everything needed to chapterize a single audio file.
+#[derive(Clone, Debug)]
+struct FileOptions {
+    #[cfg(feature = "asr")]
+    model_dir_path: PathBuf,
+    #[cfg(feature = "asr")]
+    ensemble_model_dir_paths: Vec<PathBuf>,
+    #[cfg(feature = "asr")]
+    retranscribe_weak_candidates: bool,
+    #[cfg(feature = "asr")]
+    nest_chapter_parts: bool,
+    #[cfg(feature = "asr")]
+    include_rejected: bool,
+    #[cfg(feature = "asr")]
+    seek_hints: bool,
+    #[cfg(feature = "asr")]
+    confirm_audio: bool,
+    #[cfg(feature = "asr")]
+    ignore_regions: Vec<IgnoreRegion>,
+    #[cfg(feature = "asr")]
+    min_confidence: Option<f32>,
+    #[cfg(feature = "asr")]
+    calibration_duration: Option<Duration>,
+    #[cfg(feature = "asr")]
+    save_profile_path: Option<PathBuf>,
+    #[cfg(feature = "asr")]
+    write_candidate_audio: bool,
+    #[cfg(feature = "asr")]
+    detection_config: DetectionConfig,
+    #[cfg(feature = "asr")]
+    matches_file_path: Option<PathBuf>,
+    #[cfg(feature = "asr")]
+    status_file_path: Option<PathBuf>,
+    #[cfg(feature = "asr")]
+    transcript_file_path: Option<PathBuf>,
+    #[cfg(feature = "asr")]
+    prescan_duration: bool,
+    #[cfg(feature = "asr")]
+    segment_duration: Option<Duration>,
+    #[cfg(feature = "asr")]
+    spot_keywords: bool,
+    #[cfg(feature = "asr")]
+    top_alternatives: u16,
+    #[cfg(feature = "asr")]
+    chunk_duration: Duration,
+    #[cfg(feature = "asr")]
+    max_runtime: Option<Duration>,
+    #[cfg(feature = "asr")]
+    detect_speaker_changes: bool,
+    #[cfg(feature = "asr")]
+    on_no_chapters: NoChaptersAction,
+    #[cfg(feature = "asr")]
+    cross_check: bool,
+    #[cfg(feature = "asr")]
+    format: Option<String>,
+    #[cfg(feature = "asr")]
+    mime_type: Option<String>,
+    #[cfg(feature = "asr")]
+    raw: Option<RawFormat>,
+    #[cfg(feature = "asr")]
+    ffmpeg_fallback: bool,
+    #[cfg(feature = "asr")]
+    track_id: Option<u32>,
+    /// The sources to try to take chapters from, in order.
+    source_order: Vec<Detector>,
+    /// Whether to move on to the next source if the metadata can't be read.
+    ignore_metadata_errors: bool,
+    /// A chapter file to take the chapters from instead of the sources in the source order.
+    chapter_list_file_path: Option<PathBuf>,
+    audio_file_path: PathBuf,
+    cue_file_path: Option<PathBuf>,
+    cue_disc_starts: Vec<Duration>,
+    ffmetadata_file_path: Option<PathBuf>,
+    json_file_path: Option<PathBuf>,
+    split_script_file_path: Option<PathBuf>,
+    audiobookshelf_file_path: Option<PathBuf>,
+    nfo_file_path: Option<PathBuf>,
+    matroska_file_path: Option<PathBuf>,
+    vtt_file_path: Option<PathBuf>,
+    srt_file_path: Option<PathBuf>,
+    embed: Option<InPlaceOptions>,
+    retime: Retime,
+    output_config: OutputConfig,
+}
+
+impl FileOptions {
+    fn output_file_paths(&self) -> Vec<PathBuf> {
+        let cue_file_paths = self.cue_file_path.iter().flat_map(|cue_file_path| {
+            if self.cue_disc_starts.is_empty() {
+                vec![cue_file_path.clone()]
+            } else {
+                (1..=self.cue_disc_starts.len() + 1)
+                    .map(|disc_num| disc_file_path(cue_file_path, disc_num))
+                    .collect()
+            }
+        });
+
+        cue_file_paths
+            .chain(self.ffmetadata_file_path.clone())
+            .chain(self.json_file_path.clone())
+            .chain(self.split_script_file_path.clone())
+            .chain(self.audiobookshelf_file_path.clone())
+            .chain(self.nfo_file_path.clone())
+            .chain(self.matroska_file_path.clone())
+            .chain(self.vtt_file_path.clone())
+            .chain(self.srt_file_path.clone())
+            .collect()
+    }
+
+    /// Returns the paths of the output files that chapters can be read back from, for --diff.
+    fn readable_output_file_paths(&self) -> Vec<PathBuf> {
+        self.cue_file_path
+            .iter()
+            .filter(|_| self.cue_disc_starts.is_empty())
+            .chain(&self.ffmetadata_file_path)
+            .chain(&self.json_file_path)
+            .chain(&self.vtt_file_path)
+            .cloned()
+            .collect()
+    }
+
+    /// Returns the options to chapterize the given track of the audio file with, writing to files
+    /// with the label of the track in their names.
+    #[cfg(feature = "asr")]
+    fn for_track(&self, track_id: u32, track_label: &str) -> FileOptions {
+        let track_path = |path: &Option<PathBuf>| {
+            path.as_deref()
+                .map(|path| track_file_path(path, track_label))
+        };
+        FileOptions {
+            track_id: Some(track_id),
+            matches_file_path: track_path(&self.matches_file_path),
+            status_file_path: track_path(&self.status_file_path),
+            transcript_file_path: track_path(&self.transcript_file_path),
+            cue_file_path: track_path(&self.cue_file_path),
+            ffmetadata_file_path: track_path(&self.ffmetadata_file_path),
+            json_file_path: track_path(&self.json_file_path),
+            split_script_file_path: track_path(&self.split_script_file_path),
+            audiobookshelf_file_path: track_path(&self.audiobookshelf_file_path),
+            nfo_file_path: track_path(&self.nfo_file_path),
+            matroska_file_path: track_path(&self.matroska_file_path),
+            vtt_file_path: track_path(&self.vtt_file_path),
+            srt_file_path: track_path(&self.srt_file_path),
+            // Each track would replace the chapters embedded for the track before it
+            embed: None,
+            ..self.clone()
+        }
+    }
+}
+
+/// Returns the path with the label of a track before its extension, e.g. "book.track2.cue" for
+/// "book.cue". The extension of a compressed file stays last, e.g. "book.track2.jsonl.gz".
+#[cfg(feature = "asr")]
+fn track_file_path(path: &Path, track_label: &str) -> PathBuf {
+    let compression = FileCompression::from_path(path);
+    let uncompressed_path = compression.strip_extension(path);
+    let stem = uncompressed_path
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy();
+    let mut file_name = match uncompressed_path.extension() {
+        Some(ext) => format!("{}.{}.{}", stem, track_label, ext.to_string_lossy()),
+        None => format!("{}.{}", stem, track_label),
+    };
+    if compression != FileCompression::None {
+        if let Some(ext) = path.extension() {
+            file_name = format!("{}.{}", file_name, ext.to_string_lossy());
+        }
+    }
+    path.with_file_name(file_name)
+}
+
+/// Returns the options to chapterize each audio track of the file with, for --all_tracks. Raw
+/// audio only has one track, so its options are returned as they are.
+#[cfg(feature = "asr")]
+fn all_track_options(
+    cli: &Cli,
+    options: &FileOptions,
+) -> Result<Vec<FileOptions>, ChapterizerError> {
+    if options.raw.is_some() {
+        return Ok(vec![options.clone()]);
+    }
+    if options.embed.is_some() {
+        log::warn!(
+            "Not embedding chapters in {}, since the chapters of each track would replace those of \
+            the track before it",
+            options.audio_file_path.display()
+        );
+    }
+
+    let format_hint = cli_format_hint(cli, &options.audio_file_path);
+    let properties = inspect_audio_file(&options.audio_file_path, &format_hint)?;
+
+    let track_options = properties
+        .tracks
+        .iter()
+        .filter(|track| track.is_supported)
+        .map(|track| {
+            let track_label = match track.language.as_deref() {
+                Some(language) if language != "und" => {
+                    format!("track{}-{}", track.id, sanitize_file_name(language))
+                }
+                _ => format!("track{}", track.id),
+            };
+            options.for_track(track.id, &track_label)
+        })
+        .collect::<Vec<_>>();
+    if track_options.is_empty() {
+        return Err(ChapterizerError::UnsupportedAudio(
+            "File contains no supported audio tracks",
+        ));
+    }
+    Ok(track_options)
+}
+
+#[cfg(feature = "asr")]
+impl From<&FileOptions> for ChapterizeOptions {
+    fn from(val: &FileOptions) -> Self {
         ChapterizeOptions {
-            model_dir_path: val.model_dir_path,
-            matches_file_path: val.matches_file_path,
-            audio_file_path: val.audio_file_path,
-            cue_file_path: val.outputs.cue_file_path,
-            ffmetadata_file_path: val.outputs.ffmetadata_file_path,
+            model_dir_path: val.model_dir_path.clone(),
+            ensemble_model_dir_paths: val.ensemble_model_dir_paths.clone(),
+            retranscribe_weak_candidates: val.retranscribe_weak_candidates,
+            nest_chapter_parts: val.nest_chapter_parts,
+            include_rejected: val.include_rejected,
+            seek_hints: val.seek_hints,
+            confirm_audio: val.confirm_audio,
+            ignore_regions: val.ignore_regions.clone(),
+            min_confidence: val.min_confidence,
+            calibration_duration: val.calibration_duration,
+            save_profile_path: val.save_profile_path.clone(),
+            write_candidate_audio: val.write_candidate_audio,
+            detection_config: val.detection_config.clone(),
+            matches_file_path: val.matches_file_path.clone(),
+            status_file_path: val.status_file_path.clone(),
+            transcript_file_path: val.transcript_file_path.clone(),
+            audio_file_path: val.audio_file_path.clone(),
+            prescan_duration: val.prescan_duration,
+            segment_duration: val.segment_duration,
+            spot_keywords: val.spot_keywords,
+            top_alternatives: val.top_alternatives,
+            chunk_duration: val.chunk_duration,
+            max_runtime: val.max_runtime,
+            detect_speaker_changes: val.detect_speaker_changes,
+            format: val.format.clone(),
+            mime_type: val.mime_type.clone(),
+            raw: val.raw,
+            ffmpeg_fallback: val.ffmpeg_fallback,
+            track_id: val.track_id,
+            cue_file_path: val.cue_file_path.clone(),
+            cue_disc_starts: val.cue_disc_starts.clone(),
+            ffmetadata_file_path: val.ffmetadata_file_path.clone(),
+            json_file_path: val.json_file_path.clone(),
+            split_script_file_path: val.split_script_file_path.clone(),
+            audiobookshelf_file_path: val.audiobookshelf_file_path.clone(),
+            nfo_file_path: val.nfo_file_path.clone(),
+            matroska_file_path: val.matroska_file_path.clone(),
+            vtt_file_path: val.vtt_file_path.clone(),
+            srt_file_path: val.srt_file_path.clone(),
+            embed: val.embed,
+            retime: val.retime,
+            output_config: val.output_config.clone(),
+            cross_check: CrossCheck::default(),
         }
     }
 }
 
-impl From<Cli> for ExtractOptions {
-    fn from(val: Cli) -> Self {
+impl From<&FileOptions> for ExtractOptions {
+    fn from(val: &FileOptions) -> Self {
         ExtractOptions {
-            audio_file_path: val.audio_file_path,
-            cue_file_path: val.outputs.cue_file_path,
-            ffmetadata_file_path: val.outputs.ffmetadata_file_path,
+            audio_file_path: val.audio_file_path.clone(),
+            cue_file_path: val.cue_file_path.clone(),
+            cue_disc_starts: val.cue_disc_starts.clone(),
+            ffmetadata_file_path: val.ffmetadata_file_path.clone(),
+            json_file_path: val.json_file_path.clone(),
+            split_script_file_path: val.split_script_file_path.clone(),
+            audiobookshelf_file_path: val.audiobookshelf_file_path.clone(),
+            nfo_file_path: val.nfo_file_path.clone(),
+            matroska_file_path: val.matroska_file_path.clone(),
+            vtt_file_path: val.vtt_file_path.clone(),
+            srt_file_path: val.srt_file_path.clone(),
+            embed: val.embed,
+            retime: val.retime,
+            output_config: val.output_config.clone(),
+            cross_check: CrossCheck::default(),
+        }
+    }
+}
+
+fn load_output_config(cli: &Cli) -> eyre::Result<OutputConfig> {
+    let mut output_config = match &cli.output_config_path {
+        Some(path) => OutputConfig::load(path)?,
+        None => OutputConfig::default(),
+    };
+    output_config
+        .ffmetadata
+        .metadata
+        .extend(cli.metadata.iter().cloned());
+    Ok(output_config)
+}
+
+fn cli_in_place_options(cli: &Cli) -> InPlaceOptions {
+    InPlaceOptions {
+        keep_backup: cli.keep_backup,
+        ..Default::default()
+    }
+}
+
+fn cli_retime(cli: &Cli) -> Retime {
+    Retime {
+        tempo_ratio: cli.tempo_ratio,
+        offset: cli.offset,
+    }
+}
+
+/// Chapterizes a single audio file using the first source in the source order that yields
+/// chapters, or using the chapter list file if one was given. Returns
+/// [`ChapterizerError::NoChapters`] if none of them do.
+fn process_file(options: &FileOptions) -> Result<ChapterList, ChapterizerError> {
+    #[cfg(feature = "asr")]
+    verify_stdin_options(options)?;
+    #[cfg(feature = "asr")]
+    let cross_check = if options.cross_check {
+        cross_check(options)
+    } else {
+        CrossCheck::default()
+    };
+    #[cfg(not(feature = "asr"))]
+    let cross_check = CrossCheck::default();
+    let extract_options = || ExtractOptions {
+        cross_check: cross_check.clone(),
+        ..options.into()
+    };
+
+    if let Some(chapter_list_file_path) = &options.chapter_list_file_path {
+        log::debug!("Taking chapters from {}", chapter_list_file_path.display());
+        let chapter_list = read_chapter_file(chapter_list_file_path)?;
+        return import_chapters(&extract_options(), &chapter_list);
+    }
+
+    for detector in &options.source_order {
+        #[cfg(feature = "asr")]
+        if *detector == Detector::Metadata && options.raw.is_some() {
+            log::debug!("Skipping {:?}, since raw audio has none", detector);
+            continue;
+        }
+        log::debug!("Trying to find chapters using {:?}", detector);
+
+        let result = match detector {
+            Detector::Metadata => extract_chapters(&extract_options()),
+            #[cfg(feature = "asr")]
+            Detector::Asr => chapterize(&ChapterizeOptions {
+                cross_check: cross_check.clone(),
+                ..options.into()
+            })
+            .and_then(|chapter_list| handle_no_chapters(options, &extract_options(), chapter_list)),
+        };
+
+        match result {
+            Err(ChapterizerError::NoChapters) => continue,
+            Err(err @ ChapterizerError::Ffprobe(_)) if options.ignore_metadata_errors => {
+                log::warn!(
+                    "Ignoring unreadable metadata of {}: {}",
+                    options.audio_file_path.display(),
+                    format_error_chain(&err)
+                );
+                continue;
+            }
+            result => return result,
+        }
+    }
+
+    Err(ChapterizerError::NoChapters)
+}
+
+/// Returns the highest chapter number in the existing outputs of the audio file that can be read
+/// back, if any of them has one.
+fn highest_output_chapter_number(options: &FileOptions) -> Option<u32> {
+    options
+        .readable_output_file_paths()
+        .iter()
+        .filter_map(|path| read_chapter_file(path).ok())
+        .flat_map(|chapter_list| chapter_list.chapters)
+        .filter_map(|chapter| chapter_number(&chapter))
+        .max()
+}
+
+/// Stops continuing the chapter numbering for the rest of the batch, since the chapters of the
+/// audio file are unknown, so the numbers the audio files after it would continue from are too.
+fn stop_numbering(number_offset: &mut Option<u32>, audio_file_path: &Path) {
+    if number_offset.take().is_some() {
+        log::error!(
+            "The chapters of {} are unknown, so the numbering of the audio files after it isn't \
+            continued",
+            audio_file_path.display()
+        );
+    }
+}
+
+/// Chapterizes a single audio file like [`process_file`], but rather than writing the outputs,
+/// logs how the chapters differ from those in the outputs that already exist, for --diff. The
+/// chapters are written to a temporary JSON file and read back, so that they're compared exactly
+/// as they'd be written.
+fn diff_file(options: &FileOptions) -> Result<ChapterList, ChapterizerError> {
+    let existing_file_paths = options
+        .readable_output_file_paths()
+        .into_iter()
+        .filter(|path| path.exists())
+        .collect::<Vec<_>>();
+    if existing_file_paths.is_empty() {
+        return Err(ChapterizerError::InvalidOptions(
+            "there are no existing outputs to compare the chapters against",
+        ));
+    }
+
+    let temp_file_path = std::env::temp_dir().join(format!(
+        "audiobook-chapterizer-diff-{}.json",
+        std::process::id()
+    ));
+    let diff_options = FileOptions {
+        #[cfg(feature = "asr")]
+        matches_file_path: None,
+        #[cfg(feature = "asr")]
+        status_file_path: None,
+        #[cfg(feature = "asr")]
+        transcript_file_path: None,
+        cue_file_path: None,
+        ffmetadata_file_path: None,
+        json_file_path: Some(temp_file_path.clone()),
+        split_script_file_path: None,
+        audiobookshelf_file_path: None,
+        nfo_file_path: None,
+        matroska_file_path: None,
+        vtt_file_path: None,
+        srt_file_path: None,
+        embed: None,
+        ..options.clone()
+    };
+    let result = process_file(&diff_options)
+        .and_then(|chapter_list| Ok((chapter_list, read_chapter_file(&temp_file_path)?)));
+    // The file may not have been created if chapterizing failed
+    let _ = fs::remove_file(&temp_file_path);
+    let (chapter_list, new_chapters) = result?;
+
+    for existing_file_path in existing_file_paths {
+        let existing_chapters = match read_chapter_file(&existing_file_path) {
+            Ok(chapters) => chapters,
+            Err(err) => {
+                log::warn!(
+                    "Not comparing against {}: {}",
+                    existing_file_path.display(),
+                    format_error_chain(&err)
+                );
+                continue;
+            }
+        };
+        let diff = diff_chapters(&existing_chapters, &new_chapters, DEFAULT_DIFF_TOLERANCE);
+        if diff.is_empty() {
+            log::info!("{}: no changes", existing_file_path.display());
+            continue;
+        }
+        log::info!("{}: {}", existing_file_path.display(), diff.summary());
+        for change in &diff.changes {
+            log::info!("  {}", change);
+        }
+    }
+
+    Ok(chapter_list)
+}
+
+/// Runs the detectors that are cheap enough to check the chapters against, without writing their
+/// chapters anywhere. Detectors that fail are left out of the check.
+#[cfg(feature = "asr")]
+fn cross_check(options: &FileOptions) -> CrossCheck {
+    let audio_file_path = options.audio_file_path.display();
+    let mut cross_check = CrossCheck::new(DEFAULT_AGREEMENT_TOLERANCE);
+    // Raw audio has no metadata
+    if options.raw.is_none() {
+        match read_metadata_chapters(&options.audio_file_path) {
+            Ok(chapters) => cross_check.add_reference(ChapterSource::Metadata, &chapters),
+            Err(err) => log::warn!(
+                "Not checking the chapters of {} against its metadata: {}",
+                audio_file_path,
+                format_error_chain(&err)
+            ),
+        }
+    }
+    // Detecting the silences would use up the audio piped in before it's chapterized
+    if is_stdin(&options.audio_file_path) {
+        log::warn!(
+            "Not checking the chapters against the silences of audio from the standard input"
+        );
+        return cross_check;
+    }
+    match detect_silence_chapters(&options.into()) {
+        Ok(chapter_list) => {
+            cross_check.add_reference(ChapterSource::Silence, &chapter_list.chapters)
+        }
+        Err(err) => log::warn!(
+            "Not checking the chapters of {} against its silences: {}",
+            audio_file_path,
+            format_error_chain(&err)
+        ),
+    }
+    cross_check
+}
+
+/// Rejects the options that need to read the audio file again, or to probe it, if it's the
+/// standard input, before any of the audio piped in is used up.
+#[cfg(feature = "asr")]
+fn verify_stdin_options(options: &FileOptions) -> Result<(), ChapterizerError> {
+    if !is_stdin(&options.audio_file_path) {
+        return Ok(());
+    }
+    if options.chapter_list_file_path.is_some() {
+        return Err(ChapterizerError::InvalidOptions(
+            "importing a chapter list needs an audio file, since the standard input can't be \
+             probed",
+        ));
+    }
+    if options.on_no_chapters == NoChaptersAction::Silence {
+        return Err(ChapterizerError::InvalidOptions(
+            "falling back to silence detection needs an audio file, since the standard input can \
+             only be read once",
+        ));
+    }
+    Ok(())
+}
+
+/// Applies the action for when speech recognition finds no chapters, if it found none. The chapter
+/// list only holds the chapter inserted at 0:00 in that case.
+#[cfg(feature = "asr")]
+fn handle_no_chapters(
+    options: &FileOptions,
+    extract_options: &ExtractOptions,
+    chapter_list: ChapterList,
+) -> Result<ChapterList, ChapterizerError> {
+    if chapter_list
+        .chapters
+        .iter()
+        .any(|chapter| chapter.source != ChapterSource::Inserted)
+    {
+        return Ok(chapter_list);
+    }
+
+    let audio_file_path = options.audio_file_path.display();
+    match options.on_no_chapters {
+        NoChaptersAction::Single => {
+            log::warn!(
+                "No chapters found in {}, wrote a single chapter",
+                audio_file_path
+            );
+            Ok(chapter_list)
+        }
+        NoChaptersAction::Fail => {
+            log::warn!("No chapters found in {}", audio_file_path);
+            for path in options.output_file_paths() {
+                if let Err(err) = fs::remove_file(&path) {
+                    log::warn!("Failed to remove {}: {}", path.display(), err);
+                }
+            }
+            Err(ChapterizerError::NoChapters)
+        }
+        NoChaptersAction::Silence => {
+            log::warn!(
+                "No chapters found in {}, falling back to silence detection",
+                audio_file_path
+            );
+            let silence_chapters = detect_silence_chapters(&options.into())?;
+            import_chapters(extract_options, &silence_chapters)
+        }
+    }
+}
+
+/// Reads the paths of the audio files to process from a batch list file.
+fn read_batch_list(list_file_path: &Path) -> eyre::Result<Vec<PathBuf>> {
+    let contents = fs::read_to_string(list_file_path).wrap_err("Failed to read batch list file")?;
+
+    Ok(contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .filter(|line| {
+            if line.starts_with('#') {
+                log::debug!("Skipping comment: {}", line);
+                return false;
+            }
+            true
+        })
+        .map(PathBuf::from)
+        .collect())
+}
+
+fn log_review_warnings(report: &FileReport) {
+    for warning in &report.warnings {
+        log::warn!("{}: {}", report.audio_file_path.display(), warning);
+    }
+    if report.needs_review {
+        log::warn!(
+            "{}: chapters need manual review",
+            report.audio_file_path.display()
+        );
+    }
+}
+
+fn run_batch(cli: &Cli, args: &BatchArgs, reports: &mut Vec<FileReport>) -> eyre::Result<()> {
+    let audio_file_paths = read_batch_list(&args.list_file_path)?;
+    #[cfg(feature = "asr")]
+    let detection_config = load_detection_config(cli)?;
+    #[cfg(feature = "asr")]
+    let save_profile_path = save_profile_path(cli)?;
+    #[cfg(feature = "asr")]
+    let model_dir_path = model_dir_path(cli)?;
+    let output_config = load_output_config(cli)?;
+    reports.reserve(audio_file_paths.len());
+
+    // The highest chapter number of the audio files so far, for --continue_numbering. It's None
+    // once the chapters of an audio file are unknown, since the numbers after it would be wrong
+    let mut number_offset = args.continue_numbering.then_some(0);
+    for (volume_index, audio_file_path) in audio_file_paths.into_iter().enumerate() {
+        let audio_name = audio_file_path
+            .file_stem()
+            .ok_or_else(|| eyre!("Invalid audio file path: {}", audio_file_path.display()))?
+            .to_string_lossy()
+            .to_string();
+        let out_dir_path = args.output_dir_path.join(&audio_name);
+        fs::create_dir_all(&out_dir_path).wrap_err("Failed to create output directory")?;
+
+        let output_path = |format: OutputFormat, ext: &str| {
+            args.formats
+                .contains(&format)
+                .then(|| out_dir_path.join(format!("{}.{}", audio_name, ext)))
+        };
+        let embed = match args.formats.contains(&OutputFormat::Embed) {
+            true if can_embed_chapters(&audio_file_path) => Some(cli_in_place_options(cli)),
+            true => {
+                log::warn!(
+                    "Not embedding chapters in {}, since it isn't an MP4 or MP3 file",
+                    audio_file_path.display()
+                );
+                None
+            }
+            false => None,
+        };
+        let mut file_output_config = output_config.clone();
+        if let Some(number_offset) = number_offset {
+            file_output_config.titles.number_offset = number_offset;
+        }
+        if let Some(volume_label) = &args.volume_label {
+            file_output_config.titles.prefix =
+                Some(format!("{} {}, ", volume_label, volume_index + 1));
+        }
+
+        let options = FileOptions {
+            #[cfg(feature = "asr")]
+            model_dir_path: model_dir_path.clone(),
+            #[cfg(feature = "asr")]
+            ensemble_model_dir_paths: cli.ensemble_model_dir_paths.clone(),
+            #[cfg(feature = "asr")]
+            retranscribe_weak_candidates: cli.retranscribe_weak_candidates,
+            #[cfg(feature = "asr")]
+            nest_chapter_parts: cli.nest_chapter_parts,
+            #[cfg(feature = "asr")]
+            include_rejected: cli.include_rejected,
+            #[cfg(feature = "asr")]
+            seek_hints: cli.seek_hints,
+            #[cfg(feature = "asr")]
+            confirm_audio: false,
+            #[cfg(feature = "asr")]
+            ignore_regions: cli.ignore_regions.clone(),
+            #[cfg(feature = "asr")]
+            min_confidence: cli.min_confidence,
+            #[cfg(feature = "asr")]
+            calibration_duration: cli.calibration_duration,
+            #[cfg(feature = "asr")]
+            save_profile_path: save_profile_path.clone(),
+            #[cfg(feature = "asr")]
+            write_candidate_audio: cli.write_candidate_audio,
+            #[cfg(feature = "asr")]
+            detection_config: detection_config.clone(),
+            #[cfg(feature = "asr")]
+            matches_file_path: args
+                .write_matches
+                .then(|| out_dir_path.join(format!("{}.jsonl", audio_name))),
+            #[cfg(feature = "asr")]
+            status_file_path: args
+                .write_status
+                .then(|| out_dir_path.join(format!("{}.status.json", audio_name))),
+            #[cfg(feature = "asr")]
+            transcript_file_path: args
+                .write_transcripts
+                .then(|| out_dir_path.join(format!("{}.transcript.txt", audio_name))),
+            #[cfg(feature = "asr")]
+            prescan_duration: cli.prescan_duration,
+            #[cfg(feature = "asr")]
+            segment_duration: cli.segment_duration,
+            #[cfg(feature = "asr")]
+            spot_keywords: cli.spot_keywords,
+            #[cfg(feature = "asr")]
+            top_alternatives: cli.top_alternatives,
+            #[cfg(feature = "asr")]
+            chunk_duration: cli.chunk_duration,
+            #[cfg(feature = "asr")]
+            max_runtime: cli.max_runtime,
+            #[cfg(feature = "asr")]
+            detect_speaker_changes: cli.detect_speaker_changes,
+            #[cfg(feature = "asr")]
+            on_no_chapters: cli.on_no_chapters,
+            #[cfg(feature = "asr")]
+            cross_check: cli.cross_check,
+            #[cfg(feature = "asr")]
+            format: cli.format.clone(),
+            #[cfg(feature = "asr")]
+            mime_type: cli.mime_type.clone(),
+            #[cfg(feature = "asr")]
+            raw: cli_raw_format(cli),
+            #[cfg(feature = "asr")]
+            ffmpeg_fallback: cli.ffmpeg_fallback,
+            #[cfg(feature = "asr")]
+            track_id: None,
+            source_order: cli.source_order.clone(),
+            ignore_metadata_errors: cli.ignore_metadata_errors,
+            chapter_list_file_path: None,
+            cue_file_path: output_path(OutputFormat::Cue, "cue"),
+            cue_disc_starts: cli.cue_disc_starts.clone(),
+            ffmetadata_file_path: output_path(OutputFormat::Ffmetadata, "ffmetadata"),
+            json_file_path: output_path(OutputFormat::Json, "json"),
+            split_script_file_path: output_path(OutputFormat::SplitScript, "split.sh"),
+            audiobookshelf_file_path: output_path(OutputFormat::Audiobookshelf, "metadata.json"),
+            nfo_file_path: output_path(OutputFormat::Nfo, "nfo"),
+            matroska_file_path: output_path(OutputFormat::Matroska, "chapters.xml"),
+            vtt_file_path: output_path(OutputFormat::Vtt, "vtt"),
+            srt_file_path: output_path(OutputFormat::Srt, "srt"),
+            embed,
+            retime: cli_retime(cli),
+            output_config: file_output_config,
+            audio_file_path: audio_file_path.clone(),
+        };
+
+        #[cfg(feature = "asr")]
+        let file_options = if cli.all_tracks {
+            match all_track_options(cli, &options) {
+                Ok(track_options) => track_options,
+                Err(err) => {
+                    log::error!(
+                        "Failed to read the tracks of {}: {}",
+                        options.audio_file_path.display(),
+                        err
+                    );
+                    reports.push(FileReport::new(
+                        options.audio_file_path,
+                        &Err(err),
+                        Duration::ZERO,
+                    ));
+                    stop_numbering(&mut number_offset, &audio_file_path);
+                    continue;
+                }
+            }
+        } else {
+            vec![options]
+        };
+        #[cfg(not(feature = "asr"))]
+        let file_options = vec![options];
+
+        // Each track of a file holds the same chapters, so the highest number of any of them
+        let mut highest_number = None;
+        // Whether the chapters of a track are unknown, because it was skipped or failed
+        let mut missing_chapters = false;
+        for mut options in file_options {
+            let output_file_paths = options.output_file_paths();
+            // Existing outputs are what --diff compares against, so they're never a reason to skip
+            if args.skip_if_output_exists
+                && !cli.diff
+                && !output_file_paths.is_empty()
+                && output_file_paths.iter().all(|path| path.exists())
+            {
+                log::info!(
+                    "Skipping {}: outputs already exist",
+                    options.audio_file_path.display()
+                );
+                if let Some(number_offset) = number_offset {
+                    // The outputs were numbered by an earlier run of the batch, which continued
+                    // the numbering the same way
+                    match highest_output_chapter_number(&options) {
+                        Some(number) => {
+                            highest_number =
+                                highest_number.max(Some(number.saturating_sub(number_offset)))
+                        }
+                        None => missing_chapters = true,
+                    }
+                }
+                reports.push(FileReport::skipped(
+                    options.audio_file_path,
+                    "outputs already exist",
+                ));
+                continue;
+            }
+
+            // Held until the end of the iteration
+            let _locks = if args.lock {
+                let paths = std::iter::once(options.audio_file_path.clone())
+                    .chain(options.output_file_paths())
+                    .collect::<Vec<_>>();
+                match try_lock_all(paths.iter().map(PathBuf::as_path)) {
+                    Ok(Ok(locks)) => locks,
+                    Ok(Err(locked_path)) => {
+                        let reason =
+                            format!("{} is locked by another process", locked_path.display());
+                        log::info!("Skipping {}: {}", options.audio_file_path.display(), reason);
+                        reports.push(FileReport::skipped(options.audio_file_path, reason));
+                        missing_chapters = true;
+                        continue;
+                    }
+                    Err(err) => {
+                        log::error!(
+                            "Failed to lock {}: {}",
+                            options.audio_file_path.display(),
+                            err
+                        );
+                        reports.push(FileReport::new(
+                            options.audio_file_path,
+                            &Err(err),
+                            Duration::ZERO,
+                        ));
+                        missing_chapters = true;
+                        continue;
+                    }
+                }
+            } else {
+                Vec::new()
+            };
+
+            let start_time = Instant::now();
+
+            if let Some(min_chapters) = args.skip_if_chaptered {
+                match count_metadata_chapters(&options.audio_file_path) {
+                    Ok(num_chapters) if num_chapters >= min_chapters => {
+                        let reason = format!("metadata already contains {} chapters", num_chapters);
+                        log::info!("Skipping {}: {}", options.audio_file_path.display(), reason);
+                        reports.push(FileReport::skipped(options.audio_file_path, reason));
+                        missing_chapters = true;
+                        continue;
+                    }
+                    Ok(num_chapters) => {
+                        if num_chapters > 0 {
+                            log::info!(
+                                "Ignoring {} chapters in metadata of {}",
+                                num_chapters,
+                                options.audio_file_path.display()
+                            );
+                        }
+                        options
+                            .source_order
+                            .retain(|detector| *detector != Detector::Metadata);
+                    }
+                    Err(err) if options.ignore_metadata_errors => {
+                        log::warn!(
+                            "Ignoring unreadable metadata of {}: {}",
+                            options.audio_file_path.display(),
+                            format_error_chain(&err)
+                        );
+                        options
+                            .source_order
+                            .retain(|detector| *detector != Detector::Metadata);
+                    }
+                    Err(err) => {
+                        log::error!(
+                            "Failed to read metadata of {}: {}",
+                            options.audio_file_path.display(),
+                            err
+                        );
+                        reports.push(FileReport::new(
+                            options.audio_file_path,
+                            &Err(err),
+                            start_time.elapsed(),
+                        ));
+                        missing_chapters = true;
+                        continue;
+                    }
+                }
+            }
+
+            log::info!("Chapterizing {}", options.audio_file_path.display());
+            let result = if cli.diff {
+                diff_file(&options)
+            } else {
+                process_file(&options)
+            };
+            if let Err(err) = &result {
+                log::error!(
+                    "Failed to chapterize {}: {}",
+                    options.audio_file_path.display(),
+                    err
+                );
+                missing_chapters = true;
+            }
+            if let Ok(chapter_list) = &result {
+                highest_number = chapter_list
+                    .chapters
+                    .iter()
+                    .filter_map(chapter_number)
+                    .chain(highest_number)
+                    .max();
+            }
+
+            let report = FileReport::new(options.audio_file_path, &result, start_time.elapsed());
+            log_review_warnings(&report);
+            reports.push(report);
+        }
+
+        match (&mut number_offset, highest_number) {
+            (None, _) => {}
+            (Some(number_offset), Some(highest_number)) => *number_offset += highest_number,
+            (Some(_), None) if missing_chapters => {
+                stop_numbering(&mut number_offset, &audio_file_path)
+            }
+            (Some(_), None) => log::warn!(
+                "Found no chapter numbers in {}, so the numbering of the next audio file \
+                continues from the audio file before it",
+                audio_file_path.display()
+            ),
+        }
+    }
+
+    if let Some(report_file_path) = &args.report_file_path {
+        write_report(report_file_path, reports)?;
+    }
+
+    let num_failed = reports.iter().filter(|r| r.error.is_some()).count();
+    if num_failed > 0 {
+        return Err(eyre!(
+            "Failed to chapterize {} of {} files",
+            num_failed,
+            reports.len()
+        ));
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "asr")]
+fn load_detection_config(cli: &Cli) -> eyre::Result<DetectionConfig> {
+    let mut config = if let Some(profile) = &cli.profile {
+        let path = profile_path(&profiles_dir(cli)?, profile);
+        if !path.exists() {
+            return Err(eyre!(
+                "No profile named {:?} in {}",
+                profile,
+                path.display()
+            ));
+        }
+        log::info!("Using profile {:?}", profile);
+        DetectionConfig::load(&path)?
+    } else {
+        match &cli.detection_config_path {
+            Some(path) => DetectionConfig::load(path)?,
+            None => DetectionConfig::default(),
+        }
+    };
+    if let Some(language) = cli.language {
+        let user_config = load_user_config(cli)?;
+        config.apply_language(language, user_config.models.get(&language));
+    }
+    Ok(config)
+}
+
+/// Reads the config file passed with --config, or the user's config file if there is one.
+#[cfg(feature = "asr")]
+fn load_user_config(cli: &Cli) -> eyre::Result<UserConfig> {
+    if let Some(path) = &cli.user_config_path {
+        return Ok(UserConfig::load(path)?);
+    }
+    match default_user_config_path() {
+        Some(path) if path.exists() => Ok(UserConfig::load(&path)?),
+        _ => Ok(UserConfig::default()),
+    }
+}
+
+/// Returns the path of the model to use: the one passed with --model, or else the one of the
+/// language preset, or else ./model.
+#[cfg(feature = "asr")]
+fn model_dir_path(cli: &Cli) -> eyre::Result<PathBuf> {
+    if let Some(model_dir_path) = &cli.model_dir_path {
+        return Ok(model_dir_path.clone());
+    }
+    let preset_model = match cli.language {
+        Some(language) => load_user_config(cli)?
+            .models
+            .remove(&language)
+            .and_then(|preset| preset.model),
+        None => None,
+    };
+    Ok(preset_model.unwrap_or_else(|| PathBuf::from("./model")))
+}
+
+#[cfg(feature = "asr")]
+fn profiles_dir(cli: &Cli) -> eyre::Result<PathBuf> {
+    cli.profile_dir_path
+        .clone()
+        .or_else(default_profiles_dir)
+        .ok_or_else(|| eyre!("Could not find the config directory, pass --profile_dir"))
+}
+
+/// Returns the path to save the narrator profile to, if one should be saved.
+#[cfg(feature = "asr")]
+fn save_profile_path(cli: &Cli) -> eyre::Result<Option<PathBuf>> {
+    cli.save_profile
+        .as_ref()
+        .map(|name| Ok(profile_path(&profiles_dir(cli)?, name)))
+        .transpose()
+}
+
+#[cfg(feature = "asr")]
+fn run_tune(cli: &Cli, args: &TuneArgs) -> eyre::Result<()> {
+    let report = tune(&args.matches_file_path, &load_detection_config(cli)?)?;
+
+    log::info!(
+        "Tuned on {} reviewed candidate(s): accuracy {:.1}% -> {:.1}%",
+        report.num_candidates,
+        report.original_accuracy * 100.0,
+        report.tuned_accuracy * 100.0
+    );
+    log::info!(
+        "Suggested detection config:\n{}",
+        serde_json::to_string_pretty(&report.config)?
+    );
+
+    if let Some(output_config_path) = &args.output_config_path {
+        report.config.save(output_config_path)?;
+        log::info!("Wrote detection config to {}", output_config_path.display());
+    }
+
+    Ok(())
+}
+
+fn run_status(args: &StatusArgs) -> eyre::Result<()> {
+    let status = read_status(&args.status_file_path)?;
+    for line in format_status(&status).lines() {
+        log::info!("{}", line);
+    }
+    Ok(())
+}
+
+fn run_apply(cli: &Cli, args: &ApplyArgs) -> eyre::Result<()> {
+    let options = InPlaceOptions {
+        strip_existing: args.strip_existing,
+        ..cli_in_place_options(cli)
+    };
+    let output_file_path = args.output_file_path.as_deref();
+    apply_chapters(
+        &args.audio_file_path,
+        &args.ffmetadata_file_path,
+        output_file_path,
+        &options,
+    )
+    .wrap_err("Failed to apply chapters")?;
+    log::info!(
+        "Wrote the chapterized audio file to {}",
+        output_file_path.unwrap_or(&args.audio_file_path).display()
+    );
+    Ok(())
+}
+
+fn run_evaluate(args: &EvaluateArgs) -> eyre::Result<()> {
+    let detected =
+        read_chapter_file(&args.detected_file_path).wrap_err("Failed to read detected chapters")?;
+    let reference = read_chapter_file(&args.reference_file_path)
+        .wrap_err("Failed to read reference chapters")?;
+
+    let report = evaluate(&detected, &reference, args.tolerance);
+
+    for chapter in &report.missed {
+        log::info!(
+            "Missed: {} {}",
+            format_duration(&Some(chapter.start)),
+            chapter.flat_title()
+        );
+    }
+    for chapter in &report.spurious {
+        log::info!(
+            "Spurious: {} {}",
+            format_duration(&Some(chapter.start)),
+            chapter.flat_title()
+        );
+    }
+    log::info!(
+        "Matched {} of {} detected and {} reference chapter(s): precision {:.1}%, recall {:.1}%",
+        report.num_matched,
+        report.num_detected,
+        report.num_reference,
+        report.precision * 100.0,
+        report.recall * 100.0
+    );
+    if let Some(start_error) = report.start_error {
+        log::info!(
+            "Start time error: min {:.2}s, median {:.2}s, max {:.2}s, std dev {:.2}s",
+            start_error.min.as_secs_f64(),
+            start_error.median.as_secs_f64(),
+            start_error.max.as_secs_f64(),
+            start_error.std_dev.as_secs_f64()
+        );
+    }
+
+    Ok(())
+}
+
+/// Detects the chapters of a corpus entry, using the options on the command line.
+#[cfg(feature = "asr")]
+fn detect_corpus_entry(
+    cli: &Cli,
+    args: &CorpusArgs,
+    detection_config: &DetectionConfig,
+    entry: &CorpusEntry,
+) -> eyre::Result<ChapterList> {
+    if entry.is_matches_file() {
+        return Ok(replay_matches(&entry.input, detection_config)?);
+    }
+
+    let audio_name = entry
+        .input
+        .file_stem()
+        .ok_or_else(|| eyre!("Invalid audio file path: {}", entry.input.display()))?
+        .to_string_lossy()
+        .to_string();
+    fs::create_dir_all(&args.output_dir_path).wrap_err("Failed to create output directory")?;
+
+    Ok(chapterize(&ChapterizeOptions {
+        model_dir_path: model_dir_path(cli)?,
+        matches_file_path: None,
+        audio_file_path: entry.input.clone(),
+        ensemble_model_dir_paths: cli.ensemble_model_dir_paths.clone(),
+        detection_config: detection_config.clone(),
+        retranscribe_weak_candidates: cli.retranscribe_weak_candidates,
+        nest_chapter_parts: cli.nest_chapter_parts,
+        include_rejected: false,
+        seek_hints: false,
+        confirm_audio: false,
+        ignore_regions: Vec::new(),
+        min_confidence: cli.min_confidence,
+        calibration_duration: cli.calibration_duration,
+        save_profile_path: None,
+        write_candidate_audio: false,
+        prescan_duration: cli.prescan_duration,
+        segment_duration: cli.segment_duration,
+        spot_keywords: cli.spot_keywords,
+        top_alternatives: cli.top_alternatives,
+        chunk_duration: cli.chunk_duration,
+        max_runtime: cli.max_runtime,
+        detect_speaker_changes: cli.detect_speaker_changes,
+        format: cli.format.clone(),
+        mime_type: cli.mime_type.clone(),
+        raw: cli_raw_format(cli),
+        ffmpeg_fallback: cli.ffmpeg_fallback,
+        track_id: None,
+        status_file_path: None,
+        transcript_file_path: None,
+        cue_file_path: None,
+        cue_disc_starts: Vec::new(),
+        ffmetadata_file_path: None,
+        json_file_path: Some(args.output_dir_path.join(format!("{}.json", audio_name))),
+        split_script_file_path: None,
+        audiobookshelf_file_path: None,
+        nfo_file_path: None,
+        matroska_file_path: None,
+        vtt_file_path: None,
+        srt_file_path: None,
+        embed: None,
+        retime: Retime::default(),
+        output_config: OutputConfig::default(),
+        cross_check: CrossCheck::default(),
+    })?)
+}
+
+#[cfg(feature = "asr")]
+fn run_corpus(cli: &Cli, args: &CorpusArgs) -> eyre::Result<()> {
+    let entries = read_manifest(&args.manifest_file_path)?;
+    let detection_config = load_detection_config(cli)?;
+
+    let mut scoreboard = Scoreboard::default();
+    for entry in entries {
+        log::info!("Evaluating {}", entry.input.display());
+        let result =
+            detect_corpus_entry(cli, args, &detection_config, &entry).and_then(|detected| {
+                let reference = read_chapter_file(&entry.reference)
+                    .wrap_err("Failed to read reference chapters")?;
+                Ok(evaluate(&detected, &reference, args.tolerance))
+            });
+
+        match result {
+            Ok(report) => scoreboard.add(entry.input, &report),
+            Err(err) => {
+                log::error!("{}: {:#}", entry.input.display(), err);
+                scoreboard.add_failure(entry.input, format!("{:#}", err));
+            }
+        }
+    }
+
+    for entry in &scoreboard.entries {
+        match &entry.error {
+            Some(_) => log::info!("{}: failed", entry.input.display()),
+            None => log::info!(
+                "{}: precision {:.1}%, recall {:.1}%, median start error {:.2}s",
+                entry.input.display(),
+                entry.precision * 100.0,
+                entry.recall * 100.0,
+                entry
+                    .start_error
+                    .map_or(0.0, |start_error| start_error.median.as_secs_f64())
+            ),
+        }
+    }
+    log::info!(
+        "Corpus: {} entries ({} failed), matched {} of {} detected and {} reference chapter(s): \
+        precision {:.1}%, recall {:.1}%, median start error {:.2}s",
+        scoreboard.entries.len(),
+        scoreboard.num_failed,
+        scoreboard.num_matched,
+        scoreboard.num_detected,
+        scoreboard.num_reference,
+        scoreboard.precision * 100.0,
+        scoreboard.recall * 100.0,
+        scoreboard
+            .start_error
+            .map_or(0.0, |start_error| start_error.median.as_secs_f64())
+    );
+
+    if let Some(scoreboard_file_path) = &args.scoreboard_file_path {
+        let json = serde_json::to_string_pretty(&scoreboard)?;
+        fs::write(scoreboard_file_path, json + "\n").wrap_err("Failed to write scoreboard")?;
+        log::info!("Wrote scoreboard to {}", scoreboard_file_path.display());
+    }
+
+    if scoreboard.num_failed > 0 {
+        return Err(eyre!(
+            "Failed to evaluate {} corpus entries",
+            scoreboard.num_failed
+        ));
+    }
+
+    Ok(())
+}
+
+/// The number of samples decoded at a time by subcommands that scan audio files.
+#[cfg(feature = "asr")]
+const AUDIO_BUFFER_SIZE: usize = 8 * 1024;
+
+/// Returns the format hint for the audio file, overridden by the format passed on the command line.
+#[cfg(feature = "asr")]
+fn cli_format_hint(cli: &Cli, audio_file_path: &Path) -> FormatHint {
+    let mut format_hint = FormatHint::from_path(audio_file_path);
+    if let Some(format) = &cli.format {
+        format_hint.extension = Some(format.to_lowercase());
+    }
+    format_hint.mime_type = cli.mime_type.clone();
+    format_hint.raw = cli_raw_format(cli);
+    format_hint.ffmpeg_fallback = cli.ffmpeg_fallback;
+    format_hint
+}
+
+/// Returns the format of the raw PCM audio passed on the command line, if any.
+#[cfg(feature = "asr")]
+fn cli_raw_format(cli: &Cli) -> Option<RawFormat> {
+    Some(RawFormat {
+        sample_format: cli.raw?,
+        sample_rate: cli.raw_sample_rate?,
+        num_channels: cli.raw_num_channels?,
+    })
+}
+
+/// Reads the properties of the audio file, from the probe cache if it's enabled. Only the
+/// properties of the default track of a file that isn't raw audio are cached.
+#[cfg(feature = "asr")]
+fn inspect_audio_file(
+    audio_file_path: &Path,
+    format_hint: &FormatHint,
+) -> Result<AudioProperties, ChapterizerError> {
+    let inspect = || {
+        let src = fs::File::open(audio_file_path).map_err(|source| ChapterizerError::Io {
+            context: "Failed to open audio file",
+            source,
+        })?;
+        inspect_audio(src, format_hint)
+    };
+    match (&format_hint.raw, format_hint.track_id) {
+        (None, None) => probe_cache::audio_properties(audio_file_path, inspect),
+        _ => inspect(),
+    }
+}
+
+#[cfg(feature = "asr")]
+fn run_silences(cli: &Cli, args: &SilencesArgs) -> eyre::Result<()> {
+    let format_hint = cli_format_hint(cli, &args.audio_file_path);
+    let mut audio = gimme_audio(&args.audio_file_path, &format_hint)?;
+    let mut detector = SilenceDetector::new(
+        audio.sample_rate(),
+        SilenceOptions {
+            threshold_db: args.threshold_db,
+            min_duration: args.min_duration,
+        },
+    );
+    let mut buffer = Vec::with_capacity(AUDIO_BUFFER_SIZE);
+    while audio.fill_buffer(&mut buffer, AUDIO_BUFFER_SIZE) > 0 {
+        detector.push_samples(&buffer);
+        buffer.clear();
+    }
+    let silences = detector.finish();
+    log::info!("Found {} silence(s)", silences.len());
+
+    match &args.output_file_path {
+        Some(path) => {
+            let file = fs::File::create(path).wrap_err("Failed to create silences file")?;
+            let writer = std::io::BufWriter::new(file);
+            if path
+                .extension()
+                .is_some_and(|ext| ext.eq_ignore_ascii_case("csv"))
+            {
+                write_silences_csv(writer, &silences)?;
+            } else {
+                write_silences_json(writer, &silences)?;
+            }
+        }
+        None => write_silences_json(std::io::stdout().lock(), &silences)?,
+    }
+
+    Ok(())
+}
+
+/// A candidate is rejected if no chapter starts within this long of it. Chapters start a little
+/// before the word "chapter" is recognized.
+#[cfg(feature = "asr")]
+const REJECTED_CANDIDATE_TOLERANCE: Duration = Duration::from_secs(5);
+
+#[cfg(feature = "asr")]
+fn run_visualize(cli: &Cli, args: &VisualizeArgs) -> eyre::Result<()> {
+    let chapter_list = args
+        .chapter_file_path
+        .as_ref()
+        .map(|path| read_chapter_file(path).wrap_err("Failed to read chapter file"))
+        .transpose()?;
+    let rejected_candidates = match (&args.matches_file_path, &chapter_list) {
+        (Some(matches_file_path), Some(chapter_list)) => {
+            candidate_times(matches_file_path, &load_detection_config(cli)?)?
+                .into_iter()
+                .filter(|&time| {
+                    !chapter_list
+                        .chapters
+                        .iter()
+                        .any(|chapter| chapter.start.abs_diff(time) <= REJECTED_CANDIDATE_TOLERANCE)
+                })
+                .collect()
+        }
+        _ => Vec::new(),
+    };
+
+    let format_hint = cli_format_hint(cli, &args.audio_file_path);
+    let mut audio = gimme_audio(&args.audio_file_path, &format_hint)?;
+    let mut envelope = LoudnessEnvelope::new(audio.sample_rate());
+    let mut silence_detector = SilenceDetector::new(audio.sample_rate(), SilenceOptions::default());
+    let mut buffer = Vec::with_capacity(AUDIO_BUFFER_SIZE);
+    while audio.fill_buffer(&mut buffer, AUDIO_BUFFER_SIZE) > 0 {
+        envelope.push_samples(&buffer);
+        silence_detector.push_samples(&buffer);
+        buffer.clear();
+    }
+
+    let silences = silence_detector.finish();
+    let svg = render_svg(
+        &envelope.finish(),
+        &Markers {
+            chapters: chapter_list.as_ref(),
+            silences: &silences,
+            rejected_candidates: &rejected_candidates,
+        },
+    );
+    fs::write(&args.output_file_path, svg).wrap_err("Failed to write SVG file")?;
+
+    Ok(())
+}
+
+/// The maximum length of a tag value when printing an audio file's tags.
+#[cfg(feature = "asr")]
+const MAX_TAG_VALUE_LEN: usize = 60;
+
+#[cfg(feature = "asr")]
+fn run_inspect(cli: &Cli, args: &InspectArgs) -> eyre::Result<()> {
+    let format_hint = cli_format_hint(cli, &args.audio_file_path);
+    let properties = inspect_audio_file(&args.audio_file_path, &format_hint)?;
+
+    log::info!("{}", args.audio_file_path.display());
+    for track in &properties.tracks {
+        log::info!(
+            "Track {}{}: codec {}{}, {} Hz, {} channel(s), duration {}{}",
+            track.id,
+            if properties.selected_track_id == Some(track.id) {
+                " (selected)"
+            } else {
+                ""
+            },
+            track.codec,
+            if track.is_supported {
+                ""
+            } else {
+                " (unsupported)"
+            },
+            track
+                .sample_rate
+                .map_or_else(|| "??".into(), |rate| rate.to_string()),
+            track
+                .channels
+                .map_or_else(|| "??".into(), |channels| channels.to_string()),
+            format_duration(&track.duration),
+            track
+                .language
+                .as_ref()
+                .map_or_else(String::new, |language| format!(", language {}", language)),
+        );
+    }
+    if properties.selected_track_id.is_none() {
+        log::warn!("No track can be chapterized");
+    }
+
+    match count_metadata_chapters(&args.audio_file_path) {
+        Ok(num_chapters) => log::info!("Embedded chapters: {}", num_chapters),
+        Err(err) => log::info!("Embedded chapters: unknown ({})", format_error_chain(&err)),
+    }
+
+    log::info!(
+        "Tags: {}, embedded images: {}",
+        properties.tags.len(),
+        properties.num_visuals
+    );
+    for (key, value) in &properties.tags {
+        let value = match value.char_indices().nth(MAX_TAG_VALUE_LEN) {
+            Some((index, _)) => format!("{}...", &value[..index]),
+            None => value.clone(),
+        };
+        log::info!("  {}: {}", key, value);
+    }
+
+    Ok(())
+}
+
+fn run_single(cli: &Cli, reports: &mut Vec<FileReport>) -> eyre::Result<()> {
+    let options = FileOptions {
+        #[cfg(feature = "asr")]
+        model_dir_path: model_dir_path(cli)?,
+        #[cfg(feature = "asr")]
+        ensemble_model_dir_paths: cli.ensemble_model_dir_paths.clone(),
+        #[cfg(feature = "asr")]
+        retranscribe_weak_candidates: cli.retranscribe_weak_candidates,
+        #[cfg(feature = "asr")]
+        nest_chapter_parts: cli.nest_chapter_parts,
+        #[cfg(feature = "asr")]
+        include_rejected: cli.include_rejected,
+        #[cfg(feature = "asr")]
+        seek_hints: cli.seek_hints,
+        #[cfg(feature = "asr")]
+        confirm_audio: cli.confirm_audio,
+        #[cfg(feature = "asr")]
+        ignore_regions: cli.ignore_regions.clone(),
+        #[cfg(feature = "asr")]
+        min_confidence: cli.min_confidence,
+        #[cfg(feature = "asr")]
+        calibration_duration: cli.calibration_duration,
+        #[cfg(feature = "asr")]
+        save_profile_path: save_profile_path(cli)?,
+        #[cfg(feature = "asr")]
+        write_candidate_audio: cli.write_candidate_audio,
+        #[cfg(feature = "asr")]
+        detection_config: load_detection_config(cli)?,
+        #[cfg(feature = "asr")]
+        matches_file_path: cli.matches_file_path.clone(),
+        #[cfg(feature = "asr")]
+        status_file_path: cli.status_file_path.clone(),
+        #[cfg(feature = "asr")]
+        transcript_file_path: cli.transcript_file_path.clone(),
+        #[cfg(feature = "asr")]
+        prescan_duration: cli.prescan_duration,
+        #[cfg(feature = "asr")]
+        segment_duration: cli.segment_duration,
+        #[cfg(feature = "asr")]
+        spot_keywords: cli.spot_keywords,
+        #[cfg(feature = "asr")]
+        top_alternatives: cli.top_alternatives,
+        #[cfg(feature = "asr")]
+        chunk_duration: cli.chunk_duration,
+        #[cfg(feature = "asr")]
+        max_runtime: cli.max_runtime,
+        #[cfg(feature = "asr")]
+        detect_speaker_changes: cli.detect_speaker_changes,
+        #[cfg(feature = "asr")]
+        on_no_chapters: cli.on_no_chapters,
+        #[cfg(feature = "asr")]
+        cross_check: cli.cross_check,
+        #[cfg(feature = "asr")]
+        format: cli.format.clone(),
+        #[cfg(feature = "asr")]
+        mime_type: cli.mime_type.clone(),
+        #[cfg(feature = "asr")]
+        raw: cli_raw_format(cli),
+        #[cfg(feature = "asr")]
+        ffmpeg_fallback: cli.ffmpeg_fallback,
+        #[cfg(feature = "asr")]
+        track_id: None,
+        source_order: cli.source_order.clone(),
+        ignore_metadata_errors: cli.ignore_metadata_errors,
+        chapter_list_file_path: cli.chapter_list_file_path.clone(),
+        audio_file_path: cli
+            .audio_file_path
+            .clone()
+            .expect("audio file path is required without a subcommand"),
+        cue_file_path: cli.outputs.cue_file_path.clone(),
+        cue_disc_starts: cli.cue_disc_starts.clone(),
+        ffmetadata_file_path: cli.outputs.ffmetadata_file_path.clone(),
+        json_file_path: cli.outputs.json_file_path.clone(),
+        split_script_file_path: cli.outputs.split_script_file_path.clone(),
+        audiobookshelf_file_path: cli.outputs.audiobookshelf_file_path.clone(),
+        nfo_file_path: cli.outputs.nfo_file_path.clone(),
+        matroska_file_path: cli.outputs.matroska_file_path.clone(),
+        vtt_file_path: cli.outputs.vtt_file_path.clone(),
+        srt_file_path: cli.outputs.srt_file_path.clone(),
+        embed: cli.outputs.embed.then(|| cli_in_place_options(cli)),
+        retime: cli_retime(cli),
+        output_config: load_output_config(cli)?,
+    };
+
+    #[cfg(feature = "live-input")]
+    if cli.live {
+        return run_live(cli, &options);
+    }
+
+    #[cfg(feature = "asr")]
+    if cli.all_tracks {
+        let track_options = all_track_options(cli, &options)?;
+        let num_tracks = track_options.len();
+        let mut num_failed = 0;
+        for options in track_options {
+            let track_id = options.track_id;
+            if let Some(track_id) = track_id {
+                log::info!("Chapterizing track {}", track_id);
+            }
+            if let Err(err) = run_file(cli, options, reports) {
+                log::error!(
+                    "Failed to chapterize track {}: {:#}",
+                    track_id.map_or_else(|| "??".into(), |track_id| track_id.to_string()),
+                    err
+                );
+                num_failed += 1;
+            }
+        }
+        if num_failed > 0 {
+            return Err(eyre!(
+                "Failed to chapterize {} of {} tracks",
+                num_failed,
+                num_tracks
+            ));
+        }
+        return Ok(());
+    }
+
+    run_file(cli, options, reports)
+}
+
+/// Processes the audio file of a run without a subcommand and reports how it went.
+fn run_file(cli: &Cli, options: FileOptions, reports: &mut Vec<FileReport>) -> eyre::Result<()> {
+    let start_time = Instant::now();
+    let result = if cli.diff {
+        diff_file(&options)
+    } else {
+        process_file(&options)
+    };
+    let report = FileReport::new(options.audio_file_path, &result, start_time.elapsed());
+    log_review_warnings(&report);
+    reports.push(report);
+
+    match result {
+        Ok(_) => Ok(()),
+        Err(ChapterizerError::NoChapters) if cli.chapter_list_file_path.is_some() => {
+            Err(eyre!("The chapter list contains no chapters"))
+        }
+        Err(ChapterizerError::NoChapters) => Err(eyre!(
+            "None of the sources in the source order yielded any chapters"
+        )),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Chapterizes the audio captured from the input device until Enter is pressed, printing the
+/// chapters as they're announced, and writes them to the outputs.
+#[cfg(feature = "live-input")]
+fn run_live(cli: &Cli, options: &FileOptions) -> eyre::Result<()> {
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    };
+
+    let live_options = LiveOptions {
+        model_dir_path: options.model_dir_path.clone(),
+        device_name: cli.input_device.clone(),
+        detection_config: options.detection_config.clone(),
+        nest_chapter_parts: options.nest_chapter_parts,
+        max_runtime: options.max_runtime,
+    };
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let stop_clone = stop.clone();
+    std::thread::spawn(move || {
+        // Stdin may be closed, e.g. when run in the background, in which case only --max_runtime
+        // stops capturing
+        if std::io::stdin()
+            .read_line(&mut String::new())
+            .is_ok_and(|len| len > 0)
+        {
+            stop_clone.store(true, Ordering::Relaxed);
+        }
+    });
+
+    log::info!("Listening for chapter announcements, press Enter to stop");
+    let chapter_list = chapterize_live(&live_options, &stop, |chapter| {
+        let event = serde_json::json!({
+            "start": chapter.start.as_secs_f64(),
+            "end": chapter.end.map(|end| end.as_secs_f64()),
+            "title": chapter.flat_title(),
+        });
+        println!("{}", event);
+    })?;
+    log::info!(
+        "Stopped after {}, found {} chapter(s)",
+        format_duration(&Some(chapter_list.duration)),
+        chapter_list.chapters.len()
+    );
+
+    import_chapters(&options.into(), &chapter_list)?;
+    Ok(())
+}
+
+/// Sends the notifications requested on the command line. Failing to send a notification doesn't
+/// fail the run, since the actual work has already been done at this point.
+fn send_notifications(cli: &Cli, result: &eyre::Result<()>, reports: &[FileReport]) {
+    let summary = RunSummary {
+        success: result.is_ok(),
+        error: result.as_ref().err().map(|err| format!("{:#}", err)),
+        files: reports,
+    };
+
+    if let Some(notify_url) = &cli.notify_url {
+        if let Err(err) = post_summary(notify_url, &summary) {
+            log::warn!("{}", format_error_chain(&err));
+        }
+    }
+
+    if cli.notify_desktop {
+        if let Err(err) = show_desktop_notification("audiobook-chapterizer", &summary.message()) {
+            log::warn!("{}", format_error_chain(&err));
         }
     }
 }
@@ -91,13 +2553,33 @@ fn main() -> Result<(), eyre::Error> {
         })
         .init();
 
-    // TODO: add option/subcommand to skip metadata extraction and force ASR instead
-    // TODO: add force-extract flag and force-asr (or similar) flag
-    let metadata_chapters_found = extract_chapters(&cli.clone().into())?;
-
-    if !metadata_chapters_found {
-        chapterize(&cli.into())?;
+    if cli.probe_cache {
+        match default_probe_cache_dir() {
+            Some(cache_dir) => probe_cache::enable(cache_dir),
+            None => log::warn!("No cache directory to cache probe results in, not caching them"),
+        }
     }
 
-    Ok(())
+    let mut reports = Vec::new();
+    let result = match &cli.command {
+        Some(Command::Batch(args)) => run_batch(&cli, args, &mut reports),
+        #[cfg(feature = "asr")]
+        Some(Command::Tune(args)) => run_tune(&cli, args),
+        Some(Command::Evaluate(args)) => run_evaluate(args),
+        #[cfg(feature = "asr")]
+        Some(Command::Corpus(args)) => run_corpus(&cli, args),
+        #[cfg(feature = "asr")]
+        Some(Command::Inspect(args)) => run_inspect(&cli, args),
+        #[cfg(feature = "asr")]
+        Some(Command::Silences(args)) => run_silences(&cli, args),
+        #[cfg(feature = "asr")]
+        Some(Command::Visualize(args)) => run_visualize(&cli, args),
+        Some(Command::Status(args)) => run_status(args),
+        Some(Command::Apply(args)) => run_apply(&cli, args),
+        None => run_single(&cli, &mut reports),
+    };
+
+    send_notifications(&cli, &result, &reports);
+
+    result
 }
@@ -0,0 +1,163 @@
+use std::{io::Write, time::Duration};
+
+use crate::{
+    chapter::Chapter,
+    chapter_writer::ChapterWriter,
+    error::{IoResultExt, Result},
+    escape_xml,
+};
+
+/// Writes the chapters as a Matroska chapter XML file, which mkvmerge muxes into .mka and .mkv
+/// files with --chapters. Chapters in a section are nested in a chapter for the section, which
+/// starts with its first chapter. No language is written, so mkvmerge applies the one given with
+/// --chapter-language. Each chapter is written once the next one starts, so that its end is
+/// known, and the file is only valid XML once the end of the file is reached.
+pub struct MatroskaWriter<W: Write> {
+    writer: W,
+    header_written: bool,
+    /// The title of the section whose chapter is still open, if any.
+    open_section: Option<String>,
+    /// If the chapter has no explicit end, we still need the start time of the next chapter to
+    /// actually write it.
+    partial_chapter: Option<Chapter>,
+}
+
+impl<W: Write> MatroskaWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            header_written: false,
+            open_section: None,
+            partial_chapter: None,
+        }
+    }
+
+    /// Returns the underlying writer, without flushing it.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    /// Flushes the underlying writer and returns it. The file is only valid XML once the end of
+    /// the file has been passed to the writer.
+    pub fn finalize(mut self) -> Result<W> {
+        self.writer
+            .flush()
+            .io_context("Failed to flush Matroska chapters file")?;
+        Ok(self.writer)
+    }
+
+    fn write_header(&mut self) -> Result<()> {
+        self.writer
+            .write_all(
+                b"<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<!DOCTYPE Chapters SYSTEM \"matroskachapters.dtd\">\n<Chapters>\n  <EditionEntry>\n",
+            )
+            .io_context("Failed to write Matroska chapters header")?;
+        self.header_written = true;
+        Ok(())
+    }
+
+    /// Writes the chapter, using the given end time if it doesn't have an explicit end. Its
+    /// description, if any, is written as a second display of the chapter, after the one with the
+    /// title, which is the one players show.
+    fn write_chapter(&mut self, chapter: &Chapter, end: Duration) -> Result<()> {
+        let indent = if self.open_section.is_some() {
+            "      "
+        } else {
+            "    "
+        };
+        let displays = [Some(&chapter.title), chapter.description.as_ref()]
+            .into_iter()
+            .flatten()
+            .map(|text| {
+                format!(
+                    "{indent}  <ChapterDisplay>\n{indent}    <ChapterString>{}</ChapterString>\n{indent}  </ChapterDisplay>\n",
+                    escape_xml(text),
+                    indent = indent
+                )
+            })
+            .collect::<String>();
+        writeln!(
+            self.writer,
+            "{indent}<ChapterAtom>\n{indent}  <ChapterTimeStart>{}</ChapterTimeStart>\n{indent}  <ChapterTimeEnd>{}</ChapterTimeEnd>\n{}{indent}</ChapterAtom>",
+            format_time(chapter.start),
+            format_time(chapter.end.unwrap_or(end)),
+            displays,
+            indent = indent
+        )
+        .io_context("Failed to write Matroska chapter")
+    }
+
+    /// Opens the chapter of the section that the chapter starting at start belongs to, closing the
+    /// one of the previous section first, if the section changes.
+    fn enter_section(&mut self, section: Option<&str>, start: Duration) -> Result<()> {
+        if self.open_section.as_deref() == section {
+            return Ok(());
+        }
+
+        if self.open_section.take().is_some() {
+            writeln!(self.writer, "    </ChapterAtom>")
+                .io_context("Failed to write Matroska chapter")?;
+        }
+        if let Some(section) = section {
+            writeln!(
+                self.writer,
+                "    <ChapterAtom>\n      <ChapterTimeStart>{}</ChapterTimeStart>\n      <ChapterDisplay>\n        <ChapterString>{}</ChapterString>\n      </ChapterDisplay>",
+                format_time(start),
+                escape_xml(section)
+            )
+            .io_context("Failed to write Matroska chapter")?;
+            self.open_section = Some(section.to_string());
+        }
+
+        Ok(())
+    }
+}
+
+impl<W: Write> ChapterWriter for MatroskaWriter<W> {
+    fn on_chapter_start(&mut self, chapter: &Chapter) -> Result<()> {
+        if !self.header_written {
+            self.write_header()?;
+        }
+
+        if let Some(prev_chapter) = self.partial_chapter.take() {
+            self.write_chapter(&prev_chapter, chapter.start)?;
+        }
+        self.enter_section(chapter.parent.as_deref(), chapter.start)?;
+
+        self.partial_chapter = Some(chapter.clone());
+
+        Ok(())
+    }
+
+    fn on_end_of_file(&mut self, file_duration: Duration) -> Result<()> {
+        if !self.header_written {
+            self.write_header()?;
+        }
+
+        if let Some(chapter) = self.partial_chapter.take() {
+            self.write_chapter(&chapter, file_duration)?;
+        }
+        self.enter_section(None, file_duration)?;
+
+        writeln!(self.writer, "  </EditionEntry>\n</Chapters>")
+            .io_context("Failed to write Matroska chapters footer")
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.writer
+            .flush()
+            .io_context("Failed to flush Matroska chapters file")
+    }
+}
+
+/// Formats the time as hh:mm:ss.nnnnnnnnn, the way Matroska chapter files hold them.
+fn format_time(time: Duration) -> String {
+    let secs = time.as_secs();
+    format!(
+        "{:02}:{:02}:{:02}.{:09}",
+        secs / 3600,
+        (secs / 60) % 60,
+        secs % 60,
+        time.subsec_nanos()
+    )
+}
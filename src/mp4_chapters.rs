@@ -0,0 +1,106 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::{
+    chapter::{Chapter, ChapterList},
+    chapter_writer::ChapterWriter,
+    error::{ChapterizerError, IoResultExt, Result},
+    in_place::{replace_in_place, InPlaceOptions},
+};
+
+/// The extensions of the MP4 files that chapters can be embedded in.
+pub const MP4_EXTENSIONS: [&str; 4] = ["m4b", "m4a", "mp4", "m4v"];
+
+/// Returns whether the path has the extension of an MP4 file, which chapters can be embedded in.
+pub fn is_mp4_path(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| {
+        MP4_EXTENSIONS
+            .iter()
+            .any(|mp4_ext| ext.eq_ignore_ascii_case(mp4_ext))
+    })
+}
+
+/// Writes the chapters into the MP4 file itself, both as a Nero chapter list (`chpl`), which
+/// ffmpeg and most players read, and as a QuickTime chapter track, which Apple's players read.
+/// Whatever chapters the file had are replaced in both places, whether or not existing chapters
+/// are to be stripped. Only the metadata atoms of a copy of the file are rewritten rather than
+/// remuxing it, so the audio is left as it is and it's fast even for long books. The copy
+/// replaces the file once it's verified to have the chapters, see [`replace_in_place`].
+/// Chapters can't have an explicit end in either form, so the next chapter starts at the end of
+/// a chapter.
+pub fn embed_chapters(
+    path: &Path,
+    chapter_list: &ChapterList,
+    options: &InPlaceOptions,
+) -> Result<()> {
+    replace_in_place(path, chapter_list, options, |temp_path| {
+        fs::copy(path, temp_path).io_context("Failed to copy MP4 file")?;
+        write_chapters(temp_path, &chapter_list.chapters)
+    })
+}
+
+/// Rewrites the chapter list and the chapter track of the MP4 file in place.
+fn write_chapters(path: &Path, chapters: &[Chapter]) -> Result<()> {
+    let mp4_chapters = chapters
+        .iter()
+        .map(|chapter| mp4ameta::Chapter::new(chapter.start, chapter.flat_title()))
+        .collect::<Vec<_>>();
+    let mut userdata = mp4ameta::Userdata::default();
+    *userdata.chapter_list_mut() = mp4_chapters.clone();
+    *userdata.chapter_track_mut() = mp4_chapters;
+    userdata
+        .write_with_path(
+            path,
+            &mp4ameta::WriteConfig {
+                write_chapter_list: true,
+                write_chapter_track: true,
+                ..mp4ameta::WriteConfig::NONE
+            },
+        )
+        .map_err(|source| ChapterizerError::Mp4 {
+            context: "Failed to embed chapters in MP4 file",
+            source,
+        })
+}
+
+/// Embeds the chapters in the MP4 file at the end of the file, see [`embed_chapters`]. The file
+/// is only changed once all chapters are known, so a run that's cut off leaves it untouched.
+pub struct Mp4ChapterWriter {
+    path: PathBuf,
+    options: InPlaceOptions,
+    chapters: Vec<Chapter>,
+}
+
+impl Mp4ChapterWriter {
+    pub fn new(path: &Path, options: &InPlaceOptions) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            options: *options,
+            chapters: Vec::new(),
+        }
+    }
+}
+
+impl ChapterWriter for Mp4ChapterWriter {
+    fn on_chapter_start(&mut self, chapter: &Chapter) -> Result<()> {
+        self.chapters.push(chapter.clone());
+        Ok(())
+    }
+
+    fn on_end_of_file(&mut self, file_duration: Duration) -> Result<()> {
+        let chapter_list = ChapterList {
+            chapters: std::mem::take(&mut self.chapters),
+            duration: file_duration,
+        };
+        embed_chapters(&self.path, &chapter_list, &self.options)?;
+        log::info!(
+            "Embedded {} chapter(s) in {}",
+            chapter_list.chapters.len(),
+            self.path.display()
+        );
+        Ok(())
+    }
+}
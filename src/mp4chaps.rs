@@ -0,0 +1,42 @@
+use crate::{
+    chapter::{Chapter, ChapterList, ChapterSource},
+    error::{ChapterizerError, Result},
+    parse_timestamp,
+};
+
+/// Parses a chapter file in the text format used by mp4chaps, with one chapter per line, e.g.
+/// "00:12:34.567 Chapter 3". Since the format doesn't record the duration of the audio, the
+/// duration of the chapter list is taken to be the start of the last chapter. Titles may be
+/// separated from the timestamp by a dash, as is common in lists written by hand.
+pub fn parse_mp4chaps(contents: &str) -> Result<ChapterList> {
+    let mut chapters: Vec<Chapter> = Vec::new();
+    for (index, line) in contents.lines().enumerate() {
+        let line = line.trim_start_matches('\u{feff}').trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (timestamp, title) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let start = parse_timestamp(timestamp).ok_or_else(|| ChapterizerError::ChapterParse {
+            format: "mp4chaps",
+            line: index + 1,
+            message: format!("invalid timestamp: {}", timestamp),
+        })?;
+        // Lists written by hand often separate the timestamp from the title, e.g. "12:34 - Title"
+        let title = title
+            .trim_start()
+            .trim_start_matches(['-', '\u{2013}', '\u{2014}']);
+        let title = match title.trim() {
+            "" => format!("Chapter {}", chapters.len() + 1),
+            title => title.to_string(),
+        };
+
+        chapters.push(Chapter::new(start, title, ChapterSource::UserEdit));
+    }
+
+    let duration = chapters
+        .last()
+        .map(|chapter| chapter.start)
+        .unwrap_or_default();
+    Ok(ChapterList { chapters, duration })
+}
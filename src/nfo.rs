@@ -0,0 +1,87 @@
+use std::{io::Write, time::Duration};
+
+use crate::{
+    chapter::Chapter,
+    chapter_writer::ChapterWriter,
+    error::{IoResultExt, Result},
+    escape_xml,
+};
+
+/// The number of ticks per second in the chapter positions, as media servers count them.
+const TICKS_PER_SEC: u128 = 10_000_000;
+
+/// Writes the chapters as an NFO file, the XML sidecar that Kodi and Jellyfin read the metadata of
+/// albums from, which audiobooks are shown as. Each chapter is a bookmark with its name and its
+/// start as a position in ticks of 100 nanoseconds, as well as in seconds, and the runtime of the
+/// book is written in minutes. Chapters are written as soon as they start, so the file is only
+/// valid XML once the end of the file is reached.
+pub struct NfoWriter<W: Write> {
+    writer: W,
+    header_written: bool,
+}
+
+impl<W: Write> NfoWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            header_written: false,
+        }
+    }
+
+    /// Returns the underlying writer, without flushing it.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    /// Flushes the underlying writer and returns it. The file is only valid XML once the end of
+    /// the file has been passed to the writer.
+    pub fn finalize(mut self) -> Result<W> {
+        self.writer.flush().io_context("Failed to flush nfo file")?;
+        Ok(self.writer)
+    }
+
+    fn write_header(&mut self) -> Result<()> {
+        self.writer
+            .write_all(
+                b"<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n<album>\n  <chapters>\n",
+            )
+            .io_context("Failed to write nfo header")?;
+        self.header_written = true;
+        Ok(())
+    }
+}
+
+impl<W: Write> ChapterWriter for NfoWriter<W> {
+    fn on_chapter_start(&mut self, chapter: &Chapter) -> Result<()> {
+        if !self.header_written {
+            self.write_header()?;
+        }
+
+        let ticks = chapter.start.as_nanos() * TICKS_PER_SEC / 1_000_000_000;
+        writeln!(
+            self.writer,
+            "    <chapter>\n      <name>{}</name>\n      <startpositionticks>{}</startpositionticks>\n      <start>{:.3}</start>\n    </chapter>",
+            escape_xml(&chapter.flat_title()),
+            ticks,
+            chapter.start.as_secs_f64()
+        )
+        .io_context("Failed to write nfo chapter")
+    }
+
+    fn on_end_of_file(&mut self, file_duration: Duration) -> Result<()> {
+        if !self.header_written {
+            self.write_header()?;
+        }
+
+        writeln!(
+            self.writer,
+            "  </chapters>\n  <runtime>{}</runtime>\n</album>",
+            file_duration.as_secs().div_ceil(60)
+        )
+        .io_context("Failed to write nfo footer")
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.writer.flush().io_context("Failed to flush nfo file")
+    }
+}
@@ -0,0 +1,92 @@
+use std::process::Command;
+
+use crate::{
+    error::{ChapterizerError, IoResultExt, Result},
+    report::FileReport,
+};
+
+/// A summary of a run, sent when the run completes or fails.
+#[derive(Debug, serde::Serialize)]
+pub struct RunSummary<'a> {
+    pub success: bool,
+    /// The error that the run failed with, if it failed.
+    pub error: Option<String>,
+    /// The reports of the files that were processed before the run completed or failed.
+    pub files: &'a [FileReport],
+}
+
+impl RunSummary<'_> {
+    /// A short, human readable description of the outcome of the run.
+    pub fn message(&self) -> String {
+        match &self.error {
+            Some(error) => format!("Chapterizing failed: {}", error),
+            None => {
+                let num_chapterized = self
+                    .files
+                    .iter()
+                    .filter(|report| report.skipped.is_none())
+                    .count();
+                format!("Chapterized {} file(s)", num_chapterized)
+            }
+        }
+    }
+}
+
+/// POSTs the summary as JSON to the given URL.
+pub fn post_summary(url: &str, summary: &RunSummary) -> Result<()> {
+    let body = serde_json::to_string(summary).expect("summary should be serializable");
+
+    ureq::post(url)
+        .set("Content-Type", "application/json")
+        .send_string(&body)
+        .map_err(|err| ChapterizerError::Notification(err.to_string()))?;
+
+    Ok(())
+}
+
+/// Escapes a string for use in a double-quoted AppleScript string literal.
+fn escape_applescript_string(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Returns the command that shows a desktop notification on this platform, if there is one.
+fn desktop_notification_command(title: &str, body: &str) -> Option<Command> {
+    if cfg!(target_os = "macos") {
+        let mut cmd = Command::new("osascript");
+        cmd.arg("-e").arg(format!(
+            "display notification \"{}\" with title \"{}\"",
+            escape_applescript_string(body),
+            escape_applescript_string(title)
+        ));
+        Some(cmd)
+    } else if cfg!(target_os = "windows") {
+        None
+    } else {
+        let mut cmd = Command::new("notify-send");
+        cmd.arg(title).arg(body);
+        Some(cmd)
+    }
+}
+
+/// Shows a desktop notification using the platform's notification command.
+pub fn show_desktop_notification(title: &str, body: &str) -> Result<()> {
+    let mut cmd = desktop_notification_command(title, body).ok_or_else(|| {
+        ChapterizerError::Notification(
+            "desktop notifications are not supported on this platform".into(),
+        )
+    })?;
+
+    let out = cmd
+        .output()
+        .io_context("Failed to run desktop notification command")?;
+
+    if !out.status.success() {
+        return Err(ChapterizerError::Notification(format!(
+            "notification command exited with status code {}: {}",
+            out.status,
+            String::from_utf8_lossy(&out.stderr)
+        )));
+    }
+
+    Ok(())
+}
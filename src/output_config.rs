@@ -0,0 +1,62 @@
+use std::{fs, path::Path, time::Duration};
+
+use crate::{
+    chapter::Chapter,
+    cue::CueOptions,
+    error::{ChapterizerError, IoResultExt, Result},
+    ffmetadata::FfmetadataOptions,
+    json::JsonOptions,
+    retime::ChapterRetimer,
+    split_script::SplitScriptOptions,
+    titles::TitleOptions,
+};
+
+/// The options of each of the output formats, e.g.
+/// `{"cue": {"encoding": "utf8-bom"}, "json": {"pretty": false}}`, along with the transforms
+/// applied to the titles of all formats. Unknown formats and options are rejected, so that typos
+/// don't go unnoticed.
+#[derive(Clone, Debug, Default, PartialEq, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OutputConfig {
+    pub cue: CueOptions,
+    pub ffmetadata: FfmetadataOptions,
+    pub json: JsonOptions,
+    pub split_script: SplitScriptOptions,
+    pub titles: TitleOptions,
+}
+
+impl OutputConfig {
+    /// Reads the config from a JSON file. Formats and options missing from the file keep their
+    /// defaults.
+    pub fn load(path: &Path) -> Result<Self> {
+        let json = fs::read_to_string(path).io_context("Failed to read output config file")?;
+        serde_json::from_str(&json).map_err(|source| ChapterizerError::Json {
+            context: format!("Failed to parse output config file {}", path.display()),
+            source,
+        })
+    }
+
+    /// Returns the chapters to write to the outputs now that the chapter was found, i.e. retimed
+    /// and with their titles transformed, see [`ChapterRetimer::push`].
+    pub fn finalize_chapter(
+        &self,
+        retimer: &mut ChapterRetimer,
+        chapter: &Chapter,
+    ) -> Vec<Chapter> {
+        retimer
+            .push(chapter)
+            .iter()
+            .map(|chapter| self.titles.chapter(chapter))
+            .collect()
+    }
+
+    /// Returns the chapter that's still to be written to the outputs once all chapters were found,
+    /// see [`ChapterRetimer::finish`].
+    pub fn finalize_last_chapter(
+        &self,
+        retimer: &mut ChapterRetimer,
+        duration: Duration,
+    ) -> Option<Chapter> {
+        Some(self.titles.chapter(&retimer.finish(duration)?))
+    }
+}
@@ -0,0 +1,176 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::RwLock,
+    time::{Duration, UNIX_EPOCH},
+};
+
+use lazy_static::lazy_static;
+
+#[cfg(feature = "asr")]
+use crate::audio_provider::AudioProperties;
+use crate::{extract::FfProbe, user_cache_dir};
+
+lazy_static! {
+    /// The directory of the cache, if it's enabled.
+    static ref CACHE_DIR: RwLock<Option<PathBuf>> = RwLock::new(None);
+}
+
+/// Returns the directory that probe results are cached in by default, i.e.
+/// "audiobook-chapterizer/probes" in the user's cache directory, if it can be found.
+pub fn default_probe_cache_dir() -> Option<PathBuf> {
+    Some(user_cache_dir()?.join("probes"))
+}
+
+/// Enables caching the results of probing audio files in the directory for the rest of the run.
+/// Until it's enabled, audio files are probed every time.
+pub fn enable(cache_dir: PathBuf) {
+    *CACHE_DIR.write().unwrap() = Some(cache_dir);
+}
+
+/// Identifies the version of an audio file that probe results were cached for. A file that's
+/// changed or replaced since, e.g. by embedding chapters in it, almost certainly has a different
+/// size or modification time, so its results are probed again.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+struct FileVersion {
+    path: PathBuf,
+    size: u64,
+    /// The modification time, since the Unix epoch.
+    modified: Duration,
+}
+
+impl FileVersion {
+    fn of(path: &Path) -> Option<Self> {
+        let metadata = fs::metadata(path).ok()?;
+        Some(Self {
+            path: fs::canonicalize(path).ok()?,
+            size: metadata.len(),
+            modified: metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?,
+        })
+    }
+
+    /// The path of the file that the results for this file are cached in. The name is a hash of
+    /// the path of the file, so that each file has its own small cache file.
+    fn cache_file_path(&self, cache_dir: &Path) -> PathBuf {
+        cache_dir.join(format!(
+            "{:016x}.json",
+            fnv1a(self.path.as_os_str().as_encoded_bytes())
+        ))
+    }
+}
+
+/// The cached probe results of an audio file.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct Entry {
+    file: FileVersion,
+    #[serde(default)]
+    ffprobe: Option<FfProbe>,
+    /// The duration of the default track, as found by scanning its packets.
+    #[serde(default)]
+    scanned_duration: Option<Duration>,
+    /// The properties of the file as the audio provider sees them, with the default track
+    /// selected.
+    #[cfg(feature = "asr")]
+    #[serde(default)]
+    audio_properties: Option<AudioProperties>,
+}
+
+impl Entry {
+    fn new(file: FileVersion) -> Self {
+        Self {
+            file,
+            ffprobe: None,
+            scanned_duration: None,
+            #[cfg(feature = "asr")]
+            audio_properties: None,
+        }
+    }
+}
+
+/// The 64-bit FNV-1a hash of the bytes, which unlike the hasher of the standard library is the
+/// same on every platform and in every release, so the cache files can be found again.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Returns the cached value for the audio file if there is one, or gets it, caching it if it's
+/// found. Failing to read or write the cache is never an error, the value is just not cached. The
+/// cache file is replaced atomically, so concurrent runs never read a partly written one.
+fn cached<T: Clone, E>(
+    audio_file_path: &Path,
+    field: fn(&mut Entry) -> &mut Option<T>,
+    get: impl FnOnce() -> Result<T, E>,
+) -> Result<T, E> {
+    let cache_dir = CACHE_DIR.read().unwrap().clone();
+    let Some((cache_dir, file)) =
+        cache_dir.and_then(|cache_dir| Some((cache_dir, FileVersion::of(audio_file_path)?)))
+    else {
+        return get();
+    };
+    let cache_file_path = file.cache_file_path(&cache_dir);
+
+    let mut entry = fs::read(&cache_file_path)
+        .ok()
+        .and_then(|contents| serde_json::from_slice::<Entry>(&contents).ok())
+        .filter(|entry| entry.file == file)
+        .unwrap_or_else(|| Entry::new(file));
+    if let Some(value) = field(&mut entry) {
+        log::debug!(
+            "Using cached probe results for {}",
+            audio_file_path.display()
+        );
+        return Ok(value.clone());
+    }
+
+    let value = get()?;
+    *field(&mut entry) = Some(value.clone());
+    // Each run writes its own temporary file, which is then moved over the cache file
+    let temp_file_path = cache_file_path.with_extension(format!("{}.tmp", std::process::id()));
+    let write_result = fs::create_dir_all(&cache_dir)
+        .and_then(|()| {
+            fs::write(
+                &temp_file_path,
+                serde_json::to_vec(&entry).map_err(std::io::Error::from)?,
+            )
+        })
+        .and_then(|()| fs::rename(&temp_file_path, &cache_file_path));
+    if let Err(err) = write_result {
+        let _ = fs::remove_file(&temp_file_path);
+        log::debug!(
+            "Failed to cache probe results in {}: {}",
+            cache_file_path.display(),
+            err
+        );
+    }
+    Ok(value)
+}
+
+/// Returns the cached ffprobe output for the audio file, or runs ffprobe with the function if
+/// there is none.
+pub fn ffprobe<E>(
+    audio_file_path: &Path,
+    get: impl FnOnce() -> Result<FfProbe, E>,
+) -> Result<FfProbe, E> {
+    cached(audio_file_path, |entry| &mut entry.ffprobe, get)
+}
+
+/// Returns the cached scanned duration of the default track of the audio file, or scans it with
+/// the function if there is none.
+pub fn scanned_duration<E>(
+    audio_file_path: &Path,
+    get: impl FnOnce() -> Result<Duration, E>,
+) -> Result<Duration, E> {
+    cached(audio_file_path, |entry| &mut entry.scanned_duration, get)
+}
+
+/// Returns the cached properties of the audio file with its default track selected, or inspects
+/// it with the function if there are none.
+#[cfg(feature = "asr")]
+pub fn audio_properties<E>(
+    audio_file_path: &Path,
+    get: impl FnOnce() -> Result<AudioProperties, E>,
+) -> Result<AudioProperties, E> {
+    cached(audio_file_path, |entry| &mut entry.audio_properties, get)
+}
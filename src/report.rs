@@ -0,0 +1,171 @@
+use serde_with::{serde_as, DurationSecondsWithFrac};
+use std::{
+    fs::File,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::{
+    chapter::{ChapterList, ChapterSource},
+    error::{format_error_chain, ChapterizerError, IoResultExt, Result},
+    sanity::check_chapters,
+    stats::{chapter_duration_stats, DurationStats},
+};
+
+/// The outcome of processing a single audio file, as included in the report of a batch run.
+#[serde_as]
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct FileReport {
+    pub audio_file_path: PathBuf,
+    /// The number of chapters found, not counting chapters inserted by the chapterizer itself.
+    pub chapters_found: usize,
+    /// The source that the chapters were found in, if any were found.
+    pub source: Option<ChapterSource>,
+    /// Statistics of the durations of the chapters found, if any were found.
+    pub duration_stats: Option<DurationStats>,
+    /// The wall time spent processing the file.
+    #[serde_as(as = "DurationSecondsWithFrac<f64>")]
+    pub runtime: Duration,
+    pub warnings: Vec<String>,
+    /// Whether the chapters should be reviewed manually because they're likely to be wrong.
+    pub needs_review: bool,
+    /// The error that processing the file failed with, if it failed.
+    pub error: Option<String>,
+    /// The reason that the file was skipped, if it was skipped.
+    pub skipped: Option<String>,
+}
+
+impl FileReport {
+    pub fn new(audio_file_path: PathBuf, result: &Result<ChapterList>, runtime: Duration) -> Self {
+        let (chapters_found, source, error) = match result {
+            Ok(chapter_list) => {
+                let found_chapters = chapter_list
+                    .chapters
+                    .iter()
+                    .filter(|chapter| chapter.source != ChapterSource::Inserted)
+                    .collect::<Vec<_>>();
+                (
+                    found_chapters.len(),
+                    found_chapters.first().map(|chapter| chapter.source),
+                    None,
+                )
+            }
+            Err(err) => (0, None, Some(format_error_chain(err))),
+        };
+        let duration_stats = result.as_ref().ok().and_then(chapter_duration_stats);
+
+        let mut warnings = Vec::new();
+        if error.is_none() && chapters_found == 0 {
+            warnings.push("No chapters found".to_string());
+        }
+        if source == Some(ChapterSource::Silence) {
+            warnings.push("No chapters found, chapters were placed at long silences".to_string());
+        }
+        if let Ok(chapter_list) = result {
+            warnings.extend(check_chapters(chapter_list));
+        }
+        let needs_review = !warnings.is_empty();
+
+        Self {
+            audio_file_path,
+            chapters_found,
+            source,
+            duration_stats,
+            runtime,
+            warnings,
+            needs_review,
+            error,
+            skipped: None,
+        }
+    }
+
+    pub fn skipped(audio_file_path: PathBuf, reason: impl Into<String>) -> Self {
+        Self {
+            audio_file_path,
+            chapters_found: 0,
+            source: None,
+            duration_stats: None,
+            runtime: Duration::ZERO,
+            warnings: Vec::new(),
+            needs_review: false,
+            error: None,
+            skipped: Some(reason.into()),
+        }
+    }
+}
+
+/// Quotes a CSV field if needed, per RFC 4180.
+fn escape_csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn write_csv_report(writer: &mut impl Write, reports: &[FileReport]) -> io::Result<()> {
+    writeln!(
+        writer,
+        "audio_file_path,chapters_found,source,min_chapter_duration,median_chapter_duration,\
+        max_chapter_duration,chapter_duration_std_dev,runtime,warnings,needs_review,error,skipped"
+    )?;
+
+    for report in reports {
+        let duration_stats = match report.duration_stats {
+            Some(stats) => [stats.min, stats.median, stats.max, stats.std_dev]
+                .map(|d| format!("{:.3}", d.as_secs_f64()))
+                .join(","),
+            None => ",,,".to_string(),
+        };
+
+        writeln!(
+            writer,
+            "{},{},{},{},{:.3},{},{},{},{}",
+            escape_csv_field(&report.audio_file_path.to_string_lossy()),
+            report.chapters_found,
+            report
+                .source
+                .map(|source| source.to_string())
+                .unwrap_or_default(),
+            duration_stats,
+            report.runtime.as_secs_f64(),
+            escape_csv_field(&report.warnings.join("; ")),
+            report.needs_review,
+            escape_csv_field(report.error.as_deref().unwrap_or_default()),
+            escape_csv_field(report.skipped.as_deref().unwrap_or_default()),
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Writes the reports of a batch run to a file. The format is determined by the file extension,
+/// which must be either .json or .csv
+pub fn write_report(report_file_path: &Path, reports: &[FileReport]) -> Result<()> {
+    let is_csv = match report_file_path.extension().and_then(|ext| ext.to_str()) {
+        Some("csv") => true,
+        Some("json") => false,
+        _ => {
+            return Err(ChapterizerError::InvalidOptions(
+                "report path must end in .json or .csv",
+            ))
+        }
+    };
+
+    let mut report_file =
+        File::create(report_file_path).io_context("Failed to create report file")?;
+
+    if is_csv {
+        write_csv_report(&mut report_file, reports).io_context("Failed to write report")?;
+    } else {
+        serde_json::to_writer_pretty(&mut report_file, reports)
+            .map_err(io::Error::from)
+            .io_context("Failed to write report")?;
+        report_file
+            .write_all(b"\n")
+            .io_context("Failed to write report")?;
+    }
+
+    Ok(())
+}
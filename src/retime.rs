@@ -0,0 +1,279 @@
+use std::{str::FromStr, time::Duration};
+
+use crate::{
+    chapter::{Chapter, RejectedCandidate},
+    format_duration,
+};
+
+/// An offset that can be negative, which [`Duration`] can't.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TimeOffset {
+    pub amount: Duration,
+    pub is_negative: bool,
+}
+
+impl TimeOffset {
+    /// Applies the offset to the time. Returns None if the result would be before 0:00.
+    pub fn apply(&self, time: Duration) -> Option<Duration> {
+        if self.is_negative {
+            time.checked_sub(self.amount)
+        } else {
+            Some(time + self.amount)
+        }
+    }
+}
+
+impl FromStr for TimeOffset {
+    type Err = String;
+
+    /// Parses an offset in seconds, e.g. "12.5", "+12.5" or "-12.5".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (is_negative, secs) = match s.strip_prefix('-') {
+            Some(secs) => (true, secs),
+            None => (false, s.strip_prefix('+').unwrap_or(s)),
+        };
+        let amount = secs
+            .parse::<f64>()
+            .ok()
+            .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+            .ok_or_else(|| format!("invalid offset in seconds: {}", s))?;
+        Ok(Self {
+            amount,
+            is_negative,
+        })
+    }
+}
+
+/// Adjusts the timestamps of chapters before they're written, so that the outputs match a
+/// different version of the audio file than the one the chapters were found in.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Retime {
+    /// The speed of the other version relative to this one, e.g. 1.25 for a version that was
+    /// re-encoded at 1.25x speed. Every timestamp is divided by it.
+    pub tempo_ratio: f64,
+    /// Added to every timestamp after scaling it, e.g. a negative offset for a version with the
+    /// intro trimmed off.
+    pub offset: TimeOffset,
+}
+
+impl Default for Retime {
+    fn default() -> Self {
+        Self {
+            tempo_ratio: 1.0,
+            offset: TimeOffset::default(),
+        }
+    }
+}
+
+impl Retime {
+    fn time(&self, time: Duration) -> Option<Duration> {
+        self.offset.apply(time.div_f64(self.tempo_ratio))
+    }
+
+    /// Returns the chapter with its timestamps adjusted, or None if it ends at or before 0:00
+    /// after retiming, with a warning. A start that would be before 0:00 is clamped to 0:00, with a
+    /// warning.
+    pub fn chapter(&self, chapter: &Chapter) -> Option<Chapter> {
+        let mut retimed = chapter.clone();
+        if let Some(end) = chapter.end {
+            let Some(end) = self.time(end).filter(|end| !end.is_zero()) else {
+                log::warn!(
+                    "Dropping chapter \"{}\" at {}, which ends before 0:00 after retiming",
+                    chapter.title,
+                    format_duration(&Some(chapter.start))
+                );
+                return None;
+            };
+            retimed.end = Some(end);
+        }
+        retimed.start = self.time(chapter.start).unwrap_or_else(|| {
+            log::warn!(
+                "Chapter \"{}\" at {} starts before 0:00 after retiming, clamping it to 0:00",
+                chapter.title,
+                format_duration(&Some(chapter.start))
+            );
+            Duration::ZERO
+        });
+        Some(retimed)
+    }
+
+    /// Returns the rejected candidate with its start adjusted, or None if it would be before 0:00.
+    pub fn rejected_candidate(&self, candidate: &RejectedCandidate) -> Option<RejectedCandidate> {
+        let start = self.time(candidate.start)?;
+        Some(RejectedCandidate {
+            start,
+            ..candidate.clone()
+        })
+    }
+
+    /// Returns the duration of the audio file with the adjustment applied. If it would be before
+    /// 0:00, all chapters fall off the end, which is warned about.
+    pub fn duration(&self, duration: Duration) -> Duration {
+        self.time(duration).unwrap_or_else(|| {
+            log::warn!("The end of the file is before 0:00 after retiming, all chapters fall off");
+            Duration::ZERO
+        })
+    }
+}
+
+/// Retimes the chapters passed to it in order with [`Retime::chapter`]. All chapters that would
+/// start before 0:00 start at 0:00, so only the last of those is kept, lasting until the next
+/// chapter starts; the others would end at 0:00 and are dropped. A chapter that starts at 0:00
+/// without an explicit end is therefore held back until the next chapter shows whether it's kept.
+#[derive(Debug)]
+pub struct ChapterRetimer {
+    retime: Retime,
+    pending: Option<Chapter>,
+}
+
+impl ChapterRetimer {
+    pub fn new(retime: Retime) -> Self {
+        Self {
+            retime,
+            pending: None,
+        }
+    }
+
+    /// Returns the chapters that are known to be kept now that the chapter was passed, in order.
+    pub fn push(&mut self, chapter: &Chapter) -> Vec<Chapter> {
+        let Some(retimed) = self.retime.chapter(chapter) else {
+            return Vec::new();
+        };
+        if retimed.start.is_zero() {
+            if let Some(dropped) = self.pending.take() {
+                log::warn!(
+                    "Dropping chapter \"{}\", which ends at 0:00 after retiming",
+                    dropped.title
+                );
+            }
+            if retimed.end.is_none() {
+                self.pending = Some(retimed);
+                return Vec::new();
+            }
+        }
+        self.pending.take().into_iter().chain([retimed]).collect()
+    }
+
+    /// Returns the chapter that was held back, if any, once all chapters were passed. It's dropped
+    /// too, with a warning, if the file of the given duration ends at or before 0:00 after
+    /// retiming.
+    pub fn finish(&mut self, duration: Duration) -> Option<Chapter> {
+        let pending = self.pending.take()?;
+        if self.retime.time(duration).is_some_and(|end| !end.is_zero()) {
+            Some(pending)
+        } else {
+            log::warn!(
+                "Dropping chapter \"{}\", which ends at 0:00 after retiming",
+                pending.title
+            );
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chapter::ChapterSource;
+
+    fn secs(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    fn offset(s: &str) -> Retime {
+        Retime {
+            offset: s.parse().unwrap(),
+            ..Default::default()
+        }
+    }
+
+    fn chapter(start: u64, title: &str) -> Chapter {
+        Chapter::new(secs(start), title, ChapterSource::UserEdit)
+    }
+
+    /// Passes the chapters of a file of the given duration to a retimer, returning the title and
+    /// start of each chapter it keeps.
+    fn retime_all(retime: Retime, chapters: &[Chapter], duration: u64) -> Vec<(String, Duration)> {
+        let mut retimer = ChapterRetimer::new(retime);
+        let mut retimed = chapters
+            .iter()
+            .flat_map(|chapter| retimer.push(chapter))
+            .collect::<Vec<_>>();
+        retimed.extend(retimer.finish(secs(duration)));
+        retimed
+            .into_iter()
+            .map(|chapter| (chapter.title, chapter.start))
+            .collect()
+    }
+
+    #[test]
+    fn parses_offsets() {
+        let parse = |s: &str| s.parse::<TimeOffset>();
+        assert_eq!(
+            parse("12.5"),
+            Ok(TimeOffset {
+                amount: Duration::from_millis(12500),
+                is_negative: false,
+            })
+        );
+        assert_eq!(parse("+12.5"), parse("12.5"));
+        assert_eq!(
+            parse("-3"),
+            Ok(TimeOffset {
+                amount: secs(3),
+                is_negative: true,
+            })
+        );
+        for invalid in ["", "-", "abc", "--3", "+-3", "nan", "inf"] {
+            assert!(parse(invalid).is_err(), "{:?} parsed", invalid);
+        }
+    }
+
+    #[test]
+    fn clamps_starts_before_zero() {
+        let retimed = offset("-15").chapter(&chapter(10, "One").with_end(secs(20)));
+        let retimed = retimed.unwrap();
+        assert_eq!(
+            (retimed.start, retimed.end),
+            (Duration::ZERO, Some(secs(5)))
+        );
+    }
+
+    #[test]
+    fn drops_chapters_that_end_before_zero() {
+        let retime = offset("-15");
+        assert!(retime
+            .chapter(&chapter(0, "Intro").with_end(secs(10)))
+            .is_none());
+        assert!(retime
+            .chapter(&chapter(0, "Intro").with_end(secs(15)))
+            .is_none());
+    }
+
+    #[test]
+    fn holds_back_chapters_at_zero_until_the_next_one() {
+        let mut retimer = ChapterRetimer::new(Retime::default());
+        assert!(retimer.push(&chapter(0, "Intro")).is_empty());
+        let retimed = retimer.push(&chapter(10, "One"));
+        let titles = retimed.iter().map(|c| c.title.as_str()).collect::<Vec<_>>();
+        assert_eq!(titles, ["Intro", "One"]);
+        assert!(retimer.finish(secs(30)).is_none());
+    }
+
+    #[test]
+    fn keeps_only_the_last_chapter_clamped_to_zero() {
+        let chapters = [chapter(0, "Intro"), chapter(10, "One"), chapter(20, "Two")];
+        assert_eq!(
+            retime_all(offset("-15"), &chapters, 30),
+            [("One".into(), Duration::ZERO), ("Two".into(), secs(5))]
+        );
+    }
+
+    #[test]
+    fn offset_past_the_end_drops_all_chapters() {
+        let retime = offset("-100");
+        let chapters = [chapter(0, "Intro"), chapter(10, "One"), chapter(20, "Two")];
+        assert!(retime_all(retime, &chapters, 30).is_empty());
+        assert_eq!(retime.duration(secs(30)), Duration::ZERO);
+    }
+}
@@ -0,0 +1,109 @@
+use std::time::Duration;
+
+use crate::{
+    chapter::{ChapterList, ChapterSource},
+    format_duration,
+    stats::chapter_duration_stats,
+};
+
+/// If the average chapter is shorter than this, the chapters are likely to contain false positives.
+const MIN_AVG_CHAPTER_DURATION: Duration = Duration::from_secs(3 * 60);
+
+/// If a single chapter covers more than this fraction of the file, chapters were likely missed.
+const MAX_CHAPTER_FRACTION: f64 = 0.9;
+
+/// A chapter that is this many times shorter or longer than the median chapter is an outlier,
+/// which is a strong signal of a missed or false chapter.
+const OUTLIER_RATIO: f64 = 4.0;
+
+/// With fewer chapters than this, the median chapter duration isn't meaningful enough to flag
+/// outliers.
+const MIN_CHAPTERS_FOR_OUTLIERS: usize = 4;
+
+/// Runs a number of heuristics over the chapters to catch outputs that are likely to be wrong,
+/// returning a warning for each problem found. Chapters that trigger any warnings should be
+/// reviewed manually.
+pub fn check_chapters(chapter_list: &ChapterList) -> Vec<String> {
+    let mut warnings = Vec::new();
+    let chapters = &chapter_list.chapters;
+
+    for (index, pair) in chapters.windows(2).enumerate() {
+        if pair[1].start <= pair[0].start {
+            warnings.push(format!(
+                "Chapter {} at {} does not start after the previous chapter at {}",
+                index + 1,
+                format_duration(&Some(pair[1].start)),
+                format_duration(&Some(pair[0].start))
+            ));
+        }
+    }
+
+    for (index, chapter) in chapters.iter().enumerate() {
+        if chapter.start > chapter_list.duration {
+            warnings.push(format!(
+                "Chapter {} at {} starts after the end of the file at {}",
+                index,
+                format_duration(&Some(chapter.start)),
+                format_duration(&Some(chapter_list.duration))
+            ));
+        }
+    }
+
+    // The remaining heuristics only make sense if there is more than one chapter
+    if chapters.len() < 2 || chapter_list.duration.is_zero() {
+        return warnings;
+    }
+
+    let avg_chapter_duration = chapter_list.duration / chapters.len() as u32;
+    if avg_chapter_duration < MIN_AVG_CHAPTER_DURATION {
+        warnings.push(format!(
+            "Chapters are implausibly dense: {} chapters with an average duration of {}",
+            chapters.len(),
+            format_duration(&Some(avg_chapter_duration))
+        ));
+    }
+
+    for (index, (chapter, end)) in chapter_list.iter_with_end().enumerate() {
+        let chapter_duration = end.saturating_sub(chapter.start);
+        let fraction = chapter_duration.as_secs_f64() / chapter_list.duration.as_secs_f64();
+        if fraction > MAX_CHAPTER_FRACTION {
+            warnings.push(format!(
+                "Chapter {} at {} covers {:.0}% of the file",
+                index,
+                format_duration(&Some(chapter.start)),
+                fraction * 100.0
+            ));
+        }
+    }
+
+    let real_chapters = chapters
+        .iter()
+        .filter(|chapter| chapter.source != ChapterSource::Inserted)
+        .count();
+    if real_chapters >= MIN_CHAPTERS_FOR_OUTLIERS {
+        let median = chapter_duration_stats(chapter_list)
+            .expect("there should be chapters")
+            .median
+            .as_secs_f64();
+
+        // Every chapter would be an outlier compared to a median of zero, which can't be divided by
+        for (index, (chapter, end)) in chapter_list.iter_with_end().enumerate() {
+            if chapter.source == ChapterSource::Inserted || median == 0.0 {
+                continue;
+            }
+            let chapter_duration = end.saturating_sub(chapter.start);
+            let ratio = chapter_duration.as_secs_f64() / median;
+            if !(1.0 / OUTLIER_RATIO..=OUTLIER_RATIO).contains(&ratio) {
+                warnings.push(format!(
+                    "Chapter {} at {} is an outlier: its duration of {} is {:.1}x the median",
+                    index,
+                    format_duration(&Some(chapter.start)),
+                    format_duration(&Some(chapter_duration)),
+                    ratio
+                ));
+            }
+        }
+    }
+
+    warnings
+}
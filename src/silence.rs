@@ -0,0 +1,184 @@
+use serde_with::{serde_as, DurationSecondsWithFrac};
+use std::{io::Write, time::Duration};
+
+use crate::{
+    chapter::{Chapter, ChapterList, ChapterSource},
+    error::{IoResultExt, Result},
+};
+
+/// The length of the frames whose loudness is compared against the threshold.
+const FRAME_SECS: f64 = 0.01;
+
+/// The thresholds used to detect silences.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SilenceOptions {
+    /// Audio quieter than this, in dBFS, is silent.
+    pub threshold_db: f32,
+    /// The minimum duration of a silence.
+    pub min_duration: Duration,
+}
+
+impl Default for SilenceOptions {
+    fn default() -> Self {
+        Self {
+            threshold_db: -40.0,
+            min_duration: Duration::from_millis(500),
+        }
+    }
+}
+
+/// A stretch of audio quieter than the threshold.
+#[serde_as]
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize)]
+pub struct SilenceRegion {
+    #[serde_as(as = "DurationSecondsWithFrac<f64>")]
+    pub start: Duration,
+    #[serde_as(as = "DurationSecondsWithFrac<f64>")]
+    pub duration: Duration,
+    /// The RMS level of the audio in the silence, in dBFS.
+    pub rms_db: f32,
+}
+
+/// Converts the mean of the squared samples to dBFS.
+fn to_db(mean_square: f64) -> f32 {
+    let rms = mean_square.sqrt() / i16::MAX as f64;
+    (20.0 * rms.max(1e-10).log10()) as f32
+}
+
+/// Finds the silences in mono audio, frame by frame.
+pub struct SilenceDetector {
+    sample_rate: f64,
+    options: SilenceOptions,
+    frame_len: usize,
+    /// The sum of the squared samples of the current frame, and how many have been added.
+    frame: (f64, usize),
+    frames_done: u64,
+    /// The first frame of the current silence, and the sum of its squared samples.
+    silence: Option<(u64, f64)>,
+    regions: Vec<SilenceRegion>,
+}
+
+impl SilenceDetector {
+    pub fn new(sample_rate: u32, options: SilenceOptions) -> Self {
+        Self {
+            sample_rate: sample_rate as f64,
+            options,
+            frame_len: ((sample_rate as f64 * FRAME_SECS) as usize).max(1),
+            frame: (0.0, 0),
+            frames_done: 0,
+            silence: None,
+            regions: Vec::new(),
+        }
+    }
+
+    pub fn push_samples(&mut self, samples: &[i16]) {
+        for &sample in samples {
+            let (sum_squares, count) = &mut self.frame;
+            *sum_squares += sample as f64 * sample as f64;
+            *count += 1;
+            if *count == self.frame_len {
+                let (sum_squares, _) = std::mem::replace(&mut self.frame, (0.0, 0));
+                self.finish_frame(sum_squares);
+            }
+        }
+    }
+
+    fn frame_start(&self, frame: u64) -> Duration {
+        Duration::from_secs_f64((frame * self.frame_len as u64) as f64 / self.sample_rate)
+    }
+
+    fn finish_frame(&mut self, sum_squares: f64) {
+        let is_silent = to_db(sum_squares / self.frame_len as f64) < self.options.threshold_db;
+        match (&mut self.silence, is_silent) {
+            (Some((_, silence_sum_squares)), true) => *silence_sum_squares += sum_squares,
+            (None, true) => self.silence = Some((self.frames_done, sum_squares)),
+            (Some(_), false) => self.end_silence(),
+            (None, false) => (),
+        }
+        self.frames_done += 1;
+    }
+
+    /// Ends the current silence at the current frame, keeping it if it's long enough.
+    fn end_silence(&mut self) {
+        let Some((first_frame, sum_squares)) = self.silence.take() else {
+            return;
+        };
+        let num_frames = self.frames_done - first_frame;
+        let start = self.frame_start(first_frame);
+        let duration = self.frame_start(self.frames_done) - start;
+        if duration >= self.options.min_duration {
+            self.regions.push(SilenceRegion {
+                start,
+                duration,
+                rms_db: to_db(sum_squares / (num_frames * self.frame_len as u64) as f64),
+            });
+        }
+    }
+
+    /// Returns the silences found, in order. A trailing partial frame is ignored.
+    pub fn finish(mut self) -> Vec<SilenceRegion> {
+        self.end_silence();
+        self.regions
+    }
+}
+
+/// Places a chapter after each silence, as a last resort for books in which no chapter
+/// announcements were recognized. Silences that end within the minimum chapter duration of the
+/// previous chapter are skipped, since a book has more pauses than chapters. The silences are
+/// expected to be long enough to stand out from the pauses between sentences. The first chapter
+/// starts at 0:00, and the chapters are numbered in order.
+pub fn chapters_at_silences(
+    silences: &[SilenceRegion],
+    duration: Duration,
+    min_chapter_duration: Duration,
+) -> ChapterList {
+    let mut starts = vec![Duration::ZERO];
+    for silence in silences {
+        let start = silence.start + silence.duration;
+        let prev_start = *starts.last().unwrap();
+        if start.saturating_sub(prev_start) >= min_chapter_duration
+            && duration.saturating_sub(start) >= min_chapter_duration
+        {
+            starts.push(start);
+        }
+    }
+
+    let chapters = starts
+        .into_iter()
+        .enumerate()
+        .map(|(index, start)| {
+            Chapter::new(
+                start,
+                format!("Chapter {:02}", index + 1),
+                ChapterSource::Silence,
+            )
+        })
+        .collect();
+    ChapterList { chapters, duration }
+}
+
+/// Writes the silences as a JSON array, e.g. `[{"start": 12.3, "duration": 1.5, "rms_db": -52.1}]`.
+pub fn write_silences_json(mut writer: impl Write, silences: &[SilenceRegion]) -> Result<()> {
+    serde_json::to_writer_pretty(&mut writer, silences)
+        .map_err(std::io::Error::from)
+        .io_context("Failed to write silences")?;
+    writer
+        .write_all(b"\n")
+        .io_context("Failed to write silences")
+}
+
+/// Writes the silences as CSV with a header row, with times in seconds.
+pub fn write_silences_csv(mut writer: impl Write, silences: &[SilenceRegion]) -> Result<()> {
+    let mut csv = String::from("start,duration,rms_db\n");
+    for silence in silences {
+        csv.push_str(&format!(
+            "{:.3},{:.3},{:.1}\n",
+            silence.start.as_secs_f64(),
+            silence.duration.as_secs_f64(),
+            silence.rms_db
+        ));
+    }
+    writer
+        .write_all(csv.as_bytes())
+        .io_context("Failed to write silences")
+}
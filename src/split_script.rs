@@ -0,0 +1,198 @@
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::{
+    chapter::Chapter,
+    chapter_writer::ChapterWriter,
+    error::{IoResultExt, Result},
+    format_duration, sanitize_file_name,
+};
+
+/// The shell that a split script is written for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScriptShell {
+    /// A POSIX shell, e.g. sh or bash.
+    Sh,
+    PowerShell,
+}
+
+impl ScriptShell {
+    /// Picks the shell from the extension of the script, i.e. PowerShell for ".ps1" and a POSIX
+    /// shell for anything else.
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension() {
+            Some(extension) if extension.eq_ignore_ascii_case("ps1") => ScriptShell::PowerShell,
+            _ => ScriptShell::Sh,
+        }
+    }
+
+    /// Quotes the string so that the shell passes it on as is.
+    fn quote(&self, s: &str) -> String {
+        match self {
+            ScriptShell::Sh => format!("'{}'", s.replace('\'', r"'\''")),
+            ScriptShell::PowerShell => format!("'{}'", s.replace('\'', "''")),
+        }
+    }
+}
+
+/// The options of the split script writer.
+#[derive(Clone, Debug, PartialEq, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SplitScriptOptions {
+    /// Whether to copy the audio rather than encode it again. Copying is fast and lossless, but
+    /// can only cut between packets, so the cuts may be off by a fraction of a second.
+    pub copy: bool,
+    /// The extension of the files that the chapters are split into, e.g. "mp3". Defaults to the
+    /// extension of the audio file.
+    pub extension: Option<String>,
+}
+
+impl Default for SplitScriptOptions {
+    fn default() -> Self {
+        Self {
+            copy: true,
+            extension: None,
+        }
+    }
+}
+
+/// Writes a shell script that splits the audio file into one file per chapter with ffmpeg, for
+/// those who'd rather run the cutting themselves. The files are named after the chapters, e.g.
+/// "03 - Chapter 02.m4b", and written to the directory the script is run from. Since the script
+/// can only be written once the end of the file is known, chapters are collected in memory until
+/// then.
+pub struct SplitScriptWriter<W: Write> {
+    writer: W,
+    shell: ScriptShell,
+    options: SplitScriptOptions,
+    audio_file_path: PathBuf,
+    /// The chapters along with their ends.
+    chapters: Vec<(Chapter, Duration)>,
+    /// If the chapter has no explicit end, we still need the start time of the next chapter to
+    /// actually add it.
+    partial_chapter: Option<Chapter>,
+}
+
+impl<W: Write> SplitScriptWriter<W> {
+    pub fn new(
+        writer: W,
+        shell: ScriptShell,
+        audio_file_path: &Path,
+        options: &SplitScriptOptions,
+    ) -> Self {
+        Self {
+            writer,
+            shell,
+            options: options.clone(),
+            audio_file_path: audio_file_path.to_path_buf(),
+            chapters: Vec::new(),
+            partial_chapter: None,
+        }
+    }
+
+    /// Returns the underlying writer, without flushing it. Nothing is written to it before the
+    /// end of the file.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    /// Flushes the underlying writer and returns it. The script is written at the end of the
+    /// file, so that should be passed to the writer first.
+    pub fn finalize(mut self) -> Result<W> {
+        self.writer
+            .flush()
+            .io_context("Failed to flush split script file")?;
+        Ok(self.writer)
+    }
+
+    fn push_chapter(&mut self, chapter: Chapter, end: Duration) {
+        let end = chapter.end.unwrap_or(end);
+        self.chapters.push((chapter, end));
+    }
+
+    fn script(&self) -> String {
+        let extension = self.options.extension.clone().unwrap_or_else(|| {
+            self.audio_file_path
+                .extension()
+                .map(|extension| extension.to_string_lossy().into_owned())
+                .unwrap_or_else(|| "m4a".into())
+        });
+        let number_width = self.chapters.len().to_string().len().max(2);
+        let codec_args = if self.options.copy { " -c copy" } else { "" };
+        let input = self.shell.quote(&self.audio_file_path.to_string_lossy());
+
+        let mut lines = match self.shell {
+            ScriptShell::Sh => vec![
+                "#!/bin/sh".to_string(),
+                "# Splits the audio file into one file per chapter.".into(),
+                "set -e".into(),
+                format!("input={}", input),
+            ],
+            ScriptShell::PowerShell => vec![
+                "# Splits the audio file into one file per chapter.".to_string(),
+                format!("$inputFile = {}", input),
+            ],
+        };
+        let input_var = match self.shell {
+            ScriptShell::Sh => "\"$input\"",
+            ScriptShell::PowerShell => "$inputFile",
+        };
+
+        for (index, (chapter, end)) in self.chapters.iter().enumerate() {
+            let file_name = format!(
+                "{:0width$} - {}.{}",
+                index + 1,
+                sanitize_file_name(&chapter.flat_title()),
+                extension,
+                width = number_width
+            );
+            lines.push(format!(
+                "ffmpeg -nostdin -hide_banner -i {} -ss {} -to {} -map 0:a{} {}",
+                input_var,
+                format_duration(&Some(chapter.start)),
+                format_duration(&Some(*end)),
+                codec_args,
+                self.shell.quote(&file_name)
+            ));
+            // Unlike sh with set -e, PowerShell carries on when a command fails
+            if self.shell == ScriptShell::PowerShell {
+                lines.push("if ($LASTEXITCODE -ne 0) { exit $LASTEXITCODE }".into());
+            }
+        }
+
+        lines.push(String::new());
+        lines.join("\n")
+    }
+}
+
+impl<W: Write> ChapterWriter for SplitScriptWriter<W> {
+    fn on_chapter_start(&mut self, chapter: &Chapter) -> Result<()> {
+        if let Some(prev_chapter) = self.partial_chapter.take() {
+            self.push_chapter(prev_chapter, chapter.start);
+        }
+
+        self.partial_chapter = Some(chapter.clone());
+
+        Ok(())
+    }
+
+    fn on_end_of_file(&mut self, file_duration: Duration) -> Result<()> {
+        if let Some(chapter) = self.partial_chapter.take() {
+            self.push_chapter(chapter, file_duration);
+        }
+
+        let script = self.script();
+        self.writer
+            .write_all(script.as_bytes())
+            .io_context("Failed to write split script")
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.writer
+            .flush()
+            .io_context("Failed to flush split script")
+    }
+}
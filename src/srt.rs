@@ -0,0 +1,95 @@
+use std::{io::Write, time::Duration};
+
+use crate::{
+    chapter::Chapter,
+    chapter_writer::ChapterWriter,
+    error::{IoResultExt, Result},
+};
+
+/// Writes the chapters as an SRT file, one numbered cue per chapter with its title, including the
+/// section, as the text, for players and head units that show subtitles but don't read chapter
+/// tracks. Cues need an end time, so each chapter is written once the next one starts.
+pub struct SrtWriter<W: Write> {
+    writer: W,
+    /// The number of cues written so far, as cues are numbered from 1.
+    num_cues: usize,
+    /// If the chapter has no explicit end, we still need the start time of the next chapter to
+    /// actually write it.
+    partial_chapter: Option<Chapter>,
+}
+
+impl<W: Write> SrtWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            num_cues: 0,
+            partial_chapter: None,
+        }
+    }
+
+    /// Returns the underlying writer, without flushing it.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    /// Flushes the underlying writer and returns it. The last chapter is only written once the end
+    /// of the file has been passed to the writer.
+    pub fn finalize(mut self) -> Result<W> {
+        self.writer.flush().io_context("Failed to flush SRT file")?;
+        Ok(self.writer)
+    }
+
+    /// Writes the chapter, using the given end time if it doesn't have an explicit end.
+    fn write_chapter(&mut self, chapter: &Chapter, end: Duration) -> Result<()> {
+        // Cues are separated by a blank line
+        if self.num_cues > 0 {
+            writeln!(self.writer).io_context("Failed to write SRT cue")?;
+        }
+        self.num_cues += 1;
+        writeln!(
+            self.writer,
+            "{}\n{} --> {}\n{}",
+            self.num_cues,
+            format_time(chapter.start),
+            format_time(chapter.end.unwrap_or(end)),
+            // A blank line would end the cue
+            chapter.flat_title().replace(['\r', '\n'], " ")
+        )
+        .io_context("Failed to write SRT cue")
+    }
+}
+
+impl<W: Write> ChapterWriter for SrtWriter<W> {
+    fn on_chapter_start(&mut self, chapter: &Chapter) -> Result<()> {
+        if let Some(prev_chapter) = self.partial_chapter.take() {
+            self.write_chapter(&prev_chapter, chapter.start)?;
+        }
+        self.partial_chapter = Some(chapter.clone());
+
+        Ok(())
+    }
+
+    fn on_end_of_file(&mut self, file_duration: Duration) -> Result<()> {
+        if let Some(chapter) = self.partial_chapter.take() {
+            self.write_chapter(&chapter, file_duration)?;
+        }
+
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.writer.flush().io_context("Failed to flush SRT file")
+    }
+}
+
+/// Formats the time as hh:mm:ss,mmm, the way SRT cue timings hold them.
+fn format_time(time: Duration) -> String {
+    let secs = time.as_secs();
+    format!(
+        "{:02}:{:02}:{:02},{:03}",
+        secs / 3600,
+        (secs / 60) % 60,
+        secs % 60,
+        time.subsec_millis()
+    )
+}
@@ -0,0 +1,67 @@
+use serde_with::{serde_as, DurationSecondsWithFrac};
+use std::time::Duration;
+
+use crate::chapter::{ChapterList, ChapterSource};
+
+/// Summary statistics of a set of durations.
+#[serde_as]
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize)]
+pub struct DurationStats {
+    #[serde_as(as = "DurationSecondsWithFrac<f64>")]
+    pub min: Duration,
+    #[serde_as(as = "DurationSecondsWithFrac<f64>")]
+    pub median: Duration,
+    #[serde_as(as = "DurationSecondsWithFrac<f64>")]
+    pub max: Duration,
+    /// The population standard deviation.
+    #[serde_as(as = "DurationSecondsWithFrac<f64>")]
+    pub std_dev: Duration,
+}
+
+impl DurationStats {
+    /// Calculates the statistics of the durations. Returns None if there are no durations.
+    pub fn from_durations(durations: &[Duration]) -> Option<Self> {
+        if durations.is_empty() {
+            return None;
+        }
+
+        let mut sorted = durations.to_vec();
+        sorted.sort();
+
+        let len = sorted.len();
+        let median = if len.is_multiple_of(2) {
+            (sorted[len / 2 - 1] + sorted[len / 2]) / 2
+        } else {
+            sorted[len / 2]
+        };
+
+        let mean = sorted.iter().map(Duration::as_secs_f64).sum::<f64>() / len as f64;
+        let variance = sorted
+            .iter()
+            .map(|d| (d.as_secs_f64() - mean).powi(2))
+            .sum::<f64>()
+            / len as f64;
+
+        Some(Self {
+            min: sorted[0],
+            median,
+            max: sorted[len - 1],
+            std_dev: Duration::from_secs_f64(variance.sqrt()),
+        })
+    }
+}
+
+/// Returns the durations of the chapters, skipping chapters inserted by the chapterizer itself
+/// since those aren't real chapters and tend to be much shorter than the rest.
+pub fn chapter_durations(chapter_list: &ChapterList) -> Vec<Duration> {
+    chapter_list
+        .iter_with_end()
+        .filter(|(chapter, _)| chapter.source != ChapterSource::Inserted)
+        .map(|(chapter, end)| end.saturating_sub(chapter.start))
+        .collect()
+}
+
+/// Calculates the statistics of the durations of the chapters.
+pub fn chapter_duration_stats(chapter_list: &ChapterList) -> Option<DurationStats> {
+    DurationStats::from_durations(&chapter_durations(chapter_list))
+}
@@ -0,0 +1,145 @@
+use serde_with::{serde_as, DurationSecondsWithFrac};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::{
+    error::{ChapterizerError, IoResultExt, Result},
+    format_duration,
+};
+
+/// Whether the run that writes a status file is still going.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RunState {
+    Running,
+    Finished,
+    /// The run gave up before reaching the end of the audio, e.g. because it ran for too long.
+    Aborted,
+}
+
+/// A chapter found so far, as listed in a status file.
+#[serde_as]
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct StatusChapter {
+    #[serde_as(as = "DurationSecondsWithFrac<f64>")]
+    pub start: Duration,
+    pub title: String,
+}
+
+/// The calibration of a run to its narrator, as listed in a status file.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct StatusCalibration {
+    /// The number of words recognized in the calibration pass.
+    pub num_words: usize,
+    pub mean_word_confidence: f32,
+    /// Words per second, counting only the time spent speaking.
+    pub speech_rate: f32,
+    /// The thresholds used after calibration.
+    pub min_vocal_pause_before_chapter: f32,
+    pub reading_phrase_window: f32,
+    pub min_confidence: Option<f32>,
+}
+
+/// The progress of a chapterizer run, written to a status file while it runs so that it can be
+/// monitored from elsewhere, e.g. by a dashboard or by the status subcommand.
+#[serde_as]
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ProgressStatus {
+    pub audio_file: PathBuf,
+    pub state: RunState,
+    /// When the status was written, as an RFC 3339 timestamp.
+    pub updated_at: String,
+    /// How far into the audio the recognizer is.
+    #[serde_as(as = "DurationSecondsWithFrac<f64>")]
+    pub position: Duration,
+    #[serde_as(as = "Option<DurationSecondsWithFrac<f64>>")]
+    pub total_duration: Option<Duration>,
+    /// Whether the total duration, and so the percentage and ETA, are estimated from the file
+    /// size because the metadata doesn't specify the duration.
+    pub duration_is_estimate: bool,
+    pub percent: Option<f32>,
+    /// How many seconds of audio are processed per second.
+    pub speed: f32,
+    /// When the run is expected to finish, as an RFC 3339 timestamp.
+    pub eta: Option<String>,
+    /// The chapters found so far. A chapter is only listed once the next one is found.
+    pub chapters: Vec<StatusChapter>,
+    /// The calibration of the run, if it was calibrated.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub calibration: Option<StatusCalibration>,
+}
+
+/// Writes the status file. The file is replaced atomically, so that readers never see a partly
+/// written status.
+pub fn write_status(path: &Path, status: &ProgressStatus) -> Result<()> {
+    let mut temp_file_name = path.file_name().unwrap_or_default().to_os_string();
+    temp_file_name.push(".tmp");
+    let temp_path = path.with_file_name(temp_file_name);
+
+    let json = serde_json::to_vec_pretty(status).expect("status is serializable");
+    fs::write(&temp_path, json).io_context("Failed to write status file")?;
+    fs::rename(&temp_path, path).io_context("Failed to replace status file")
+}
+
+pub fn read_status(path: &Path) -> Result<ProgressStatus> {
+    let json = fs::read(path).io_context("Failed to read status file")?;
+    serde_json::from_slice(&json).map_err(|source| ChapterizerError::Json {
+        context: format!("Failed to parse status file {}", path.display()),
+        source,
+    })
+}
+
+/// Formats the status for display, one property per line, followed by the chapters found so far.
+pub fn format_status(status: &ProgressStatus) -> String {
+    let estimate_marker = if status.duration_is_estimate { "~" } else { "" };
+    let mut lines = vec![
+        format!("Audio file: {}", status.audio_file.display()),
+        format!(
+            "State: {}",
+            match status.state {
+                RunState::Running => "running",
+                RunState::Finished => "finished",
+                RunState::Aborted => "aborted",
+            }
+        ),
+        format!("Updated: {}", status.updated_at),
+        format!(
+            "Progress: {} @ {} of {}{}",
+            match status.percent {
+                Some(percent) => format!("{}{:05.2}%", estimate_marker, percent),
+                None => "??%".into(),
+            },
+            format_duration(&Some(status.position)),
+            estimate_marker,
+            format_duration(&status.total_duration)
+        ),
+        format!("Speed: {:.2}x", status.speed),
+    ];
+    if status.state == RunState::Running {
+        lines.push(format!(
+            "ETA: {}{}",
+            estimate_marker,
+            status.eta.as_deref().unwrap_or("??")
+        ));
+    }
+    if let Some(calibration) = &status.calibration {
+        lines.push(format!(
+            "Calibration: {:.2} words/s, mean word confidence {:.2}, min pause {:.2}s",
+            calibration.speech_rate,
+            calibration.mean_word_confidence,
+            calibration.min_vocal_pause_before_chapter
+        ));
+    }
+    lines.push(format!("Chapters so far: {}", status.chapters.len()));
+    for chapter in &status.chapters {
+        lines.push(format!(
+            "  {}  {}",
+            format_duration(&Some(chapter.start)),
+            chapter.title
+        ));
+    }
+    lines.join("\n")
+}
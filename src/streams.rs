@@ -0,0 +1,167 @@
+use std::{io::Write, path::Path};
+
+#[cfg(feature = "asr")]
+use std::io::SeekFrom;
+
+#[cfg(feature = "asr")]
+use symphonia::core::io::MediaSource;
+
+#[cfg(feature = "asr")]
+use crate::{
+    audio_provider,
+    chapter::Chapter,
+    error::{ChapterizerError, IoResultExt},
+    id3_chapters,
+};
+use crate::{
+    audiobookshelf::AudiobookshelfWriter,
+    chapter::ChapterList,
+    chapter_writer::ChapterWriter,
+    cue::{CueOptions, CueWriter},
+    error::Result,
+    ffmetadata::{FfmetadataOptions, FfmetadataWriter},
+    json::{JsonOptions, JsonWriter},
+    matroska::MatroskaWriter,
+    nfo::NfoWriter,
+    split_script::{ScriptShell, SplitScriptOptions, SplitScriptWriter},
+    srt::SrtWriter,
+    vtt::VttWriter,
+};
+
+/// Reads the chapters embedded in the audio from the reader, for applications that have the
+/// audio in memory or as a stream rather than as a file. Chapters are read from the ID3v2 CHAP
+/// frames of the audio if it has any, or from the cue points of its container otherwise, e.g.
+/// the cuesheet of a FLAC file. Unlike [`crate::extract::extract_chapters`], this doesn't run
+/// ffprobe, so the chapters of MP4 and Matroska files aren't read. Returns
+/// [`ChapterizerError::NoChapters`] if the audio has no chapters.
+#[cfg(feature = "asr")]
+pub fn extract_chapters_from_reader(
+    mut reader: impl MediaSource + 'static,
+) -> Result<Vec<Chapter>> {
+    match id3::Tag::read_from2(&mut reader) {
+        Ok(tag) => {
+            let chapters = id3_chapters::read_chapters(&tag);
+            if !chapters.is_empty() {
+                return Ok(chapters);
+            }
+        }
+        Err(err) if matches!(err.kind, id3::ErrorKind::NoTag) => {}
+        Err(err) => log::debug!("Failed to read ID3v2 tag: {}", err),
+    }
+    reader
+        .seek(SeekFrom::Start(0))
+        .io_context("Failed to rewind audio")?;
+
+    let chapters = audio_provider::read_cue_chapters(reader, &Default::default())?;
+    if chapters.is_empty() {
+        return Err(ChapterizerError::NoChapters);
+    }
+    Ok(chapters)
+}
+
+/// Writes the chapter list to the writer as a cue sheet, referring to the audio by the file name
+/// of audio_file_path.
+pub fn write_cue(
+    chapter_list: &ChapterList,
+    audio_file_path: &Path,
+    options: &CueOptions,
+    writer: &mut impl Write,
+) -> Result<()> {
+    let mut cue_writer = CueWriter::new(writer, options);
+    cue_writer.write_header(audio_file_path)?;
+    write_chapter_list(chapter_list, &mut cue_writer)?;
+    cue_writer.finalize()?;
+    Ok(())
+}
+
+/// Writes the chapter list to the writer as an ffmetadata file.
+pub fn write_ffmetadata(
+    chapter_list: &ChapterList,
+    options: &FfmetadataOptions,
+    writer: &mut impl Write,
+) -> Result<()> {
+    let mut ffmetadata_writer = FfmetadataWriter::new(writer, options);
+    ffmetadata_writer.write_header()?;
+    write_chapter_list(chapter_list, &mut ffmetadata_writer)?;
+    ffmetadata_writer.finalize()?;
+    Ok(())
+}
+
+/// Writes the chapter list to the writer as JSON.
+pub fn write_json(
+    chapter_list: &ChapterList,
+    options: &JsonOptions,
+    writer: &mut impl Write,
+) -> Result<()> {
+    let mut json_writer = JsonWriter::new(writer, options);
+    write_chapter_list(chapter_list, &mut json_writer)?;
+    json_writer.finalize()?;
+    Ok(())
+}
+
+/// Writes a script that splits the audio file into the chapters of the chapter list to the writer.
+pub fn write_split_script(
+    chapter_list: &ChapterList,
+    shell: ScriptShell,
+    audio_file_path: &Path,
+    options: &SplitScriptOptions,
+    writer: &mut impl Write,
+) -> Result<()> {
+    let mut split_script_writer = SplitScriptWriter::new(writer, shell, audio_file_path, options);
+    write_chapter_list(chapter_list, &mut split_script_writer)?;
+    split_script_writer.finalize()?;
+    Ok(())
+}
+
+/// Writes the chapter list to the writer as an Audiobookshelf metadata.json file.
+pub fn write_audiobookshelf(chapter_list: &ChapterList, writer: &mut impl Write) -> Result<()> {
+    let mut audiobookshelf_writer = AudiobookshelfWriter::new(writer);
+    write_chapter_list(chapter_list, &mut audiobookshelf_writer)?;
+    audiobookshelf_writer.finalize()?;
+    Ok(())
+}
+
+/// Writes the chapter list to the writer as a Kodi/Jellyfin NFO file.
+pub fn write_nfo(chapter_list: &ChapterList, writer: &mut impl Write) -> Result<()> {
+    let mut nfo_writer = NfoWriter::new(writer);
+    write_chapter_list(chapter_list, &mut nfo_writer)?;
+    nfo_writer.finalize()?;
+    Ok(())
+}
+
+/// Writes the chapter list to the writer as a Matroska chapter XML file.
+pub fn write_matroska(chapter_list: &ChapterList, writer: &mut impl Write) -> Result<()> {
+    let mut matroska_writer = MatroskaWriter::new(writer);
+    write_chapter_list(chapter_list, &mut matroska_writer)?;
+    matroska_writer.finalize()?;
+    Ok(())
+}
+
+/// Writes the chapter list to the writer as a WebVTT chapters track.
+pub fn write_vtt(chapter_list: &ChapterList, writer: &mut impl Write) -> Result<()> {
+    let mut vtt_writer = VttWriter::new(writer);
+    write_chapter_list(chapter_list, &mut vtt_writer)?;
+    vtt_writer.finalize()?;
+    Ok(())
+}
+
+/// Writes the chapter list to the writer as an SRT file.
+pub fn write_srt(chapter_list: &ChapterList, writer: &mut impl Write) -> Result<()> {
+    let mut srt_writer = SrtWriter::new(writer);
+    write_chapter_list(chapter_list, &mut srt_writer)?;
+    srt_writer.finalize()?;
+    Ok(())
+}
+
+/// Passes the chapters of the chapter list to the chapter writer, followed by the end of the
+/// file. Chapters are written as they are, so they should be finalized with
+/// [`crate::output_config::OutputConfig::finalize_chapter`] first if needed.
+fn write_chapter_list(
+    chapter_list: &ChapterList,
+    chapter_writer: &mut impl ChapterWriter,
+) -> Result<()> {
+    for chapter in &chapter_list.chapters {
+        chapter_writer.on_chapter_start(chapter)?;
+    }
+    chapter_writer.on_end_of_file(chapter_list.duration)
+}
@@ -0,0 +1,128 @@
+use std::{
+    ops::{Add, AddAssign, Sub},
+    time::Duration,
+};
+
+/// A point in time in the audio, in seconds from its start, as the recognizer reports the times of
+/// words. Recognition results are parsed in these, and they're converted to a [`Duration`] once
+/// they become part of a chapter.
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, PartialOrd, serde::Serialize, serde::Deserialize,
+)]
+#[serde(transparent)]
+pub struct AudioTimestamp(f32);
+
+impl AudioTimestamp {
+    pub const ZERO: Self = Self(0.0);
+
+    /// A timestamp after every point in the audio.
+    pub const END: Self = Self(f32::INFINITY);
+
+    pub const fn from_secs(secs: f32) -> Self {
+        Self(secs)
+    }
+
+    pub fn from_duration(duration: Duration) -> Self {
+        Self(duration.as_secs_f32())
+    }
+
+    pub const fn as_secs(self) -> f32 {
+        self.0
+    }
+
+    /// Converts the timestamp to a duration since the start of the audio. Timestamps before the
+    /// start are clamped to it, and [`AudioTimestamp::END`] becomes [`Duration::MAX`].
+    pub fn to_duration(self) -> Duration {
+        Duration::try_from_secs_f32(self.0.max(0.0)).unwrap_or(Duration::MAX)
+    }
+
+    /// Returns the timestamp the given number of seconds later, or earlier if negative.
+    pub fn add_secs(self, secs: f32) -> Self {
+        Self(self.0 + secs)
+    }
+
+    /// Returns the number of seconds since the earlier timestamp, which is negative if it's
+    /// actually later.
+    pub fn secs_since(self, earlier: Self) -> f32 {
+        self.0 - earlier.0
+    }
+
+    /// Moves a timestamp that's relative to origin, e.g. the time of a word in a window of audio
+    /// that was recognized on its own, to the audio as a whole.
+    pub fn relative_to(self, origin: Self) -> Self {
+        Self(origin.0 + self.0)
+    }
+
+    /// Returns the timestamp halfway between the two.
+    pub fn midpoint(self, other: Self) -> Self {
+        Self((self.0 + other.0) / 2.0)
+    }
+}
+
+/// A position in the audio as the number of samples before it, at the sample rate the audio is
+/// decoded at. The audio is downmixed to mono, so there's one sample per point in time.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SampleOffset(u64);
+
+impl SampleOffset {
+    pub const ZERO: Self = Self(0);
+
+    pub const fn new(samples: u64) -> Self {
+        Self(samples)
+    }
+
+    /// Returns the offset of the sample at the given timestamp, rounded down. Timestamps before
+    /// the start of the audio are clamped to it.
+    pub fn from_timestamp(timestamp: AudioTimestamp, sample_rate: u32) -> Self {
+        Self((timestamp.as_secs().max(0.0) as f64 * sample_rate as f64) as u64)
+    }
+
+    /// Returns the offset of the sample at the given duration since the start of the audio,
+    /// rounded down.
+    pub fn from_duration(duration: Duration, sample_rate: u32) -> Self {
+        Self((duration.as_secs_f64() * sample_rate as f64) as u64)
+    }
+
+    pub const fn samples(self) -> u64 {
+        self.0
+    }
+
+    pub fn to_timestamp(self, sample_rate: u32) -> AudioTimestamp {
+        AudioTimestamp::from_secs((self.0 as f64 / sample_rate as f64) as f32)
+    }
+
+    pub fn to_duration(self, sample_rate: u32) -> Duration {
+        Duration::from_secs_f64(self.0 as f64 / sample_rate as f64)
+    }
+}
+
+impl Add<u64> for SampleOffset {
+    type Output = Self;
+
+    fn add(self, samples: u64) -> Self {
+        Self(self.0 + samples)
+    }
+}
+
+impl AddAssign<u64> for SampleOffset {
+    fn add_assign(&mut self, samples: u64) {
+        self.0 += samples;
+    }
+}
+
+impl Sub<u64> for SampleOffset {
+    type Output = Self;
+
+    fn sub(self, samples: u64) -> Self {
+        Self(self.0 - samples)
+    }
+}
+
+impl Sub for SampleOffset {
+    /// The number of samples between the two offsets.
+    type Output = u64;
+
+    fn sub(self, earlier: Self) -> u64 {
+        self.0 - earlier.0
+    }
+}
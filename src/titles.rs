@@ -0,0 +1,189 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+use unicode_normalization::UnicodeNormalization;
+
+use crate::chapter::Chapter;
+
+lazy_static! {
+    /// Matches the number at the start of titles such as "Chapter 05" or "Chapter 5: The End".
+    static ref CHAPTER_NUMBER_REGEX: Regex = Regex::new(r"(?i)^(chapter\s+)(\d+)").unwrap();
+}
+
+/// Words that aren't capitalized in title case, unless they're the first or last word.
+const MINOR_WORDS: &[&str] = &[
+    "a", "an", "and", "as", "at", "but", "by", "for", "from", "in", "into", "nor", "of", "on",
+    "or", "over", "the", "to", "up", "vs", "with",
+];
+
+/// A Unicode normalization form.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NormalizationForm {
+    Nfc,
+    Nfd,
+    Nfkc,
+    Nfkd,
+}
+
+/// Transforms applied to the titles of chapters before they're written, e.g.
+/// `{"normalize": "nfc", "ascii": true, "title_case": true, "max_length": 40}`. They're applied
+/// in that order, after the chapter numbers are offset and the prefix is added.
+#[derive(Clone, Debug, Default, PartialEq, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TitleOptions {
+    /// The Unicode normalization form to convert titles to, if any.
+    pub normalize: Option<NormalizationForm>,
+    /// Whether to transliterate titles to ASCII, e.g. "Café Müller" to "Cafe Muller", for players
+    /// that can't display anything else. Since the separator between the title of a section and
+    /// of a chapter in it isn't ASCII, chapters in sections are flattened, e.g. to
+    /// "Part 02 - Chapter 05".
+    pub ascii: bool,
+    /// Whether to convert titles to title case. Minor words such as "of" and "the" are kept in
+    /// lowercase, and words that already contain capitals after their first letter, such as
+    /// acronyms, are kept as they are.
+    pub title_case: bool,
+    /// The maximum length of a title in characters. Longer titles are cut off with an ellipsis.
+    pub max_length: Option<usize>,
+    /// Added to the numbers of chapters titled e.g. "Chapter 05", to continue the numbering of the
+    /// previous volume of a set, e.g. 13 turns "Chapter 01" into "Chapter 14". Chapter 00, which
+    /// is inserted before the first chapter, keeps its number.
+    pub number_offset: u32,
+    /// Put before the title of each chapter, or of its section if it's in one, e.g. "Book 2, ".
+    pub prefix: Option<String>,
+}
+
+impl TitleOptions {
+    /// Returns the chapter with the transforms applied to its title and the title of its section.
+    pub fn chapter(&self, chapter: &Chapter) -> Chapter {
+        let mut transformed = chapter.clone();
+        if self.number_offset > 0 {
+            transformed.title = offset_chapter_number(&transformed.title, self.number_offset);
+            transformed.parent = transformed
+                .parent
+                .map(|parent| offset_chapter_number(&parent, self.number_offset));
+        }
+        if let Some(prefix) = &self.prefix {
+            match &mut transformed.parent {
+                Some(parent) => parent.insert_str(0, prefix),
+                None => transformed.title.insert_str(0, prefix),
+            }
+        }
+        if self.ascii {
+            if let Some(parent) = transformed.parent.take() {
+                transformed.title = format!("{} - {}", parent, transformed.title);
+            }
+        }
+        transformed.title = self.title(&transformed.title);
+        transformed.parent = transformed.parent.map(|parent| self.title(&parent));
+        transformed
+    }
+
+    pub fn title(&self, title: &str) -> String {
+        let mut title = match self.normalize {
+            Some(NormalizationForm::Nfc) => title.nfc().collect(),
+            Some(NormalizationForm::Nfd) => title.nfd().collect(),
+            Some(NormalizationForm::Nfkc) => title.nfkc().collect(),
+            Some(NormalizationForm::Nfkd) => title.nfkd().collect(),
+            None => title.to_string(),
+        };
+        if self.ascii {
+            title = deunicode::deunicode(&title);
+        }
+        if self.title_case {
+            title = title_case(&title);
+        }
+        if let Some(max_length) = self.max_length {
+            title = truncate(&title, max_length, if self.ascii { "..." } else { "…" });
+        }
+        title
+    }
+}
+
+/// Returns the number of the chapter if it's titled e.g. "Chapter 05" or "Chapter 5: The End", or
+/// if its section is, for the parts of a chapter.
+pub fn chapter_number(chapter: &Chapter) -> Option<u32> {
+    let number = |title: &str| {
+        CHAPTER_NUMBER_REGEX
+            .captures(title)
+            .and_then(|captures| captures[2].parse().ok())
+    };
+    number(&chapter.title).or_else(|| chapter.parent.as_deref().and_then(number))
+}
+
+/// Adds the offset to the number of a title such as "Chapter 05", keeping at least as many
+/// digits. Other titles, and chapter 0, are returned as they are.
+fn offset_chapter_number(title: &str, offset: u32) -> String {
+    let Some(captures) = CHAPTER_NUMBER_REGEX.captures(title) else {
+        return title.to_string();
+    };
+    let digits = &captures[2];
+    match digits.parse::<u32>() {
+        Ok(number) if number > 0 => format!(
+            "{}{:0width$}{}",
+            &captures[1],
+            number.saturating_add(offset),
+            &title[captures[0].len()..],
+            width = digits.len()
+        ),
+        _ => title.to_string(),
+    }
+}
+
+/// Capitalizes the first letter of each word, except for minor words in the middle of the title.
+fn title_case(title: &str) -> String {
+    let words = title.split(' ').collect::<Vec<_>>();
+    let last_index = words.iter().rposition(|word| !word.is_empty());
+    words
+        .iter()
+        .enumerate()
+        .map(|(index, word)| {
+            let mut chars = word.chars();
+            let Some(first) = chars.next() else {
+                return String::new();
+            };
+            let rest = chars.as_str();
+            if rest.chars().any(char::is_uppercase) {
+                // e.g. "NASA" or "iPhone"
+                return word.to_string();
+            }
+            let is_first = index == 0;
+            let is_last = Some(index) == last_index;
+            // A minor word after a colon or dash starts a subtitle, e.g. "Chapter 5: The End"
+            let starts_subtitle = index > 0 && words[index - 1].ends_with([':', '-', '–', '—']);
+            let lowercase = word.to_lowercase();
+            if !is_first
+                && !is_last
+                && !starts_subtitle
+                && MINOR_WORDS.contains(&lowercase.as_str())
+            {
+                lowercase
+            } else {
+                first.to_uppercase().chain(rest.chars()).collect()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Cuts the title off at max_length characters, including the ellipsis, at a word boundary if
+/// there's one in the second half of what's kept. If the ellipsis would leave no room for the
+/// title, the title is cut off without one instead.
+fn truncate(title: &str, max_length: usize, ellipsis: &str) -> String {
+    if title.chars().count() <= max_length {
+        return title.to_string();
+    }
+    let ellipsis_length = ellipsis.chars().count();
+    if max_length <= ellipsis_length {
+        return title.chars().take(max_length).collect();
+    }
+    let keep = max_length - ellipsis_length;
+    let kept = match title.char_indices().nth(keep) {
+        Some((index, _)) => &title[..index],
+        None => title,
+    };
+    let kept = match kept.rfind(' ') {
+        Some(index) if kept[..index].chars().count() >= keep / 2 => &kept[..index],
+        _ => kept,
+    };
+    format!("{}{}", kept.trim_end(), ellipsis)
+}
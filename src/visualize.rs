@@ -0,0 +1,160 @@
+use std::{fmt::Write as _, time::Duration};
+
+use crate::{chapter::ChapterList, escape_xml, format_duration, silence::SilenceRegion};
+
+/// The length of the buckets of audio whose loudness makes up the envelope.
+const BUCKET_SECS: f64 = 0.5;
+
+/// The width and height of the drawing, in pixels.
+const WIDTH: f64 = 2000.0;
+const HEIGHT: f64 = 240.0;
+
+/// The height reserved above the envelope for chapter titles.
+const LABEL_HEIGHT: f64 = 40.0;
+
+/// The loudness at the bottom of the envelope, in dBFS. Quieter audio is clamped to it.
+const FLOOR_DB: f32 = -60.0;
+
+/// Tracks the loudness of mono audio over time, as the RMS level of each bucket in dBFS.
+pub struct LoudnessEnvelope {
+    bucket_len: usize,
+    /// The sum of the squared samples of the current bucket, and how many have been added.
+    bucket: (f64, usize),
+    levels: Vec<f32>,
+}
+
+impl LoudnessEnvelope {
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            bucket_len: ((sample_rate as f64 * BUCKET_SECS) as usize).max(1),
+            bucket: (0.0, 0),
+            levels: Vec::new(),
+        }
+    }
+
+    pub fn push_samples(&mut self, samples: &[i16]) {
+        for &sample in samples {
+            let (sum_squares, count) = &mut self.bucket;
+            *sum_squares += sample as f64 * sample as f64;
+            *count += 1;
+            if *count == self.bucket_len {
+                self.finish_bucket();
+            }
+        }
+    }
+
+    fn finish_bucket(&mut self) {
+        let (sum_squares, count) = std::mem::replace(&mut self.bucket, (0.0, 0));
+        let rms = (sum_squares / count as f64).sqrt() / i16::MAX as f64;
+        self.levels
+            .push((20.0 * rms.max(1e-10).log10()).max(FLOOR_DB as f64) as f32);
+    }
+
+    /// Returns the level of each bucket, including a trailing partial bucket.
+    pub fn finish(mut self) -> Vec<f32> {
+        if self.bucket.1 > 0 {
+            self.finish_bucket();
+        }
+        self.levels
+    }
+}
+
+/// What to mark on the drawing besides the loudness envelope.
+pub struct Markers<'a> {
+    pub chapters: Option<&'a ChapterList>,
+    pub silences: &'a [SilenceRegion],
+    /// The times of candidates that didn't become chapters, e.g. the word "chapter" without a
+    /// number after it.
+    pub rejected_candidates: &'a [Duration],
+}
+
+/// Draws the loudness envelope of the audio as an SVG strip, with silences shaded, chapters marked
+/// in blue and rejected candidates marked in red. Hovering over a marker shows its time.
+pub fn render_svg(levels: &[f32], markers: &Markers) -> String {
+    let duration = levels.len() as f64 * BUCKET_SECS;
+    let x = |time: Duration| time.as_secs_f64() / duration.max(BUCKET_SECS) * WIDTH;
+    let envelope_height = HEIGHT - LABEL_HEIGHT;
+    let y = |level: f32| {
+        HEIGHT - ((level - FLOOR_DB) / -FLOOR_DB).clamp(0.0, 1.0) as f64 * envelope_height
+    };
+
+    let mut svg = String::new();
+    // Writing to a String can't fail
+    let _ = writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{}" viewBox="0 0 {} {}" font-family="sans-serif" font-size="11">"#,
+        WIDTH, HEIGHT, WIDTH, HEIGHT
+    );
+    let _ = writeln!(
+        svg,
+        r##"<rect width="{}" height="{}" fill="#ffffff"/>"##,
+        WIDTH, HEIGHT
+    );
+
+    for silence in markers.silences {
+        let start = x(silence.start);
+        let _ = writeln!(
+            svg,
+            r##"<rect x="{:.1}" y="{}" width="{:.1}" height="{}" fill="#e0e0e0"><title>Silence at {} ({:.2}s, {:.1} dBFS)</title></rect>"##,
+            start,
+            LABEL_HEIGHT,
+            (x(silence.start + silence.duration) - start).max(0.5),
+            envelope_height,
+            format_duration(&Some(silence.start)),
+            silence.duration.as_secs_f64(),
+            silence.rms_db
+        );
+    }
+
+    // The envelope is filled down to the bottom edge, so only its top needs to be traced
+    let mut points = format!("0,{}", HEIGHT);
+    for (i, &level) in levels.iter().enumerate() {
+        let _ = write!(
+            points,
+            " {:.1},{:.1}",
+            x(Duration::from_secs_f64((i as f64 + 0.5) * BUCKET_SECS)),
+            y(level)
+        );
+    }
+    let _ = write!(points, " {},{}", WIDTH, HEIGHT);
+    let _ = writeln!(svg, r##"<polygon points="{}" fill="#7a869a"/>"##, points);
+
+    for &time in markers.rejected_candidates {
+        let _ = writeln!(
+            svg,
+            r##"<line x1="{0:.1}" y1="{1}" x2="{0:.1}" y2="{2}" stroke="#d62728" stroke-dasharray="4 3"><title>Rejected candidate at {3}</title></line>"##,
+            x(time),
+            LABEL_HEIGHT,
+            HEIGHT,
+            format_duration(&Some(time))
+        );
+    }
+
+    if let Some(chapter_list) = markers.chapters {
+        for (i, chapter) in chapter_list.chapters.iter().enumerate() {
+            let chapter_x = x(chapter.start);
+            let title = escape_xml(&chapter.flat_title());
+            // Stagger the labels so that those of short chapters don't overlap as much
+            let label_y = if i % 2 == 0 { 14.0 } else { 30.0 };
+            let _ = writeln!(
+                svg,
+                r##"<line x1="{0:.1}" y1="{1}" x2="{0:.1}" y2="{2}" stroke="#1f77b4" stroke-width="2"><title>{3} at {4}</title></line>"##,
+                chapter_x,
+                label_y - 10.0,
+                HEIGHT,
+                title,
+                format_duration(&Some(chapter.start))
+            );
+            let _ = writeln!(
+                svg,
+                r##"<text x="{:.1}" y="{}" fill="#1f77b4">{}</text>"##,
+                chapter_x + 3.0,
+                label_y,
+                title
+            );
+        }
+    }
+
+    svg.push_str("</svg>\n");
+    svg
+}
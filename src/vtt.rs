@@ -0,0 +1,179 @@
+use std::{io::Write, time::Duration};
+
+use crate::{
+    chapter::{Chapter, ChapterList, ChapterSource},
+    chapter_writer::ChapterWriter,
+    error::{ChapterizerError, IoResultExt, Result},
+    parse_timestamp,
+};
+
+/// Writes the chapters as a WebVTT chapters track, which web players show as the chapters of the
+/// media it's added to with `<track kind="chapters">`. Each chapter is a cue with its title,
+/// including the section, as the text. Cues need an end time, so each chapter is written once the
+/// next one starts.
+pub struct VttWriter<W: Write> {
+    writer: W,
+    header_written: bool,
+    /// If the chapter has no explicit end, we still need the start time of the next chapter to
+    /// actually write it.
+    partial_chapter: Option<Chapter>,
+}
+
+impl<W: Write> VttWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            header_written: false,
+            partial_chapter: None,
+        }
+    }
+
+    /// Returns the underlying writer, without flushing it.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    /// Flushes the underlying writer and returns it. The last chapter is only written once the end
+    /// of the file has been passed to the writer.
+    pub fn finalize(mut self) -> Result<W> {
+        self.writer
+            .flush()
+            .io_context("Failed to flush WebVTT file")?;
+        Ok(self.writer)
+    }
+
+    fn write_header(&mut self) -> Result<()> {
+        self.writer
+            .write_all(b"WEBVTT\n")
+            .io_context("Failed to write WebVTT header")?;
+        self.header_written = true;
+        Ok(())
+    }
+
+    /// Writes the chapter, using the given end time if it doesn't have an explicit end.
+    fn write_chapter(&mut self, chapter: &Chapter, end: Duration) -> Result<()> {
+        write!(
+            self.writer,
+            "\n{} --> {}\n{}\n",
+            format_time(chapter.start),
+            format_time(chapter.end.unwrap_or(end)),
+            escape_cue_text(&chapter.flat_title())
+        )
+        .io_context("Failed to write WebVTT cue")
+    }
+}
+
+impl<W: Write> ChapterWriter for VttWriter<W> {
+    fn on_chapter_start(&mut self, chapter: &Chapter) -> Result<()> {
+        if !self.header_written {
+            self.write_header()?;
+        }
+
+        if let Some(prev_chapter) = self.partial_chapter.take() {
+            self.write_chapter(&prev_chapter, chapter.start)?;
+        }
+        self.partial_chapter = Some(chapter.clone());
+
+        Ok(())
+    }
+
+    fn on_end_of_file(&mut self, file_duration: Duration) -> Result<()> {
+        if !self.header_written {
+            self.write_header()?;
+        }
+
+        if let Some(chapter) = self.partial_chapter.take() {
+            self.write_chapter(&chapter, file_duration)?;
+        }
+
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.writer
+            .flush()
+            .io_context("Failed to flush WebVTT file")
+    }
+}
+
+/// Formats the time as hh:mm:ss.mmm, the way WebVTT cue timings hold them.
+fn format_time(time: Duration) -> String {
+    let secs = time.as_secs();
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        secs / 3600,
+        (secs / 60) % 60,
+        secs % 60,
+        time.subsec_millis()
+    )
+}
+
+/// Escapes the characters that have a special meaning in the text of a WebVTT cue. Line breaks
+/// are replaced with spaces, as a blank line would end the cue.
+fn escape_cue_text(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace(['\r', '\n'], " ")
+}
+
+/// Reverses [`escape_cue_text`], apart from line breaks. `&amp;` is replaced last, so that e.g.
+/// an escaped "&lt;" stays as it is.
+fn unescape_cue_text(s: &str) -> String {
+    s.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+/// Parses the cues of a WebVTT chapters file as chapters. The duration of the chapter list is taken
+/// to be the end of the last cue.
+pub fn parse_vtt(contents: &str) -> Result<ChapterList> {
+    let parse_error = |line: usize, message: String| ChapterizerError::ChapterParse {
+        format: "vtt",
+        line,
+        message,
+    };
+
+    let mut lines = contents.lines().enumerate().peekable();
+    if !lines
+        .next()
+        .is_some_and(|(_, line)| line.trim_start_matches('\u{feff}').starts_with("WEBVTT"))
+    {
+        return Err(parse_error(1, "missing WEBVTT header".into()));
+    }
+
+    let mut chapters = Vec::new();
+    while let Some((index, line)) = lines.next() {
+        // Cues are the blocks containing a timing line. Other blocks, e.g. notes and styles, and
+        // the identifiers of cues are skipped.
+        let Some((start, rest)) = line.split_once("-->") else {
+            continue;
+        };
+        // The end time may be followed by cue settings
+        let end = rest.split_whitespace().next().unwrap_or_default();
+        let (Some(start), Some(end)) = (parse_timestamp(start.trim()), parse_timestamp(end)) else {
+            return Err(parse_error(
+                index + 1,
+                format!("invalid cue timing: {}", line),
+            ));
+        };
+
+        let mut title = Vec::new();
+        while let Some((_, line)) = lines.next_if(|(_, line)| !line.trim().is_empty()) {
+            title.push(line.trim());
+        }
+        let title = match title.is_empty() {
+            true => format!("Chapter {}", chapters.len() + 1),
+            false => unescape_cue_text(&title.join(" ")),
+        };
+
+        chapters.push(Chapter::new(start, title, ChapterSource::UserEdit).with_end(end));
+    }
+
+    let duration = chapters
+        .iter()
+        .filter_map(|chapter| chapter.end)
+        .max()
+        .unwrap_or(Duration::ZERO);
+    Ok(ChapterList { chapters, duration })
+}